@@ -0,0 +1,146 @@
+//! Throughput and accuracy of the conversion modes.
+//!
+//! Run with `cargo bench --features bench`. Criterion is not used to keep the crate free of
+//! dependencies; each case is timed over a fixed grid of points covering China.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use undrift_gps::batch::{convert_batch, BatchOptions};
+use undrift_gps::bulk::{BulkConverter, Kernel};
+use undrift_gps::drift_field::DriftField;
+use undrift_gps::GeodeticSystem::*;
+use undrift_gps::{wgs_to_gcj, Algorithm, Converter, CHINA_BBOX};
+
+const SIDE: usize = 500;
+const ROUNDS: usize = 5;
+
+fn grid() -> Vec<(f64, f64)> {
+    let mut points = Vec::with_capacity(SIDE * SIDE);
+    for i in 0..SIDE {
+        for j in 0..SIDE {
+            let lat = 18.0 + 36.0 * i as f64 / SIDE as f64;
+            let lon = 73.0 + 62.0 * j as f64 / SIDE as f64;
+            points.push(wgs_to_gcj(lat, lon));
+        }
+    }
+    points
+}
+
+/// Runs `f` over copies of the points, returning the best time of a few rounds.
+fn time<F: FnMut(&mut [(f64, f64)])>(points: &[(f64, f64)], mut f: F) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let mut copy = points.to_vec();
+        let start = Instant::now();
+        f(&mut copy);
+        best = best.min(start.elapsed());
+        black_box(&copy);
+    }
+    best
+}
+
+fn report(name: &str, points: usize, elapsed: Duration, error: Option<f64>) {
+    let rate = points as f64 / elapsed.as_secs_f64() / 1e6;
+    match error {
+        Some(error) => println!("{:<28} {:>8.2} Mpt/s {:>12.3e} m", name, rate, error),
+        None => println!("{:<28} {:>8.2} Mpt/s", name, rate),
+    }
+}
+
+/// Largest distance between the GCJ-02 points and the forward transform of their inverse.
+fn residual(input: &[(f64, f64)], output: &[(f64, f64)]) -> f64 {
+    const METERS_PER_DEGREE: f64 = 111_320.0;
+    input
+        .iter()
+        .zip(output)
+        .map(|(&(lat, lon), &(x, y))| {
+            let (p, q) = wgs_to_gcj(x, y);
+            let east = (q - lon) * lat.to_radians().cos();
+            (p - lat).hypot(east) * METERS_PER_DEGREE
+        })
+        .fold(0.0, f64::max)
+}
+
+fn main() {
+    let points = grid();
+    println!("GCJ-02 to WGS-84, {} points\n", points.len());
+
+    let elapsed = time(&points, |points| {
+        for point in points {
+            *point = undrift_gps::gcj_to_wgs(point.0, point.1);
+        }
+    });
+    let output: Vec<_> = points
+        .iter()
+        .map(|&(lat, lon)| undrift_gps::gcj_to_wgs(lat, lon))
+        .collect();
+    report(
+        "scalar gcj_to_wgs",
+        points.len(),
+        elapsed,
+        Some(residual(&points, &output)),
+    );
+
+    for &algorithm in &Algorithm::ALL {
+        let converter = Converter::new(Gcj02, Wgs84).with_algorithm(algorithm);
+        let elapsed = time(&points, |points| {
+            for point in points {
+                *point = converter.convert(point.0, point.1);
+            }
+        });
+        let output: Vec<_> = points
+            .iter()
+            .map(|&(lat, lon)| converter.convert(lat, lon))
+            .collect();
+        let name = format!("converter {}", algorithm.key());
+        report(
+            &name,
+            points.len(),
+            elapsed,
+            Some(residual(&points, &output)),
+        );
+    }
+
+    let converter = Converter::new(Gcj02, Wgs84);
+    let options = BatchOptions::default();
+    let elapsed = time(&points, |points| {
+        convert_batch(&converter, points, &options);
+    });
+    report("batch", points.len(), elapsed, None);
+
+    let mut bulk = BulkConverter::new(converter);
+    let elapsed = time(&points, |points| {
+        bulk.convert(points);
+    });
+    report("bulk pairs", points.len(), elapsed, None);
+
+    let (lats, lons): (Vec<f64>, Vec<f64>) = points.iter().copied().unzip();
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let (mut lats, mut lons) = (lats.clone(), lons.clone());
+        best = best.min(bulk.convert_split(&mut lats, &mut lons).elapsed);
+    }
+    report("bulk split", points.len(), best, None);
+
+    let converter = Converter::new(Gcj02, Wgs84).with_algorithm(Algorithm::FastApprox);
+    for &kernel in &[
+        Kernel::Scalar,
+        Kernel::Portable,
+        Kernel::Avx2,
+        Kernel::Avx512,
+        Kernel::Neon,
+    ] {
+        if !kernel.is_supported() {
+            continue;
+        }
+        let mut bulk = BulkConverter::new(converter.clone()).with_kernel(kernel);
+        let elapsed = time(&points, |points| {
+            bulk.convert(points);
+        });
+        let name = format!("bulk fast-approx {:?}", kernel);
+        report(&name, points.len(), elapsed, None);
+    }
+
+    let start = Instant::now();
+    black_box(DriftField::sample(CHINA_BBOX, SIDE, SIDE));
+    report("drift field grid", SIDE * SIDE, start.elapsed(), None);
+}
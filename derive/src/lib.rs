@@ -0,0 +1,166 @@
+//! `#[derive(ConvertCoords)]` for undrift_gps, re-exported by it with the `derive` feature.
+//!
+//! The fields to convert are marked with `#[coords(...)]`:
+//!
+//! - `lat` and `lon` mark `f64` fields, paired in order of appearance;
+//! - `position` marks a `(lat, lon)` tuple field;
+//! - `nested` marks a field whose type itself implements `ConvertCoords`.
+//!
+//! Only structs with named fields and without generics are supported. The parsing is done by hand
+//! to keep the crate free of dependencies.
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+
+/// How a field is converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Lat,
+    Lon,
+    Position,
+    Nested,
+}
+
+#[proc_macro_derive(ConvertCoords, attributes(coords))]
+pub fn derive_convert_coords(input: TokenStream) -> TokenStream {
+    let code = match expand(input) {
+        Ok(code) => code,
+        Err(message) => format!("compile_error!({:?});", message),
+    };
+    code.parse().unwrap()
+}
+
+fn expand(input: TokenStream) -> Result<String, String> {
+    let mut tokens = input.into_iter();
+    let mut name = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ident) if ident.to_string() == "struct" => match tokens.next() {
+                Some(TokenTree::Ident(ident)) => {
+                    name = Some(ident.to_string());
+                    break;
+                }
+                _ => return Err("expected a struct name".to_owned()),
+            },
+            TokenTree::Ident(ident) if ["enum", "union"].contains(&&*ident.to_string()) => {
+                return Err("ConvertCoords can only be derived for structs".to_owned())
+            }
+            _ => {}
+        }
+    }
+    let name = name.ok_or("expected a struct")?;
+
+    let body = match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => group.stream(),
+        Some(TokenTree::Punct(punct)) if punct.as_char() == '<' => {
+            return Err("ConvertCoords cannot be derived for generic structs".to_owned())
+        }
+        _ => return Err("ConvertCoords needs a struct with named fields".to_owned()),
+    };
+
+    let fields = fields(body)?;
+    let mut lats = Vec::new();
+    let mut lons = Vec::new();
+    let mut statements = Vec::new();
+    for (field, role) in fields {
+        match role {
+            Role::Lat => lats.push(field),
+            Role::Lon => lons.push(field),
+            Role::Position => statements.push(format!(
+                "self.{0} = converter.convert(self.{0}.0, self.{0}.1);",
+                field
+            )),
+            Role::Nested => statements.push(format!(
+                "::undrift_gps::ConvertCoords::convert_coords_with(&mut self.{}, converter);",
+                field
+            )),
+        }
+    }
+    if lats.len() != lons.len() {
+        return Err("every #[coords(lat)] field needs a #[coords(lon)] field".to_owned());
+    }
+    for (lat, lon) in lats.iter().zip(&lons) {
+        statements.push(format!(
+            "let (lat, lon) = converter.convert(self.{0}, self.{1}); \
+             self.{0} = lat; self.{1} = lon;",
+            lat, lon
+        ));
+    }
+
+    Ok(format!(
+        "impl ::undrift_gps::ConvertCoords for {} {{ \
+             fn convert_coords_with(&mut self, converter: &::undrift_gps::Converter) {{ {} }} \
+         }}",
+        name,
+        statements.join(" ")
+    ))
+}
+
+/// Parses named fields, returning the annotated ones with their role.
+fn fields(body: TokenStream) -> Result<Vec<(String, Role)>, String> {
+    let mut fields = Vec::new();
+    let mut role = None;
+    let mut name = None;
+    // Whether the type of the field is being read, with the depth of its angle brackets.
+    let mut in_type = None;
+    let mut tokens = body.into_iter();
+    while let Some(token) = tokens.next() {
+        match (&token, in_type) {
+            (TokenTree::Punct(punct), None) if punct.as_char() == '#' => {
+                if let Some(TokenTree::Group(attribute)) = tokens.next() {
+                    if let Some(r) = attribute_role(attribute.stream())? {
+                        if role.replace(r).is_some() {
+                            return Err("a field can only have one #[coords] role".to_owned());
+                        }
+                    }
+                }
+            }
+            (TokenTree::Punct(punct), None) if punct.as_char() == ':' => in_type = Some(0),
+            (TokenTree::Ident(ident), None) => name = Some(ident.to_string()),
+            (TokenTree::Punct(punct), Some(depth)) => match punct.as_char() {
+                '<' => in_type = Some(depth + 1),
+                // Skip the `>` of `->` in function types.
+                '-' if punct.spacing() == Spacing::Joint => {
+                    tokens.next();
+                }
+                '>' => in_type = Some(depth - 1),
+                ',' if depth == 0 => {
+                    let field = name.take().ok_or("expected a field name")?;
+                    if let Some(role) = role.take() {
+                        fields.push((field, role));
+                    }
+                    in_type = None;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    if let (Some(field), Some(role)) = (name, role) {
+        fields.push((field, role));
+    }
+    Ok(fields)
+}
+
+/// The role given by an attribute, if it is `#[coords(...)]`.
+fn attribute_role(attribute: TokenStream) -> Result<Option<Role>, String> {
+    let mut tokens = attribute.into_iter();
+    match tokens.next() {
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "coords" => {}
+        _ => return Ok(None),
+    }
+    let argument = match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+            group.stream().to_string()
+        }
+        _ => String::new(),
+    };
+    match argument.trim() {
+        "lat" => Ok(Some(Role::Lat)),
+        "lon" => Ok(Some(Role::Lon)),
+        "position" => Ok(Some(Role::Position)),
+        "nested" => Ok(Some(Role::Nested)),
+        _ => Err(
+            "expected #[coords(lat)], #[coords(lon)], #[coords(position)] or #[coords(nested)]"
+                .to_owned(),
+        ),
+    }
+}
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use undrift_gps::{fit, Converter, GeodeticSystem};
+
+fuzz_target!(|data: &[u8]| {
+    let converter = Converter::new(GeodeticSystem::Gcj02, GeodeticSystem::Wgs84);
+    let _ = fit::positions(data);
+    let _ = fit::track(data);
+    let _ = fit::convert(&converter, &mut data.to_vec());
+});
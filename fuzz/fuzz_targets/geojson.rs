@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use undrift_gps::{geojson, topojson, Converter, GeodeticSystem};
+
+fuzz_target!(|text: &str| {
+    let converter = Converter::new(GeodeticSystem::Gcj02, GeodeticSystem::Wgs84);
+    let _ = geojson::convert(&converter, text, &Default::default());
+    let _ = geojson::positions_3d(text);
+    let _ = topojson::convert(&converter, text);
+});
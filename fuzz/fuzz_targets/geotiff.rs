@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use undrift_gps::georef::{self, MapUnits};
+use undrift_gps::{Converter, GeodeticSystem};
+
+fuzz_target!(|data: &[u8]| {
+    let converter = Converter::new(GeodeticSystem::Gcj02, GeodeticSystem::Wgs84);
+    for &units in &[MapUnits::Degrees, MapUnits::WebMercator] {
+        let _ = georef::adjust_geotiff(&converter, units, &mut data.to_vec());
+    }
+});
@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use undrift_gps::mvt::{self, TileId};
+use undrift_gps::{Converter, GeodeticSystem};
+
+// The first bytes choose the tile address and the buffer, the rest is the tile.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 6 {
+        return;
+    }
+    let converter = Converter::new(GeodeticSystem::Gcj02, GeodeticSystem::Wgs84);
+    let tile = TileId {
+        z: data[0],
+        x: u32::from(data[1]),
+        y: u32::from(data[2]),
+    };
+    let buffer = u32::from(u16::from_le_bytes([data[3], data[4]]));
+    let _ = mvt::reproject(&converter, &[(tile, &data[5..])], buffer);
+});
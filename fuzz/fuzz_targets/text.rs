@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use undrift_gps::format;
+use undrift_gps::georef::WorldFile;
+use undrift_gps::spec::PipelineSpec;
+use undrift_gps::srt;
+
+// The text formats: coordinates, pipeline specs, world files and DJI SRT files.
+fuzz_target!(|text: &str| {
+    let _ = format::parse_coordinate(text);
+    let _ = PipelineSpec::from_toml(text);
+    let _ = PipelineSpec::from_json(text);
+    let _ = text.parse::<WorldFile>();
+    let _ = srt::positions(text);
+});
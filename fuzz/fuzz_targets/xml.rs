@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use undrift_gps::{gml, gpx, kml, osm, tcx, xmp, Converter, GeodeticSystem};
+
+fuzz_target!(|text: &str| {
+    let converter = Converter::new(GeodeticSystem::Gcj02, GeodeticSystem::Wgs84);
+    let _ = gpx::convert(&converter, text);
+    let _ = gpx::track(text);
+    let _ = kml::convert(&converter, text);
+    let _ = kml::positions_3d(text);
+    let _ = tcx::convert(&converter, text);
+    let _ = tcx::track(text);
+    let _ = osm::convert(&converter, text);
+    let _ = gml::convert(&converter, text);
+    let _ = gml::geometries(text);
+    let _ = xmp::convert(&converter, text);
+    let _ = xmp::convert_embedded(&converter, &mut text.as_bytes().to_vec());
+});
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use undrift_gps::{zip, Converter, GeodeticSystem};
+
+fuzz_target!(|data: &[u8]| {
+    let converter = Converter::new(GeodeticSystem::Gcj02, GeodeticSystem::Wgs84);
+    let _ = zip::convert_archive(&converter, data, Vec::new());
+});
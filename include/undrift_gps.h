@@ -0,0 +1,33 @@
+/* C interface of undrift_gps, built with `cargo build --release --features ffi`. */
+#ifndef UNDRIFT_GPS_H
+#define UNDRIFT_GPS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define UNDRIFT_WGS84 0
+#define UNDRIFT_GCJ02 1
+#define UNDRIFT_BD09 2
+
+#define UNDRIFT_OK 0
+#define UNDRIFT_INVALID_SYSTEM (-1)
+#define UNDRIFT_NULL_POINTER (-2)
+
+/* Converts `len` interleaved lat, lon pairs. `output` may be `input`. Never allocates. */
+int32_t undrift_convert_interleaved(int32_t from, int32_t to, const double *input,
+                                    double *output, size_t len);
+
+/* Converts `len` points in separate arrays. Outputs may be their inputs. Never allocates. */
+int32_t undrift_convert_split(int32_t from, int32_t to, const double *lats_in,
+                              const double *lons_in, double *lats_out, double *lons_out,
+                              size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,73 @@
+// Reader of the offset grids of the Rust `offset_grid` module, for web maps converting
+// coordinates approximately without the WebAssembly module. With Leaflet:
+//
+//     import { parseOffsetGrid } from "./offset_grid.js";
+//     const grid = parseOffsetGrid(await (await fetch("beijing.grid")).arrayBuffer());
+//     const [lat, lng] = grid.apply(point.lat, point.lng);
+//     L.marker([lat, lng]).addTo(map);
+//
+// and with OpenLayers, as a transform of `ol/proj` in longitude, latitude order:
+//
+//     addCoordinateTransforms("EPSG:4326", "GCJ-02", ([lng, lat]) => grid.apply(lat, lng).reverse());
+
+const SYSTEMS = ["WGS-84", "GCJ-02", "BD-09"];
+
+// Parses a grid from the bytes of the binary format, or from the text or object of the JSON one.
+export function parseOffsetGrid(source) {
+  let grid;
+  if (source instanceof ArrayBuffer || ArrayBuffer.isView(source)) {
+    const bytes = ArrayBuffer.isView(source)
+      ? new Uint8Array(source.buffer, source.byteOffset, source.byteLength)
+      : new Uint8Array(source);
+    const view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+    if (bytes.length < 48 || String.fromCharCode(...bytes.subarray(0, 4)) !== "UGRD" || bytes[4] !== 1) {
+      throw new Error("not an offset grid");
+    }
+    const cols = view.getUint32(40, true);
+    const rows = view.getUint32(44, true);
+    if (bytes.length !== 48 + cols * rows * 8) {
+      throw new Error("invalid grid size");
+    }
+    const offsets = new Int32Array(cols * rows * 2);
+    for (let i = 0; i < offsets.length; i++) {
+      offsets[i] = view.getInt32(48 + i * 4, true);
+    }
+    grid = {
+      from: SYSTEMS[bytes[5]],
+      to: SYSTEMS[bytes[6]],
+      bbox: [0, 1, 2, 3].map((i) => view.getFloat64(8 + i * 8, true)),
+      cols,
+      rows,
+      unit: 1e-7,
+      offsets,
+    };
+  } else {
+    grid = typeof source === "string" ? JSON.parse(source) : source;
+  }
+  const [west, south, east, north] = grid.bbox;
+  const { cols, rows, unit, offsets } = grid;
+
+  return {
+    from: grid.from,
+    to: grid.to,
+    // Converts a coordinate, returning `[lat, lng]`; those outside the grid are unchanged.
+    apply(lat, lng) {
+      if (!(lat >= south && lat <= north && lng >= west && lng <= east)) {
+        return [lat, lng];
+      }
+      const v = ((north - lat) / (north - south)) * (rows - 1);
+      const u = ((lng - west) / (east - west)) * (cols - 1);
+      const row = Math.min(Math.floor(v), rows - 2);
+      const col = Math.min(Math.floor(u), cols - 2);
+      const fy = v - row;
+      const fx = u - col;
+      const at = (r, c, i) => offsets[(r * cols + c) * 2 + i];
+      const offset = (i) => {
+        const upper = at(row, col, i) + (at(row, col + 1, i) - at(row, col, i)) * fx;
+        const lower = at(row + 1, col, i) + (at(row + 1, col + 1, i) - at(row + 1, col, i)) * fx;
+        return (upper + (lower - upper) * fy) * unit;
+      };
+      return [lat + offset(0), lng + offset(1)];
+    },
+  };
+}
@@ -0,0 +1,53 @@
+// JavaScript API of the `wasm` feature, for browsers and other WebAssembly hosts.
+//
+// Build with `cargo build --release --target wasm32-unknown-unknown --features wasm`, then:
+//
+//     import { load } from "./undrift_gps.js";
+//     const undrift = await load(fetch("undrift_gps.wasm"));
+//     const converted = undrift.convert(await file.text(), "gcj02", "wgs84");
+
+const encoder = new TextEncoder();
+const decoder = new TextDecoder();
+
+// Loads the module from a `Response`, a promise of one, or the bytes of the file.
+export async function load(source) {
+  const { instance } =
+    source instanceof ArrayBuffer || ArrayBuffer.isView(source)
+      ? await WebAssembly.instantiate(source)
+      : await WebAssembly.instantiateStreaming(source);
+  const wasm = instance.exports;
+
+  // Copies a string into module memory, returning its pointer and length.
+  function pass(text) {
+    const bytes = encoder.encode(text);
+    const ptr = wasm.undrift_alloc(bytes.length);
+    new Uint8Array(wasm.memory.buffer, ptr, bytes.length).set(bytes);
+    return [ptr, bytes.length];
+  }
+
+  return {
+    // Converts a GeoJSON string between two systems, named as in the Rust crate: "wgs84",
+    // "gcj02" and "bd09", with their aliases. `precision` is the number of decimals written,
+    // omitted for the shortest exact text. Throws an `Error` for invalid input.
+    convert(geojson, from, to, precision) {
+      const args = [pass(geojson), pass(from), pass(to)];
+      try {
+        const code = wasm.undrift_convert_geojson(
+          ...args.flat(),
+          precision === undefined ? -1 : precision
+        );
+        const output = decoder.decode(
+          new Uint8Array(wasm.memory.buffer, wasm.undrift_result_ptr(), wasm.undrift_result_len())
+        );
+        if (code !== 0) {
+          throw new Error(output);
+        }
+        return output;
+      } finally {
+        for (const [ptr, len] of args) {
+          wasm.undrift_free(ptr, len);
+        }
+      }
+    },
+  };
+}
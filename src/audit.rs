@@ -0,0 +1,182 @@
+//! Summary reports for datasets about to be converted.
+use crate::{bd_to_gcj_refined, gcj_to_wgs_converged, haversine, is_in_china, INVERT_EPS};
+use crate::{BoundingBox, GeodeticSystem};
+
+/// Statistics of the distance between the input and converted points, in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DriftStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// Summary of a conversion over a point set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditReport {
+    /// Number of points given, including the invalid ones.
+    pub count: usize,
+    /// Bounds of the finite input points, `None` if there is none.
+    pub bounds: Option<BoundingBox>,
+    /// Finite points inside the region of the obfuscation, by the built-in China boundary or, for
+    /// the reports of [`crate::zip::check_entry`], by the region of the converter.
+    pub inside_china: usize,
+    pub outside_china: usize,
+    /// Points with a NaN or infinite component, which are not converted.
+    pub nan_inputs: usize,
+    /// Points whose iterative inversion did not converge.
+    pub non_convergent: usize,
+    /// Displacement statistics, `None` if no point was converted.
+    pub drift: Option<DriftStats>,
+}
+
+/// Converts every point and summarizes the outcome.
+pub fn audit(points: &[(f64, f64)], from: GeodeticSystem, to: GeodeticSystem) -> AuditReport {
+    let mut auditor = Auditor::new();
+    for &(lat, lon) in points {
+        if !lat.is_finite() || !lon.is_finite() {
+            auditor.record((lat, lon), None, false);
+            continue;
+        }
+        let output = convert_checked(from, to, lat, lon);
+        auditor.record((lat, lon), Some(output), is_in_china(lat, lon));
+    }
+    auditor.report()
+}
+
+/// Builds an [`AuditReport`] one conversion at a time, such as the ones reported by a
+/// [`crate::Converter`] to its observer.
+pub(crate) struct Auditor {
+    report: AuditReport,
+    drift_sum: f64,
+    converted: usize,
+}
+
+impl Auditor {
+    pub(crate) fn new() -> Self {
+        Auditor {
+            report: AuditReport {
+                count: 0,
+                bounds: None,
+                inside_china: 0,
+                outside_china: 0,
+                nan_inputs: 0,
+                non_convergent: 0,
+                drift: None,
+            },
+            drift_sum: 0.0,
+            converted: 0,
+        }
+    }
+
+    /// Records a point, its conversion with whether it converged, and whether it is in the region
+    /// of the obfuscation. Non-finite points are counted as such, converted or not.
+    pub(crate) fn record(
+        &mut self,
+        (lat, lon): (f64, f64),
+        output: Option<((f64, f64), bool)>,
+        in_region: bool,
+    ) {
+        let report = &mut self.report;
+        report.count += 1;
+        let (output, converged) = match output {
+            Some(output) if lat.is_finite() && lon.is_finite() => output,
+            _ => {
+                report.nan_inputs += 1;
+                return;
+            }
+        };
+
+        let bounds = report
+            .bounds
+            .get_or_insert(BoundingBox::new(lat, lon, lat, lon));
+        bounds.min_lat = bounds.min_lat.min(lat);
+        bounds.min_lon = bounds.min_lon.min(lon);
+        bounds.max_lat = bounds.max_lat.max(lat);
+        bounds.max_lon = bounds.max_lon.max(lon);
+
+        if in_region {
+            report.inside_china += 1;
+        } else {
+            report.outside_china += 1;
+        }
+        if !converged {
+            report.non_convergent += 1;
+        }
+
+        let distance = haversine((lat, lon), output);
+        self.converted += 1;
+        self.drift_sum += distance;
+        let stats = report.drift.get_or_insert(DriftStats {
+            min: distance,
+            max: distance,
+            mean: 0.0,
+        });
+        stats.min = stats.min.min(distance);
+        stats.max = stats.max.max(distance);
+    }
+
+    /// The report of the points recorded so far.
+    pub(crate) fn report(&self) -> AuditReport {
+        let mut report = self.report.clone();
+        if let Some(stats) = report.drift.as_mut() {
+            stats.mean = self.drift_sum / self.converted as f64;
+        }
+        report
+    }
+}
+
+fn convert_checked(
+    from: GeodeticSystem,
+    to: GeodeticSystem,
+    lat: f64,
+    lon: f64,
+) -> ((f64, f64), bool) {
+    use GeodeticSystem::*;
+    match (from, to) {
+        (Gcj02, Wgs84) => gcj_to_wgs_converged(lat, lon),
+        (Bd09, Wgs84) => {
+            let (lat, lon) = bd_to_gcj_refined(lat, lon, INVERT_EPS);
+            gcj_to_wgs_converged(lat, lon)
+        }
+        _ => (from.convert_to(to, lat, lon), true),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use GeodeticSystem::*;
+
+    #[test]
+    fn test_audit() {
+        let points = [
+            (39.0, 116.0),
+            (31.2, 121.5),
+            (48.8566, 2.3522),
+            (f64::NAN, 116.0),
+        ];
+        let report = audit(&points, Wgs84, Gcj02);
+        assert_eq!(report.count, 4);
+        assert_eq!(report.nan_inputs, 1);
+        assert_eq!(report.inside_china, 2);
+        assert_eq!(report.outside_china, 1);
+        assert_eq!(report.non_convergent, 0);
+        assert_eq!(
+            report.bounds,
+            Some(BoundingBox::new(31.2, 2.3522, 48.8566, 121.5))
+        );
+
+        let drift = report.drift.unwrap();
+        assert_eq!(drift.min, 0.0);
+        assert!(drift.max > 100.0);
+        assert!(drift.mean > 0.0 && drift.mean < drift.max);
+    }
+
+    #[test]
+    fn test_audit_empty() {
+        let report = audit(&[], Bd09, Wgs84);
+        assert_eq!(report.count, 0);
+        assert_eq!(report.bounds, None);
+        assert_eq!(report.drift, None);
+    }
+}
@@ -1,5 +1,4 @@
 //! Conversion for Baidu map's internal projection.
-use std::f64::NAN;
 
 const MCBAND: [f64; 6] = [
     12890594.86,
@@ -182,7 +181,7 @@ fn poly(factors: &[f64], x: f64) -> f64 {
 pub fn xy_to_latlon(x: f64, y: f64) -> (f64, f64) {
     let table = match lookup(&MCBAND, &MC2LL, y) {
         Some(x) => x,
-        None => return (NAN, NAN),
+        None => return (f64::NAN, f64::NAN),
     };
 
     let lon = table[0] + table[1] * x.abs();
@@ -194,7 +193,7 @@ pub fn xy_to_latlon(x: f64, y: f64) -> (f64, f64) {
 pub fn latlon_to_xy(lat: f64, lon: f64) -> (f64, f64) {
     let table = match lookup(&LLBAND, &LL2MC, lat) {
         Some(x) => x,
-        None => return (NAN, NAN),
+        None => return (f64::NAN, f64::NAN),
     };
 
     let x = table[0] + table[1] * lon.abs();
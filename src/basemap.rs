@@ -0,0 +1,304 @@
+//! Tiled basemaps of map providers, and the pixel offsets aligning overlays with them.
+//!
+//! An overlay drawn in another system than its basemap is shifted by the drift between the two,
+//! which map SDKs leave to the application. [`pixel_offset`] gives that shift in the pixels of a
+//! basemap tile, computed offline from the tile address alone, and [`overlapping_tiles`]
+//! translates tile addresses from one provider to another, as does [`convert_quadkey`] for tile
+//! caches keyed by quadkeys.
+use crate::mvt::TileId;
+use crate::transform::ProjectedSystem;
+use crate::{web_mercator, GeodeticSystem};
+use std::f64::consts::PI;
+
+/// Size in pixels of the side of a tile.
+pub const TILE_SIZE: f64 = 256.0;
+
+/// A provider of tiled basemaps, with its system and tile addressing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TileProvider {
+    /// OpenStreetMap and other WGS-84 maps: Web Mercator tiles in the XYZ scheme.
+    OpenStreetMap,
+    /// Amap (AutoNavi), also used by Apple Maps in China: GCJ-02 Web Mercator tiles, XYZ.
+    Amap,
+    /// Google Maps in China: GCJ-02 Web Mercator tiles, XYZ.
+    GoogleChina,
+    /// Tencent Maps: GCJ-02 Web Mercator tiles in the TMS scheme, rows growing northward.
+    Tencent,
+    /// Baidu Maps: BD-09 tiles of the Baidu Mercator projection, counted eastward and northward
+    /// from its origin, with one pixel per meter at zoom 18.
+    Baidu,
+}
+
+impl TileProvider {
+    /// Every variant.
+    pub const ALL: [TileProvider; 5] = [
+        TileProvider::OpenStreetMap,
+        TileProvider::Amap,
+        TileProvider::GoogleChina,
+        TileProvider::Tencent,
+        TileProvider::Baidu,
+    ];
+
+    /// Plane of the tiles.
+    pub fn plane(self) -> ProjectedSystem {
+        match self {
+            TileProvider::OpenStreetMap => ProjectedSystem::WebMercator,
+            TileProvider::Amap | TileProvider::GoogleChina | TileProvider::Tencent => {
+                ProjectedSystem::GcjWebMercator
+            }
+            TileProvider::Baidu => ProjectedSystem::BaiduMercator,
+        }
+    }
+
+    /// System of the basemap.
+    pub fn system(self) -> GeodeticSystem {
+        self.plane().geodetic()
+    }
+
+    /// Position in pixels at zoom `z`, with y growing southward for every provider.
+    pub(crate) fn pixel(self, z: u8, lat: f64, lon: f64) -> (f64, f64) {
+        let scale = 2f64.powi(i32::from(z));
+        let (x, y) = self.plane().project(lat, lon);
+        match self {
+            TileProvider::Baidu => {
+                let scale = scale / 2f64.powi(18);
+                (x * scale, -y * scale)
+            }
+            _ => {
+                let world = 2.0 * PI * web_mercator::RADIUS;
+                let scale = scale * TILE_SIZE / world;
+                ((x + world / 2.0) * scale, (world / 2.0 - y) * scale)
+            }
+        }
+    }
+
+    /// Inverse of `pixel`.
+    pub(crate) fn unpixel(self, z: u8, x: f64, y: f64) -> (f64, f64) {
+        let scale = 2f64.powi(i32::from(z));
+        let (x, y) = match self {
+            TileProvider::Baidu => {
+                let scale = scale / 2f64.powi(18);
+                (x / scale, -y / scale)
+            }
+            _ => {
+                let world = 2.0 * PI * web_mercator::RADIUS;
+                let scale = scale * TILE_SIZE / world;
+                (x / scale - world / 2.0, world / 2.0 - y / scale)
+            }
+        };
+        self.plane().unproject(x, y)
+    }
+
+    /// Row of the tile holding the pixel row `y` of `pixel`, before clamping.
+    pub(crate) fn row(self, z: u8, y: f64) -> f64 {
+        let row = (y / TILE_SIZE).floor();
+        match self {
+            TileProvider::Baidu => -row - 1.0,
+            TileProvider::Tencent => 2f64.powi(i32::from(z)) - 1.0 - row,
+            _ => row,
+        }
+    }
+
+    /// Pixel rows of `pixel` covered by the tile row `row`.
+    pub(crate) fn rows(self, z: u8, row: u32) -> (f64, f64) {
+        let row = f64::from(row);
+        let top = match self {
+            TileProvider::Baidu => -row - 1.0,
+            TileProvider::Tencent => 2f64.powi(i32::from(z)) - 1.0 - row,
+            _ => row,
+        };
+        (top * TILE_SIZE, (top + 1.0) * TILE_SIZE)
+    }
+
+    /// Largest column or row at zoom `z`: Web Mercator tiles cover the world, and Baidu counts
+    /// tiles from its origin, here without the negative addresses of the western or southern
+    /// hemisphere.
+    pub(crate) fn max_index(self, z: u8) -> f64 {
+        match self {
+            TileProvider::Baidu => f64::from(u32::MAX),
+            _ => 2f64.powi(i32::from(z)) - 1.0,
+        }
+    }
+
+    /// The tile at zoom `z` holding a point in [`TileProvider::system`], clamped to the
+    /// addressable tiles.
+    pub fn tile(self, z: u8, lat: f64, lon: f64) -> TileId {
+        let (x, y) = self.pixel(z, lat, lon);
+        let clamp = |i: f64| i.clamp(0.0, self.max_index(z)) as u32;
+        TileId {
+            z,
+            x: clamp((x / TILE_SIZE).floor()),
+            y: clamp(self.row(z, y)),
+        }
+    }
+
+    /// Latitude and longitude of the center of `tile`, addressed as the provider does.
+    pub fn tile_center(self, tile: TileId) -> (f64, f64) {
+        let (top, bottom) = self.rows(tile.z, tile.y);
+        let x = (f64::from(tile.x) + 0.5) * TILE_SIZE;
+        self.unpixel(tile.z, x, (top + bottom) / 2.0)
+    }
+}
+
+/// The tiles of `to` at zoom `z` overlapping `tile` of `from`, sorted by address.
+///
+/// The outline of the tile is converted into the system of `to`, as the drift moves it by up to
+/// a kilometer, and the tiles it touches are listed: those of a cache to warm, or to stitch
+/// together into the area of `tile`. Zooms of different providers show about the same scale at
+/// the same level, except Baidu, whose level 18 has one pixel per projected meter.
+pub fn overlapping_tiles(from: TileProvider, tile: TileId, to: TileProvider, z: u8) -> Vec<TileId> {
+    // Samples along each edge, enough for the curvature of the drift within a tile.
+    const SAMPLES: u32 = 8;
+    // Just inside the outline, so that edges on a tile boundary do not touch the tiles beyond.
+    const INSET: f64 = 1e-3;
+    let (top, bottom) = from.rows(tile.z, tile.y);
+    let (top, bottom) = (top + INSET, bottom - INSET);
+    let left = f64::from(tile.x) * TILE_SIZE + INSET;
+    let right = (f64::from(tile.x) + 1.0) * TILE_SIZE - INSET;
+    let (mut min, mut max) = ((u32::MAX, u32::MAX), (0, 0));
+    for i in 0..=SAMPLES {
+        let t = f64::from(i) / f64::from(SAMPLES);
+        let (x, y) = (left + (right - left) * t, top + (bottom - top) * t);
+        for &(x, y) in &[(x, top), (x, bottom), (left, y), (right, y)] {
+            let (lat, lon) = from.unpixel(tile.z, x, y);
+            let (lat, lon) = from.system().convert_to(to.system(), lat, lon);
+            let corner = to.tile(z, lat, lon);
+            min = (min.0.min(corner.x), min.1.min(corner.y));
+            max = (max.0.max(corner.x), max.1.max(corner.y));
+        }
+    }
+    let mut tiles = Vec::new();
+    for x in min.0..=max.0 {
+        for y in min.1..=max.1 {
+            tiles.push(TileId { z, x, y });
+        }
+    }
+    tiles.sort();
+    tiles
+}
+
+/// The quadkeys of the tiles of `to` overlapping the tile of `from` at `key`, at the same zoom.
+///
+/// Quadkeys address the XYZ grid of Web Mercator, whatever the scheme of the provider; Baidu
+/// tiles have no quadkeys, and give `None`, as do invalid keys.
+pub fn convert_quadkey(from: TileProvider, key: &str, to: TileProvider) -> Option<Vec<String>> {
+    // Tencent rows grow northward, and turning them around is its own inverse.
+    let xyz = |provider, tile: TileId| match provider {
+        TileProvider::Tencent => TileId::from_tms(tile.z, tile.x, tile.y),
+        _ => tile,
+    };
+    if from == TileProvider::Baidu || to == TileProvider::Baidu {
+        return None;
+    }
+    let tile = TileId::from_quadkey(key)?;
+    let tiles = overlapping_tiles(from, xyz(from, tile), to, tile.z);
+    let mut keys: Vec<_> = tiles.into_iter().map(|t| xyz(to, t).quadkey()).collect();
+    keys.sort();
+    Some(keys)
+}
+
+/// Offset in pixels, `(dx, dy)` with y growing southward, by which an overlay in `overlay` must be
+/// moved to line up with `tile` of the basemap of `provider`.
+///
+/// The offset is computed at the center of the tile; the drift varies by a few meters across
+/// tens of kilometers, so it holds for the whole tile at street zooms. It is zero when the
+/// systems are the same, and outside the region of the obfuscation. The opposite alignment, of a
+/// basemap under an overlay, is the negated offset.
+pub fn pixel_offset(provider: TileProvider, tile: TileId, overlay: GeodeticSystem) -> (f64, f64) {
+    let (lat, lon) = provider.tile_center(tile);
+    // The same place in the system of the overlay, drawn by the SDK as if it were in the system
+    // of the basemap.
+    let (drawn_lat, drawn_lon) = provider.system().convert_to(overlay, lat, lon);
+    let actual = provider.pixel(tile.z, lat, lon);
+    let drawn = provider.pixel(tile.z, drawn_lat, drawn_lon);
+    (actual.0 - drawn.0, actual.1 - drawn.1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_tile_center() {
+        for &provider in &TileProvider::ALL {
+            let tile = provider.tile(15, 39.9, 116.4);
+            let (lat, lon) = provider.tile_center(tile);
+            // Half a tile is about 500 meters at zoom 15.
+            assert!((lat - 39.9).abs() < 0.01 && (lon - 116.4).abs() < 0.01);
+            assert_eq!(provider.tile(15, lat, lon), tile, "{:?}", provider);
+        }
+        let amap = TileProvider::Amap.tile(15, 39.9, 116.4);
+        let tencent = TileProvider::Tencent.tile(15, 39.9, 116.4);
+        assert_eq!(TileId::from_tms(15, tencent.x, tencent.y), amap);
+    }
+
+    #[test]
+    fn test_pixel_offset() {
+        let tile = TileProvider::Amap.tile(15, 39.9, 116.4);
+        let (dx, dy) = pixel_offset(TileProvider::Amap, tile, Wgs84);
+        // WGS-84 overlays fall south-west of GCJ-02 basemaps in Beijing, by more than a hundred
+        // meters, which is tens of pixels at zoom 15.
+        assert!(dx > 30.0 && dy < -10.0, "{:?}", (dx, dy));
+        assert_eq!(pixel_offset(TileProvider::Amap, tile, Gcj02), (0.0, 0.0));
+
+        // A GCJ-02 overlay on a WGS-84 basemap moves the other way.
+        let osm = TileProvider::OpenStreetMap.tile(15, 39.9, 116.4);
+        let (ex, ey) = pixel_offset(TileProvider::OpenStreetMap, osm, Gcj02);
+        assert!(
+            (ex + dx).abs() < 1.0 && (ey + dy).abs() < 1.0,
+            "{:?}",
+            (ex, ey)
+        );
+
+        // BD-09 adds about 700 meters north-east of GCJ-02, and Baidu pixels are 2 projected
+        // meters at zoom 17.
+        let baidu = TileProvider::Baidu.tile(17, 39.9, 116.4);
+        let (bx, by) = pixel_offset(TileProvider::Baidu, baidu, Gcj02);
+        assert!(bx > 200.0 && by < -200.0, "{:?}", (bx, by));
+
+        let paris = TileProvider::Amap.tile(15, 48.86, 2.35);
+        assert_eq!(pixel_offset(TileProvider::Amap, paris, Wgs84), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_overlapping_tiles() {
+        use TileProvider::*;
+        let tile = Amap.tile(15, 39.9, 116.4);
+        assert_eq!(overlapping_tiles(Amap, tile, Amap, 15), [tile]);
+        assert_eq!(
+            overlapping_tiles(Amap, tile, Tencent, 15),
+            [Tencent.tile(15, 39.9, 116.4)]
+        );
+
+        let (lat, lon) = Amap.tile_center(tile);
+        for &to in &[OpenStreetMap, Baidu] {
+            let tiles = overlapping_tiles(Amap, tile, to, 15);
+            let (lat, lon) = Gcj02.convert_to(to.system(), lat, lon);
+            assert!(tiles.contains(&to.tile(15, lat, lon)), "{:?}", to);
+            assert!(tiles.len() <= 4, "{:?}", tiles);
+        }
+
+        // A zoom 10 tile is 4 by 4 tiles at zoom 12, plus a column and a row as the drift does
+        // not line up with the grid.
+        let tiles = overlapping_tiles(Amap, Amap.tile(10, 39.9, 116.4), OpenStreetMap, 12);
+        assert!((16..=25).contains(&tiles.len()), "{:?}", tiles);
+        assert!(tiles.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_convert_quadkey() {
+        use TileProvider::*;
+        let key = Amap.tile(15, 39.9, 116.4).quadkey();
+        assert_eq!(
+            convert_quadkey(Amap, &key, Tencent),
+            Some(vec![key.clone()])
+        );
+        let keys = convert_quadkey(Amap, &key, OpenStreetMap).unwrap();
+        let (lat, lon) = Gcj02.convert_to(Wgs84, 39.9, 116.4);
+        assert!(keys.contains(&OpenStreetMap.tile(15, lat, lon).quadkey()));
+        assert!(keys.iter().all(|k| k.len() == 15) && keys.len() <= 4);
+        assert_eq!(convert_quadkey(Amap, &key, Baidu), None);
+        assert_eq!(convert_quadkey(Amap, "0124", OpenStreetMap), None);
+    }
+}
@@ -0,0 +1,411 @@
+//! Conversion of many points at once.
+use crate::validate::{is_in_china_swapped, is_likely_swapped, validate, Issue};
+use crate::{Converter, NonFinitePolicy};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// What to do with points whose latitude and longitude look exchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapPolicy {
+    /// Convert the points as given.
+    Ignore,
+    /// Exchange the components back before converting.
+    Fix,
+    /// Convert the points as given, but report them.
+    Flag,
+}
+
+/// A flag to abort a running batch from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the batches using this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Receives the number of points processed so far and the total.
+pub type ProgressCallback = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Settings of a batch conversion.
+#[derive(Clone)]
+pub struct BatchOptions {
+    pub swap_policy: SwapPolicy,
+    /// Called every `progress_interval` points and once at the end.
+    pub on_progress: Option<ProgressCallback>,
+    pub progress_interval: usize,
+    /// Checked between points; the remaining points are left untouched once cancelled.
+    pub cancel: Option<CancelToken>,
+    /// Starts each inversion from the offset of the previous point, which converges faster on
+    /// ordered tracks. Results may differ from a cold start within the algorithm's tolerance.
+    pub warm_start: bool,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            swap_policy: SwapPolicy::Ignore,
+            on_progress: None,
+            progress_interval: 10_000,
+            cancel: None,
+            warm_start: false,
+        }
+    }
+}
+
+impl fmt::Debug for BatchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchOptions")
+            .field("swap_policy", &self.swap_policy)
+            .field("on_progress", &self.on_progress.is_some())
+            .field("progress_interval", &self.progress_interval)
+            .field("cancel", &self.cancel)
+            .field("warm_start", &self.warm_start)
+            .finish()
+    }
+}
+
+/// Why a point of a batch could not be converted properly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    /// The input is not a valid coordinate; the point is left untouched.
+    Invalid(Issue),
+    /// The inversion did not converge; the point holds the best estimate.
+    NonConvergent,
+    /// The conversion produced NaN or infinity; the point is left untouched.
+    NonFiniteResult,
+}
+
+/// A point of a batch that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Failure {
+    pub index: usize,
+    pub reason: FailureReason,
+}
+
+/// Outcome of a batch conversion.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchReport {
+    /// Indices of the points detected as swapped, whether fixed or only flagged.
+    pub swapped: Vec<usize>,
+    /// Points that could not be converted properly, in input order.
+    pub failures: Vec<Failure>,
+    /// Indices of the non-finite points left untouched under [`NonFinitePolicy::Skip`].
+    pub skipped: Vec<usize>,
+    /// Number of points processed, less than the input size if cancelled.
+    pub processed: usize,
+    pub cancelled: bool,
+}
+
+/// Checks whether the points as a whole look swapped.
+///
+/// This is the case when most of the finite points are only valid exchanged, see
+/// [`is_likely_swapped`], or when some are and all the others cluster in China once exchanged
+/// while lying outside of it as given. This catches datasets whose columns were exchanged even
+/// when some points remain valid either way, such as those of western China, without flagging
+/// datasets of the Arctic, which all are.
+pub fn detect_swapped(points: &[(f64, f64)]) -> bool {
+    let finite = points
+        .iter()
+        .filter(|(lat, lon)| lat.is_finite() && lon.is_finite());
+    let (mut total, mut swapped, mut clustered) = (0usize, 0usize, true);
+    for &(lat, lon) in finite {
+        total += 1;
+        if is_likely_swapped(lat, lon) {
+            swapped += 1;
+        } else {
+            clustered &= is_in_china_swapped(lat, lon);
+        }
+    }
+    swapped * 2 > total || (swapped > 0 && clustered)
+}
+
+/// Converts a point in place, unless the input or output is invalid.
+///
+/// Non-finite inputs follow the policy of the converter; `Ok(false)` means that the point was
+/// skipped. `warm` is the state of the warm start, if enabled.
+pub(crate) fn convert_point(
+    converter: &Converter,
+    point: &mut (f64, f64),
+    lat: f64,
+    lon: f64,
+    warm: Option<&mut Option<(f64, f64)>>,
+) -> Result<bool, FailureReason> {
+    if !lat.is_finite() || !lon.is_finite() {
+        return match converter.non_finite() {
+            NonFinitePolicy::Propagate => Ok(true),
+            NonFinitePolicy::Error => Err(FailureReason::Invalid(Issue::NotFinite)),
+            NonFinitePolicy::Skip => Ok(false),
+        };
+    }
+    let fatal = validate(lat, lon).into_iter().find(|issue| {
+        matches!(
+            issue,
+            Issue::NotFinite | Issue::LatitudeOutOfRange | Issue::LongitudeOutOfRange
+        )
+    });
+    if let Some(issue) = fatal {
+        return Err(FailureReason::Invalid(issue));
+    }
+
+    let (converted, converged) = match warm {
+        Some(warm) => converter.convert_warm(lat, lon, warm),
+        None => converter.convert_checked(lat, lon),
+    };
+    if !converted.0.is_finite() || !converted.1.is_finite() {
+        return Err(FailureReason::NonFiniteResult);
+    }
+    *point = converted;
+    if converged {
+        Ok(true)
+    } else {
+        Err(FailureReason::NonConvergent)
+    }
+}
+
+/// Converts the points in place.
+///
+/// Failing points do not stop the batch; they are listed in the report instead.
+pub fn convert_batch(
+    converter: &Converter,
+    points: &mut [(f64, f64)],
+    options: &BatchOptions,
+) -> BatchReport {
+    let mut report = BatchReport::default();
+    let all_swapped = options.swap_policy != SwapPolicy::Ignore && detect_swapped(points);
+    let total = points.len();
+    let interval = options.progress_interval.max(1);
+    let mut warm = None;
+
+    for (index, point) in points.iter_mut().enumerate() {
+        if options
+            .cancel
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+        {
+            report.cancelled = true;
+            break;
+        }
+
+        let (mut lat, mut lon) = *point;
+        if options.swap_policy != SwapPolicy::Ignore && (all_swapped || is_likely_swapped(lat, lon))
+        {
+            report.swapped.push(index);
+            if options.swap_policy == SwapPolicy::Fix {
+                std::mem::swap(&mut lat, &mut lon);
+            }
+        }
+
+        report.processed += 1;
+        let warm = if options.warm_start {
+            Some(&mut warm)
+        } else {
+            None
+        };
+        match convert_point(converter, point, lat, lon, warm) {
+            Ok(true) => {}
+            Ok(false) => report.skipped.push(index),
+            Err(reason) => report.failures.push(Failure { index, reason }),
+        }
+
+        if let Some(callback) = &options.on_progress {
+            if report.processed % interval == 0 && report.processed != total {
+                callback(report.processed, total);
+            }
+        }
+    }
+
+    if let Some(callback) = &options.on_progress {
+        callback(report.processed, total);
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_convert_batch() {
+        let converter = Converter::new(Wgs84, Gcj02);
+        let mut points = [(39.0, 116.0), (31.2, 121.5)];
+        let report = convert_batch(&converter, &mut points, &BatchOptions::default());
+        assert_eq!(points[0], converter.convert(39.0, 116.0));
+        assert_eq!(points[1], converter.convert(31.2, 121.5));
+        assert!(report.swapped.is_empty());
+    }
+
+    #[test]
+    fn test_swap_policy() {
+        let converter = Converter::new(Wgs84, Gcj02);
+        let input = [(39.0, 116.0), (121.5, 31.2)];
+
+        let mut points = input;
+        let options = BatchOptions {
+            swap_policy: SwapPolicy::Fix,
+            ..Default::default()
+        };
+        let report = convert_batch(&converter, &mut points, &options);
+        assert_eq!(report.swapped, vec![1]);
+        assert_eq!(points[1], converter.convert(31.2, 121.5));
+
+        let mut points = input;
+        let options = BatchOptions {
+            swap_policy: SwapPolicy::Flag,
+            ..Default::default()
+        };
+        let report = convert_batch(&converter, &mut points, &options);
+        assert_eq!(report.swapped, vec![1]);
+        assert_eq!(points[1], (121.5, 31.2));
+    }
+
+    #[test]
+    fn test_detect_swapped() {
+        // The last point is plausible either way; the dataset as a whole is not.
+        let points = [(116.4, 39.9), (121.5, 31.2), (30.0, 30.0)];
+        assert!(detect_swapped(&points));
+        assert!(!detect_swapped(&[(39.9, 116.4), (30.0, 30.0)]));
+        // Columns exchanged in Xinjiang, where few longitudes exceed 90 degrees.
+        assert!(detect_swapped(&[(87.6, 43.8), (81.3, 43.9), (93.5, 42.8)]));
+        assert!(!detect_swapped(&[(87.6, 43.8), (81.3, 43.9)]));
+
+        let converter = Converter::new(Wgs84, Wgs84);
+        let mut points = points;
+        let options = BatchOptions {
+            swap_policy: SwapPolicy::Fix,
+            ..Default::default()
+        };
+        let report = convert_batch(&converter, &mut points, &options);
+        assert_eq!(report.swapped, vec![0, 1, 2]);
+        assert_eq!(points, [(39.9, 116.4), (31.2, 121.5), (30.0, 30.0)]);
+    }
+
+    #[test]
+    fn test_arctic() {
+        // Svalbard and Novaya Zemlya, valid as given.
+        let input = [(78.2, 15.6), (78.9, 11.9), (74.0, 56.0)];
+        assert!(!detect_swapped(&input));
+        let converter = Converter::new(Wgs84, Gcj02);
+        let mut points = input;
+        let options = BatchOptions {
+            swap_policy: SwapPolicy::Fix,
+            ..Default::default()
+        };
+        let report = convert_batch(&converter, &mut points, &options);
+        assert!(report.swapped.is_empty() && report.failures.is_empty());
+        assert_eq!(points, input);
+    }
+
+    #[test]
+    fn test_progress() {
+        use std::sync::Mutex;
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorder = calls.clone();
+        let options = BatchOptions {
+            on_progress: Some(Arc::new(move |done, total| {
+                recorder.lock().unwrap().push((done, total))
+            })),
+            progress_interval: 2,
+            ..Default::default()
+        };
+        let mut points = [(39.0, 116.0); 5];
+        convert_batch(&Converter::new(Wgs84, Gcj02), &mut points, &options);
+        assert_eq!(*calls.lock().unwrap(), [(2, 5), (4, 5), (5, 5)]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let token = CancelToken::new();
+        let canceller = token.clone();
+        let options = BatchOptions {
+            on_progress: Some(Arc::new(move |_, _| canceller.cancel())),
+            progress_interval: 2,
+            cancel: Some(token),
+            ..Default::default()
+        };
+        let mut points = [(39.0, 116.0); 5];
+        let report = convert_batch(&Converter::new(Wgs84, Gcj02), &mut points, &options);
+        assert!(report.cancelled);
+        assert_eq!(report.processed, 2);
+        assert_ne!(points[1], (39.0, 116.0));
+        assert_eq!(points[2], (39.0, 116.0));
+    }
+
+    #[test]
+    fn test_warm_start() {
+        let converter = Converter::new(Bd09, Wgs84);
+        let track: Vec<(f64, f64)> = (0..100)
+            .map(|i| (39.9 + i as f64 * 1e-4, 116.4 + i as f64 * 2e-4))
+            .collect();
+        let mut cold = track.clone();
+        convert_batch(&converter, &mut cold, &BatchOptions::default());
+        let mut warm = track;
+        let options = BatchOptions {
+            warm_start: true,
+            ..Default::default()
+        };
+        let report = convert_batch(&converter, &mut warm, &options);
+        assert!(report.failures.is_empty());
+        for (a, b) in cold.iter().zip(&warm) {
+            assert!((a.0 - b.0).abs() < 1e-7 && (a.1 - b.1).abs() < 1e-7);
+        }
+    }
+
+    #[test]
+    fn test_failures() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let mut points = [
+            (39.0, 116.0),
+            (f64::NAN, 116.0),
+            (39.0, 200.0),
+            (31.2, 121.5),
+        ];
+        let report = convert_batch(&converter, &mut points, &BatchOptions::default());
+        assert_eq!(report.processed, 4);
+        assert_eq!(
+            report.failures,
+            [
+                Failure {
+                    index: 1,
+                    reason: FailureReason::Invalid(Issue::NotFinite)
+                },
+                Failure {
+                    index: 2,
+                    reason: FailureReason::Invalid(Issue::LongitudeOutOfRange)
+                },
+            ]
+        );
+        assert_eq!(points[2], (39.0, 200.0));
+        assert_eq!(points[3], converter.convert(31.2, 121.5));
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let input = [(39.0, 116.0), (f64::INFINITY, 116.0)];
+        for &(policy, failures, skipped) in &[
+            (NonFinitePolicy::Propagate, 0, 0),
+            (NonFinitePolicy::Error, 1, 0),
+            (NonFinitePolicy::Skip, 0, 1),
+        ] {
+            let converter = Converter::new(Gcj02, Wgs84).with_non_finite(policy);
+            let mut points = input;
+            let report = convert_batch(&converter, &mut points, &BatchOptions::default());
+            assert_eq!(report.failures.len(), failures);
+            assert_eq!(report.skipped.len(), skipped);
+            assert_eq!(points[1], input[1]);
+        }
+    }
+}
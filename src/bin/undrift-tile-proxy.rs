@@ -0,0 +1,71 @@
+//! Serves the tiles of an upstream provider redrawn for another, see the `proxy` module.
+//!
+//! ```text
+//! undrift-tile-proxy --upstream 'http://tiles.example/{z}/{x}/{y}.png' --from amap --to osm
+//!     [--kind raster|vector] [--listen 127.0.0.1:8080]
+//! ```
+use std::env;
+use std::net::TcpListener;
+use std::process;
+use undrift_gps::basemap::TileProvider;
+use undrift_gps::proxy::{upstream, TileKind, TileProxy};
+
+const USAGE: &str = "usage: undrift-tile-proxy --upstream URL --from PROVIDER --to PROVIDER \
+                     [--kind raster|vector] [--listen ADDRESS]
+URL has {z}, {x} and {y} placeholders; PROVIDER is osm, amap, google, tencent or baidu.";
+
+fn provider(name: &str) -> Option<TileProvider> {
+    match name.to_ascii_lowercase().as_str() {
+        "osm" | "openstreetmap" | "wgs84" => Some(TileProvider::OpenStreetMap),
+        "amap" | "autonavi" | "gaode" => Some(TileProvider::Amap),
+        "google" | "googlechina" => Some(TileProvider::GoogleChina),
+        "tencent" | "qq" => Some(TileProvider::Tencent),
+        "baidu" => Some(TileProvider::Baidu),
+        _ => None,
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(2)
+}
+
+fn main() {
+    let (mut template, mut from, mut to) = (None, None, None);
+    let (mut kind, mut listen) = (TileKind::Raster, "127.0.0.1:8080".to_owned());
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .unwrap_or_else(|| fail(&format!("missing value of {}", flag)));
+        match flag.as_str() {
+            "--upstream" => template = Some(value),
+            "--from" => from = Some(provider(&value).unwrap_or_else(|| fail("unknown provider"))),
+            "--to" => to = Some(provider(&value).unwrap_or_else(|| fail("unknown provider"))),
+            "--kind" => {
+                kind = match value.as_str() {
+                    "raster" => TileKind::Raster,
+                    "vector" => TileKind::Vector,
+                    _ => fail("unknown kind"),
+                }
+            }
+            "--listen" => listen = value,
+            _ => fail(&format!("unknown option {}", flag)),
+        }
+    }
+    let (template, from, to) = match (template, from, to) {
+        (Some(template), Some(from), Some(to)) => (template, from, to),
+        _ => fail("--upstream, --from and --to are required"),
+    };
+
+    let listener = TcpListener::bind(&listen).unwrap_or_else(|e| {
+        eprintln!("cannot listen on {}: {}", listen, e);
+        process::exit(1)
+    });
+    eprintln!("serving {:?} tiles as {:?} on http://{}", from, to, listen);
+    let proxy = TileProxy::new(from, to, kind, upstream(&template));
+    if let Err(e) = proxy.serve(listener) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,506 @@
+//! High-throughput conversion of large arrays of points.
+//!
+//! Unlike [`crate::batch`], no validation is done: invalid inputs simply produce meaningless
+//! outputs.
+//!
+//! Points may be stored as pairs ([`BulkConverter::convert`]) or as separate latitude and
+//! longitude arrays ([`BulkConverter::convert_split`]); both run the same kernel on split arrays.
+//! Pairs are processed in chunks copied into scratch buffers owned by the converter, so that
+//! repeated calls do not allocate and the copies stay in cache. Split arrays are converted where
+//! they are.
+//!
+//! With [`Algorithm::FastApprox`], the offsets are computed over whole chunks by a kernel
+//! compiled for the widest instruction set detected at runtime, see [`Kernel`].
+//!
+//! Whatever the kernel, the chunk size and the layout, every point is converted independently of
+//! the others, and the results are bitwise identical to [`Converter::convert`], in input order. They
+//! can be hashed or used as keys.
+use crate::{
+    bd_to_gcj, gcj_offset_approx, gcj_to_bd, normalize, Algorithm, Converter, Damping,
+    GeodeticSystem, INVERT_EPS, INVERT_MAX_ROUND,
+};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Instruction set used to convert chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kernel {
+    /// One point at a time; the only kernel for algorithms other than `FastApprox`.
+    Scalar,
+    /// Whole chunks with the baseline vector instructions of the target, such as SSE2.
+    Portable,
+    Avx2,
+    Avx512,
+    Neon,
+}
+
+impl Kernel {
+    /// The widest kernel supported by the running CPU.
+    pub fn detect() -> Kernel {
+        [Kernel::Avx512, Kernel::Avx2, Kernel::Neon]
+            .iter()
+            .copied()
+            .find(|kernel| kernel.is_supported())
+            .unwrap_or(Kernel::Portable)
+    }
+
+    /// Whether the running CPU can use the kernel.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Kernel::Scalar | Kernel::Portable => true,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Kernel::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Kernel::Avx512 => is_x86_feature_detected!("avx512f"),
+            // NEON is part of the baseline of AArch64.
+            #[cfg(target_arch = "aarch64")]
+            Kernel::Neon => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+}
+
+/// Statistics of a bulk conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BulkStats {
+    pub points: usize,
+    /// Number of points whose inversion did not converge; they hold the best estimate.
+    pub non_convergent: usize,
+    pub elapsed: Duration,
+}
+
+impl BulkStats {
+    /// Throughput of the conversion.
+    pub fn points_per_second(&self) -> f64 {
+        self.points as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// A converter for large arrays of points, reusing its buffers between calls.
+#[derive(Clone)]
+pub struct BulkConverter {
+    converter: Converter,
+    kernel: Kernel,
+    lats: Vec<f64>,
+    lons: Vec<f64>,
+    wide: WideScratch,
+}
+
+impl fmt::Debug for BulkConverter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BulkConverter")
+            .field("converter", &self.converter)
+            .field("kernel", &self.kernel)
+            .field("chunk_size", &self.chunk_size())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BulkConverter {
+    /// Default number of points per chunk, using 64 KiB of scratch space.
+    pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+    /// Creates a converter using the kernel detected for the running CPU.
+    pub fn new(converter: Converter) -> Self {
+        BulkConverter {
+            converter,
+            kernel: Kernel::detect(),
+            lats: vec![0.0; Self::DEFAULT_CHUNK_SIZE],
+            lons: vec![0.0; Self::DEFAULT_CHUNK_SIZE],
+            wide: WideScratch::new(Self::DEFAULT_CHUNK_SIZE),
+        }
+    }
+
+    /// Sets the number of points processed at once.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        let chunk_size = chunk_size.max(1);
+        self.lats = vec![0.0; chunk_size];
+        self.lons = vec![0.0; chunk_size];
+        self.wide = WideScratch::new(chunk_size);
+        self
+    }
+
+    /// Forces a kernel, falling back to [`Kernel::Portable`] if the CPU does not support it.
+    pub fn with_kernel(mut self, kernel: Kernel) -> Self {
+        self.kernel = if kernel.is_supported() {
+            kernel
+        } else {
+            Kernel::Portable
+        };
+        self
+    }
+
+    /// Kernel used for the conversions.
+    pub fn kernel(&self) -> Kernel {
+        self.kernel
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.lats.len()
+    }
+
+    pub fn converter(&self) -> &Converter {
+        &self.converter
+    }
+
+    /// Converts the points in place.
+    pub fn convert(&mut self, points: &mut [(f64, f64)]) -> BulkStats {
+        let start = Instant::now();
+        let mut non_convergent = 0;
+        for chunk in points.chunks_mut(self.lats.len()) {
+            let (lats, lons) = (&mut self.lats[..chunk.len()], &mut self.lons[..chunk.len()]);
+            for ((lat, lon), &(x, y)) in lats.iter_mut().zip(lons.iter_mut()).zip(chunk.iter()) {
+                *lat = x;
+                *lon = y;
+            }
+            non_convergent += self.wide.convert(&self.converter, self.kernel, lats, lons);
+            for ((point, &lat), &lon) in chunk.iter_mut().zip(lats.iter()).zip(lons.iter()) {
+                *point = (lat, lon);
+            }
+        }
+        BulkStats {
+            points: points.len(),
+            non_convergent,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Converts points stored as separate arrays in place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the arrays have different lengths.
+    pub fn convert_split(&mut self, lats: &mut [f64], lons: &mut [f64]) -> BulkStats {
+        assert_eq!(lats.len(), lons.len(), "arrays of different lengths");
+        let start = Instant::now();
+        let mut non_convergent = 0;
+        let chunk_size = self.chunk_size();
+        for (lats, lons) in lats.chunks_mut(chunk_size).zip(lons.chunks_mut(chunk_size)) {
+            non_convergent += self.wide.convert(&self.converter, self.kernel, lats, lons);
+        }
+        BulkStats {
+            points: lats.len(),
+            non_convergent,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+/// Converts split arrays in place one point at a time, returning the number of non-convergent
+/// points.
+fn convert_scalar(converter: &Converter, lats: &mut [f64], lons: &mut [f64]) -> usize {
+    let mut non_convergent = 0;
+    for (lat, lon) in lats.iter_mut().zip(lons.iter_mut()) {
+        let ((x, y), converged) = converter.convert_checked(*lat, *lon);
+        *lat = x;
+        *lon = y;
+        non_convergent += !converged as usize;
+    }
+    non_convergent
+}
+
+/// Buffers of the wide kernels, one entry per point of a chunk.
+#[derive(Clone)]
+struct WideScratch {
+    lat_d: Vec<f64>,
+    lon_d: Vec<f64>,
+    target_lats: Vec<f64>,
+    target_lons: Vec<f64>,
+    active: Vec<bool>,
+    damping: Vec<Damping>,
+}
+
+impl WideScratch {
+    fn new(chunk_size: usize) -> Self {
+        WideScratch {
+            lat_d: vec![0.0; chunk_size],
+            lon_d: vec![0.0; chunk_size],
+            target_lats: vec![0.0; chunk_size],
+            target_lons: vec![0.0; chunk_size],
+            active: vec![false; chunk_size],
+            damping: vec![Damping::new(); chunk_size],
+        }
+    }
+
+    /// Converts a chunk in place, returning the number of non-convergent points.
+    ///
+    /// The steps mirror `Converter::convert_checked` operation by operation, so that the results
+    /// are the same.
+    fn convert(
+        &mut self,
+        converter: &Converter,
+        kernel: Kernel,
+        lats: &mut [f64],
+        lons: &mut [f64],
+    ) -> usize {
+        use GeodeticSystem::*;
+        if kernel == Kernel::Scalar
+            || converter.algorithm() != Algorithm::FastApprox
+            // The BD-09 steps of the wide path apply the offset everywhere.
+            || converter.bd_passthrough()
+            // The wide path chains the inverses of both offsets.
+            || converter.whole_chain()
+        {
+            return convert_scalar(converter, lats, lons);
+        }
+
+        let map = |f: fn(f64, f64) -> (f64, f64), lats: &mut [f64], lons: &mut [f64]| {
+            for (lat, lon) in lats.iter_mut().zip(lons.iter_mut()) {
+                let (x, y) = f(*lat, *lon);
+                *lat = x;
+                *lon = y;
+            }
+        };
+        let non_convergent = match (converter.source(), converter.target()) {
+            (Wgs84, Gcj02) => {
+                self.forward(converter, kernel, lats, lons);
+                0
+            }
+            (Wgs84, Bd09) => {
+                self.forward(converter, kernel, lats, lons);
+                map(gcj_to_bd, lats, lons);
+                0
+            }
+            (Gcj02, Wgs84) => self.inverse(converter, kernel, lats, lons),
+            (Bd09, Wgs84) => {
+                map(bd_to_gcj, lats, lons);
+                self.inverse(converter, kernel, lats, lons)
+            }
+            _ => return convert_scalar(converter, lats, lons),
+        };
+        map(normalize, lats, lons);
+        non_convergent
+    }
+
+    fn forward(
+        &mut self,
+        converter: &Converter,
+        kernel: Kernel,
+        lats: &mut [f64],
+        lons: &mut [f64],
+    ) {
+        let n = lats.len();
+        let (lat_d, lon_d) = (&mut self.lat_d[..n], &mut self.lon_d[..n]);
+        offsets(kernel, lats, lons, lat_d, lon_d);
+        for i in 0..n {
+            if converter.in_region(lats[i], lons[i]) {
+                lats[i] += lat_d[i];
+                lons[i] += lon_d[i];
+            }
+        }
+    }
+
+    /// Same iteration as `crate::invert`, run on every point of the chunk at once.
+    fn inverse(
+        &mut self,
+        converter: &Converter,
+        kernel: Kernel,
+        lats: &mut [f64],
+        lons: &mut [f64],
+    ) -> usize {
+        let n = lats.len();
+        let (lat_d, lon_d) = (&mut self.lat_d[..n], &mut self.lon_d[..n]);
+        let targets = (&mut self.target_lats[..n], &mut self.target_lons[..n]);
+        targets.0.copy_from_slice(lats);
+        targets.1.copy_from_slice(lons);
+        let active = &mut self.active[..n];
+        active.fill(true);
+        let damping = &mut self.damping[..n];
+        damping.fill(Damping::new());
+
+        let mut remaining = n;
+        for _ in 0..INVERT_MAX_ROUND {
+            offsets(kernel, lats, lons, lat_d, lon_d);
+            for i in 0..n {
+                if !active[i] {
+                    continue;
+                }
+                let (lat, lon) = if converter.in_region(lats[i], lons[i]) {
+                    (lats[i] + lat_d[i], lons[i] + lon_d[i])
+                } else {
+                    (lats[i], lons[i])
+                };
+                let delta = (targets.0[i] - lat, targets.1[i] - lon);
+                if delta.0.abs() < INVERT_EPS && delta.1.abs() < INVERT_EPS {
+                    active[i] = false;
+                    remaining -= 1;
+                } else {
+                    let factor = damping[i].update(delta);
+                    lats[i] += delta.0 * factor;
+                    lons[i] += delta.1 * factor;
+                }
+            }
+            if remaining == 0 {
+                break;
+            }
+        }
+        remaining
+    }
+}
+
+/// Computes the `FastApprox` offsets of every point.
+fn offsets(kernel: Kernel, lats: &[f64], lons: &[f64], lat_d: &mut [f64], lon_d: &mut [f64]) {
+    match kernel {
+        // Safety: `BulkConverter` only keeps supported kernels.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Kernel::Avx2 => unsafe { offsets_avx2(lats, lons, lat_d, lon_d) },
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Kernel::Avx512 => unsafe { offsets_avx512(lats, lons, lat_d, lon_d) },
+        _ => offsets_portable(lats, lons, lat_d, lon_d),
+    }
+}
+
+/// Loop vectorized by the compiler for whatever features the caller enables.
+///
+/// Floating-point operations are never contracted or reordered, so every instruction set gives
+/// the same results as `gcj_offset_approx` on a single point.
+#[inline(always)]
+fn offsets_portable(lats: &[f64], lons: &[f64], lat_d: &mut [f64], lon_d: &mut [f64]) {
+    let n = lats.len();
+    let (lons, lat_d, lon_d) = (&lons[..n], &mut lat_d[..n], &mut lon_d[..n]);
+    for i in 0..n {
+        let (a, b) = gcj_offset_approx(lats[i], lons[i]);
+        lat_d[i] = a;
+        lon_d[i] = b;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn offsets_avx2(lats: &[f64], lons: &[f64], lat_d: &mut [f64], lon_d: &mut [f64]) {
+    offsets_portable(lats, lons, lat_d, lon_d)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx512f")]
+unsafe fn offsets_avx512(lats: &[f64], lons: &[f64], lat_d: &mut [f64], lon_d: &mut [f64]) {
+    offsets_portable(lats, lons, lat_d, lon_d)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Bd09, Wgs84);
+        let input: Vec<(f64, f64)> = (0..10).map(|i| (30.0 + i as f64, 116.0)).collect();
+        let mut bulk = BulkConverter::new(converter.clone()).with_chunk_size(3);
+
+        let mut points = input.clone();
+        let stats = bulk.convert(&mut points);
+        assert_eq!(stats.points, 10);
+        assert_eq!(stats.non_convergent, 0);
+        for (point, &(lat, lon)) in points.iter().zip(&input) {
+            assert_eq!(*point, converter.convert(lat, lon));
+        }
+
+        let buffer = bulk.lats.as_ptr();
+        bulk.convert(&mut points);
+        assert_eq!(bulk.lats.as_ptr(), buffer);
+    }
+
+    #[test]
+    fn test_kernels() {
+        let input: Vec<(f64, f64)> = (0..50)
+            .map(|i| (20.0 + i as f64 * 0.7, 75.0 + i as f64 * 1.3))
+            .collect();
+        for &(from, to) in &[(Wgs84, Gcj02), (Gcj02, Wgs84), (Bd09, Wgs84), (Wgs84, Bd09)] {
+            let converter = Converter::new(from, to).with_algorithm(Algorithm::FastApprox);
+            let expected: Vec<_> = input
+                .iter()
+                .map(|&(lat, lon)| converter.convert(lat, lon))
+                .collect();
+            for &kernel in &[Kernel::Portable, Kernel::Avx2, Kernel::Avx512, Kernel::Neon] {
+                let mut bulk = BulkConverter::new(converter.clone())
+                    .with_kernel(kernel)
+                    .with_chunk_size(16);
+                let mut points = input.clone();
+                assert_eq!(bulk.convert(&mut points).non_convergent, 0);
+                assert_eq!(points, expected, "{:?} {:?}", kernel, (from, to));
+            }
+        }
+    }
+
+    #[test]
+    fn test_edge() {
+        // Just inside the western edge, where some inversions are damped.
+        let input: Vec<(f64, f64)> = (0..40).map(|i| (40.0 + i as f64 * 0.37, 72.009)).collect();
+        let converter = Converter::new(Gcj02, Wgs84).with_algorithm(Algorithm::FastApprox);
+        let expected: Vec<_> = input
+            .iter()
+            .map(|&(lat, lon)| converter.convert(lat, lon))
+            .collect();
+        for &kernel in &[Kernel::Portable, Kernel::Avx2, Kernel::Avx512, Kernel::Neon] {
+            let mut bulk = BulkConverter::new(converter.clone()).with_kernel(kernel);
+            let mut points = input.clone();
+            assert!(bulk.convert(&mut points).non_convergent > 0);
+            assert_eq!(points, expected, "{:?}", kernel);
+        }
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let b = crate::CHINA_BBOX;
+        let mut input: Vec<(f64, f64)> = (0..37)
+            .map(|i| (20.0 + i as f64 * 0.9, 75.0 + i as f64 * 1.7))
+            .collect();
+        input.extend(&[
+            (48.8566, 2.3522),
+            (b.max_lat - 0.005, b.min_lon + 0.005),
+            (-90.0, 180.0),
+        ]);
+        let bits = |p: &[(f64, f64)]| -> Vec<_> {
+            p.iter().map(|p| (p.0.to_bits(), p.1.to_bits())).collect()
+        };
+        for &algorithm in &Algorithm::ALL {
+            for &from in &GeodeticSystem::ALL {
+                for &to in &GeodeticSystem::ALL {
+                    let converter = Converter::new(from, to).with_algorithm(algorithm);
+                    let expected: Vec<_> = input
+                        .iter()
+                        .map(|&(lat, lon)| converter.convert_checked(lat, lon))
+                        .collect();
+                    let non_convergent = expected.iter().filter(|e| !e.1).count();
+                    let expected: Vec<_> = expected.into_iter().map(|e| e.0).collect();
+                    for &kernel in &[Kernel::Scalar, Kernel::Portable, Kernel::detect()] {
+                        for &chunk_size in &[1, 7, BulkConverter::DEFAULT_CHUNK_SIZE] {
+                            let mut bulk = BulkConverter::new(converter.clone())
+                                .with_kernel(kernel)
+                                .with_chunk_size(chunk_size);
+                            let context = (algorithm, from, to, kernel, chunk_size);
+
+                            let mut points = input.clone();
+                            let stats = bulk.convert(&mut points);
+                            assert_eq!(stats.non_convergent, non_convergent, "{:?}", context);
+                            assert_eq!(bits(&points), bits(&expected), "{:?}", context);
+
+                            let mut lats: Vec<f64> = input.iter().map(|p| p.0).collect();
+                            let mut lons: Vec<f64> = input.iter().map(|p| p.1).collect();
+                            bulk.convert_split(&mut lats, &mut lons);
+                            let split: Vec<_> = lats.into_iter().zip(lons).collect();
+                            assert_eq!(bits(&split), bits(&expected), "{:?}", context);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert_split() {
+        let mut bulk = BulkConverter::new(Converter::new(Wgs84, Gcj02));
+        let mut points = [(39.0, 116.0), (31.2, 121.5), (1.0, 1.0)];
+        let mut lats: Vec<f64> = points.iter().map(|p| p.0).collect();
+        let mut lons: Vec<f64> = points.iter().map(|p| p.1).collect();
+
+        bulk.convert(&mut points);
+        let stats = bulk.convert_split(&mut lats, &mut lons);
+        assert_eq!(stats.points, 3);
+        for (i, point) in points.iter().enumerate() {
+            assert_eq!(*point, (lats[i], lons[i]));
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Circles on the Earth and the boxes around them, for building spatial queries.
+//!
+//! A geofence of a center and a radius is searched in an index of boxes with its bounding box in
+//! the system of the index, and a box is searched in an index of circles with its enclosing
+//! circle. Radii are in meters on the sphere of [`haversine`](crate::distance::haversine), as
+//! measured in WGS-84 whatever the system of the coordinates:
+//!
+//! ```
+//! use undrift_gps::circle::Circle;
+//! use undrift_gps::point::Coordinate;
+//! use undrift_gps::GeodeticSystem::*;
+//!
+//! let center = Coordinate::from_degrees(Wgs84, 39.9087, 116.3975).unwrap();
+//! let fence = Circle::new(center, 500.0);
+//! let query = fence.bounds_in(Gcj02);
+//! let inside = center.convert_to(Gcj02);
+//! assert!(query.contains(inside.lat.degrees(), inside.lon.degrees()));
+//! assert!(fence.contains(inside));
+//! ```
+use crate::distance::{distance_meters, Method};
+use crate::point::Coordinate;
+use crate::validate::Issue;
+use crate::{haversine, BoundingBox, Converter, GeodeticSystem, EARTH_RADIUS};
+
+/// A circle of a radius in meters around a coordinate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Circle {
+    pub center: Coordinate,
+    pub radius: f64,
+}
+
+impl Circle {
+    pub fn new(center: impl Into<Coordinate>, radius: f64) -> Self {
+        assert!(radius >= 0.0, "a circle needs a radius of at least zero");
+        Circle {
+            center: center.into(),
+            radius,
+        }
+    }
+
+    /// The smallest box around the circle, in the system of its center.
+    pub fn bounds(&self) -> BoundingBox {
+        self.bounds_in(self.center.system)
+    }
+
+    /// The smallest box around the circle in `system`, exact on the sphere in WGS-84 and as by
+    /// [`BoundingBox::converted`] in the other systems.
+    ///
+    /// Longitudes are not wrapped, as by [`BoundingBox::buffered`], so that the box of a circle
+    /// across the antimeridian goes beyond ±180°. Circles around a pole get every longitude.
+    pub fn bounds_in(&self, system: GeodeticSystem) -> BoundingBox {
+        let center = self.center.convert_to(GeodeticSystem::Wgs84);
+        let (lat, lon) = (center.lat.degrees(), center.lon.degrees());
+        let angle = self.radius / EARTH_RADIUS;
+        let degrees = angle.to_degrees();
+        let wgs = if lat + degrees >= 90.0 || lat - degrees <= -90.0 {
+            BoundingBox::new(
+                (lat - degrees).max(-90.0),
+                -180.0,
+                (lat + degrees).min(90.0),
+                180.0,
+            )
+        } else {
+            // The meridians tangent to the circle.
+            let lon_degrees = (angle.sin() / lat.to_radians().cos()).asin().to_degrees();
+            BoundingBox::new(
+                lat - degrees,
+                lon - lon_degrees,
+                lat + degrees,
+                lon + lon_degrees,
+            )
+        };
+        match system {
+            GeodeticSystem::Wgs84 => wgs,
+            _ => wgs.converted(&Converter::new(GeodeticSystem::Wgs84, system)),
+        }
+    }
+
+    /// Checks whether a coordinate of any system is in the circle, edge included.
+    pub fn contains(&self, point: impl Into<Coordinate>) -> bool {
+        distance_meters(self.center, point, Method::Haversine) <= self.radius
+    }
+
+    /// A circle around a box of `system`, centered on the middle of its latitudes and
+    /// longitudes rather than the smallest one.
+    ///
+    /// The box is converted as by [`BoundingBox::converted`]; a box with a bound that is not a
+    /// valid coordinate is rejected.
+    pub fn enclosing(bbox: BoundingBox, system: GeodeticSystem) -> Result<Circle, Issue> {
+        let wgs = match system {
+            GeodeticSystem::Wgs84 => bbox,
+            _ => bbox.converted(&Converter::new(system, GeodeticSystem::Wgs84)),
+        };
+        let middle = (
+            (wgs.min_lat + wgs.max_lat) / 2.0,
+            (wgs.min_lon + wgs.max_lon) / 2.0,
+        );
+        let (lat, lon) = crate::normalize(middle.0, middle.1);
+        let center = Coordinate::from_degrees(GeodeticSystem::Wgs84, lat, lon)?;
+        // Along each edge, the distance to the middle is the largest at the corners.
+        let radius = [
+            (wgs.min_lat, wgs.min_lon),
+            (wgs.min_lat, wgs.max_lon),
+            (wgs.max_lat, wgs.min_lon),
+            (wgs.max_lat, wgs.max_lon),
+        ]
+        .iter()
+        .map(|&corner| haversine(middle, corner))
+        .fold(0.0, f64::max);
+        Ok(Circle::new(center.convert_to(system), radius))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::distance::destination;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_bounds() {
+        let center = Coordinate::from_degrees(Wgs84, 39.9, 116.4).unwrap();
+        let circle = Circle::new(center, 10_000.0);
+        let bounds = circle.bounds();
+        let gcj = circle.bounds_in(Gcj02);
+        let mut reached = BoundingBox::new(90.0, 180.0, -90.0, -180.0);
+        for i in 0..3600 {
+            let point = destination(center, f64::from(i) / 10.0, 10_000.0, Method::Haversine);
+            let (lat, lon) = (point.lat.degrees(), point.lon.degrees());
+            assert!(bounds.buffered(1e-6).contains(lat, lon), "{} {}", lat, lon);
+            reached = BoundingBox::new(
+                reached.min_lat.min(lat),
+                reached.min_lon.min(lon),
+                reached.max_lat.max(lat),
+                reached.max_lon.max(lon),
+            );
+            let point = point.convert_to(Gcj02);
+            assert!(gcj
+                .buffered(0.01)
+                .contains(point.lat.degrees(), point.lon.degrees()));
+        }
+        // The box touches the circle on every side.
+        assert!(reached
+            .buffered(0.01)
+            .contains(bounds.min_lat, bounds.min_lon));
+        assert!(reached
+            .buffered(0.01)
+            .contains(bounds.max_lat, bounds.max_lon));
+        // The same circle, to the precision of the inversion.
+        let same = Circle::new(center.convert_to(Gcj02), 10_000.0).bounds();
+        assert!((same.min_lat - gcj.min_lat).abs() < 1e-8);
+        assert!((same.max_lon - gcj.max_lon).abs() < 1e-8);
+
+        let circle = Circle::new(Coordinate::from_degrees(Wgs84, 10.0, 179.95).unwrap(), 10e3);
+        assert!(circle.bounds().max_lon > 180.0);
+        let circle = Circle::new(Coordinate::from_degrees(Wgs84, 89.95, 0.0).unwrap(), 10e3);
+        let bounds = circle.bounds();
+        assert_eq!(
+            (bounds.min_lon, bounds.max_lat, bounds.max_lon),
+            (-180.0, 90.0, 180.0)
+        );
+        assert!((bounds.min_lat - 89.86).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_contains() {
+        let center = Coordinate::from_degrees(Gcj02, 39.9, 116.4).unwrap();
+        let circle = Circle::new(center, 1000.0);
+        assert!(circle.contains(center) && circle.contains(center.convert_to(Wgs84)));
+        assert!(circle.contains(destination(center, 30.0, 999.0, Method::Haversine)));
+        assert!(!circle.contains(destination(center, 30.0, 1001.0, Method::Haversine)));
+    }
+
+    #[test]
+    fn test_enclosing() {
+        let bbox = BoundingBox::new(39.8, 116.2, 40.0, 116.5);
+        for &system in &GeodeticSystem::ALL {
+            let circle = Circle::enclosing(bbox, system).unwrap();
+            assert_eq!(circle.center.system, system);
+            for i in 0..=100 {
+                let t = f64::from(i) / 100.0;
+                for &(lat, lon) in &[
+                    (39.8, 116.2 + 0.3 * t),
+                    (40.0, 116.2 + 0.3 * t),
+                    (39.8 + 0.2 * t, 116.2),
+                    (39.8 + 0.2 * t, 116.5),
+                ] {
+                    let point = Coordinate::from_degrees(system, lat, lon).unwrap();
+                    assert!(Circle::new(circle.center, circle.radius + 0.01).contains(point));
+                }
+            }
+            // Half the diagonal.
+            assert!(
+                (circle.radius - 17_000.0).abs() < 500.0,
+                "{}",
+                circle.radius
+            );
+        }
+        let bbox = BoundingBox::new(f64::NAN, 116.2, 40.0, 116.5);
+        assert_eq!(Circle::enclosing(bbox, Wgs84), Err(Issue::NotFinite));
+    }
+}
@@ -0,0 +1,1042 @@
+//! Configurable conversion between two coordinate systems.
+use crate::format::{parse_coordinate, ParseCoordError, Precision};
+use crate::validate::{validate, Issue};
+use crate::{
+    bd_to_gcj, bd_to_gcj_refined, gcj_offset, gcj_offset_approx, gcj_offset_v1, gcj_to_bd, invert,
+    is_in_china, normalize, Damping, Error, GeodeticSystem, INVERT_EPS,
+};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+type Region = Arc<dyn Fn(f64, f64) -> bool + Send + Sync>;
+/// Called with every coordinate a converter is given and its conversion with whether it
+/// converged, `None` when it is left unconverted for not being finite.
+pub(crate) type Observer =
+    Arc<dyn Fn(&Converter, (f64, f64), Option<((f64, f64), bool)>) + Send + Sync>;
+
+/// Latitude beyond which the GCJ-02 obfuscation is never applied, whatever the region: its
+/// longitude offset is divided by the cosine of the latitude, and diverges at the poles.
+pub const MAX_OBFUSCATED_LAT: f64 = 85.0;
+
+/// Method used to compute the GCJ-02 obfuscation and its inverse.
+///
+/// Converted coordinates are often stored, so each variant is frozen: its results will not
+/// change in future versions, even bitwise. Improvements are added as new variants, and only the
+/// default may move to them. The free functions such as `gcj_to_wgs` are not frozen and follow
+/// the latest implementation. Inversions reported as non-convergent, which have no exact result,
+/// are the exception: their estimate may be improved, as was done by damping the iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Algorithm {
+    /// Fixed-point iteration stopping at 1e-7 degrees, as done by `gcj_to_wgs` up to 0.3.
+    V1Classic,
+    /// Newton's method with a finite-difference Jacobian, converging in fewer rounds.
+    V2Newton,
+    /// Fixed-point iteration continued until the residual stops decreasing.
+    Exact,
+    /// Same iteration as `V1Classic` with a distortion sharing its trigonometric terms, which
+    /// differs from it by less than 1e-15 degrees.
+    V3SharedTrig,
+    /// Same iteration as `V1Classic` with polynomial approximations of the sines, for rendering
+    /// where speed matters more than accuracy. Stays within 1 cm of `V3SharedTrig` inside China.
+    FastApprox,
+    /// Same as `V3SharedTrig`, except that from BD-09 to WGS-84 the closed-form inverse of BD-09
+    /// is refined down to the tolerance before GCJ-02 is inverted, as done by `bd_to_wgs`, so
+    /// that its error of up to two decimeters is not carried over.
+    #[default]
+    V4RefinedBd,
+}
+
+impl Algorithm {
+    /// Every variant.
+    pub const ALL: [Algorithm; 6] = [
+        Algorithm::V1Classic,
+        Algorithm::V2Newton,
+        Algorithm::Exact,
+        Algorithm::V3SharedTrig,
+        Algorithm::FastApprox,
+        Algorithm::V4RefinedBd,
+    ];
+
+    /// Identifier used in configuration, such as "v2-newton".
+    pub fn key(self) -> &'static str {
+        match self {
+            Algorithm::V1Classic => "v1-classic",
+            Algorithm::V2Newton => "v2-newton",
+            Algorithm::Exact => "exact",
+            Algorithm::V3SharedTrig => "v3-shared-trig",
+            Algorithm::FastApprox => "fast-approx",
+            Algorithm::V4RefinedBd => "v4-refined-bd",
+        }
+    }
+
+    /// Residual in degrees, on each axis, below which the inversion of GCJ-02 stops.
+    pub fn tolerance(self) -> f64 {
+        match self {
+            Algorithm::V1Classic
+            | Algorithm::V3SharedTrig
+            | Algorithm::FastApprox
+            | Algorithm::V4RefinedBd => INVERT_EPS,
+            Algorithm::V2Newton => NEWTON_EPS,
+            Algorithm::Exact => EXACT_EPS,
+        }
+    }
+
+    /// Offset added by GCJ-02 inside the obfuscated region.
+    pub(crate) fn offset(self, lat: f64, lon: f64) -> (f64, f64) {
+        match self {
+            Algorithm::V1Classic | Algorithm::V2Newton | Algorithm::Exact => {
+                gcj_offset_v1(lat, lon)
+            }
+            Algorithm::V3SharedTrig | Algorithm::V4RefinedBd => gcj_offset(lat, lon),
+            Algorithm::FastApprox => gcj_offset_approx(lat, lon),
+        }
+    }
+
+    /// Finds the input of `forward` producing the given output, iterating from `start`.
+    pub(crate) fn invert<F>(
+        self,
+        forward: F,
+        lat: f64,
+        lon: f64,
+        start: (f64, f64),
+    ) -> ((f64, f64), bool)
+    where
+        F: Fn(f64, f64) -> (f64, f64),
+    {
+        match self {
+            Algorithm::V1Classic
+            | Algorithm::V3SharedTrig
+            | Algorithm::FastApprox
+            | Algorithm::V4RefinedBd => invert(forward, lat, lon, start),
+            Algorithm::V2Newton => invert_newton(forward, lat, lon, start),
+            Algorithm::Exact => invert_exact(forward, lat, lon, start),
+        }
+    }
+}
+
+/// Error returned when parsing an unknown algorithm key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAlgorithmError(String);
+
+impl fmt::Display for ParseAlgorithmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown algorithm: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseAlgorithmError {}
+
+impl FromStr for Algorithm {
+    type Err = ParseAlgorithmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Algorithm::ALL
+            .iter()
+            .copied()
+            .find(|a| a.key() == s)
+            .ok_or_else(|| ParseAlgorithmError(s.to_owned()))
+    }
+}
+
+/// Tolerance in degrees of `invert_newton`.
+const NEWTON_EPS: f64 = 1e-9;
+/// Tolerance in degrees of `invert_exact`.
+const EXACT_EPS: f64 = 1e-12;
+
+fn invert_newton<F>(forward: F, lat: f64, lon: f64, start: (f64, f64)) -> ((f64, f64), bool)
+where
+    F: Fn(f64, f64) -> (f64, f64),
+{
+    const STEP: f64 = 1e-6;
+    const MAX_ROUND: u32 = 10;
+
+    let mut x = start;
+    let mut damping = Damping::new();
+    for _ in 0..MAX_ROUND {
+        let cur = forward(x.0, x.1);
+        let residual = (cur.0 - lat, cur.1 - lon);
+        if residual.0.abs() < NEWTON_EPS && residual.1.abs() < NEWTON_EPS {
+            return (x, true);
+        }
+        let factor = damping.update(residual);
+
+        // Central differences of the forward transform.
+        let (lat_p, lat_m) = (forward(x.0 + STEP, x.1), forward(x.0 - STEP, x.1));
+        let (lon_p, lon_m) = (forward(x.0, x.1 + STEP), forward(x.0, x.1 - STEP));
+        let a = (lat_p.0 - lat_m.0) / (2.0 * STEP);
+        let b = (lon_p.0 - lon_m.0) / (2.0 * STEP);
+        let c = (lat_p.1 - lat_m.1) / (2.0 * STEP);
+        let d = (lon_p.1 - lon_m.1) / (2.0 * STEP);
+        let det = a * d - b * c;
+        if det.abs() < 1e-12 {
+            return (x, false);
+        }
+
+        x.0 -= (d * residual.0 - b * residual.1) / det * factor;
+        x.1 -= (a * residual.1 - c * residual.0) / det * factor;
+    }
+    (x, false)
+}
+
+fn invert_exact<F>(forward: F, lat: f64, lon: f64, start: (f64, f64)) -> ((f64, f64), bool)
+where
+    F: Fn(f64, f64) -> (f64, f64),
+{
+    const MAX_ROUND: u32 = 100;
+
+    let mut x = start;
+    let mut best = (x, f64::INFINITY);
+    for _ in 0..MAX_ROUND {
+        let cur = forward(x.0, x.1);
+        let delta = (lat - cur.0, lon - cur.1);
+        let error = delta.0.abs().max(delta.1.abs());
+        if error >= best.1 {
+            // Any further step would be below the precision of f64.
+            return (best.0, best.1 < EXACT_EPS);
+        }
+
+        best = (x, error);
+        x.0 += delta.0;
+        x.1 += delta.1;
+    }
+    (best.0, best.1 < EXACT_EPS)
+}
+
+/// What to do with coordinates having a NaN or infinite component.
+///
+/// Followed by [`Converter::filter_convert`], [`crate::batch`] and the file converters. Plain
+/// [`Converter::convert`] does not check its input, whatever the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum NonFinitePolicy {
+    /// Pass them through: batches leave them unreported, and files keep them as written.
+    Propagate,
+    /// Reject them: batches report them as failures, and file conversions fail.
+    #[default]
+    Error,
+    /// Leave them out: batches list them as skipped, and files keep them as written.
+    Skip,
+}
+
+/// A floating point type coordinates can be given in.
+///
+/// Conversions are computed in `f64`, whose precision they need; this trait is the boundary for
+/// other types, such as `f32` or wrapper types of an application.
+pub trait Float: Copy {
+    fn to_f64(self) -> f64;
+    fn from_f64(x: f64) -> Self;
+}
+
+impl Float for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(x: f64) -> Self {
+        x
+    }
+}
+
+impl Float for f32 {
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+
+    fn from_f64(x: f64) -> Self {
+        x as f32
+    }
+}
+
+/// Types holding coordinates that convert together.
+///
+/// With the `derive` feature, it can be derived for structs whose coordinate fields are marked
+/// with `#[coords(lat)]`, `#[coords(lon)]`, `#[coords(position)]` or `#[coords(nested)]`.
+pub trait ConvertCoords {
+    /// Converts every coordinate in place.
+    fn convert_coords_with(&mut self, converter: &Converter);
+
+    /// Converts every coordinate in place with the default settings.
+    fn convert_coords(&mut self, from: GeodeticSystem, to: GeodeticSystem) {
+        self.convert_coords_with(&Converter::new(from, to));
+    }
+}
+
+impl<T: ConvertCoords> ConvertCoords for [T] {
+    fn convert_coords_with(&mut self, converter: &Converter) {
+        self.iter_mut()
+            .for_each(|x| x.convert_coords_with(converter));
+    }
+}
+
+impl<T: ConvertCoords> ConvertCoords for Vec<T> {
+    fn convert_coords_with(&mut self, converter: &Converter) {
+        self.as_mut_slice().convert_coords_with(converter);
+    }
+}
+
+impl<T: ConvertCoords> ConvertCoords for Option<T> {
+    fn convert_coords_with(&mut self, converter: &Converter) {
+        if let Some(x) = self {
+            x.convert_coords_with(converter);
+        }
+    }
+}
+
+/// Converts coordinates from one system to another with adjustable behavior.
+///
+/// With the default settings, `Converter::new(from, to).convert(lat, lon)` gives the same result
+/// as `from.convert_to(to, lat, lon)`, up to the tolerance of the inversion of GCJ-02, if any: both
+/// stop within 1e-7 degrees of the exact inverse, after different rounds. Next to the edges of the
+/// region, where the inversion may not converge, they can differ further.
+///
+/// Inputs are expected within [-90, 90] for latitudes and [-180, 180] for longitudes. For such
+/// inputs the results are finite and in range too: latitudes pushed over a pole are clamped and
+/// longitudes pushed over the antimeridian are wrapped, see [`crate::normalize`]. Past
+/// [`MAX_OBFUSCATED_LAT`], coordinates are outside of every region.
+#[derive(Clone)]
+pub struct Converter {
+    from: GeodeticSystem,
+    to: GeodeticSystem,
+    region: Region,
+    algorithm: Algorithm,
+    non_finite: NonFinitePolicy,
+    precision: Precision,
+    bd_passthrough: bool,
+    whole_chain: bool,
+    observer: Option<Observer>,
+}
+
+impl fmt::Debug for Converter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Converter")
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("algorithm", &self.algorithm)
+            .field("non_finite", &self.non_finite)
+            .field("precision", &self.precision)
+            .field("bd_passthrough", &self.bd_passthrough)
+            .field("whole_chain", &self.whole_chain)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Converter {
+    /// Creates a converter using the built-in China boundary.
+    pub fn new(from: GeodeticSystem, to: GeodeticSystem) -> Self {
+        Converter {
+            from,
+            to,
+            region: Arc::new(is_in_china),
+            algorithm: Algorithm::default(),
+            non_finite: NonFinitePolicy::default(),
+            precision: Precision::default(),
+            bd_passthrough: false,
+            whole_chain: false,
+            observer: None,
+        }
+    }
+
+    /// Replaces the predicate deciding where the GCJ-02 obfuscation applies.
+    ///
+    /// The predicate receives WGS-84 coordinates.
+    pub fn with_region<F>(mut self, region: F) -> Self
+    where
+        F: Fn(f64, f64) -> bool + Send + Sync + 'static,
+    {
+        self.region = Arc::new(region);
+        self
+    }
+
+    /// Selects how GCJ-02 coordinates are converted back to WGS-84.
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Method used for GCJ-02.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Selects how coordinates with NaN or infinite components are handled.
+    pub fn with_non_finite(mut self, policy: NonFinitePolicy) -> Self {
+        self.non_finite = policy;
+        self
+    }
+
+    pub fn non_finite(&self) -> NonFinitePolicy {
+        self.non_finite
+    }
+
+    /// Selects how many digits the file converters write for the converted coordinates. By
+    /// default they write the shortest text reading back to the same value.
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// Makes the BD-09 offset follow the region like the GCJ-02 obfuscation, so that coordinates
+    /// outside of it are passed through in all six directions. By default the offset applies
+    /// everywhere, as Baidu does.
+    ///
+    /// The region is tested at the coordinate being offset, GCJ-02 or BD-09, rather than at its
+    /// WGS-84 position, which is up to about a kilometer away next to the boundary.
+    pub fn with_bd_passthrough(mut self, enabled: bool) -> Self {
+        self.bd_passthrough = enabled;
+        self
+    }
+
+    pub fn bd_passthrough(&self) -> bool {
+        self.bd_passthrough
+    }
+
+    /// Makes BD-09 to WGS-84 invert the composition of both offsets as a whole, iterating until
+    /// the BD-09 output of the estimate is within the tolerance of the algorithm from the input.
+    ///
+    /// Otherwise the inverse of BD-09 is followed by the inversion of GCJ-02, which carries its
+    /// error over. [`Algorithm::V4RefinedBd`] refines the closed-form inverse first, so that round
+    /// trips are within its tolerance too; with the other algorithms, which keep the closed form,
+    /// exact to a few centimeters, round trips are off by up to two decimeters. The whole chain
+    /// costs an evaluation of the BD-09 offset per round.
+    pub fn with_whole_chain(mut self, enabled: bool) -> Self {
+        self.whole_chain = enabled;
+        self
+    }
+
+    pub fn whole_chain(&self) -> bool {
+        self.whole_chain
+    }
+
+    /// Reports every conversion to `observer`, such as to audit a file while converting it.
+    pub(crate) fn with_observer(mut self, observer: Observer) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Whether the GCJ-02 obfuscation applies at a WGS-84 coordinate.
+    pub(crate) fn in_region(&self, lat: f64, lon: f64) -> bool {
+        lat.abs() <= MAX_OBFUSCATED_LAT && (self.region)(lat, lon)
+    }
+
+    /// The same converter in the opposite direction.
+    pub(crate) fn reversed(&self) -> Self {
+        Converter {
+            from: self.to,
+            to: self.from,
+            ..self.clone()
+        }
+    }
+
+    /// System of the input coordinates.
+    pub fn source(&self) -> GeodeticSystem {
+        self.from
+    }
+
+    /// System of the output coordinates.
+    pub fn target(&self) -> GeodeticSystem {
+        self.to
+    }
+
+    /// Converts a coordinate.
+    pub fn convert(&self, lat: f64, lon: f64) -> (f64, f64) {
+        self.convert_checked(lat, lon).0
+    }
+
+    /// Converts a coordinate with an altitude, which is passed through unchanged.
+    ///
+    /// None of the systems touches altitudes, so they are kept with their position instead of
+    /// being split off and joined back afterwards.
+    pub fn convert_3d(&self, lat: f64, lon: f64, alt: f64) -> (f64, f64, f64) {
+        let (lat, lon) = self.convert(lat, lon);
+        (lat, lon, alt)
+    }
+
+    /// Converts a coordinate given in another [`Float`] type.
+    pub fn convert_float<T: Float>(&self, lat: T, lon: T) -> (T, T) {
+        let (lat, lon) = self.convert(lat.to_f64(), lon.to_f64());
+        (T::from_f64(lat), T::from_f64(lon))
+    }
+
+    /// Converts a fixed number of points, without allocating.
+    pub fn convert_array<const N: usize>(&self, points: [(f64, f64); N]) -> [(f64, f64); N] {
+        points.map(|(lat, lon)| self.convert(lat, lon))
+    }
+
+    /// Converts a fixed number of points stored as `[lat0, lon0, lat1, lon1, ...]`, without
+    /// allocating. An odd length is a compile error.
+    pub fn convert_flat_array<const M: usize>(&self, mut values: [f64; M]) -> [f64; M] {
+        const { assert!(M % 2 == 0, "flat arrays hold latitude and longitude pairs") };
+        for pair in values.chunks_exact_mut(2) {
+            let (lat, lon) = self.convert(pair[0], pair[1]);
+            pair[0] = lat;
+            pair[1] = lon;
+        }
+        values
+    }
+
+    /// Converts a coordinate, also telling whether the inversion, if any, converged.
+    pub(crate) fn convert_checked(&self, lat: f64, lon: f64) -> ((f64, f64), bool) {
+        self.convert_warm(lat, lon, &mut None)
+    }
+
+    /// Same as `convert_checked`, starting the inversion, if any, from the offset in `warm`.
+    ///
+    /// `warm` holds the WGS-84 minus GCJ-02 difference of the previous inverted point, and is
+    /// updated with the one of this point. On ordered tracks the offset barely changes between
+    /// points, so the inversion converges in one or two rounds.
+    pub(crate) fn convert_warm(
+        &self,
+        lat: f64,
+        lon: f64,
+        warm: &mut Option<(f64, f64)>,
+    ) -> ((f64, f64), bool) {
+        let result = self.convert_unobserved(lat, lon, warm);
+        if let Some(observer) = &self.observer {
+            observer(self, (lat, lon), Some(result));
+        }
+        result
+    }
+
+    fn convert_unobserved(
+        &self,
+        lat: f64,
+        lon: f64,
+        warm: &mut Option<(f64, f64)>,
+    ) -> ((f64, f64), bool) {
+        use GeodeticSystem::*;
+        let ((lat, lon), converged) = match (self.from, self.to) {
+            (x, y) if x == y => return ((lat, lon), true),
+            (Wgs84, Gcj02) => (self.wgs_to_gcj(lat, lon), true),
+            (Wgs84, Bd09) => {
+                let (lat, lon) = self.wgs_to_gcj(lat, lon);
+                (self.gcj_to_bd(lat, lon), true)
+            }
+            (Gcj02, Wgs84) => self.gcj_to_wgs(lat, lon, warm),
+            (Gcj02, Bd09) => (self.gcj_to_bd(lat, lon), true),
+            (Bd09, Wgs84) if self.passes_bd(lat, lon) => return ((lat, lon), true),
+            (Bd09, Wgs84) if self.whole_chain => self.bd_to_wgs(lat, lon, warm),
+            (Bd09, Wgs84) => {
+                let (lat, lon) = match self.algorithm {
+                    Algorithm::V4RefinedBd => bd_to_gcj_refined(lat, lon, INVERT_EPS),
+                    _ => bd_to_gcj(lat, lon),
+                };
+                self.gcj_to_wgs(lat, lon, warm)
+            }
+            (Bd09, Gcj02) => (self.bd_to_gcj(lat, lon), true),
+            _ => unreachable!(),
+        };
+        (normalize(lat, lon), converged)
+    }
+
+    /// Validates the coordinate before converting it, rejecting it if any issue is found.
+    pub fn try_convert(&self, lat: f64, lon: f64) -> Result<(f64, f64), Vec<Issue>> {
+        let issues = validate(lat, lon);
+        if !issues.is_empty() {
+            return Err(issues);
+        }
+        Ok(self.convert(lat, lon))
+    }
+
+    /// Same as `try_convert`, also failing where the inversion, if any, does not converge.
+    pub fn convert_strict(&self, lat: f64, lon: f64) -> Result<(f64, f64), Error> {
+        let issues = validate(lat, lon);
+        if !issues.is_empty() {
+            return Err(Error::InvalidInput(issues));
+        }
+        match self.convert_checked(lat, lon) {
+            (converted, true) => Ok(converted),
+            (_, false) => Err(Error::NonConvergence { lat, lon }),
+        }
+    }
+
+    /// Converts a coordinate following the [`NonFinitePolicy`]: non-finite coordinates are
+    /// returned unchanged, `None`, or `Issue::NotFinite`.
+    pub fn filter_convert(&self, lat: f64, lon: f64) -> Result<Option<(f64, f64)>, Issue> {
+        match self.convert_written(lat, lon) {
+            Ok(None) if self.non_finite == NonFinitePolicy::Propagate => Ok(Some((lat, lon))),
+            result => result,
+        }
+    }
+
+    /// Converts a coordinate read from a file following the [`NonFinitePolicy`], `None` meaning
+    /// that it is kept as written.
+    pub(crate) fn convert_written(&self, lat: f64, lon: f64) -> Result<Option<(f64, f64)>, Issue> {
+        if lat.is_finite() && lon.is_finite() {
+            return Ok(Some(self.convert(lat, lon)));
+        }
+        if let Some(observer) = &self.observer {
+            observer(self, (lat, lon), None);
+        }
+        if self.non_finite == NonFinitePolicy::Error {
+            Err(Issue::NotFinite)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parses a coordinate string (see [`crate::format`]) and converts it.
+    pub fn convert_str(&self, s: &str) -> Result<(f64, f64), ParseCoordError> {
+        let (lat, lon) = parse_coordinate(s)?;
+        Ok(self.convert(lat, lon))
+    }
+
+    fn wgs_to_gcj(&self, lat: f64, lon: f64) -> (f64, f64) {
+        if !self.in_region(lat, lon) {
+            return (lat, lon);
+        }
+
+        let (lat_d, lon_d) = self.algorithm.offset(lat, lon);
+        (lat + lat_d, lon + lon_d)
+    }
+
+    /// Whether the BD-09 offset is skipped at a coordinate.
+    fn passes_bd(&self, lat: f64, lon: f64) -> bool {
+        self.bd_passthrough && !self.in_region(lat, lon)
+    }
+
+    fn gcj_to_bd(&self, lat: f64, lon: f64) -> (f64, f64) {
+        if self.passes_bd(lat, lon) {
+            (lat, lon)
+        } else {
+            gcj_to_bd(lat, lon)
+        }
+    }
+
+    fn bd_to_gcj(&self, lat: f64, lon: f64) -> (f64, f64) {
+        if self.passes_bd(lat, lon) {
+            (lat, lon)
+        } else {
+            bd_to_gcj(lat, lon)
+        }
+    }
+
+    fn gcj_to_wgs(&self, lat: f64, lon: f64, warm: &mut Option<(f64, f64)>) -> ((f64, f64), bool) {
+        let start = match *warm {
+            Some((lat_d, lon_d)) => (lat + lat_d, lon + lon_d),
+            None => (lat, lon),
+        };
+        let forward = |lat, lon| self.wgs_to_gcj(lat, lon);
+        let (wgs, converged) = self.algorithm.invert(forward, lat, lon, start);
+        *warm = if converged {
+            Some((wgs.0 - lat, wgs.1 - lon))
+        } else {
+            None
+        };
+        (wgs, converged)
+    }
+
+    /// Inverts both offsets at once, iterating on their composition from the closed-form inverse
+    /// of BD-09, see [`Converter::with_whole_chain`].
+    ///
+    /// `warm` holds the WGS-84 minus GCJ-02 difference as in `gcj_to_wgs`, the GCJ-02 point
+    /// being the closed-form one.
+    fn bd_to_wgs(&self, lat: f64, lon: f64, warm: &mut Option<(f64, f64)>) -> ((f64, f64), bool) {
+        let gcj = bd_to_gcj(lat, lon);
+        let start = match *warm {
+            Some((lat_d, lon_d)) => (gcj.0 + lat_d, gcj.1 + lon_d),
+            None => gcj,
+        };
+        let forward = |lat, lon| {
+            let (lat, lon) = self.wgs_to_gcj(lat, lon);
+            self.gcj_to_bd(lat, lon)
+        };
+        let (wgs, converged) = self.algorithm.invert(forward, lat, lon, start);
+        *warm = if converged {
+            Some((wgs.0 - gcj.0, wgs.1 - gcj.1))
+        } else {
+            None
+        };
+        (wgs, converged)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::eps_assert;
+    use GeodeticSystem::*;
+
+    #[test]
+    fn test_default_region() {
+        for &from in &GeodeticSystem::ALL {
+            for &to in &GeodeticSystem::ALL {
+                let converter = Converter::new(from, to);
+                let (lat, lon) = converter.convert(39.0, 116.0);
+                let (p, q) = from.convert_to(to, 39.0, 116.0);
+                eps_assert(lat, p, "lat");
+                eps_assert(lon, q, "lon");
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_region() {
+        // Only Beijing is obfuscated.
+        let converter = Converter::new(Wgs84, Gcj02)
+            .with_region(|lat, lon| (39.4..=41.1).contains(&lat) && (115.4..=117.5).contains(&lon));
+        assert_ne!(converter.convert(40.0, 116.4), (40.0, 116.4));
+        assert_eq!(converter.convert(31.2, 121.5), (31.2, 121.5));
+
+        let inverse = Converter::new(Gcj02, Wgs84).with_region(|_, _| false);
+        assert_eq!(inverse.convert(40.0, 116.4), (40.0, 116.4));
+    }
+
+    #[test]
+    fn test_domain_edges() {
+        let edges = [
+            (90.0, 0.0),
+            (-90.0, 116.0),
+            (89.9999, 180.0),
+            (0.0, 180.0),
+            (45.0, -180.0),
+            (-90.0, -180.0),
+        ];
+        for &algorithm in &Algorithm::ALL {
+            for &from in &GeodeticSystem::ALL {
+                for &to in &GeodeticSystem::ALL {
+                    let default = Converter::new(from, to).with_algorithm(algorithm);
+                    let everywhere = default.clone().with_region(|_, _| true);
+                    for converter in &[default, everywhere] {
+                        for &(lat, lon) in &edges {
+                            let (a, b) = converter.convert(lat, lon);
+                            assert!(
+                                a.abs() <= 90.0 && b.abs() <= 180.0,
+                                "{:?}: ({}, {}) -> ({}, {})",
+                                converter,
+                                lat,
+                                lon,
+                                a,
+                                b
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        let everywhere = Converter::new(Wgs84, Gcj02).with_region(|_, _| true);
+        assert_eq!(everywhere.convert(86.0, 116.0), (86.0, 116.0));
+        assert_ne!(everywhere.convert(84.0, 116.0), (84.0, 116.0));
+    }
+
+    #[test]
+    fn test_bd_passthrough() {
+        let paris = (48.8566, 2.3522);
+        let beijing = (39.9, 116.4);
+        for &from in &GeodeticSystem::ALL {
+            for &to in &GeodeticSystem::ALL {
+                let converter = Converter::new(from, to);
+                let passthrough = converter.clone().with_bd_passthrough(true);
+                assert!(passthrough.bd_passthrough());
+                assert_eq!(passthrough.convert(paris.0, paris.1), paris);
+                assert_eq!(
+                    passthrough.convert(beijing.0, beijing.1),
+                    converter.convert(beijing.0, beijing.1)
+                );
+            }
+        }
+        assert_ne!(Converter::new(Gcj02, Bd09).convert(paris.0, paris.1), paris);
+    }
+
+    #[test]
+    fn test_edge_damping() {
+        // GCJ-02 points just inside the edges of the region, some without a WGS-84 preimage.
+        let b = crate::CHINA_BBOX;
+        let mut edges = vec![(b.max_lat - 0.005, b.min_lon + 0.005)];
+        for i in 0..40 {
+            let t = i as f64 / 40.0;
+            edges.push((b.min_lat + (b.max_lat - b.min_lat) * t, b.min_lon + 0.005));
+            edges.push((b.max_lat - 0.005, b.min_lon + (b.max_lon - b.min_lon) * t));
+        }
+        let distance = |(lat, lon): (f64, f64)| {
+            let lat = (lat - b.min_lat).abs().min((lat - b.max_lat).abs());
+            lat.min((lon - b.min_lon).abs())
+                .min((lon - b.max_lon).abs())
+        };
+        for &algorithm in &[
+            Algorithm::V1Classic,
+            Algorithm::V2Newton,
+            Algorithm::V3SharedTrig,
+            Algorithm::FastApprox,
+        ] {
+            let converter = Converter::new(Gcj02, Wgs84).with_algorithm(algorithm);
+            let mut damped = 0;
+            for &(lat, lon) in &edges {
+                let (wgs, converged) = converter.convert_checked(lat, lon);
+                if !converged {
+                    // Settled next to the edge rather than left on either side of it.
+                    damped += 1;
+                    assert!(distance(wgs) < 0.002, "{:?} {:?}", algorithm, wgs);
+                }
+            }
+            assert!(damped > 0, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_default_matches_free_functions() {
+        let b = crate::CHINA_BBOX;
+        for &from in &GeodeticSystem::ALL {
+            for &to in &GeodeticSystem::ALL {
+                let converter = Converter::new(from, to);
+                for i in 0..50 {
+                    for j in 0..50 {
+                        let lat = b.min_lat + 0.1 + (b.max_lat - b.min_lat - 0.2) * i as f64 / 49.0;
+                        let lon = b.min_lon + 0.1 + (b.max_lon - b.min_lon - 0.2) * j as f64 / 49.0;
+                        let (converted, converged) = converter.convert_checked(lat, lon);
+                        let free = from.convert_to(to, lat, lon);
+                        let difference = (converted.0 - free.0)
+                            .abs()
+                            .max((converted.1 - free.1).abs());
+                        assert!(
+                            !converged || difference < 2.0 * INVERT_EPS,
+                            "{:?} to {:?} at {:?}: {}",
+                            from,
+                            to,
+                            (lat, lon),
+                            difference
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_whole_chain() {
+        let forward = Converter::new(Wgs84, Bd09);
+        let mut worst_chained: f64 = 0.0;
+        for &algorithm in &Algorithm::ALL {
+            let chained = Converter::new(Bd09, Wgs84).with_algorithm(algorithm);
+            let whole = chained.clone().with_whole_chain(true);
+            assert!(whole.whole_chain() && !chained.whole_chain());
+            for i in 0..100 {
+                let bd = forward.convert(20.0 + i as f64 * 0.3, 80.0 + i as f64 * 0.5);
+                let ((lat, lon), converged) = whole.convert_checked(bd.0, bd.1);
+                assert!(converged);
+                let (x, y) = forward.convert(lat, lon);
+                let error = (x - bd.0).abs().max((y - bd.1).abs());
+                assert!(
+                    error < algorithm.tolerance().max(1e-12),
+                    "{:?} {}",
+                    algorithm,
+                    error
+                );
+
+                let (lat, lon) = chained.convert(bd.0, bd.1);
+                let (x, y) = forward.convert(lat, lon);
+                worst_chained = worst_chained.max((x - bd.0).abs().max((y - bd.1).abs()));
+            }
+        }
+        assert!(worst_chained > 1e-6, "{}", worst_chained);
+
+        // Outside of the region, only the BD-09 offset is inverted.
+        let whole = Converter::new(Bd09, Wgs84).with_whole_chain(true);
+        let paris = forward.convert(48.8566, 2.3522);
+        let (lat, lon) = whole.convert(paris.0, paris.1);
+        assert!((lat - 48.8566).abs() < 1e-7 && (lon - 2.3522).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_try_convert() {
+        let converter = Converter::new(Wgs84, Gcj02);
+        assert_eq!(
+            converter.try_convert(39.0, 116.0),
+            Ok(converter.convert(39.0, 116.0))
+        );
+        assert_eq!(
+            converter.try_convert(0.0, 0.0),
+            Err(vec![Issue::NullIsland])
+        );
+    }
+
+    #[test]
+    fn test_convert_strict() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        assert_eq!(
+            converter.convert_strict(39.0, 116.0).unwrap(),
+            converter.convert(39.0, 116.0)
+        );
+        assert!(matches!(
+            converter.convert_strict(f64::NAN, 116.0),
+            Err(Error::InvalidInput(ref issues)) if issues == &[Issue::NotFinite]
+        ));
+        // Along the northern edge of the region, some points have no preimage.
+        let b = crate::CHINA_BBOX;
+        let failed = (0..40)
+            .map(|i| b.min_lon + (b.max_lon - b.min_lon) * i as f64 / 40.0)
+            .filter_map(|lon| converter.convert_strict(b.max_lat - 0.005, lon).err())
+            .collect::<Vec<_>>();
+        assert!(!failed.is_empty());
+        for e in failed {
+            assert!(matches!(e, Error::NonConvergence { lat, .. } if lat == b.max_lat - 0.005));
+        }
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        assert_eq!(converter.non_finite(), NonFinitePolicy::Error);
+        assert_eq!(
+            converter.filter_convert(39.0, 116.0),
+            Ok(Some(converter.convert(39.0, 116.0)))
+        );
+        assert_eq!(
+            converter.filter_convert(f64::NAN, 116.0),
+            Err(Issue::NotFinite)
+        );
+        let skip = converter.clone().with_non_finite(NonFinitePolicy::Skip);
+        assert_eq!(skip.filter_convert(39.0, f64::INFINITY), Ok(None));
+        let propagate = converter.with_non_finite(NonFinitePolicy::Propagate);
+        assert_eq!(
+            propagate.filter_convert(39.0, f64::INFINITY),
+            Ok(Some((39.0, f64::INFINITY)))
+        );
+    }
+
+    #[test]
+    fn test_convert_str() {
+        let converter = Converter::new(Wgs84, Gcj02);
+        assert_eq!(
+            converter.convert_str("39°N 116°E"),
+            Ok(converter.convert(39.0, 116.0))
+        );
+        assert!(converter.convert_str("nowhere").is_err());
+    }
+
+    #[test]
+    fn test_algorithms() {
+        let default = Converter::new(Gcj02, Wgs84);
+        let (lat, lon) = default.convert(39.0, 116.0);
+        let (p, q) = crate::gcj_to_wgs(39.0, 116.0);
+        eps_assert(lat, p, "lat");
+        eps_assert(lon, q, "lon");
+
+        for &algorithm in &[Algorithm::V2Newton, Algorithm::Exact] {
+            let converter = Converter::new(Gcj02, Wgs84).with_algorithm(algorithm);
+            let (lat, lon) = converter.convert(39.0, 116.0);
+            let (p, q) = crate::wgs_to_gcj(lat, lon);
+            assert!((p - 39.0).abs() < 1e-9, "{:?}: lat = {}", algorithm, p);
+            assert!((q - 116.0).abs() < 1e-9, "{:?}: lon = {}", algorithm, q);
+        }
+    }
+
+    #[test]
+    fn test_frozen_v1() {
+        // Bitwise results of version 0.3.1.
+        let v1 = Converter::new(Wgs84, Gcj02).with_algorithm(Algorithm::V1Classic);
+        let (lat, lon) = v1.convert(39.0, 116.0);
+        assert_eq!((lat, lon), (39.000885589439214, 116.00601802563622));
+        let inverse = Converter::new(Gcj02, Wgs84).with_algorithm(Algorithm::V1Classic);
+        assert_eq!(
+            inverse.convert(39.0, 116.0),
+            (38.99913321947961, 115.99400246342097)
+        );
+
+        let v3 = Converter::new(Wgs84, Gcj02).convert(39.0, 116.0);
+        assert!((lat - v3.0).abs() < 1e-15 && (lon - v3.1).abs() < 1e-15);
+    }
+
+    #[test]
+    fn test_fast_approx() {
+        let exact = Converter::new(Wgs84, Gcj02);
+        let fast = Converter::new(Wgs84, Gcj02).with_algorithm(Algorithm::FastApprox);
+        let mut max = 0.0f64;
+        for i in 0..=50 {
+            for j in 0..=50 {
+                let lat = 18.0 + 36.0 * i as f64 / 50.0;
+                let lon = 73.0 + 62.0 * j as f64 / 50.0;
+                let error = crate::haversine(exact.convert(lat, lon), fast.convert(lat, lon));
+                max = max.max(error);
+            }
+        }
+        assert!(max < 0.01, "max error = {} m", max);
+    }
+
+    #[test]
+    fn test_convert_3d() {
+        let converter = Converter::new(Bd09, Wgs84);
+        let (lat, lon) = converter.convert(39.9, 116.4);
+        assert_eq!(converter.convert_3d(39.9, 116.4, 52.5), (lat, lon, 52.5));
+        let (lat, lon) = Bd09.convert_to(Wgs84, 39.9, 116.4);
+        assert_eq!(
+            Bd09.convert_to_3d(Wgs84, 39.9, 116.4, -3.0),
+            (lat, lon, -3.0)
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive() {
+        #[derive(crate::ConvertCoords)]
+        struct Stop {
+            name: &'static str,
+            #[coords(lat)]
+            latitude: f64,
+            #[coords(lon)]
+            longitude: f64,
+        }
+
+        #[derive(crate::ConvertCoords)]
+        pub struct Route {
+            #[coords(position)]
+            pub(crate) start: (f64, f64),
+            #[coords(nested)]
+            stops: Vec<Stop>,
+            extra: std::collections::HashMap<String, fn(f64) -> f64>,
+        }
+
+        let mut route = Route {
+            start: (39.90, 116.40),
+            stops: vec![Stop {
+                name: "Gate",
+                latitude: 39.91,
+                longitude: 116.41,
+            }],
+            extra: Default::default(),
+        };
+        route.convert_coords(Gcj02, Wgs84);
+        let converter = Converter::new(Gcj02, Wgs84);
+        assert_eq!(route.start, converter.convert(39.90, 116.40));
+        let stop = &route.stops[0];
+        assert_eq!(
+            (stop.latitude, stop.longitude),
+            converter.convert(39.91, 116.41)
+        );
+        assert_eq!((stop.name, route.extra.len()), ("Gate", 0));
+    }
+
+    #[test]
+    fn test_convert_array() {
+        let converter = Converter::new(Wgs84, Bd09);
+        let a = converter.convert(39.9, 116.4);
+        let b = converter.convert(31.2, 121.5);
+        assert_eq!(
+            converter.convert_array([(39.9, 116.4), (31.2, 121.5)]),
+            [a, b]
+        );
+        assert_eq!(
+            converter.convert_flat_array([39.9, 116.4, 31.2, 121.5]),
+            [a.0, a.1, b.0, b.1]
+        );
+        assert_eq!(converter.convert_flat_array([]), []);
+    }
+
+    #[test]
+    fn test_convert_float() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let (lat, lon) = converter.convert(f64::from(39.9f32), f64::from(116.4f32));
+        assert_eq!(
+            converter.convert_float(39.9f32, 116.4f32),
+            (lat as f32, lon as f32)
+        );
+        assert_eq!(
+            converter.convert_float(39.9, 116.4),
+            converter.convert(39.9, 116.4)
+        );
+    }
+}
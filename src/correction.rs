@@ -0,0 +1,210 @@
+//! Local corrections fitted on top of the standard transforms.
+//!
+//! Some providers apply a slightly different obfuscation than the standard one. Given pairs of
+//! observed points and their known true location, a small polynomial model of the remaining
+//! residual is fitted by least squares and applied after the standard conversion.
+use crate::{haversine, GeodeticSystem};
+
+/// An observed point and its known true location.
+pub type PointPair = ((f64, f64), (f64, f64));
+
+/// Shape of the residual model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CorrectionModel {
+    /// `c0 + c1 * lat + c2 * lon`, requiring at least 3 non-collinear pairs.
+    Affine,
+    /// Adds the `lat^2`, `lat * lon` and `lon^2` terms, requiring at least 6 pairs.
+    Quadratic,
+}
+
+impl CorrectionModel {
+    fn terms(self) -> usize {
+        match self {
+            CorrectionModel::Affine => 3,
+            CorrectionModel::Quadratic => 6,
+        }
+    }
+}
+
+/// A fitted correction for one conversion direction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalCorrection {
+    pub from: GeodeticSystem,
+    pub to: GeodeticSystem,
+    pub model: CorrectionModel,
+    /// Origin of the local frame the polynomials are evaluated in.
+    pub origin: (f64, f64),
+    pub lat_coeffs: Vec<f64>,
+    pub lon_coeffs: Vec<f64>,
+    /// Root mean square error of the fitted pairs after correction, in meters.
+    pub rms_meters: f64,
+}
+
+impl LocalCorrection {
+    /// Fits the correction from `(observed, truth)` pairs.
+    ///
+    /// Observed points are in `from`, truths in `to`. Returns `None` if there are too few pairs
+    /// or they are degenerate (e.g. all on one line).
+    pub fn fit(
+        from: GeodeticSystem,
+        to: GeodeticSystem,
+        model: CorrectionModel,
+        pairs: &[PointPair],
+    ) -> Option<Self> {
+        let n = model.terms();
+        if pairs.len() < n {
+            return None;
+        }
+
+        let count = pairs.len() as f64;
+        let origin = pairs.iter().fold((0.0, 0.0), |acc, (observed, _)| {
+            (acc.0 + observed.0 / count, acc.1 + observed.1 / count)
+        });
+
+        // Normal equations, with the right-hand sides of both components side by side.
+        let mut ata = vec![vec![0.0; n]; n];
+        let mut atb = vec![[0.0; 2]; n];
+        for &(observed, truth) in pairs {
+            let converted = from.convert_to(to, observed.0, observed.1);
+            let residual = (truth.0 - converted.0, truth.1 - converted.1);
+            let basis = basis(model, origin, observed);
+            for i in 0..n {
+                for j in 0..n {
+                    ata[i][j] += basis[i] * basis[j];
+                }
+                atb[i][0] += basis[i] * residual.0;
+                atb[i][1] += basis[i] * residual.1;
+            }
+        }
+
+        let solution = solve(ata, atb)?;
+        let mut correction = LocalCorrection {
+            from,
+            to,
+            model,
+            origin,
+            lat_coeffs: solution.iter().map(|x| x[0]).collect(),
+            lon_coeffs: solution.iter().map(|x| x[1]).collect(),
+            rms_meters: 0.0,
+        };
+
+        let squares: f64 = pairs
+            .iter()
+            .map(|&((lat, lon), truth)| haversine(correction.apply(lat, lon), truth).powi(2))
+            .sum();
+        correction.rms_meters = (squares / count).sqrt();
+        Some(correction)
+    }
+
+    /// Converts a point with the standard transform, then applies the correction.
+    pub fn apply(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let (out_lat, out_lon) = self.from.convert_to(self.to, lat, lon);
+        let basis = basis(self.model, self.origin, (lat, lon));
+        let dot = |coeffs: &[f64]| -> f64 { coeffs.iter().zip(&basis).map(|(c, b)| c * b).sum() };
+        (
+            out_lat + dot(&self.lat_coeffs),
+            out_lon + dot(&self.lon_coeffs),
+        )
+    }
+}
+
+fn basis(model: CorrectionModel, origin: (f64, f64), (lat, lon): (f64, f64)) -> Vec<f64> {
+    let (y, x) = (lat - origin.0, lon - origin.1);
+    match model {
+        CorrectionModel::Affine => vec![1.0, y, x],
+        CorrectionModel::Quadratic => vec![1.0, y, x, y * y, y * x, x * x],
+    }
+}
+
+/// Solves the linear system by Gaussian elimination with partial pivoting.
+pub(crate) fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<[f64; 2]>) -> Option<Vec<[f64; 2]>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            let (upper, lower) = a.split_at_mut(row);
+            for (dst, src) in lower[0][col..].iter_mut().zip(&upper[col][col..]) {
+                *dst -= factor * src;
+            }
+            b[row][0] -= factor * b[col][0];
+            b[row][1] -= factor * b[col][1];
+        }
+    }
+
+    let mut x = vec![[0.0; 2]; n];
+    for row in (0..n).rev() {
+        for c in 0..2 {
+            let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k][c]).sum();
+            x[row][c] = (b[row][c] - sum) / a[row][row];
+        }
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use GeodeticSystem::*;
+
+    fn shifted_pairs(shift: impl Fn(f64, f64) -> (f64, f64)) -> Vec<PointPair> {
+        let mut pairs = Vec::new();
+        for i in 0..4 {
+            for j in 0..4 {
+                let wgs = (39.8 + i as f64 * 0.05, 116.3 + j as f64 * 0.05);
+                let (lat, lon) = Wgs84.convert_to(Gcj02, wgs.0, wgs.1);
+                let (d_lat, d_lon) = shift(lat, lon);
+                pairs.push(((lat + d_lat, lon + d_lon), wgs));
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn test_affine() {
+        let shift = |lat: f64, _| (0.0002, 0.0001 * (lat - 39.8));
+        let pairs = shifted_pairs(shift);
+        let correction =
+            LocalCorrection::fit(Gcj02, Wgs84, CorrectionModel::Affine, &pairs).unwrap();
+        assert!(
+            correction.rms_meters < 0.05,
+            "rms = {}",
+            correction.rms_meters
+        );
+
+        let truth = (39.93, 116.42);
+        let (lat, lon) = Wgs84.convert_to(Gcj02, truth.0, truth.1);
+        let (d_lat, d_lon) = shift(lat, lon);
+        let uncorrected = Gcj02.convert_to(Wgs84, lat + d_lat, lon + d_lon);
+        let corrected = correction.apply(lat + d_lat, lon + d_lon);
+        assert!(haversine(uncorrected, truth) > 20.0);
+        assert!(haversine(corrected, truth) < 0.05);
+    }
+
+    #[test]
+    fn test_quadratic() {
+        let pairs = shifted_pairs(|_, lon| (0.001 * (lon - 116.3).powi(2), 0.0));
+        let correction =
+            LocalCorrection::fit(Gcj02, Wgs84, CorrectionModel::Quadratic, &pairs).unwrap();
+        assert!(
+            correction.rms_meters < 0.05,
+            "rms = {}",
+            correction.rms_meters
+        );
+    }
+
+    #[test]
+    fn test_degenerate() {
+        let pairs: Vec<_> = (0..5)
+            .map(|i| ((39.0 + i as f64 * 0.1, 116.0), (39.0, 116.0)))
+            .collect();
+        assert!(LocalCorrection::fit(Gcj02, Wgs84, CorrectionModel::Affine, &pairs).is_none());
+        assert!(LocalCorrection::fit(Gcj02, Wgs84, CorrectionModel::Affine, &pairs[..2]).is_none());
+    }
+}
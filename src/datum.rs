@@ -0,0 +1,234 @@
+//! Helmert transformations between the legacy Chinese datums and WGS-84.
+//!
+//! Older datasets are on Beijing-54 or Xian-80, whose ellipsoids are placed differently from
+//! the geocentric WGS-84 and CGCS2000, by up to a hundred meters. A point is moved between them
+//! through its geocentric coordinates, with the seven parameters of a [`Helmert`]
+//! transformation. The parameters depend on the region and are published by the local
+//! surveying authorities: there is no nationwide set for Xian-80, and
+//! [`Helmert::BEIJING54_TO_WGS84`] is only a rough nationwide approximation for
+//! Beijing-54.
+//!
+//! Survey coordinates are reduced to web map ones by chaining the transformations:
+//!
+//! ```
+//! use undrift_gps::datum::{DatumTransform, Helmert};
+//! use undrift_gps::gauss_kruger::{Ellipsoid, GaussKruger, ZoneWidth};
+//! use undrift_gps::transform::Transform;
+//! use undrift_gps::{Converter, GeodeticSystem::*};
+//!
+//! let zone = GaussKruger::zone(Ellipsoid::Beijing54, ZoneWidth::Six, 20).with_zone_prefix();
+//! let to_gcj = zone
+//!     .inverse()
+//!     .then(DatumTransform::to_wgs84(Ellipsoid::Beijing54, Helmert::BEIJING54_TO_WGS84))
+//!     .then(Converter::new(Wgs84, Gcj02));
+//! let (lat, lon) = to_gcj.apply(20_445_000.0, 4_419_000.0);
+//! ```
+pub use crate::gauss_kruger::Ellipsoid;
+use crate::transform::Transform;
+
+/// Converts a latitude, longitude and ellipsoidal height to geocentric coordinates in meters.
+pub fn to_geocentric(ellipsoid: Ellipsoid, lat: f64, lon: f64, height: f64) -> [f64; 3] {
+    let (a, f) = ellipsoid.parameters();
+    let e2 = f * (2.0 - f);
+    let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+    let (sin_lon, cos_lon) = lon.to_radians().sin_cos();
+    let n = a / (1.0 - e2 * sin_lat * sin_lat).sqrt();
+    [
+        (n + height) * cos_lat * cos_lon,
+        (n + height) * cos_lat * sin_lon,
+        (n * (1.0 - e2) + height) * sin_lat,
+    ]
+}
+
+/// Inverse of [`to_geocentric`], with the formula of Bowring, exact to the millimeter near the
+/// Earth.
+pub fn from_geocentric(ellipsoid: Ellipsoid, [x, y, z]: [f64; 3]) -> (f64, f64, f64) {
+    let (a, f) = ellipsoid.parameters();
+    let b = a * (1.0 - f);
+    let e2 = f * (2.0 - f);
+    let ep2 = e2 / (1.0 - e2);
+    let p = x.hypot(y);
+    let (sin_theta, cos_theta) = (z * a).atan2(p * b).sin_cos();
+    let lat = (z + ep2 * b * sin_theta.powi(3)).atan2(p - e2 * a * cos_theta.powi(3));
+    let (sin_lat, cos_lat) = lat.sin_cos();
+    let n = a / (1.0 - e2 * sin_lat * sin_lat).sqrt();
+    let height = p * cos_lat + z * sin_lat - a * a / n;
+    (lat.to_degrees(), y.atan2(x).to_degrees(), height)
+}
+
+/// The seven parameters of a Helmert transformation of geocentric coordinates.
+///
+/// Rotations follow the position vector convention, as the `towgs84` parameters of PROJ; the
+/// coordinate frame convention of some publications has them with the opposite signs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Helmert {
+    /// Translation in meters.
+    pub translation: [f64; 3],
+    /// Rotations about the axes in arc seconds.
+    pub rotation: [f64; 3],
+    /// Scale difference in parts per million.
+    pub scale: f64,
+}
+
+impl Helmert {
+    /// The transformation that does nothing.
+    pub const IDENTITY: Helmert = Helmert::translation(0.0, 0.0, 0.0);
+
+    /// The three-parameter shift of the EPSG dataset from Beijing-54 to WGS-84.
+    pub const BEIJING54_TO_WGS84: Helmert = Helmert::translation(15.8, -154.4, -82.3);
+
+    /// A transformation that only translates.
+    pub const fn translation(dx: f64, dy: f64, dz: f64) -> Self {
+        Helmert {
+            translation: [dx, dy, dz],
+            rotation: [0.0; 3],
+            scale: 0.0,
+        }
+    }
+
+    /// Transforms geocentric coordinates.
+    pub fn apply_geocentric(&self, [x, y, z]: [f64; 3]) -> [f64; 3] {
+        let [rx, ry, rz] = self.rotation.map(|r| (r / 3600.0).to_radians());
+        let [dx, dy, dz] = self.translation;
+        let m = 1.0 + self.scale * 1e-6;
+        [
+            dx + m * (x - rz * y + ry * z),
+            dy + m * (rz * x + y - rx * z),
+            dz + m * (-ry * x + rx * y + z),
+        ]
+    }
+
+    /// The transformation back, with all parameters negated, which is right to a fraction of a
+    /// millimeter for the small rotations and scales of datums.
+    pub fn inverse(&self) -> Helmert {
+        Helmert {
+            translation: self.translation.map(|t| -t),
+            rotation: self.rotation.map(|r| -r),
+            scale: -self.scale,
+        }
+    }
+}
+
+/// A transformation of `(lat, lon)` from a datum to another, on the ellipsoid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DatumTransform {
+    pub from: Ellipsoid,
+    pub to: Ellipsoid,
+    pub helmert: Helmert,
+}
+
+impl DatumTransform {
+    /// The transformation of a datum to WGS-84, or CGCS2000 within centimeters.
+    pub fn to_wgs84(datum: Ellipsoid, helmert: Helmert) -> Self {
+        DatumTransform {
+            from: datum,
+            to: Ellipsoid::Cgcs2000,
+            helmert,
+        }
+    }
+
+    /// The transformation of WGS-84 to a datum, where `helmert` goes from the datum to WGS-84.
+    pub fn from_wgs84(datum: Ellipsoid, helmert: Helmert) -> Self {
+        DatumTransform::to_wgs84(datum, helmert).inverse()
+    }
+
+    /// The transformation back.
+    pub fn inverse(&self) -> Self {
+        DatumTransform {
+            from: self.to,
+            to: self.from,
+            helmert: self.helmert.inverse(),
+        }
+    }
+
+    /// Transforms a latitude, longitude and ellipsoidal height.
+    pub fn transform(&self, lat: f64, lon: f64, height: f64) -> (f64, f64, f64) {
+        let xyz = to_geocentric(self.from, lat, lon, height);
+        from_geocentric(self.to, self.helmert.apply_geocentric(xyz))
+    }
+}
+
+impl Transform for DatumTransform {
+    /// Transforms a latitude and longitude on the ellipsoid: heights of a few hundred meters
+    /// change the result by less than a millimeter.
+    fn apply(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let (lat, lon, _) = self.transform(lat, lon, 0.0);
+        (lat, lon)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_geocentric() {
+        let [x, y, z] = to_geocentric(Ellipsoid::Cgcs2000, 0.0, 0.0, 0.0);
+        assert!((x - 6_378_137.0).abs() < 1e-6 && y.abs() < 1e-6 && z.abs() < 1e-6);
+        let [x, _, z] = to_geocentric(Ellipsoid::Cgcs2000, 90.0, 0.0, 0.0);
+        assert!(x.abs() < 1e-6 && (z - 6_356_752.314).abs() < 1e-3);
+        for &ellipsoid in &[Ellipsoid::Cgcs2000, Ellipsoid::Beijing54, Ellipsoid::Xian80] {
+            for &(lat, lon, height) in &[
+                (39.9, 116.4, 50.0),
+                (-33.9, -70.6, 4000.0),
+                (90.0, 0.0, 0.0),
+            ] {
+                let xyz = to_geocentric(ellipsoid, lat, lon, height);
+                let (lat2, lon2, height2) = from_geocentric(ellipsoid, xyz);
+                assert!((lat2 - lat).abs() < 1e-9 && (height2 - height).abs() < 1e-3);
+                assert!(lat == 90.0 || (lon2 - lon).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_helmert() {
+        let xyz = [-2_178_000.0, 4_385_000.0, 4_070_000.0];
+        assert_eq!(Helmert::IDENTITY.apply_geocentric(xyz), xyz);
+        let [x, y, z] = Helmert::BEIJING54_TO_WGS84.apply_geocentric(xyz);
+        assert!((x - xyz[0] - 15.8).abs() < 1e-6 && (y - xyz[1] + 154.4).abs() < 1e-6);
+        assert!((z - xyz[2] + 82.3).abs() < 1e-6);
+
+        // A rotation of one second about the axis of the poles moves the equator eastward.
+        let rotation = Helmert {
+            rotation: [0.0, 0.0, 1.0],
+            ..Helmert::IDENTITY
+        };
+        let [_, y, _] = rotation.apply_geocentric([6_378_137.0, 0.0, 0.0]);
+        assert!((y - 30.92).abs() < 0.01);
+
+        let helmert = Helmert {
+            translation: [24.0, -123.0, -94.0],
+            rotation: [-0.02, 0.25, 0.13],
+            scale: 1.1,
+        };
+        let back = helmert
+            .inverse()
+            .apply_geocentric(helmert.apply_geocentric(xyz));
+        for i in 0..3 {
+            assert!((back[i] - xyz[i]).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_datum_transform() {
+        let transform = DatumTransform::to_wgs84(Ellipsoid::Beijing54, Helmert::BEIJING54_TO_WGS84);
+        let (lat, lon) = transform.apply(39.9, 116.4);
+        let local = to_geocentric(Ellipsoid::Cgcs2000, 39.9, 116.4, 0.0);
+        let moved = to_geocentric(Ellipsoid::Cgcs2000, lat, lon, 0.0);
+        let distance = (0..3)
+            .map(|i| (moved[i] - local[i]).powi(2))
+            .sum::<f64>()
+            .sqrt();
+        assert!(distance > 10.0 && distance < 200.0, "{}", distance);
+
+        let (lat2, lon2) =
+            DatumTransform::from_wgs84(Ellipsoid::Beijing54, Helmert::BEIJING54_TO_WGS84)
+                .apply(lat, lon);
+        assert!((lat2 - 39.9).abs() < 1e-8 && (lon2 - 116.4).abs() < 1e-8);
+
+        let same = DatumTransform::to_wgs84(Ellipsoid::Cgcs2000, Helmert::IDENTITY);
+        let (lat, lon) = same.apply(39.9, 116.4);
+        assert!((lat - 39.9).abs() < 1e-12 && (lon - 116.4).abs() < 1e-12);
+    }
+}
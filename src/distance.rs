@@ -0,0 +1,834 @@
+//! Distances, bearings and destinations between coordinates of any system.
+//!
+//! Obfuscated coordinates are converted to WGS-84 before measuring, whether or not both points
+//! are in the same system: the GCJ-02 offsets vary along the way, so that distances measured
+//! between GCJ-02 coordinates are off by up to half a percent.
+//!
+//! ```
+//! use undrift_gps::distance::{distance_meters, Method};
+//! use undrift_gps::point::Coordinate;
+//! use undrift_gps::GeodeticSystem::*;
+//!
+//! let gcj = Coordinate::from_degrees(Gcj02, 39.9087, 116.3975).unwrap();
+//! let bd = Coordinate::from_degrees(Bd09, 39.9219, 116.4109).unwrap();
+//! let meters = distance_meters(gcj, bd, Method::Geodesic);
+//! ```
+use crate::point::Coordinate;
+use crate::GeodeticSystem;
+
+/// How distances, bearings and destinations are computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Method {
+    /// Great circle on the sphere of mean radius, within half a percent of the geodesic.
+    #[default]
+    Haversine,
+    /// Shortest path on the WGS-84 ellipsoid, with the method of Karney, exact far below the
+    /// millimeter.
+    Geodesic,
+}
+
+/// Distance in meters between two coordinates, converted to WGS-84 first.
+pub fn distance_meters(a: impl Into<Coordinate>, b: impl Into<Coordinate>, method: Method) -> f64 {
+    let wgs = |c: Coordinate| {
+        let c = c.convert_to(GeodeticSystem::Wgs84);
+        (c.lat.degrees(), c.lon.degrees())
+    };
+    let (a, b) = (wgs(a.into()), wgs(b.into()));
+    match method {
+        Method::Haversine => haversine(a, b),
+        Method::Geodesic => geodesic(a, b),
+    }
+}
+
+/// Great-circle distance in meters between two WGS-84 coordinates.
+pub fn haversine(a: (f64, f64), b: (f64, f64)) -> f64 {
+    crate::haversine(a, b)
+}
+
+/// The point at `fraction` of the great circle from `a` to `b`, both `(lat, lon)` in degrees.
+///
+/// Unlike the interpolation of degrees, the path crosses the antimeridian the short way, and
+/// bends toward the pole as straight lines of the Earth do. Points of any one system are
+/// interpolated as they are, before their conversion. The path between antipodes is undefined.
+pub fn interpolate(a: (f64, f64), b: (f64, f64), fraction: f64) -> (f64, f64) {
+    let (u, v) = (unit_vector(a), unit_vector(b));
+    let cross = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let dot = u[0] * v[0] + u[1] * v[1] + u[2] * v[2];
+    let angle = (cross[0].hypot(cross[1]).hypot(cross[2])).atan2(dot);
+    if angle == 0.0 {
+        return a;
+    }
+    let wa = ((1.0 - fraction) * angle).sin() / angle.sin();
+    let wb = (fraction * angle).sin() / angle.sin();
+    let [x, y, z] = [0, 1, 2].map(|i| wa * u[i] + wb * v[i]);
+    (z.atan2(x.hypot(y)).to_degrees(), y.atan2(x).to_degrees())
+}
+
+/// Most segments [`sample_every_meters`] splits a path into.
+pub const MAX_SAMPLE_SEGMENTS: usize = 1 << 16;
+
+/// Points from `a` to `b`, both included, spaced evenly on the great circle and no more than
+/// `step` meters apart, by [`haversine`], to fill the gaps of a track.
+///
+/// The path is split into [`MAX_SAMPLE_SEGMENTS`] at most, so that points are further apart
+/// than a step too small for the path. Points with a coordinate that is not finite are returned
+/// as the two ends. Returns `None` if `step` is not positive.
+pub fn sample_every_meters(a: (f64, f64), b: (f64, f64), step: f64) -> Option<Vec<(f64, f64)>> {
+    if step.is_nan() || step <= 0.0 {
+        return None;
+    }
+    let segments = if [a.0, a.1, b.0, b.1].iter().all(|x| x.is_finite()) {
+        let segments = (haversine(a, b) / step).ceil();
+        (segments.min(MAX_SAMPLE_SEGMENTS as f64) as usize).max(1)
+    } else {
+        1
+    };
+    let mut points = Vec::with_capacity(segments + 1);
+    points.push(a);
+    points.extend((1..segments).map(|i| interpolate(a, b, i as f64 / segments as f64)));
+    points.push(b);
+    Some(points)
+}
+
+fn unit_vector((lat, lon): (f64, f64)) -> [f64; 3] {
+    let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+    let (sin_lon, cos_lon) = lon.to_radians().sin_cos();
+    [cos_lat * cos_lon, cos_lat * sin_lon, sin_lat]
+}
+
+const A: f64 = 6_378_137.0;
+const F: f64 = 1.0 / 298.257_223_563;
+const TINY: f64 = f64::MIN_POSITIVE;
+
+/// Geodesic distance in meters between two WGS-84 coordinates.
+///
+/// The inverse problem is solved as by Karney (2013): Newton's method on the azimuth at the
+/// first point, falling back to bisection where it fails, as next to antipodal points. Series
+/// are truncated to the sixth order in the third flattening.
+pub fn geodesic(a: (f64, f64), b: (f64, f64)) -> f64 {
+    inverse(a, b).0
+}
+
+/// Initial bearing in degrees, clockwise from the north within [0, 360), of the path from `a`
+/// to `b`, converted to WGS-84 first.
+///
+/// The bearing of a point to itself, or to its antipode with [`Method::Haversine`], is
+/// meaningless; it is then north.
+pub fn initial_bearing(a: impl Into<Coordinate>, b: impl Into<Coordinate>, method: Method) -> f64 {
+    let (a, b) = (wgs(a.into()), wgs(b.into()));
+    let bearing = match method {
+        Method::Haversine => sphere_bearing(a, b),
+        Method::Geodesic => {
+            let (_, salp1, calp1) = inverse(a, b);
+            salp1.atan2(calp1).to_degrees()
+        }
+    };
+    // Keeps -0 and rounding just below a full turn out of the range.
+    let bearing = bearing.rem_euclid(360.0);
+    if bearing < 360.0 {
+        bearing + 0.0
+    } else {
+        0.0
+    }
+}
+
+/// The point reached from `point` after `distance` meters toward `bearing`, in degrees
+/// clockwise from the north, in the system of `point`.
+///
+/// The path is computed in WGS-84, the inverse of [`initial_bearing`] and [`distance_meters`]
+/// with the same method. Paths through a pole come back on the other side of it.
+pub fn destination(
+    point: impl Into<Coordinate>,
+    bearing: f64,
+    distance: f64,
+    method: Method,
+) -> Coordinate {
+    let point = point.into();
+    let start = wgs(point);
+    let (lat, lon) = match method {
+        Method::Haversine => sphere_destination(start, bearing, distance),
+        Method::Geodesic => direct(start, bearing, distance),
+    };
+    let (lat, lon) = crate::normalize(lat, lon);
+    Coordinate::from_degrees(GeodeticSystem::Wgs84, lat, lon)
+        .expect("destination out of range")
+        .convert_to(point.system)
+}
+
+fn wgs(c: Coordinate) -> (f64, f64) {
+    let c = c.convert_to(GeodeticSystem::Wgs84);
+    (c.lat.degrees(), c.lon.degrees())
+}
+
+fn sphere_bearing((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (sin1, cos1) = lat1.to_radians().sin_cos();
+    let (sin2, cos2) = lat2.to_radians().sin_cos();
+    let (sin12, cos12) = (lon2 - lon1).to_radians().sin_cos();
+    (sin12 * cos2)
+        .atan2(cos1 * sin2 - sin1 * cos2 * cos12)
+        .to_degrees()
+}
+
+fn sphere_destination((lat, lon): (f64, f64), bearing: f64, distance: f64) -> (f64, f64) {
+    let (sin1, cos1) = lat.to_radians().sin_cos();
+    let (sin_bearing, cos_bearing) = bearing.to_radians().sin_cos();
+    let (sin_d, cos_d) = (distance / crate::EARTH_RADIUS).sin_cos();
+    let sin2 = (sin1 * cos_d + cos1 * sin_d * cos_bearing).clamp(-1.0, 1.0);
+    let lon12 = (sin_bearing * sin_d * cos1).atan2(cos_d - sin1 * sin2);
+    (sin2.asin().to_degrees(), lon + lon12.to_degrees())
+}
+
+/// Distance, and sine and cosine of the azimuth at the first point, of the geodesic between
+/// two points.
+fn inverse((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> (f64, f64, f64) {
+    let g = Geodesic::new();
+    let lon12 = (lon2 - lon1).rem_euclid(360.0);
+    let mut lon_sign = if lon12 <= 180.0 { 1.0 } else { -1.0 };
+    let lon12 = lon12.min(360.0 - lon12);
+
+    // Put the first point on the southern hemisphere, farther from the equator than the second,
+    // and the second eastward. The azimuths are brought back to the points as given at the end.
+    let (mut lat1, mut lat2) = (lat1, lat2);
+    let swap_sign = if lat1.abs() < lat2.abs() { -1.0 } else { 1.0 };
+    if swap_sign < 0.0 {
+        lon_sign = -lon_sign;
+        std::mem::swap(&mut lat1, &mut lat2);
+    }
+    let lat_sign = if lat1 > 0.0 { -1.0 } else { 1.0 };
+    lat1 *= lat_sign;
+    lat2 *= lat_sign;
+
+    let (sbet1, cbet1) = g.reduced(lat1);
+    let (mut sbet2, mut cbet2) = g.reduced(lat2);
+    // Make the reduced latitudes of points on a parallel exactly equal.
+    if cbet1 < -sbet1 {
+        if cbet2 == cbet1 {
+            sbet2 = sbet1.copysign(sbet2);
+        }
+    } else if sbet2.abs() == -sbet1 {
+        cbet2 = cbet1;
+    }
+    let line = Line {
+        sbet1,
+        cbet1,
+        dn1: (1.0 + g.ep2 * sbet1 * sbet1).sqrt(),
+        sbet2,
+        cbet2,
+        dn2: (1.0 + g.ep2 * sbet2 * sbet2).sqrt(),
+    };
+    let (slam12, clam12) = sin_cos_degrees(lon12);
+    let (s12, mut alp1, mut alp2) = g.solve(&line, lat1, lon12, slam12, clam12);
+    if swap_sign < 0.0 {
+        std::mem::swap(&mut alp1, &mut alp2);
+    }
+    let (salp1, calp1) = (alp1.0 * swap_sign * lon_sign, alp1.1 * swap_sign * lat_sign);
+    (s12, salp1, calp1)
+}
+
+/// The point reached on the geodesic of an azimuth in degrees, after a distance in meters.
+///
+/// The direct problem is solved as by Karney (2013), the arc length on the auxiliary sphere
+/// being found by Newton's method on the distance series.
+fn direct((lat1, lon1): (f64, f64), azi1: f64, s12: f64) -> (f64, f64) {
+    let g = Geodesic::new();
+    let (salp1, calp1) = sin_cos_degrees(azi1);
+    let (sbet1, cbet1) = g.reduced(lat1);
+    // Azimuth at the equator.
+    let salp0 = salp1 * cbet1;
+    let calp0 = calp1.hypot(salp1 * sbet1);
+    let somg1 = salp0 * sbet1;
+    let comg1 = if sbet1 != 0.0 || calp1 != 0.0 {
+        cbet1 * calp1
+    } else {
+        1.0
+    };
+    let sig1 = normalize(sbet1, comg1);
+
+    let k2 = calp0 * calp0 * g.ep2;
+    let eps = k2 / (2.0 * (1.0 + (1.0 + k2).sqrt()) + k2);
+    let (a1, c1) = distance_series(eps);
+    let sig1a = sig1.0.atan2(sig1.1);
+    // The arc length where the distance, divided by `b a1`, reaches the target.
+    let target = sig1a + sin_series(sig1, &c1) + s12 / (g.b * a1);
+    let mut sig2a = target - sin_series(sig1, &c1);
+    for _ in 0..10 {
+        let (ssig, csig) = sig2a.sin_cos();
+        let error = sig2a + sin_series((ssig, csig), &c1) - target;
+        let step = error * a1 / (1.0 + k2 * ssig * ssig).sqrt();
+        sig2a -= step;
+        if step.abs() <= f64::EPSILON * sig2a.abs().max(1.0) {
+            break;
+        }
+    }
+    let sig12 = sig2a - sig1a;
+    let (ssig2, csig2) = sig2a.sin_cos();
+
+    let sbet2 = calp0 * ssig2;
+    let cbet2 = salp0.hypot(calp0 * csig2).max(TINY);
+    let (somg2, comg2) = (salp0 * ssig2, csig2);
+    // Longitude on the auxiliary sphere, unrolled past half turns.
+    let e = 1.0f64.copysign(salp0);
+    let omg12 = e
+        * (sig12 - (ssig2.atan2(csig2) - sig1a)
+            + ((e * somg2).atan2(comg2) - (e * somg1).atan2(comg1)));
+    let (a3, c3) = g.longitude_series(eps);
+    let b312 = sin_series((ssig2, csig2), &c3) - sin_series(sig1, &c3);
+    let lam12 = omg12 - F * a3 * salp0 * (sig12 + b312);
+    (
+        sbet2.atan2(g.f1 * cbet2).to_degrees(),
+        lon1 + lam12.to_degrees(),
+    )
+}
+
+/// Sine and cosine of an angle in degrees, exact at multiples of 90°.
+fn sin_cos_degrees(x: f64) -> (f64, f64) {
+    let r = x.rem_euclid(360.0);
+    let q = (r / 90.0).round();
+    let (s, c) = (r - 90.0 * q).to_radians().sin_cos();
+    match q as i32 % 4 {
+        0 => (s, c),
+        1 => (c, -s),
+        2 => (-s, -c),
+        _ => (-c, s),
+    }
+}
+
+/// The value if positive, else a positive zero, which keeps `atan2` from returning -π.
+fn positive(x: f64) -> f64 {
+    if x > 0.0 {
+        x
+    } else {
+        0.0
+    }
+}
+
+fn normalize(s: f64, c: f64) -> (f64, f64) {
+    let r = s.hypot(c);
+    (s / r, c / r)
+}
+
+/// Sum of `c[l - 1] sin(2 l x)`, from the sine and cosine of `x`, by the method of Clenshaw.
+fn sin_series((sinx, cosx): (f64, f64), c: &[f64]) -> f64 {
+    let ar = 2.0 * (cosx - sinx) * (cosx + sinx);
+    let (mut y0, mut y1) = (0.0, 0.0);
+    for &ck in c.iter().rev() {
+        let y = ar * y0 - y1 + ck;
+        y1 = y0;
+        y0 = y;
+    }
+    2.0 * sinx * cosx * y0
+}
+
+/// Factor and coefficients of the series of the distance on the ellipsoid.
+fn distance_series(eps: f64) -> (f64, [f64; 6]) {
+    let (e2, e3) = (eps * eps, eps * eps * eps);
+    let (e4, e5, e6) = (e2 * e2, e2 * e3, e3 * e3);
+    let a1 = (1.0 + e2 / 4.0 + e4 / 64.0 + e6 / 256.0) / (1.0 - eps);
+    let c1 = [
+        -eps / 2.0 + 3.0 * e3 / 16.0 - e5 / 32.0,
+        -e2 / 16.0 + e4 / 32.0 - 9.0 * e6 / 2048.0,
+        -e3 / 48.0 + 3.0 * e5 / 256.0,
+        -5.0 * e4 / 512.0 + 3.0 * e6 / 1024.0,
+        -7.0 * e5 / 1280.0,
+        -7.0 * e6 / 2048.0,
+    ];
+    (a1, c1)
+}
+
+/// Reduced latitudes of both points, as sines and cosines, and the factors `sqrt(1 + e'² sin²β)`.
+struct Line {
+    sbet1: f64,
+    cbet1: f64,
+    dn1: f64,
+    sbet2: f64,
+    cbet2: f64,
+    dn2: f64,
+}
+
+/// The longitude error of an azimuth at the first point.
+struct Lambda {
+    /// Longitude difference of the geodesic minus the wanted one, in radians.
+    v: f64,
+    /// Derivative of `v` by the azimuth, or zero when not computed.
+    dv: f64,
+    sig12: f64,
+    sig1: (f64, f64),
+    sig2: (f64, f64),
+    /// Azimuth at the second point, as sine and cosine.
+    alp2: (f64, f64),
+    eps: f64,
+}
+
+/// Constants of the WGS-84 ellipsoid.
+struct Geodesic {
+    b: f64,
+    f1: f64,
+    ep2: f64,
+    n: f64,
+}
+
+impl Geodesic {
+    fn new() -> Self {
+        let f1 = 1.0 - F;
+        let e2 = F * (2.0 - F);
+        Geodesic {
+            b: A * f1,
+            f1,
+            ep2: e2 / (f1 * f1),
+            n: F / (2.0 - F),
+        }
+    }
+
+    /// Sine and cosine of the reduced latitude.
+    fn reduced(&self, lat: f64) -> (f64, f64) {
+        let (s, c) = sin_cos_degrees(lat);
+        let (s, c) = normalize(self.f1 * s, c);
+        (s, c.max(TINY))
+    }
+
+    /// Factor and coefficients of the series of the longitude on the ellipsoid minus the one on
+    /// the auxiliary sphere.
+    fn longitude_series(&self, eps: f64) -> (f64, [f64; 5]) {
+        let n = self.n;
+        let (e2, e3, e4, e5) = (eps * eps, eps.powi(3), eps.powi(4), eps.powi(5));
+        let a3 = 1.0
+            - (0.5 - n / 2.0) * eps
+            - (0.25 + n / 8.0 - 3.0 * n * n / 8.0) * e2
+            - (1.0 / 16.0 + 3.0 * n / 16.0 + n * n / 16.0) * e3
+            - (3.0 / 64.0 + n / 32.0) * e4
+            - 3.0 / 128.0 * e5;
+        let c3 = [
+            (0.25 - n / 4.0) * eps
+                + (0.125 - n * n / 8.0) * e2
+                + (3.0 / 64.0 + 3.0 * n / 64.0 - n * n / 64.0) * e3
+                + (5.0 / 128.0 + n / 64.0) * e4
+                + 3.0 / 128.0 * e5,
+            (1.0 / 16.0 - 3.0 * n / 32.0 + n * n / 32.0) * e2
+                + (3.0 / 64.0 - n / 32.0 - 3.0 * n * n / 64.0) * e3
+                + (3.0 / 128.0 + n / 128.0) * e4
+                + 5.0 / 256.0 * e5,
+            (5.0 / 192.0 - 3.0 * n / 64.0 + 5.0 * n * n / 192.0) * e3
+                + (3.0 / 128.0 - 5.0 * n / 192.0) * e4
+                + 7.0 / 512.0 * e5,
+            (7.0 / 512.0 - 7.0 * n / 256.0) * e4 + 7.0 / 512.0 * e5,
+            21.0 / 2560.0 * e5,
+        ];
+        (a3, c3)
+    }
+
+    /// Distance and reduced length on the unit sphere of the auxiliary latitudes, from the
+    /// arc lengths of both points.
+    fn lengths(
+        &self,
+        eps: f64,
+        sig12: f64,
+        sig1: (f64, f64),
+        sig2: (f64, f64),
+        line: &Line,
+    ) -> (f64, f64) {
+        let (a1, c1) = distance_series(eps);
+        let (e2, e3) = (eps * eps, eps * eps * eps);
+        let (e4, e5, e6) = (e2 * e2, e2 * e3, e3 * e3);
+        let a2 = (1.0 - 3.0 * e2 / 4.0 - 7.0 * e4 / 64.0 - 11.0 * e6 / 256.0) / (1.0 + eps);
+        let c2 = [
+            eps / 2.0 + e3 / 16.0 + e5 / 32.0,
+            3.0 * e2 / 16.0 + e4 / 32.0 + 35.0 * e6 / 1024.0,
+            5.0 * e3 / 48.0 + 5.0 * e5 / 256.0,
+            35.0 * e4 / 512.0 + 7.0 * e6 / 128.0,
+            63.0 * e5 / 1280.0,
+            77.0 * e6 / 2048.0,
+        ];
+        let b1 = sin_series(sig2, &c1) - sin_series(sig1, &c1);
+        let b2 = sin_series(sig2, &c2) - sin_series(sig1, &c2);
+        let s12 = a1 * (sig12 + b1);
+        let j12 = (a1 - a2) * sig12 + (a1 * b1 - a2 * b2);
+        let m12 =
+            line.dn2 * (sig1.1 * sig2.0) - line.dn1 * (sig1.0 * sig2.1) - sig1.1 * sig2.1 * j12;
+        (s12, m12)
+    }
+
+    /// Distance in meters and azimuths at both points, as sines and cosines, of the geodesic
+    /// between points put in the order of [`inverse`].
+    fn solve(
+        &self,
+        line: &Line,
+        lat1: f64,
+        lon12: f64,
+        slam12: f64,
+        clam12: f64,
+    ) -> (f64, (f64, f64), (f64, f64)) {
+        let Line {
+            sbet1,
+            cbet1,
+            sbet2,
+            cbet2,
+            ..
+        } = *line;
+        if lat1 == -90.0 || slam12 == 0.0 {
+            // Along a meridian, through a pole where the longitudes differ by half a turn.
+            let (ssig1, csig1) = (sbet1, clam12 * cbet1);
+            let (ssig2, csig2) = (sbet2, cbet2);
+            let sig12 =
+                positive(csig1 * ssig2 - ssig1 * csig2).atan2(csig1 * csig2 + ssig1 * ssig2);
+            let (s12, m12) = self.lengths(self.n, sig12, (ssig1, csig1), (ssig2, csig2), line);
+            // Past a conjugate point, which only happens near the poles, the meridian is not
+            // the shortest path.
+            if sig12 < 1.0 || m12 >= 0.0 {
+                return (self.b * s12, (slam12, clam12), (0.0, 1.0));
+            }
+        }
+        if sbet1 == 0.0 && lon12 <= self.f1 * 180.0 {
+            // Along the equator.
+            return (A * lon12.to_radians(), (1.0, 0.0), (1.0, 0.0));
+        }
+
+        let (mut salp1, mut calp1) = self.start(line, lon12.to_radians());
+        // Bracket of the azimuth, as sine and cosine, between too short and too long.
+        let (mut salp1a, mut calp1a, mut salp1b, mut calp1b) = (TINY, 1.0, TINY, -1.0);
+        let (mut tripn, mut tripb) = (false, false);
+        const MAX_NEWTON: u32 = 20;
+        const MAX_ROUND: u32 = MAX_NEWTON + f64::MANTISSA_DIGITS + 10;
+        const TOL0: f64 = f64::EPSILON;
+        let tolb = TOL0 * TOL0.sqrt();
+        let mut numit = 0;
+        let mut result = self.lambda12(line, salp1, calp1, slam12, clam12, true);
+        loop {
+            let v = result.v;
+            let tol = if tripn { 8.0 } else { 1.0 } * TOL0;
+            if tripb || v.abs() < tol || numit >= MAX_ROUND {
+                break;
+            }
+            if v > 0.0 && (numit > MAX_NEWTON || calp1 / salp1 > calp1b / salp1b) {
+                salp1b = salp1;
+                calp1b = calp1;
+            } else if v < 0.0 && (numit > MAX_NEWTON || calp1 / salp1 < calp1a / salp1a) {
+                salp1a = salp1;
+                calp1a = calp1;
+            }
+            numit += 1;
+            let mut stepped = false;
+            if numit < MAX_NEWTON && result.dv > 0.0 {
+                let dalp1 = -v / result.dv;
+                let (sdalp1, cdalp1) = dalp1.sin_cos();
+                let nsalp1 = salp1 * cdalp1 + calp1 * sdalp1;
+                if nsalp1 > 0.0 && dalp1.abs() < std::f64::consts::PI {
+                    let ncalp1 = calp1 * cdalp1 - salp1 * sdalp1;
+                    (salp1, calp1) = normalize(nsalp1, ncalp1);
+                    tripn = v.abs() <= 16.0 * TOL0;
+                    stepped = true;
+                }
+            }
+            if !stepped {
+                (salp1, calp1) = normalize((salp1a + salp1b) / 2.0, (calp1a + calp1b) / 2.0);
+                tripn = false;
+                tripb = (salp1a - salp1).abs() + (calp1a - calp1) < tolb
+                    || (salp1 - salp1b).abs() + (calp1 - calp1b) < tolb;
+            }
+            result = self.lambda12(line, salp1, calp1, slam12, clam12, numit < MAX_NEWTON);
+        }
+        let (s12, _) = self.lengths(result.eps, result.sig12, result.sig1, result.sig2, line);
+        (self.b * s12, (salp1, calp1), result.alp2)
+    }
+
+    /// Starting azimuth at the first point, from the great circle on the auxiliary sphere.
+    fn start(&self, line: &Line, lam12: f64) -> (f64, f64) {
+        let Line {
+            sbet1,
+            cbet1,
+            sbet2,
+            cbet2,
+            ..
+        } = *line;
+        let sbet12 = sbet2 * cbet1 - cbet2 * sbet1;
+        let cbet12 = cbet2 * cbet1 + sbet2 * sbet1;
+        let sbet12a = sbet2 * cbet1 + cbet2 * sbet1;
+        let omg12 = if cbet12 >= 0.0 && sbet12 < 0.5 && cbet2 * lam12 < 0.5 {
+            // Short lines: the mean of the latitudes scales the longitude.
+            let sbetm2 = (sbet1 + sbet2).powi(2);
+            let sbetm2 = sbetm2 / (sbetm2 + (cbet1 + cbet2).powi(2));
+            lam12 / (self.f1 * (1.0 + self.ep2 * sbetm2).sqrt())
+        } else {
+            lam12
+        };
+        let (somg12, comg12) = omg12.sin_cos();
+        let salp1 = cbet2 * somg12;
+        let calp1 = if comg12 >= 0.0 {
+            sbet12 + cbet2 * sbet1 * somg12 * somg12 / (1.0 + comg12)
+        } else {
+            sbet12a - cbet2 * sbet1 * somg12 * somg12 / (1.0 - comg12)
+        };
+        normalize(salp1.max(TINY), calp1)
+    }
+
+    /// The longitude reached by the geodesic of an azimuth, compared to the wanted one.
+    fn lambda12(
+        &self,
+        line: &Line,
+        salp1: f64,
+        calp1: f64,
+        slam12: f64,
+        clam12: f64,
+        derivative: bool,
+    ) -> Lambda {
+        let Line {
+            sbet1,
+            cbet1,
+            dn1,
+            sbet2,
+            cbet2,
+            ..
+        } = *line;
+        let calp1 = if sbet1 == 0.0 && calp1 == 0.0 {
+            -TINY
+        } else {
+            calp1
+        };
+        // Azimuth at the equator.
+        let salp0 = salp1 * cbet1;
+        let calp0 = calp1.hypot(salp1 * sbet1);
+
+        let (somg1, comg1) = (salp0 * sbet1, calp1 * cbet1);
+        let sig1 = normalize(sbet1, comg1);
+        let calp2 = if cbet2 != cbet1 || sbet2.abs() != -sbet1 {
+            let d = if cbet1 < -sbet1 {
+                (cbet2 - cbet1) * (cbet1 + cbet2)
+            } else {
+                (sbet1 - sbet2) * (sbet1 + sbet2)
+            };
+            ((calp1 * cbet1).powi(2) + d).sqrt() / cbet2
+        } else {
+            calp1.abs()
+        };
+        let salp2 = if cbet2 != cbet1 { salp0 / cbet2 } else { salp1 };
+        let (somg2, comg2) = (salp0 * sbet2, calp2 * cbet2);
+        let sig2 = normalize(sbet2, comg2);
+        let sig12 =
+            positive(sig1.1 * sig2.0 - sig1.0 * sig2.1).atan2(sig1.1 * sig2.1 + sig1.0 * sig2.0);
+        let somg12 = positive(comg1 * somg2 - somg1 * comg2);
+        let comg12 = comg1 * comg2 + somg1 * somg2;
+        // Longitude on the auxiliary sphere minus the wanted one.
+        let eta = (somg12 * clam12 - comg12 * slam12).atan2(comg12 * clam12 + somg12 * slam12);
+
+        let k2 = calp0 * calp0 * self.ep2;
+        let eps = k2 / (2.0 * (1.0 + (1.0 + k2).sqrt()) + k2);
+        let (a3, c3) = self.longitude_series(eps);
+        let b312 = sin_series(sig2, &c3) - sin_series(sig1, &c3);
+        let domg12 = -F * a3 * salp0 * (sig12 + b312);
+
+        let dv = if !derivative {
+            0.0
+        } else if calp2 == 0.0 {
+            -2.0 * self.f1 * dn1 / sbet1
+        } else {
+            let (_, m12) = self.lengths(eps, sig12, sig1, sig2, line);
+            m12 * self.f1 / (calp2 * cbet2)
+        };
+        Lambda {
+            v: eta + domg12,
+            dv,
+            sig12,
+            sig1,
+            sig2,
+            alp2: (salp2, calp2),
+            eps,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::point::Coordinate;
+    use crate::GeodeticSystem::*;
+
+    fn dms(d: f64, m: f64, s: f64) -> f64 {
+        d.signum() * (d.abs() + m / 60.0 + s / 3600.0)
+    }
+
+    #[test]
+    fn test_geodesic() {
+        // From Flinders Peak to Buninyong, the example of Vincenty used by Geoscience Australia.
+        let flinders = (dms(-37.0, 57.0, 3.72030), dms(144.0, 25.0, 29.52440));
+        let buninyong = (dms(-37.0, 39.0, 10.15610), dms(143.0, 55.0, 35.38390));
+        let s = geodesic(flinders, buninyong);
+        assert!((s - 54_972.271).abs() < 1e-3, "{}", s);
+        assert!((geodesic(buninyong, flinders) - s).abs() < 1e-8);
+
+        // Quarter meridian, quarter of the equator, and antipodes on the equator, whose
+        // shortest paths go through the poles.
+        let quarter = 10_001_965.729;
+        for &(a, b, expected) in &[
+            ((0.0, 30.0), (90.0, 30.0), quarter),
+            ((-90.0, 0.0), (90.0, 0.0), 2.0 * quarter),
+            ((0.0, 0.0), (0.0, 90.0), 10_018_754.171),
+            ((0.0, 0.0), (0.0, 180.0), 2.0 * quarter),
+            ((0.0, -170.0), (0.0, 170.0), 2_226_389.816),
+        ] {
+            let s = geodesic(a, b);
+            assert!((s - expected).abs() < 1e-3, "{:?} {:?}: {}", a, b, s);
+        }
+        assert_eq!(geodesic((39.9, 116.4), (39.9, 116.4)), 0.0);
+    }
+
+    #[test]
+    fn test_nearly_antipodal() {
+        // Beyond the reach of the method of Vincenty: the distance only grows toward the
+        // antipode, staying below half a meridian.
+        let mut last = 0.0;
+        for i in 0..=20 {
+            let lon = 179.0 + i as f64 * 0.05;
+            let s = geodesic((0.5, 0.0), (-0.5, lon));
+            assert!(s > last && s < 20_003_931.46, "{} {}", lon, s);
+            last = s;
+        }
+    }
+
+    #[test]
+    fn test_haversine() {
+        let (a, b) = ((39.9, 116.4), (31.2, 121.5));
+        let (h, g) = (haversine(a, b), geodesic(a, b));
+        assert!((h - g).abs() / g < 5e-3, "{} {}", h, g);
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let (a, b) = ((39.9, 116.4), (31.2, 121.5));
+        assert_eq!(interpolate(a, b, 0.0), a);
+        let end = interpolate(a, b, 1.0);
+        assert!((end.0 - b.0).abs() < 1e-12 && (end.1 - b.1).abs() < 1e-12);
+        let middle = interpolate(a, b, 0.5);
+        assert!((haversine(a, middle) - haversine(middle, b)).abs() < 1e-6);
+        assert!((haversine(a, middle) * 2.0 - haversine(a, b)).abs() < 1e-6);
+
+        // The short way across the antimeridian, and north of the parallel.
+        let (lat, lon) = interpolate((60.0, 170.0), (60.0, -170.0), 0.5);
+        assert!(
+            lat > 60.3 && (lon.abs() - 180.0).abs() < 1e-9,
+            "{} {}",
+            lat,
+            lon
+        );
+        assert_eq!(interpolate(a, a, 0.5), a);
+    }
+
+    #[test]
+    fn test_sample_every_meters() {
+        let (a, b) = ((39.90, 116.40), (39.901, 116.401));
+        let points = sample_every_meters(a, b, 50.0).unwrap();
+        assert_eq!(points.len(), 4);
+        assert_eq!((points[0], points[3]), (a, b));
+        for pair in points.windows(2) {
+            let length = haversine(pair[0], pair[1]);
+            assert!(length < 50.0 && (length - haversine(a, b) / 3.0).abs() < 1e-6);
+        }
+        assert_eq!(sample_every_meters(a, b, 1000.0).unwrap(), [a, b]);
+        assert_eq!(sample_every_meters(a, b, f64::INFINITY).unwrap(), [a, b]);
+        assert_eq!(sample_every_meters(a, a, 1.0).unwrap(), [a, a]);
+        let nan = sample_every_meters(a, (f64::NAN, 116.4), 1.0).unwrap();
+        assert_eq!(nan.len(), 2);
+        for &step in &[0.0, -1.0, f64::NAN] {
+            assert_eq!(sample_every_meters(a, b, step), None);
+        }
+        let points = sample_every_meters(a, b, 1e-300).unwrap();
+        assert_eq!(points.len(), MAX_SAMPLE_SEGMENTS + 1);
+    }
+
+    #[test]
+    fn test_distance_meters() {
+        let wgs = Coordinate::from_degrees(Wgs84, 39.9, 116.4).unwrap();
+        let other = Coordinate::from_degrees(Wgs84, 39.95, 116.5).unwrap();
+        let expected = geodesic((39.9, 116.4), (39.95, 116.5));
+        assert_eq!(distance_meters(wgs, other, Method::Geodesic), expected);
+        // Obfuscated points are measured where they really are, to the precision of the
+        // inversions.
+        for &system in &[Gcj02, Bd09] {
+            let a = wgs.convert_to(system);
+            let b = other.convert_to(system);
+            assert!((distance_meters(a, b, Method::Geodesic) - expected).abs() < 0.5);
+            assert!((distance_meters(a, other, Method::Geodesic) - expected).abs() < 0.5);
+        }
+        assert!(distance_meters(wgs, wgs.convert_to(Gcj02), Method::Haversine) < 0.05);
+    }
+
+    #[test]
+    fn test_initial_bearing() {
+        let coordinate = |(lat, lon)| Coordinate::from_degrees(Wgs84, lat, lon).unwrap();
+        // Both azimuths of the example of Vincenty, to the rounding of its seconds.
+        let flinders = coordinate((dms(-37.0, 57.0, 3.72030), dms(144.0, 25.0, 29.52440)));
+        let buninyong = coordinate((dms(-37.0, 39.0, 10.15610), dms(143.0, 55.0, 35.38390)));
+        let forward = initial_bearing(flinders, buninyong, Method::Geodesic);
+        assert!(
+            (forward - dms(306.0, 52.0, 5.37)).abs() < 1e-5,
+            "{}",
+            forward
+        );
+        let back = initial_bearing(buninyong, flinders, Method::Geodesic);
+        assert!((back - dms(127.0, 10.0, 25.07)).abs() < 1e-5, "{}", back);
+
+        for &(b, expected) in &[
+            ((10.0, 0.0), 0.0),
+            ((0.0, 90.0), 90.0),
+            ((-10.0, 0.0), 180.0),
+            ((0.0, -90.0), 270.0),
+            ((0.0, 0.0), 0.0),
+        ] {
+            for &method in &[Method::Haversine, Method::Geodesic] {
+                let bearing = initial_bearing(coordinate((0.0, 0.0)), coordinate(b), method);
+                assert!((bearing - expected).abs() < 1e-9, "{:?} {}", b, bearing);
+            }
+        }
+        // Across the antimeridian, eastward.
+        let bearing = initial_bearing(
+            coordinate((10.0, 170.0)),
+            coordinate((10.0, -170.0)),
+            Method::Geodesic,
+        );
+        assert!((bearing - 88.246).abs() < 1e-3, "{}", bearing);
+
+        let gcj = coordinate((39.9, 116.4)).convert_to(Gcj02);
+        let north = coordinate((40.0, 116.4));
+        assert!(initial_bearing(gcj, north, Method::Geodesic) < 1e-4);
+    }
+
+    #[test]
+    fn test_destination() {
+        let start = Coordinate::from_degrees(Wgs84, 39.9, 116.4).unwrap();
+        for &method in &[Method::Haversine, Method::Geodesic] {
+            for &bearing in &[0.0, 45.0, 135.0, 271.0] {
+                for &distance in &[1.0, 1e4, 1e7] {
+                    let end = destination(start, bearing, distance, method);
+                    let measured = distance_meters(start, end, method);
+                    assert!((measured - distance).abs() < 1e-6 * distance.max(1.0));
+                    let back = initial_bearing(start, end, method);
+                    assert!((back - bearing).abs() < 1e-6, "{} {}", bearing, back);
+                }
+            }
+        }
+        // Over the pole and across the antimeridian.
+        let end = destination(
+            Coordinate::from_degrees(Wgs84, 89.0, 0.0).unwrap(),
+            0.0,
+            222_000.0,
+            Method::Geodesic,
+        );
+        assert!((end.lat.degrees() - 89.012).abs() < 1e-3 && end.lon.degrees() == 180.0);
+        let end = destination(
+            Coordinate::from_degrees(Wgs84, 0.0, 179.5).unwrap(),
+            90.0,
+            A * 1f64.to_radians(),
+            Method::Geodesic,
+        );
+        assert!(end.lat.degrees().abs() < 1e-12 && (end.lon.degrees() + 179.5).abs() < 1e-9);
+
+        // The destination is in the system of the start, where it really is.
+        let gcj = start.convert_to(Gcj02);
+        let end = destination(gcj, 90.0, 1000.0, Method::Geodesic);
+        assert_eq!(end.system, Gcj02);
+        let wgs = destination(start, 90.0, 1000.0, Method::Geodesic).convert_to(Gcj02);
+        assert!(distance_meters(end, wgs, Method::Geodesic) < 0.05);
+    }
+}
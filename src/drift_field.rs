@@ -0,0 +1,107 @@
+//! Sampling of the GCJ-02 offset field over a region, with heatmap export.
+use crate::png::{self, Image};
+use crate::{drift_meters, BoundingBox, Drift};
+use std::io::{self, Write};
+
+/// The drift sampled on a regular grid, stored row by row from north to south.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriftField {
+    pub bbox: BoundingBox,
+    pub cols: usize,
+    pub rows: usize,
+    pub values: Vec<Drift>,
+}
+
+impl DriftField {
+    /// Samples the drift at the center of each cell of a `cols` x `rows` grid.
+    pub fn sample(bbox: BoundingBox, cols: usize, rows: usize) -> Self {
+        let lat_step = (bbox.max_lat - bbox.min_lat) / rows as f64;
+        let lon_step = (bbox.max_lon - bbox.min_lon) / cols as f64;
+        let mut values = Vec::with_capacity(cols * rows);
+        for row in 0..rows {
+            let lat = bbox.max_lat - (row as f64 + 0.5) * lat_step;
+            for col in 0..cols {
+                let lon = bbox.min_lon + (col as f64 + 0.5) * lon_step;
+                values.push(drift_meters(lat, lon));
+            }
+        }
+        DriftField {
+            bbox,
+            cols,
+            rows,
+            values,
+        }
+    }
+
+    /// Returns the drift of the cell at the given row and column.
+    pub fn get(&self, row: usize, col: usize) -> Option<&Drift> {
+        if row >= self.rows || col >= self.cols {
+            return None;
+        }
+        self.values.get(row * self.cols + col)
+    }
+
+    /// Largest drift magnitude in the field.
+    pub fn max_magnitude(&self) -> f64 {
+        self.values.iter().map(|d| d.magnitude).fold(0.0, f64::max)
+    }
+
+    /// Renders the drift magnitude as an opaque heatmap PNG.
+    ///
+    /// Colors go from blue (no drift) to red (the largest drift in the field).
+    pub fn write_png<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let max = self.max_magnitude();
+        let mut image = Image::new(self.cols, self.rows);
+        for (i, drift) in self.values.iter().enumerate() {
+            let t = if max > 0.0 {
+                drift.magnitude / max
+            } else {
+                0.0
+            };
+            let [r, g, b] = heat_color(t);
+            image.rgba[i * 4..i * 4 + 4].copy_from_slice(&[r, g, b, 255]);
+        }
+        out.write_all(&png::encode(&image))
+    }
+}
+
+fn heat_color(t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0);
+    let (r, g, b) = if t < 0.5 {
+        (0.0, t * 2.0, 1.0 - t * 2.0)
+    } else {
+        ((t - 0.5) * 2.0, 1.0 - (t - 0.5) * 2.0, 0.0)
+    };
+    [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zip::crc32;
+
+    #[test]
+    fn test_sample() {
+        let bbox = BoundingBox::new(30.0, 110.0, 40.0, 120.0);
+        let field = DriftField::sample(bbox, 4, 2);
+        assert_eq!(field.values.len(), 8);
+        let top_left = field.get(0, 0).unwrap();
+        assert_eq!(*top_left, drift_meters(37.5, 111.25));
+        assert!(field.get(2, 0).is_none());
+        assert!(field.max_magnitude() > 100.0);
+    }
+
+    #[test]
+    fn test_png() {
+        let bbox = BoundingBox::new(30.0, 110.0, 40.0, 120.0);
+        let field = DriftField::sample(bbox, 3, 2);
+        let mut png = Vec::new();
+        field.write_png(&mut png).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 3, 0, 0, 0, 2]);
+        // The chunk CRC covers the type and data.
+        assert_eq!(&png[29..33], &crc32(&png[12..29]).to_be_bytes());
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+}
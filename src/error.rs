@@ -0,0 +1,142 @@
+//! An error for every failure of the crate.
+//!
+//! Each module reports its failures with its own error, which converts into [`Error`], so that
+//! code calling several modules propagates them with `?` and matches on their kind:
+//!
+//! ```
+//! use undrift_gps::{Error, GeodeticSystem};
+//!
+//! fn convert(system: &str, coordinate: &str) -> Result<(f64, f64), Error> {
+//!     let from: GeodeticSystem = system.parse()?;
+//!     let (lat, lon) = undrift_gps::format::parse_coordinate(coordinate)?;
+//!     Ok(from.convert_to(GeodeticSystem::Wgs84, lat, lon))
+//! }
+//!
+//! assert!(convert("gcj02", "39.9, 116.4").is_ok());
+//! assert!(matches!(convert("mercator", "39.9, 116.4"), Err(Error::Parse(_))));
+//! ```
+use crate::validate::Issue;
+use std::{fmt, io};
+
+/// A failure of the crate.
+#[derive(Debug)]
+pub enum Error {
+    /// A coordinate that cannot be converted, with every issue found.
+    InvalidInput(Vec<Issue>),
+    /// The inversion of an obfuscation did not converge at a coordinate, which has no exact
+    /// preimage.
+    NonConvergence { lat: f64, lon: f64 },
+    /// A name, coordinate, code, option or file that is not valid. The error of the parser is
+    /// kept, and can be downcast for the details.
+    Parse(Box<dyn std::error::Error + Send + Sync>),
+    /// An error of reading or writing.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidInput(issues) => write!(f, "invalid coordinate: {:?}", issues),
+            Error::NonConvergence { lat, lon } => {
+                write!(f, "conversion did not converge at ({}, {})", lat, lon)
+            }
+            Error::Parse(e) => e.fmt(f),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Parse(e) => Some(&**e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Issue> for Error {
+    fn from(issue: Issue) -> Self {
+        Error::InvalidInput(vec![issue])
+    }
+}
+
+impl From<Vec<Issue>> for Error {
+    fn from(issues: Vec<Issue>) -> Self {
+        Error::InvalidInput(issues)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    /// Keeps the errors of reading and writing, and makes the others invalid data.
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+macro_rules! parse_errors {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(e: $error) -> Self {
+                    Error::Parse(Box::new(e))
+                }
+            }
+        )*
+    };
+}
+
+parse_errors!(
+    crate::ParseSystemError,
+    crate::converter::ParseAlgorithmError,
+    crate::format::ParseCoordError,
+    crate::JsonError,
+    crate::XmlError,
+    crate::fit::FitError,
+    crate::georef::ParseWorldFileError,
+    crate::georef::GeoTiffError,
+    crate::maidenhead::LocatorError,
+    crate::mvt::MvtError,
+    crate::pipeline::PipelineError,
+    crate::plus_code::PlusCodeError,
+    crate::spec::SpecError,
+    crate::srt::SrtError,
+    crate::takeout::TakeoutError,
+    crate::topojson::TopoJsonError,
+    crate::ubx::UbxError,
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plus_code::PlusCodeError;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_conversions() {
+        let e = Error::from(PlusCodeError::Short);
+        assert_eq!(e.to_string(), PlusCodeError::Short.to_string());
+        let source = e.source().unwrap().downcast_ref::<PlusCodeError>();
+        assert_eq!(source, Some(&PlusCodeError::Short));
+
+        let e = Error::from(Issue::NotFinite);
+        assert!(matches!(e, Error::InvalidInput(ref issues) if issues == &[Issue::NotFinite]));
+        assert!(e.source().is_none());
+        assert_eq!(io::Error::from(e).kind(), io::ErrorKind::InvalidData);
+
+        let e = Error::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+        assert_eq!(io::Error::from(e).kind(), io::ErrorKind::UnexpectedEof);
+        let e = Error::NonConvergence { lat: 1.0, lon: 2.0 };
+        assert_eq!(e.to_string(), "conversion did not converge at (1, 2)");
+    }
+}
@@ -0,0 +1,147 @@
+//! C interface, enabled by the `ffi` feature. See `include/undrift_gps.h` for the declarations.
+//!
+//! The functions work on caller-provided buffers and never allocate, so they can be called under
+//! a latency budget. Systems are identified by the `UNDRIFT_*` constants.
+use crate::GeodeticSystem;
+
+pub const UNDRIFT_WGS84: i32 = 0;
+pub const UNDRIFT_GCJ02: i32 = 1;
+pub const UNDRIFT_BD09: i32 = 2;
+
+/// Returned on success.
+pub const UNDRIFT_OK: i32 = 0;
+/// Returned for an unknown system constant.
+pub const UNDRIFT_INVALID_SYSTEM: i32 = -1;
+/// Returned for a null buffer with a non-zero length.
+pub const UNDRIFT_NULL_POINTER: i32 = -2;
+
+fn system(code: i32) -> Option<GeodeticSystem> {
+    match code {
+        UNDRIFT_WGS84 => Some(GeodeticSystem::Wgs84),
+        UNDRIFT_GCJ02 => Some(GeodeticSystem::Gcj02),
+        UNDRIFT_BD09 => Some(GeodeticSystem::Bd09),
+        _ => None,
+    }
+}
+
+fn systems(from: i32, to: i32) -> Result<(GeodeticSystem, GeodeticSystem), i32> {
+    match (system(from), system(to)) {
+        (Some(from), Some(to)) => Ok((from, to)),
+        _ => Err(UNDRIFT_INVALID_SYSTEM),
+    }
+}
+
+/// Converts `len` points stored as interleaved `lat, lon` pairs.
+///
+/// `input` and `output` hold `2 * len` doubles each and may be the same buffer.
+///
+/// # Safety
+///
+/// The buffers must be valid for `2 * len` doubles, and must not overlap unless identical.
+#[no_mangle]
+pub unsafe extern "C" fn undrift_convert_interleaved(
+    from: i32,
+    to: i32,
+    input: *const f64,
+    output: *mut f64,
+    len: usize,
+) -> i32 {
+    let (from, to) = match systems(from, to) {
+        Ok(systems) => systems,
+        Err(code) => return code,
+    };
+    if len > 0 && (input.is_null() || output.is_null()) {
+        return UNDRIFT_NULL_POINTER;
+    }
+
+    // Raw accesses, as references to the same buffer would alias.
+    for i in 0..len {
+        let (lat, lon) = (*input.add(2 * i), *input.add(2 * i + 1));
+        let (lat, lon) = from.convert_to(to, lat, lon);
+        *output.add(2 * i) = lat;
+        *output.add(2 * i + 1) = lon;
+    }
+    UNDRIFT_OK
+}
+
+/// Converts `len` points stored as separate latitude and longitude arrays.
+///
+/// Each array holds `len` doubles; an output array may be the same as its input array.
+///
+/// # Safety
+///
+/// The arrays must be valid for `len` doubles, and must not overlap unless identical.
+#[no_mangle]
+pub unsafe extern "C" fn undrift_convert_split(
+    from: i32,
+    to: i32,
+    lats_in: *const f64,
+    lons_in: *const f64,
+    lats_out: *mut f64,
+    lons_out: *mut f64,
+    len: usize,
+) -> i32 {
+    let (from, to) = match systems(from, to) {
+        Ok(systems) => systems,
+        Err(code) => return code,
+    };
+    if len > 0
+        && (lats_in.is_null() || lons_in.is_null() || lats_out.is_null() || lons_out.is_null())
+    {
+        return UNDRIFT_NULL_POINTER;
+    }
+
+    for i in 0..len {
+        let (lat, lon) = from.convert_to(to, *lats_in.add(i), *lons_in.add(i));
+        *lats_out.add(i) = lat;
+        *lons_out.add(i) = lon;
+    }
+    UNDRIFT_OK
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interleaved() {
+        let mut buffer = [39.0, 116.0, 31.2, 121.5];
+        let code = unsafe {
+            undrift_convert_interleaved(
+                UNDRIFT_WGS84,
+                UNDRIFT_BD09,
+                buffer.as_ptr(),
+                buffer.as_mut_ptr(),
+                2,
+            )
+        };
+        assert_eq!(code, UNDRIFT_OK);
+        assert_eq!((buffer[2], buffer[3]), crate::wgs_to_bd(31.2, 121.5));
+
+        let null = std::ptr::null_mut();
+        let code = unsafe { undrift_convert_interleaved(0, 1, null, null, 1) };
+        assert_eq!(code, UNDRIFT_NULL_POINTER);
+        let code = unsafe { undrift_convert_interleaved(0, 3, null, null, 0) };
+        assert_eq!(code, UNDRIFT_INVALID_SYSTEM);
+    }
+
+    #[test]
+    fn test_split() {
+        let lats = [39.0, 31.2];
+        let lons = [116.0, 121.5];
+        let mut out = ([0.0; 2], [0.0; 2]);
+        let code = unsafe {
+            undrift_convert_split(
+                UNDRIFT_GCJ02,
+                UNDRIFT_WGS84,
+                lats.as_ptr(),
+                lons.as_ptr(),
+                out.0.as_mut_ptr(),
+                out.1.as_mut_ptr(),
+                2,
+            )
+        };
+        assert_eq!(code, UNDRIFT_OK);
+        assert_eq!((out.0[1], out.1[1]), crate::gcj_to_wgs(31.2, 121.5));
+    }
+}
@@ -0,0 +1,483 @@
+//! Garmin FIT activity and course files.
+//!
+//! Positions are stored as signed 32-bit semicircles, `2^31` of them making 180 degrees. The
+//! conversion rewrites them in place, in the positions of records, laps, sessions and course
+//! points, and updates the file CRC; the size of the file and every other byte are kept.
+use std::fmt;
+use std::ops::Range;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::track::{Track, TrackPoint};
+use crate::Converter;
+
+/// Error returned for an invalid FIT file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitError {
+    /// The file ends in the middle of a header or message, at the given offset.
+    Truncated(usize),
+    /// The file header at the given offset is invalid.
+    InvalidHeader(usize),
+    /// A data message at the given offset uses a local message type that was not defined.
+    UndefinedMessage(usize),
+    /// The CRC of the file starting at the given offset does not match its content.
+    InvalidCrc(usize),
+}
+
+impl fmt::Display for FitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FitError::Truncated(offset) => write!(f, "truncated FIT file at byte {}", offset),
+            FitError::InvalidHeader(offset) => write!(f, "invalid FIT header at byte {}", offset),
+            FitError::UndefinedMessage(offset) => {
+                write!(f, "undefined FIT message type at byte {}", offset)
+            }
+            FitError::InvalidCrc(offset) => write!(f, "invalid CRC of FIT file at byte {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for FitError {}
+
+/// Global message number and field numbers of the latitude and longitude of each position.
+const POSITION_FIELDS: [(u16, u8, u8); 7] = [
+    // record: position_lat, position_long
+    (20, 0, 1),
+    // lap: start_position_lat/long, end_position_lat/long
+    (19, 3, 4),
+    (19, 5, 6),
+    // session: start_position_lat/long, nec_lat/long, swc_lat/long
+    (18, 3, 4),
+    (18, 29, 30),
+    (18, 31, 32),
+    // course_point: position_lat, position_long
+    (32, 2, 3),
+];
+
+/// Semicircle value marking a missing position.
+const INVALID: i32 = i32::MAX;
+
+const SEMICIRCLES_PER_DEGREE: f64 = 2_147_483_648.0 / 180.0;
+
+/// Seconds from the Unix epoch to the FIT epoch, 1989-12-31 00:00 UTC.
+const FIT_EPOCH: u64 = 631_065_600;
+
+/// A message definition, reduced to what the conversion needs.
+#[derive(Debug, Clone, Default)]
+struct Definition {
+    big_endian: bool,
+    size: usize,
+    /// Offsets of the latitude and longitude of each position within the message.
+    positions: Vec<(usize, usize)>,
+    /// Whether this defines record messages, the points of tracks.
+    is_record: bool,
+    /// Offset of the timestamp within the message.
+    timestamp: Option<usize>,
+    /// Offset and size of the altitude within the message, preferring the enhanced one.
+    altitude: Option<(usize, usize)>,
+}
+
+/// Time and altitude of a record message.
+#[derive(Debug, Clone, Copy)]
+struct Fix {
+    /// Seconds since the FIT epoch.
+    time: Option<u32>,
+    altitude: Option<f64>,
+}
+
+/// A position in the file: the offsets of its latitude and longitude.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    lat: usize,
+    lon: usize,
+    big_endian: bool,
+    /// Set for the positions of record messages.
+    fix: Option<Fix>,
+}
+
+impl Slot {
+    fn read(&self, data: &[u8], offset: usize) -> i32 {
+        let bytes = [
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ];
+        if self.big_endian {
+            i32::from_be_bytes(bytes)
+        } else {
+            i32::from_le_bytes(bytes)
+        }
+    }
+
+    fn write(&self, data: &mut [u8], offset: usize, value: i32) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        data[offset..offset + 4].copy_from_slice(&bytes);
+    }
+
+    /// The position in degrees, unless missing.
+    fn get(&self, data: &[u8]) -> Option<(f64, f64)> {
+        let (lat, lon) = (self.read(data, self.lat), self.read(data, self.lon));
+        if lat == INVALID || lon == INVALID {
+            return None;
+        }
+        Some((
+            lat as f64 / SEMICIRCLES_PER_DEGREE,
+            lon as f64 / SEMICIRCLES_PER_DEGREE,
+        ))
+    }
+
+    fn set(&self, data: &mut [u8], (lat, lon): (f64, f64)) {
+        let semicircles = |x: f64| {
+            (x * SEMICIRCLES_PER_DEGREE)
+                .round()
+                .clamp(i32::MIN as f64, (INVALID - 1) as f64) as i32
+        };
+        self.write(data, self.lat, semicircles(lat));
+        self.write(data, self.lon, semicircles(lon));
+    }
+}
+
+/// CRC-16 used by FIT.
+fn crc16(data: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xcc01, 0xd801, 0x1400, 0xf001, 0x3c00, 0x2800, 0xe401, 0xa001, 0x6c00, 0x7800,
+        0xb401, 0x5000, 0x9c01, 0x8801, 0x4400,
+    ];
+    data.iter().fold(0, |mut crc, &byte| {
+        for nibble in [byte & 0xf, byte >> 4] {
+            let tmp = TABLE[(crc & 0xf) as usize];
+            crc = (crc >> 4) ^ tmp ^ TABLE[nibble as usize];
+        }
+        crc
+    })
+}
+
+/// Reads an unsigned integer of up to 4 bytes.
+fn uint(bytes: &[u8], big_endian: bool) -> u32 {
+    let fold = |acc: u32, &b: &u8| acc << 8 | u32::from(b);
+    if big_endian {
+        bytes.iter().fold(0, fold)
+    } else {
+        bytes.iter().rev().fold(0, fold)
+    }
+}
+
+fn bytes(data: &[u8], range: Range<usize>) -> Result<&[u8], FitError> {
+    let start = range.start;
+    data.get(range).ok_or(FitError::Truncated(start))
+}
+
+/// Finds the positions of every file in `data`, and the ranges covered by each file CRC.
+fn scan(data: &[u8]) -> Result<(Vec<Slot>, Vec<Range<usize>>), FitError> {
+    let mut slots = Vec::new();
+    let mut files = Vec::new();
+    let mut pos = 0;
+    // Several FIT files may be chained.
+    while pos < data.len() {
+        let start = pos;
+        let header_size = bytes(data, pos..pos + 1)?[0] as usize;
+        let header = bytes(data, pos..pos + header_size.max(12))?;
+        if !matches!(header_size, 12 | 14) || &header[8..12] != b".FIT" {
+            return Err(FitError::InvalidHeader(start));
+        }
+        let data_size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let end = start + header_size + data_size;
+        let stored = bytes(data, end..end + 2)?;
+        if crc16(&data[start..end]) != u16::from_le_bytes([stored[0], stored[1]]) {
+            return Err(FitError::InvalidCrc(start));
+        }
+
+        let mut definitions: [Option<Definition>; 16] = Default::default();
+        let mut last_time: Option<u32> = None;
+        pos += header_size;
+        while pos < end {
+            let record = data[pos];
+            pos += 1;
+            if record & 0x80 == 0 && record & 0x40 != 0 {
+                let (definition, size) = parse_definition(&data[..end], pos, record & 0x20 != 0)?;
+                definitions[(record & 0xf) as usize] = Some(definition);
+                pos += size;
+                continue;
+            }
+
+            // Compressed timestamp headers hold the local type in bits 5 and 6.
+            let local = if record & 0x80 != 0 {
+                (record >> 5) & 0x3
+            } else {
+                record & 0xf
+            };
+            let definition = definitions[local as usize]
+                .as_ref()
+                .ok_or(FitError::UndefinedMessage(pos - 1))?;
+            bytes(&data[..end], pos..pos + definition.size)?;
+            let read = |offset: usize, size: usize| {
+                uint(
+                    &data[pos + offset..pos + offset + size],
+                    definition.big_endian,
+                )
+            };
+            let time = if record & 0x80 != 0 {
+                // A 5-bit offset from the previous timestamp, rolling over every 32 seconds.
+                let offset = u32::from(record & 0x1f);
+                // Timestamps wrap around as the FIT protocol allows.
+                last_time.map(|last| {
+                    let time = (last & !0x1f) + offset;
+                    if offset < last & 0x1f {
+                        time.wrapping_add(0x20)
+                    } else {
+                        time
+                    }
+                })
+            } else {
+                definition
+                    .timestamp
+                    .map(|offset| read(offset, 4))
+                    .filter(|&t| t != u32::MAX)
+            };
+            last_time = time.or(last_time);
+            let fix = Fix {
+                time,
+                altitude: definition.altitude.and_then(|(offset, size)| {
+                    let raw = read(offset, size);
+                    let invalid = if size == 2 { 0xffff } else { u32::MAX };
+                    (raw != invalid).then(|| f64::from(raw) / 5.0 - 500.0)
+                }),
+            };
+            for &(lat, lon) in &definition.positions {
+                slots.push(Slot {
+                    lat: pos + lat,
+                    lon: pos + lon,
+                    big_endian: definition.big_endian,
+                    fix: Some(fix).filter(|_| definition.is_record),
+                });
+            }
+            pos += definition.size;
+        }
+        files.push(start..end);
+        pos = end + 2;
+    }
+    Ok((slots, files))
+}
+
+/// Parses a definition message starting at `pos`, returning it with its size.
+fn parse_definition(
+    data: &[u8],
+    pos: usize,
+    developer: bool,
+) -> Result<(Definition, usize), FitError> {
+    let fixed = bytes(data, pos..pos + 5)?;
+    let big_endian = fixed[1] == 1;
+    let global = if big_endian {
+        u16::from_be_bytes([fixed[2], fixed[3]])
+    } else {
+        u16::from_le_bytes([fixed[2], fixed[3]])
+    };
+    let count = fixed[4] as usize;
+    let fields = bytes(data, pos + 5..pos + 5 + 3 * count)?;
+    let mut len = 5 + 3 * count;
+
+    // Offset and size of each field, by field number.
+    let mut offsets = Vec::with_capacity(count);
+    let mut size = 0;
+    for field in fields.chunks_exact(3) {
+        offsets.push((field[0], size, field[1] as usize));
+        size += field[1] as usize;
+    }
+    if developer {
+        let count = bytes(data, pos + len..pos + len + 1)?[0] as usize;
+        let fields = bytes(data, pos + len + 1..pos + len + 1 + 3 * count)?;
+        size += fields.chunks_exact(3).map(|f| f[1] as usize).sum::<usize>();
+        len += 1 + 3 * count;
+    }
+
+    let find_sized = |number: u8, expected: usize| {
+        offsets
+            .iter()
+            .find(|&&(n, _, size)| n == number && size == expected)
+            .map(|&(_, offset, _)| offset)
+    };
+    let find = |number: u8| find_sized(number, 4);
+    let positions = POSITION_FIELDS
+        .iter()
+        .filter(|&&(message, _, _)| message == global)
+        .filter_map(|&(_, lat, lon)| Some((find(lat)?, find(lon)?)))
+        .collect();
+    let is_record = global == 20;
+    let altitude = find(78)
+        .map(|offset| (offset, 4))
+        .or_else(|| find_sized(2, 2).map(|offset| (offset, 2)));
+    let definition = Definition {
+        big_endian,
+        size,
+        positions,
+        is_record,
+        timestamp: find(253).filter(|_| is_record),
+        altitude: altitude.filter(|_| is_record),
+    };
+    Ok((definition, len))
+}
+
+/// Reads the positions of a FIT file, in order, skipping missing ones.
+pub fn positions(data: &[u8]) -> Result<Vec<(f64, f64)>, FitError> {
+    let (slots, _) = scan(data)?;
+    Ok(slots.iter().filter_map(|slot| slot.get(data)).collect())
+}
+
+/// Reads the position records of a FIT file with their altitudes and times, in order, skipping
+/// those without a position.
+pub fn track(data: &[u8]) -> Result<Track, FitError> {
+    let (slots, _) = scan(data)?;
+    let points = slots
+        .iter()
+        .filter_map(|slot| {
+            let fix = slot.fix?;
+            let (lat, lon) = slot.get(data)?;
+            Some(TrackPoint {
+                elevation: fix.altitude,
+                time: fix
+                    .time
+                    .map(|t| UNIX_EPOCH + Duration::from_secs(FIT_EPOCH + u64::from(t))),
+                ..TrackPoint::new(lat, lon)
+            })
+        })
+        .collect();
+    Ok(Track { points })
+}
+
+/// Converts the positions of a FIT file in place, returning how many were converted.
+pub fn convert(converter: &Converter, data: &mut [u8]) -> Result<usize, FitError> {
+    let (slots, files) = scan(data)?;
+    let mut count = 0;
+    for slot in &slots {
+        if let Some((lat, lon)) = slot.get(data) {
+            slot.set(data, converter.convert(lat, lon));
+            count += 1;
+        }
+    }
+    for file in files {
+        let crc = crc16(&data[file.clone()]);
+        data[file.end..file.end + 2].copy_from_slice(&crc.to_le_bytes());
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    fn semicircles(x: f64) -> i32 {
+        (x * SEMICIRCLES_PER_DEGREE).round() as i32
+    }
+
+    /// A file with records holding a timestamp, a position and a heart rate.
+    fn activity() -> Vec<u8> {
+        let mut records = vec![
+            // Definition of local type 0 as a little-endian record.
+            0x40, 0, 0, 20, 0, 4, 253, 4, 0x86, 0, 4, 0x85, 1, 4, 0x85, 3, 1, 0x02,
+        ];
+        for &(lat, lon, hr) in &[(39.9042, 116.4074, 140u8), (31.2, 121.5, 141)] {
+            records.push(0x00);
+            records.extend_from_slice(&1000u32.to_le_bytes());
+            records.extend_from_slice(&semicircles(lat).to_le_bytes());
+            records.extend_from_slice(&semicircles(lon).to_le_bytes());
+            records.push(hr);
+        }
+        // A compressed timestamp record without position.
+        records.push(0x80 | 5);
+        records.extend_from_slice(&0u32.to_le_bytes());
+        records.extend_from_slice(&INVALID.to_le_bytes());
+        records.extend_from_slice(&INVALID.to_le_bytes());
+        records.push(142);
+        // A big-endian course point with a developer field.
+        records.extend_from_slice(&[0x61, 0, 1, 0, 32, 2, 2, 4, 0x85, 3, 4, 0x85, 1, 0, 1, 0]);
+        records.push(0x01);
+        records.extend_from_slice(&semicircles(30.0).to_be_bytes());
+        records.extend_from_slice(&semicircles(110.0).to_be_bytes());
+        records.push(7);
+        file(&records)
+    }
+
+    /// A file of the given records, with its header and CRCs.
+    fn file(records: &[u8]) -> Vec<u8> {
+        let mut data = vec![14, 0x20, 0x08, 0x08];
+        data.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        data.extend_from_slice(b".FIT");
+        data.extend_from_slice(&crc16(&data).to_le_bytes());
+        data.extend_from_slice(records);
+        data.extend_from_slice(&crc16(&data).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_convert() {
+        let input = activity();
+        let points = positions(&input).unwrap();
+        assert_eq!(points.len(), 3);
+        assert!((points[0].0 - 39.9042).abs() < 1e-7 && (points[2].1 - 110.0).abs() < 1e-7);
+
+        let converter = Converter::new(Gcj02, Wgs84);
+        let mut output = input.clone();
+        assert_eq!(convert(&converter, &mut output), Ok(3));
+        for (&(lat, lon), &(x, y)) in points.iter().zip(&positions(&output).unwrap()) {
+            let (p, q) = converter.convert(lat, lon);
+            assert!((x - p).abs() < 1e-7 && (y - q).abs() < 1e-7);
+        }
+
+        // Only the positions and the CRC change.
+        let changed: Vec<usize> = (0..input.len())
+            .filter(|&i| input[i] != output[i])
+            .collect();
+        let positions = [37..45, 51..59, 91..99, input.len() - 2..input.len()];
+        assert!(changed
+            .iter()
+            .all(|i| positions.iter().any(|r| r.contains(i))));
+    }
+
+    #[test]
+    fn test_track() {
+        let points = track(&activity()).unwrap().points;
+        assert_eq!(points.len(), 2);
+        let time = |t: u64| Some(UNIX_EPOCH + Duration::from_secs(FIT_EPOCH + t));
+        assert_eq!(points[0].time, time(1000));
+        assert_eq!(points[1].time, time(1000));
+        assert!((points[1].lat - 31.2).abs() < 1e-7);
+        assert_eq!(points[0].elevation, None);
+    }
+
+    #[test]
+    fn test_timestamp_rollover() {
+        // A full timestamp at the end of the range, then a compressed one rolling over past it.
+        let mut records = vec![0x40, 0, 0, 20, 0, 3, 253, 4, 0x86, 0, 4, 0x85, 1, 4, 0x85];
+        records.push(0x00);
+        records.extend_from_slice(&0xffff_fff0u32.to_le_bytes());
+        records.extend_from_slice(&semicircles(39.9).to_le_bytes());
+        records.extend_from_slice(&semicircles(116.4).to_le_bytes());
+        records.push(0x80 | 5);
+        records.extend_from_slice(&0u32.to_le_bytes());
+        records.extend_from_slice(&semicircles(39.91).to_le_bytes());
+        records.extend_from_slice(&semicircles(116.41).to_le_bytes());
+        let points = track(&file(&records)).unwrap().points;
+        let time = |t: u64| Some(UNIX_EPOCH + Duration::from_secs(FIT_EPOCH + t));
+        assert_eq!(points[0].time, time(0xffff_fff0));
+        assert_eq!(points[1].time, time(5));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut data = activity();
+        assert!(matches!(
+            positions(&data[..20]),
+            Err(FitError::Truncated(_))
+        ));
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert_eq!(positions(&data), Err(FitError::InvalidCrc(0)));
+        data[8] = b'X';
+        assert_eq!(positions(&data), Err(FitError::InvalidHeader(0)));
+    }
+}
@@ -0,0 +1,266 @@
+//! Integer-only WGS-84 to GCJ-02 conversion, enabled by the `embedded` feature, for trackers
+//! without an FPU.
+//!
+//! Coordinates are `i32` in units of 1e-7 degrees, as reported by most GNSS receivers. The
+//! distortion is a sum of polynomials, which are evaluated exactly in `i64`, and of sines of the
+//! latitude or of the longitude alone, which are read from tables with linear interpolation, like
+//! the two scale factors from the distortion to degrees. The tables are computed by the compiler,
+//! so that no floating-point code runs on the target; they take 10 KiB.
+//!
+//! By the bounds of the second derivatives, the interpolation error is below 0.15 m on each axis,
+//! and a dense sweep of [`crate::CHINA_BBOX`] stays below 0.16 m of [`crate::wgs_to_gcj`]: the
+//! result is guaranteed within 1 m.
+use std::f64::consts::PI;
+
+/// Bounds of [`crate::CHINA_BBOX`] in units of 1e-7 degrees.
+const MIN_LAT: i32 = 8_293_000;
+const MAX_LAT: i32 = 558_271_000;
+const MIN_LON: i32 = 720_040_000;
+const MAX_LON: i32 = 1_378_347_000;
+
+/// Units in a degree.
+const DEGREE: i64 = 10_000_000;
+/// Units of the tables, and of the distortion while it is summed, in a unit of the distortion.
+const MICRO: f64 = 1e6;
+
+/// Sine for the tables, from its Taylor series after reducing the angle to [-PI / 2, PI / 2].
+const fn sin(a: f64) -> f64 {
+    let k = (a / PI + if a < 0.0 { -0.5 } else { 0.5 }) as i64;
+    let r = a - k as f64 * PI;
+    let mut term = r;
+    let mut sum = r;
+    let mut n = 1;
+    while n < 12 {
+        term = -term * r * r / ((2 * n) * (2 * n + 1)) as f64;
+        sum += term;
+        n += 1;
+    }
+    if k % 2 == 0 {
+        sum
+    } else {
+        -sum
+    }
+}
+
+const fn sqrt(x: f64) -> f64 {
+    let mut r = 1.0;
+    let mut i = 0;
+    while i < 8 {
+        r = (r + x / r) / 2.0;
+        i += 1;
+    }
+    r
+}
+
+const fn round(x: f64) -> i32 {
+    (x + if x < 0.0 { -0.5 } else { 0.5 }) as i32
+}
+
+/// Steps of the latitude and longitude tables per degree.
+const STEPS: i64 = 16;
+/// Steps of the table of the periodic term per degree, its period.
+const PERIODIC_STEPS: i64 = 128;
+/// Steps of the scale tables per degree.
+const SCALE_STEPS: i64 = 4;
+
+/// The latitude terms of the latitude distortion, from 0 to 56 degrees of latitude.
+const LAT_TERMS: [i32; 56 * STEPS as usize + 1] = {
+    let mut table = [0; 56 * STEPS as usize + 1];
+    let mut i = 0;
+    while i < table.len() {
+        let x = i as f64 / STEPS as f64 - 35.0;
+        let p = 20.0 * sin(PI * x) + 40.0 * sin(PI / 3.0 * x);
+        let q = 160.0 * sin(PI / 12.0 * x) + 320.0 * sin(PI / 30.0 * x);
+        table[i] = round(2.0 / 3.0 * (p + q) * MICRO);
+        i += 1;
+    }
+    table
+};
+
+/// The longitude terms of the longitude distortion, from 72 to 138 degrees of longitude.
+const LON_TERMS: [i32; 66 * STEPS as usize + 1] = {
+    let mut table = [0; 66 * STEPS as usize + 1];
+    let mut i = 0;
+    while i < table.len() {
+        let y = i as f64 / STEPS as f64 + 72.0 - 105.0;
+        let p = 20.0 * sin(PI * y) + 40.0 * sin(PI / 3.0 * y);
+        let q = 150.0 * sin(PI / 12.0 * y) + 300.0 * sin(PI / 30.0 * y);
+        table[i] = round(2.0 / 3.0 * (p + q) * MICRO);
+        i += 1;
+    }
+    table
+};
+
+/// The term of both distortions with a period of one degree of longitude.
+const PERIODIC_TERMS: [i32; PERIODIC_STEPS as usize + 1] = {
+    let mut table = [0; PERIODIC_STEPS as usize + 1];
+    let mut i = 0;
+    while i < table.len() {
+        let y = i as f64 / PERIODIC_STEPS as f64;
+        let r = 20.0 * sin(6.0 * PI * y) + 20.0 * sin(2.0 * PI * y);
+        table[i] = round(2.0 / 3.0 * r * MICRO);
+        i += 1;
+    }
+    table
+};
+
+/// Degrees of latitude and longitude per unit of distortion, in millionths of a unit, from 0 to
+/// 56 degrees of latitude.
+const SCALES: [(i32, i32); 56 * SCALE_STEPS as usize + 1] = {
+    const A: f64 = 6378245.0;
+    const EE: f64 = 0.006_693_421_622_965_943;
+    let mut table = [(0, 0); 56 * SCALE_STEPS as usize + 1];
+    let mut i = 0;
+    while i < table.len() {
+        let lat = i as f64 / SCALE_STEPS as f64 * (PI / 180.0);
+        let lat_sin = sin(lat);
+        let lat_cos = sin(lat + PI / 2.0);
+        let magic = 1.0 - EE * lat_sin * lat_sin;
+        let lat_scale = 180.0 / (PI * A * (1.0 - EE) / (magic * sqrt(magic)));
+        let lon_scale = 180.0 / (PI * A / sqrt(magic) * lat_cos);
+        let units = DEGREE as f64 * MICRO;
+        table[i] = (round(lat_scale * units), round(lon_scale * units));
+        i += 1;
+    }
+    table
+};
+
+/// Reads `table` at `pos` steps, given in units, with linear interpolation.
+fn lookup(table: &[i32], pos: i64) -> i64 {
+    let (i, frac) = ((pos / DEGREE) as usize, pos % DEGREE);
+    let (a, b) = (i64::from(table[i]), i64::from(table[i + 1]));
+    a + div_round((b - a) * frac, DEGREE)
+}
+
+/// Square root rounded down, digit by digit.
+fn isqrt(n: u64) -> u64 {
+    let (mut n, mut root) = (n, 0);
+    let mut bit = 1 << 62;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if n >= root + bit {
+            n -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+fn div_round(a: i64, b: i64) -> i64 {
+    let half = b / 2;
+    if a < 0 {
+        (a - half) / b
+    } else {
+        (a + half) / b
+    }
+}
+
+/// Converts a WGS-84 coordinate into GCJ-02, in units of 1e-7 degrees, without floating-point
+/// arithmetic.
+///
+/// Coordinates outside [`crate::CHINA_BBOX`] are returned unchanged.
+pub fn wgs_to_gcj(lat: i32, lon: i32) -> (i32, i32) {
+    if !(MIN_LAT..=MAX_LAT).contains(&lat) || !(MIN_LON..=MAX_LON).contains(&lon) {
+        return (lat, lon);
+    }
+    let x = i64::from(lat) - 35 * DEGREE;
+    let y = i64::from(lon) - 105 * DEGREE;
+    // Millionths of the abs(y).sqrt() of the distortion.
+    let sqrt = isqrt(y.unsigned_abs() * 100_000) as i64;
+    let periodic = lookup(&PERIODIC_TERMS, y.rem_euclid(DEGREE) * PERIODIC_STEPS);
+
+    // In millionths, x / 10 is x in degrees and x * y / 1e8 their product.
+    let lat_t = -100_000_000
+        + (2 * y + 3 * x) / 10
+        + (2 * x * x + x * y) / 1_000_000_000
+        + sqrt / 5
+        + periodic
+        + lookup(&LAT_TERMS, i64::from(lat) * STEPS);
+    let lon_t = 300_000_000
+        + (y + 2 * x) / 10
+        + (y * y + x * y) / 1_000_000_000
+        + sqrt / 10
+        + periodic
+        + lookup(&LON_TERMS, (i64::from(lon) - 72 * DEGREE) * STEPS);
+
+    let pos = i64::from(lat) * SCALE_STEPS;
+    let (i, frac) = ((pos / DEGREE) as usize, pos % DEGREE);
+    let scale = |a: i32, b: i32| {
+        let (a, b) = (i64::from(a), i64::from(b));
+        a + div_round((b - a) * frac, DEGREE)
+    };
+    let lat_scale = scale(SCALES[i].0, SCALES[i + 1].0);
+    let lon_scale = scale(SCALES[i].1, SCALES[i + 1].1);
+    let micro2 = 1_000_000_000_000;
+    (
+        lat + div_round(lat_t * lat_scale, micro2) as i32,
+        lon + div_round(lon_t * lon_scale, micro2) as i32,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{haversine, CHINA_BBOX};
+
+    fn error(lat: i32, lon: i32) -> f64 {
+        let (p, q) = wgs_to_gcj(lat, lon);
+        let degrees = |x: i32| f64::from(x) / DEGREE as f64;
+        let expected = crate::wgs_to_gcj(degrees(lat), degrees(lon));
+        haversine((degrees(p), degrees(q)), expected)
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut max: f64 = 0.0;
+        let b = CHINA_BBOX;
+        let units = |x: f64| (x * DEGREE as f64) as i32;
+        let (min_lat, max_lat) = (units(b.min_lat), units(b.max_lat));
+        let (min_lon, max_lon) = (units(b.min_lon), units(b.max_lon));
+        // Odd steps, so that the interpolation fractions vary.
+        for lat in (min_lat..=max_lat).step_by(500_001) {
+            for lon in (min_lon..=max_lon).step_by(333_343) {
+                max = max.max(error(lat, lon));
+            }
+        }
+        for &(lat, lon) in &[
+            (MIN_LAT, MIN_LON),
+            (MAX_LAT, MAX_LON),
+            (MIN_LAT, MAX_LON),
+            (350_000_000, 1_050_000_000),
+        ] {
+            max = max.max(error(lat, lon));
+        }
+        assert!(max < 0.16, "{}", max);
+    }
+
+    #[test]
+    fn test_isqrt() {
+        for n in (0..10_000).chain([u64::from(u32::MAX), 7_300_000_000_000, u64::MAX]) {
+            let root = isqrt(n);
+            assert!(root * root <= n, "{}", n);
+            assert!((root + 1).checked_mul(root + 1).is_none_or(|next| next > n));
+        }
+    }
+
+    #[test]
+    fn test_region() {
+        assert_eq!(
+            wgs_to_gcj(488_566_000, 23_522_000),
+            (488_566_000, 23_522_000)
+        );
+        assert_eq!(
+            wgs_to_gcj(MIN_LAT - 1, 1_164_000_000),
+            (MIN_LAT - 1, 1_164_000_000)
+        );
+        assert_ne!(
+            wgs_to_gcj(399_000_000, 1_164_000_000),
+            (399_000_000, 1_164_000_000)
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Parsing and formatting of coordinate strings.
+//!
+//! Recognized inputs include decimal degrees (`"39.9042, 116.4074"`, `"39.9042 116.4074"`) and
+//! degrees-minutes-seconds with hemispheres (`"39°54'15\"N 116°24'27\"E"`, `"N 39°54.25' E
+//! 116°24.45'"`). Components marked with E/W before N/S are reordered to latitude first.
+use std::fmt;
+
+/// Error returned when a coordinate string cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseCoordError {
+    /// A character that is not part of any coordinate notation.
+    UnexpectedCharacter(char),
+    /// The string does not split into a latitude and a longitude.
+    InvalidSyntax,
+    /// A component is out of its valid range, e.g. 61 minutes or a latitude of 100°.
+    OutOfRange,
+}
+
+impl fmt::Display for ParseCoordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseCoordError::UnexpectedCharacter(c) => write!(f, "unexpected character {:?}", c),
+            ParseCoordError::InvalidSyntax => f.write_str("not a latitude/longitude pair"),
+            ParseCoordError::OutOfRange => f.write_str("coordinate component out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ParseCoordError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(f64),
+    Degree,
+    Minute,
+    Second,
+    Hemisphere(char),
+    Separator,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, ParseCoordError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '0'..='9' | '.' | '-' | '+' => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !c.is_ascii_digit() && c != '.' {
+                        break;
+                    }
+                    end = i + 1;
+                    chars.next();
+                }
+                let text = &s[start..end];
+                Token::Number(text.parse().map_err(|_| ParseCoordError::InvalidSyntax)?)
+            }
+            '°' | 'º' => Token::Degree,
+            '\'' | '′' | '’' => {
+                // Two single quotes are often typed instead of a double quote.
+                if c == '\'' && chars.peek().map(|&(_, c)| c) == Some('\'') {
+                    chars.next();
+                    Token::Second
+                } else {
+                    Token::Minute
+                }
+            }
+            '"' | '″' | '”' => Token::Second,
+            'N' | 'S' | 'E' | 'W' => Token::Hemisphere(c),
+            'n' | 's' | 'e' | 'w' => Token::Hemisphere(c.to_ascii_uppercase()),
+            ',' | ';' | '/' => Token::Separator,
+            c => return Err(ParseCoordError::UnexpectedCharacter(c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Splits the tokens into the two components.
+fn split(tokens: &[Token]) -> Result<(&[Token], &[Token]), ParseCoordError> {
+    let count = |f: fn(&Token) -> bool| tokens.iter().filter(|t| f(t)).count();
+
+    let at = if let Some(i) = tokens.iter().position(|t| *t == Token::Separator) {
+        i
+    } else if count(|t| matches!(t, Token::Hemisphere(_))) == 2 {
+        let mut hemispheres = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| matches!(t, Token::Hemisphere(_)));
+        let (first, _) = hemispheres.next().unwrap();
+        let (second, _) = hemispheres.next().unwrap();
+        if first == 0 {
+            second
+        } else {
+            first + 1
+        }
+    } else if count(|t| *t == Token::Degree) == 2 {
+        // The second component starts at the number preceding its degree sign.
+        let degree = tokens.iter().rposition(|t| *t == Token::Degree).unwrap();
+        degree
+            .checked_sub(1)
+            .ok_or(ParseCoordError::InvalidSyntax)?
+    } else if tokens.iter().all(|t| matches!(t, Token::Number(_)))
+        && matches!(tokens.len(), 2 | 4 | 6)
+    {
+        tokens.len() / 2
+    } else {
+        return Err(ParseCoordError::InvalidSyntax);
+    };
+
+    let second = match tokens.get(at) {
+        Some(Token::Separator) => &tokens[at + 1..],
+        _ => &tokens[at..],
+    };
+    Ok((&tokens[..at], second))
+}
+
+/// Evaluates a component, returning its signed value in degrees and its hemisphere, if any.
+fn component(tokens: &[Token]) -> Result<(f64, Option<char>), ParseCoordError> {
+    let mut hemisphere = None;
+    let mut numbers = Vec::with_capacity(3);
+    for (i, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Hemisphere(c) if hemisphere.is_none() && (i == 0 || i == tokens.len() - 1) => {
+                hemisphere = Some(c)
+            }
+            Token::Number(x) if numbers.len() < 3 => numbers.push(x),
+            // Units are only checked for being in order.
+            Token::Degree if numbers.len() == 1 => {}
+            Token::Minute if numbers.len() == 2 => {}
+            Token::Second if numbers.len() == 3 => {}
+            _ => return Err(ParseCoordError::InvalidSyntax),
+        }
+    }
+
+    let degrees = *numbers.first().ok_or(ParseCoordError::InvalidSyntax)?;
+    let fractions = &numbers[1..];
+    if fractions
+        .iter()
+        .any(|x| !(0.0..60.0).contains(x) || x.is_sign_negative())
+    {
+        return Err(ParseCoordError::OutOfRange);
+    }
+    let magnitude = fractions.iter().rev().fold(0.0, |acc, x| (acc + x) / 60.0) + degrees.abs();
+
+    let negative = degrees.is_sign_negative() || matches!(hemisphere, Some('S') | Some('W'));
+    if degrees.is_sign_negative() && hemisphere.is_some() {
+        return Err(ParseCoordError::InvalidSyntax);
+    }
+    Ok((if negative { -magnitude } else { magnitude }, hemisphere))
+}
+
+/// Parses a coordinate string into latitude and longitude.
+pub fn parse_coordinate(s: &str) -> Result<(f64, f64), ParseCoordError> {
+    let tokens = tokenize(s)?;
+    let (first, second) = split(&tokens)?;
+    let (a, a_hemisphere) = component(first)?;
+    let (b, b_hemisphere) = component(second)?;
+
+    let is_lon = |h| matches!(h, Some('E') | Some('W'));
+    let is_lat = |h| matches!(h, Some('N') | Some('S'));
+    let (lat, lon) = match (a_hemisphere, b_hemisphere) {
+        (x, y) if is_lon(x) && !is_lon(y) => (b, a),
+        (x, y) if (is_lat(x) && is_lat(y)) || (is_lon(x) && is_lon(y)) => {
+            return Err(ParseCoordError::InvalidSyntax)
+        }
+        _ => (a, b),
+    };
+
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(ParseCoordError::OutOfRange);
+    }
+    Ok((lat, lon))
+}
+
+/// Formats a coordinate as decimal degrees, e.g. `"39.904200, 116.407400"`.
+pub fn format_decimal(lat: f64, lon: f64, precision: usize) -> String {
+    format!("{:.*}, {:.*}", precision, lat, precision, lon)
+}
+
+fn format_dms_component(value: f64, positive: char, negative: char, precision: usize) -> String {
+    let hemisphere = if value < 0.0 { negative } else { positive };
+    // Round once on the total so that carries propagate, e.g. 59.99" becoming 1'.
+    let scale = 10f64.powi(precision as i32);
+    let total = (value.abs() * 3600.0 * scale).round();
+    let seconds = total % (60.0 * scale) / scale;
+    let minutes = (total / (60.0 * scale)).floor() % 60.0;
+    let degrees = (total / (3600.0 * scale)).floor();
+    format!(
+        "{}°{}'{:.*}\"{}",
+        degrees, minutes, precision, seconds, hemisphere
+    )
+}
+
+/// Formats a coordinate as degrees, minutes and seconds, e.g. `"39°54'15\"N 116°24'27\"E"`.
+pub fn format_dms(lat: f64, lon: f64, precision: usize) -> String {
+    format!(
+        "{} {}",
+        format_dms_component(lat, 'N', 'S', precision),
+        format_dms_component(lon, 'E', 'W', precision)
+    )
+}
+
+/// How many digits the file converters write for converted coordinates, see
+/// [`crate::Converter::with_precision`].
+///
+/// The shortest text reading back to the same value often has 15 to 17 significant digits,
+/// implying a precision far below a millimeter. Six decimal places are about 0.1 m, and eight
+/// about a millimeter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Precision {
+    /// The shortest text reading back to the same value.
+    #[default]
+    Shortest,
+    /// At most this many decimal places.
+    Decimals(usize),
+    /// At most this many significant digits; the digits before the decimal point are kept.
+    Significant(usize),
+}
+
+impl Precision {
+    /// Formats a number, without trailing zeros after the decimal point.
+    pub fn format(self, x: f64) -> String {
+        let decimals = match self {
+            Precision::Shortest => return format!("{}", x),
+            Precision::Decimals(decimals) => decimals,
+            Precision::Significant(digits) if x.is_normal() => {
+                let exponent = x.abs().log10().floor() as i64;
+                (digits as i64 - 1 - exponent).max(0) as usize
+            }
+            Precision::Significant(digits) => digits.saturating_sub(1),
+        };
+        let text = format!("{:.*}", decimals, x);
+        let text = if text.contains('.') {
+            text.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            &text
+        };
+        match text {
+            "-0" => "0".to_owned(),
+            text => text.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::eps_assert;
+
+    fn parse_assert(s: &str, (lat, lon): (f64, f64)) {
+        let (x, y) = parse_coordinate(s).unwrap_or_else(|e| panic!("{:?}: {}", s, e));
+        eps_assert(x, lat, "lat");
+        eps_assert(y, lon, "lon");
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        parse_assert("39.9042, 116.4074", (39.9042, 116.4074));
+        parse_assert("39.9042 116.4074", (39.9042, 116.4074));
+        parse_assert("-33.8688,151.2093", (-33.8688, 151.2093));
+        parse_assert("39.9042°N, 116.4074°E", (39.9042, 116.4074));
+    }
+
+    #[test]
+    fn test_parse_dms() {
+        let beijing = (
+            39.0 + 54.0 / 60.0 + 15.0 / 3600.0,
+            116.0 + 24.0 / 60.0 + 27.0 / 3600.0,
+        );
+        parse_assert("39°54'15\"N 116°24'27\"E", beijing);
+        parse_assert("39°54′15″N, 116°24′27″E", beijing);
+        parse_assert("N 39°54'15'' E 116°24'27''", beijing);
+        parse_assert("116°24'27\"E 39°54'15\"N", beijing);
+        parse_assert("39 54 15 116 24 27", beijing);
+        parse_assert("33°52.128'S 151°12.558'E", (-33.8688, 151.2093));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        use ParseCoordError::*;
+        assert_eq!(parse_coordinate(""), Err(InvalidSyntax));
+        assert_eq!(parse_coordinate("39.9"), Err(InvalidSyntax));
+        assert_eq!(
+            parse_coordinate("39.9, 116.4x"),
+            Err(UnexpectedCharacter('x'))
+        );
+        assert_eq!(parse_coordinate("39°61'N 116°E"), Err(OutOfRange));
+        assert_eq!(parse_coordinate("116.4, 39.9"), Err(OutOfRange));
+        assert_eq!(parse_coordinate("39°N 40°N"), Err(InvalidSyntax));
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_decimal(39.9042, 116.4074, 4), "39.9042, 116.4074");
+        let (lat, lon) = parse_coordinate("39°54'15\"N 116°24'27\"E").unwrap();
+        assert_eq!(format_dms(lat, lon, 0), "39°54'15\"N 116°24'27\"E");
+        assert_eq!(format_dms(-33.8688, -70.0, 1), "33°52'7.7\"S 70°0'0.0\"W");
+        assert_eq!(format_dms(10.0 - 1e-9, 0.0, 2), "10°0'0.00\"N 0°0'0.00\"E");
+    }
+
+    #[test]
+    fn test_precision() {
+        let x = 116.407412345678;
+        assert_eq!(Precision::Shortest.format(x), "116.407412345678");
+        assert_eq!(Precision::Decimals(6).format(x), "116.407412");
+        assert_eq!(Precision::Decimals(0).format(x), "116");
+        assert_eq!(Precision::Decimals(6).format(39.9), "39.9");
+        assert_eq!(Precision::Decimals(3).format(-0.0001), "0");
+        assert_eq!(Precision::Significant(9).format(x), "116.407412");
+        assert_eq!(
+            Precision::Significant(9).format(-0.012345678912),
+            "-0.0123456789"
+        );
+        assert_eq!(Precision::Significant(2).format(x), "116");
+        assert_eq!(Precision::Significant(3).format(0.0), "0");
+        assert_eq!(Precision::Decimals(6).format(f64::NAN), "NaN");
+    }
+}
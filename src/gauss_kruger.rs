@@ -0,0 +1,329 @@
+//! Gauss-Krüger projections in 3° and 6° zones, as used by Chinese surveying and mapping.
+//!
+//! The transverse Mercator is computed with the series of Krüger to the sixth order in the third
+//! flattening, as given by Karney, which is accurate to the millimeter within a zone and beyond.
+//! The scale on the central meridian is one, and eastings are offset by 500 km, optionally
+//! prefixed by the zone number as on Chinese maps.
+//!
+//! CGCS2000 coordinates match WGS-84 to a few centimeters, so a survey in CGCS2000 is brought
+//! into the web map systems by unprojecting it and converting from [`GeodeticSystem::Wgs84`]:
+//!
+//! ```
+//! use undrift_gps::gauss_kruger::{Ellipsoid, GaussKruger, ZoneWidth};
+//! use undrift_gps::transform::Transform;
+//! use undrift_gps::{Converter, GeodeticSystem::*};
+//!
+//! let zone = GaussKruger::zone(Ellipsoid::Cgcs2000, ZoneWidth::Three, 39).with_zone_prefix();
+//! let to_gcj = zone.inverse().then(Converter::new(Wgs84, Gcj02));
+//! let (lat, lon) = to_gcj.apply(39_445_000.0, 4_419_000.0);
+//! ```
+//!
+//! Beijing-54 and Xian-80 coordinates are on other datums, up to a hundred meters from WGS-84:
+//! the projection is the same, but its latitudes and longitudes have to be shifted with
+//! [`crate::datum`] first.
+//!
+//! [`GeodeticSystem::Wgs84`]: crate::GeodeticSystem::Wgs84
+use crate::transform::Transform;
+
+/// Reference ellipsoid of a projection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ellipsoid {
+    /// China Geodetic Coordinate System 2000, with the ellipsoid of GRS 80.
+    Cgcs2000,
+    /// Beijing 1954, with the ellipsoid of Krassovsky.
+    Beijing54,
+    /// Xian 1980, with the ellipsoid of IAG 1975.
+    Xian80,
+}
+
+impl Ellipsoid {
+    /// Semi-major axis in meters and flattening.
+    pub fn parameters(self) -> (f64, f64) {
+        match self {
+            Ellipsoid::Cgcs2000 => (6_378_137.0, 1.0 / 298.257_222_101),
+            Ellipsoid::Beijing54 => (6_378_245.0, 1.0 / 298.3),
+            Ellipsoid::Xian80 => (6_378_140.0, 1.0 / 298.257),
+        }
+    }
+}
+
+/// Width of the zones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ZoneWidth {
+    /// Zones centered on multiples of 3°, numbered 25 to 45 over China.
+    Three,
+    /// Zones from 0° eastward, centered on 3°, 9°, and so on, numbered 13 to 23 over China.
+    Six,
+}
+
+impl ZoneWidth {
+    /// Number of the zone of a longitude.
+    pub fn zone(self, lon: f64) -> u32 {
+        let lon = lon.rem_euclid(360.0);
+        match self {
+            ZoneWidth::Three => ((lon / 3.0).round() as u32).max(1),
+            ZoneWidth::Six => (lon / 6.0).floor() as u32 + 1,
+        }
+    }
+
+    /// Central meridian of a zone.
+    pub fn central_meridian(self, zone: u32) -> f64 {
+        match self {
+            ZoneWidth::Three => 3.0 * f64::from(zone),
+            ZoneWidth::Six => 6.0 * f64::from(zone) - 3.0,
+        }
+    }
+}
+
+/// A Gauss-Krüger projection of `(lat, lon)` in degrees to `(x, y)` in meters, easting first.
+///
+/// Chinese surveys write the northing first, as X, and the easting second, as Y.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GaussKruger {
+    pub ellipsoid: Ellipsoid,
+    pub central_meridian: f64,
+    /// Added to the eastings: 500 km, plus the zone number in millions of meters if prefixed.
+    pub false_easting: f64,
+    zone: Option<u32>,
+}
+
+/// The coefficients of the series of an ellipsoid.
+struct Series {
+    /// Eccentricity.
+    e: f64,
+    /// Radius of the rectifying sphere.
+    a: f64,
+    alpha: [f64; 6],
+    beta: [f64; 6],
+}
+
+impl Series {
+    fn new(ellipsoid: Ellipsoid) -> Self {
+        let (a, f) = ellipsoid.parameters();
+        let n = f / (2.0 - f);
+        let (n2, n3) = (n * n, n * n * n);
+        let (n4, n5, n6) = (n2 * n2, n2 * n3, n3 * n3);
+        Series {
+            e: (f * (2.0 - f)).sqrt(),
+            a: a / (1.0 + n) * (1.0 + n2 / 4.0 + n4 / 64.0 + n6 / 256.0),
+            alpha: [
+                n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0 + 41.0 * n4 / 180.0 - 127.0 * n5 / 288.0
+                    + 7891.0 * n6 / 37800.0,
+                13.0 * n2 / 48.0 - 3.0 * n3 / 5.0 + 557.0 * n4 / 1440.0 + 281.0 * n5 / 630.0
+                    - 1_983_433.0 * n6 / 1_935_360.0,
+                61.0 * n3 / 240.0 - 103.0 * n4 / 140.0
+                    + 15061.0 * n5 / 26880.0
+                    + 167_603.0 * n6 / 181_440.0,
+                49561.0 * n4 / 161_280.0 - 179.0 * n5 / 168.0 + 6_601_661.0 * n6 / 7_257_600.0,
+                34729.0 * n5 / 80640.0 - 3_418_889.0 * n6 / 1_995_840.0,
+                212_378_941.0 * n6 / 319_334_400.0,
+            ],
+            beta: [
+                n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0 - n4 / 360.0 - 81.0 * n5 / 512.0
+                    + 96199.0 * n6 / 604_800.0,
+                n2 / 48.0 + n3 / 15.0 - 437.0 * n4 / 1440.0 + 46.0 * n5 / 105.0
+                    - 1_118_711.0 * n6 / 3_870_720.0,
+                17.0 * n3 / 480.0 - 37.0 * n4 / 840.0 - 209.0 * n5 / 4480.0 + 5569.0 * n6 / 90720.0,
+                4397.0 * n4 / 161_280.0 - 11.0 * n5 / 504.0 - 830_251.0 * n6 / 7_257_600.0,
+                4583.0 * n5 / 161_280.0 - 108_847.0 * n6 / 3_991_680.0,
+                20_648_693.0 * n6 / 638_668_800.0,
+            ],
+        }
+    }
+
+    /// The conformal latitude, as a tangent, of the tangent of a latitude.
+    fn conformal(&self, tau: f64) -> f64 {
+        let sigma = (self.e * (self.e * tau / tau.hypot(1.0)).atanh()).sinh();
+        tau * sigma.hypot(1.0) - sigma * tau.hypot(1.0)
+    }
+}
+
+impl GaussKruger {
+    /// The projection around a central meridian, with eastings offset by 500 km.
+    pub fn new(ellipsoid: Ellipsoid, central_meridian: f64) -> Self {
+        GaussKruger {
+            ellipsoid,
+            central_meridian,
+            false_easting: 500_000.0,
+            zone: None,
+        }
+    }
+
+    /// The projection of a numbered zone.
+    pub fn zone(ellipsoid: Ellipsoid, width: ZoneWidth, zone: u32) -> Self {
+        GaussKruger {
+            zone: Some(zone),
+            ..GaussKruger::new(ellipsoid, width.central_meridian(zone))
+        }
+    }
+
+    /// The projection of the zone holding a longitude.
+    pub fn for_longitude(ellipsoid: Ellipsoid, width: ZoneWidth, lon: f64) -> Self {
+        GaussKruger::zone(ellipsoid, width, width.zone(lon))
+    }
+
+    /// Prefixes the eastings with the zone number, as 39 for 39 445 000 m in 3° zone 39.
+    pub fn with_zone_prefix(mut self) -> Self {
+        let zone = self.zone.expect("only numbered zones have a prefix");
+        self.false_easting = f64::from(zone) * 1e6 + 500_000.0;
+        self
+    }
+
+    /// Projects `(lat, lon)` to `(easting, northing)`.
+    pub fn project(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let series = Series::new(self.ellipsoid);
+        let lambda = (lon - self.central_meridian).to_radians();
+        let tau = series.conformal(lat.to_radians().tan());
+        let xi = tau.atan2(lambda.cos());
+        let eta = (lambda.sin() / tau.hypot(lambda.cos())).asinh();
+        let (mut x, mut y) = (eta, xi);
+        for (j, alpha) in series.alpha.iter().enumerate() {
+            let k = 2.0 * (j + 1) as f64;
+            x += alpha * (k * xi).cos() * (k * eta).sinh();
+            y += alpha * (k * xi).sin() * (k * eta).cosh();
+        }
+        (series.a * x + self.false_easting, series.a * y)
+    }
+
+    /// Inverse of [`GaussKruger::project`], from `(easting, northing)` to `(lat, lon)`.
+    pub fn unproject(&self, x: f64, y: f64) -> (f64, f64) {
+        let series = Series::new(self.ellipsoid);
+        let (eta, xi) = ((x - self.false_easting) / series.a, y / series.a);
+        let (mut eta1, mut xi1) = (eta, xi);
+        for (j, beta) in series.beta.iter().enumerate() {
+            let k = 2.0 * (j + 1) as f64;
+            eta1 -= beta * (k * xi).cos() * (k * eta).sinh();
+            xi1 -= beta * (k * xi).sin() * (k * eta).cosh();
+        }
+        let tau1 = xi1.sin() / eta1.sinh().hypot(xi1.cos());
+        let lambda = eta1.sinh().atan2(xi1.cos());
+
+        // Newton's method on the conformal latitude, which converges in a few steps.
+        let e2 = series.e * series.e;
+        let mut tau = tau1;
+        for _ in 0..5 {
+            let t = series.conformal(tau);
+            let step = (tau1 - t) / t.hypot(1.0) * (1.0 + (1.0 - e2) * tau * tau)
+                / ((1.0 - e2) * tau.hypot(1.0));
+            tau += step;
+            if step.abs() < 1e-14 {
+                break;
+            }
+        }
+        (
+            tau.atan().to_degrees(),
+            self.central_meridian + lambda.to_degrees(),
+        )
+    }
+
+    /// The inverse projection as a [`Transform`].
+    pub fn inverse(self) -> GaussKrugerInverse {
+        GaussKrugerInverse(self)
+    }
+}
+
+impl Transform for GaussKruger {
+    fn apply(&self, lat: f64, lon: f64) -> (f64, f64) {
+        self.project(lat, lon)
+    }
+}
+
+/// Inverse of a [`GaussKruger`] projection, from `(easting, northing)` to `(lat, lon)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GaussKrugerInverse(pub GaussKruger);
+
+impl Transform for GaussKrugerInverse {
+    fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        self.0.unproject(x, y)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_meridian() {
+        // Meridian arcs of GRS 80: to 45° and to the pole.
+        let projection = GaussKruger::new(Ellipsoid::Cgcs2000, 117.0);
+        let (x, y) = projection.project(45.0, 117.0);
+        assert!(
+            (x - 500_000.0).abs() < 1e-6 && (y - 4_984_944.378).abs() < 1e-3,
+            "{}",
+            y
+        );
+        let (_, y) = projection.project(90.0, 117.0);
+        assert!((y - 10_001_965.729).abs() < 1e-3, "{}", y);
+        let (x, y) = projection.project(0.0, 118.0);
+        assert!(x > 500_000.0 && y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_series() {
+        // The power series of Snyder, accurate to the millimeter at the edge of 3° zones.
+        let (a, f) = Ellipsoid::Cgcs2000.parameters();
+        let e2 = f * (2.0 - f);
+        let ep2 = e2 / (1.0 - e2);
+        let projection = GaussKruger::new(Ellipsoid::Cgcs2000, 117.0);
+        for &(lat, lon) in &[(39.9, 115.5), (22.3, 118.5), (53.5, 116.2)] {
+            let phi = f64::to_radians(lat);
+            let n = a / (1.0 - e2 * phi.sin().powi(2)).sqrt();
+            let t = phi.tan().powi(2);
+            let c = ep2 * phi.cos().powi(2);
+            let l = f64::to_radians(lon - 117.0) * phi.cos();
+            let m = a
+                * ((1.0 - e2 / 4.0 - 3.0 * e2 * e2 / 64.0 - 5.0 * e2.powi(3) / 256.0) * phi
+                    - (3.0 * e2 / 8.0 + 3.0 * e2 * e2 / 32.0 + 45.0 * e2.powi(3) / 1024.0)
+                        * (2.0 * phi).sin()
+                    + (15.0 * e2 * e2 / 256.0 + 45.0 * e2.powi(3) / 1024.0) * (4.0 * phi).sin()
+                    - 35.0 * e2.powi(3) / 3072.0 * (6.0 * phi).sin());
+            let x = n
+                * (l + (1.0 - t + c) * l.powi(3) / 6.0
+                    + (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * l.powi(5) / 120.0);
+            let y = m + n
+                * phi.tan()
+                * (l * l / 2.0
+                    + (5.0 - t + 9.0 * c + 4.0 * c * c) * l.powi(4) / 24.0
+                    + (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2) * l.powi(6) / 720.0);
+            let (px, py) = projection.project(lat, lon);
+            assert!(
+                (px - 500_000.0 - x).abs() < 1e-2,
+                "{} {}",
+                px - 500_000.0,
+                x
+            );
+            assert!((py - y).abs() < 1e-2, "{} {}", py, y);
+        }
+    }
+
+    #[test]
+    fn test_zones() {
+        assert_eq!(ZoneWidth::Three.zone(116.4), 39);
+        assert_eq!(ZoneWidth::Six.zone(116.4), 20);
+        assert_eq!(ZoneWidth::Six.central_meridian(20), 117.0);
+        let zone = GaussKruger::for_longitude(Ellipsoid::Cgcs2000, ZoneWidth::Three, 116.4);
+        assert_eq!(zone.central_meridian, 117.0);
+        let prefixed = zone.with_zone_prefix();
+        let (x, y) = zone.project(39.9, 116.4);
+        assert_eq!(prefixed.project(39.9, 116.4), (x + 39e6, y));
+
+        // Xian-80 coordinates of the same numbers are a few meters apart, by the ellipsoid.
+        let xian = GaussKruger::new(Ellipsoid::Xian80, 117.0);
+        let (p, q) = xian.project(39.9, 116.4);
+        assert!((p - x).hypot(q - y) > 1.0 && (p - x).hypot(q - y) < 10.0);
+    }
+
+    #[test]
+    fn test_inverse() {
+        for &ellipsoid in &[Ellipsoid::Cgcs2000, Ellipsoid::Beijing54, Ellipsoid::Xian80] {
+            for &width in &[ZoneWidth::Three, ZoneWidth::Six] {
+                for &(lat, lon) in &[(39.9, 116.4), (18.2, 109.5), (53.5, 123.2), (-33.9, 151.2)] {
+                    let projection = GaussKruger::for_longitude(ellipsoid, width, lon);
+                    let (x, y) = projection.project(lat, lon);
+                    let (lat2, lon2) = projection.inverse().apply(x, y);
+                    assert!((lat2 - lat).abs() < 1e-10 && (lon2 - lon).abs() < 1e-10);
+                }
+            }
+        }
+    }
+}
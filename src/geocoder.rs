@@ -0,0 +1,503 @@
+//! Clients of the geocoders of Amap and Baidu, enabled by the `http` feature, returning
+//! coordinates in the system of the caller.
+//!
+//! Amap answers in GCJ-02 and Baidu in BD-09, and mixing those coordinates with WGS-84 state is
+//! the classic mistake: [`Geocoder`] converts the coordinates of the caller before sending them,
+//! and those of the responses before returning them. [`convert_response`] does the latter for
+//! responses fetched otherwise.
+//!
+//! **Both services take the key in the URL, so a request over plain HTTP hands it to anyone on
+//! the path.** [`Geocoder::with_transport`] sends the requests through a client of the caller,
+//! such as one speaking HTTPS. Without one, they go over plain HTTP with [`crate::http::get`], and
+//! are refused unless the base URL is on a loopback address, such as a local relay adding TLS, or
+//! [`Geocoder::with_plain_http`] allows them.
+use crate::json::{format_number, JsonError, Value};
+use crate::{http, Converter, GeodeticSystem};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::{fmt, io};
+
+/// A client fetching the body of a URL, see [`Geocoder::with_transport`].
+pub type Transport = Arc<dyn Fn(&str) -> io::Result<Vec<u8>> + Send + Sync>;
+
+/// A geocoding service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Service {
+    /// The web service API of Amap (AutoNavi), with coordinates as `"lng,lat"` strings.
+    Amap,
+    /// The web service API of Baidu Maps, with `{"lng":..,"lat":..}` objects.
+    Baidu,
+}
+
+impl Service {
+    /// System of the coordinates of the service.
+    pub fn system(self) -> GeodeticSystem {
+        match self {
+            Service::Amap => GeodeticSystem::Gcj02,
+            Service::Baidu => GeodeticSystem::Bd09,
+        }
+    }
+
+    fn base_url(self) -> &'static str {
+        match self {
+            Service::Amap => "https://restapi.amap.com",
+            Service::Baidu => "https://api.map.baidu.com",
+        }
+    }
+}
+
+/// A place found by [`Geocoder::geocode`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+    /// Normalized address, which Baidu does not return.
+    pub address: Option<String>,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// A client of a geocoding service, for a caller working in `system`.
+#[derive(Clone)]
+pub struct Geocoder {
+    service: Service,
+    key: String,
+    system: GeodeticSystem,
+    base_url: String,
+    transport: Option<Transport>,
+    plain_http: bool,
+}
+
+impl fmt::Debug for Geocoder {
+    /// Leaves the key out.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Geocoder")
+            .field("service", &self.service)
+            .field("system", &self.system)
+            .field("base_url", &self.base_url)
+            .field("plain_http", &self.plain_http)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Whether the host of `url` is a loopback address.
+fn is_loopback(url: &str) -> bool {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split('/').next().unwrap_or("");
+    match http::split_authority(authority) {
+        Ok((host, _)) => {
+            host.eq_ignore_ascii_case("localhost")
+                || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+        }
+        Err(_) => false,
+    }
+}
+
+fn invalid_json(e: JsonError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl Geocoder {
+    /// A client authenticating with `key`, the `key` of Amap or the `ak` of Baidu.
+    pub fn new(service: Service, key: &str, system: GeodeticSystem) -> Self {
+        Geocoder {
+            service,
+            key: key.to_owned(),
+            system,
+            base_url: service.base_url().to_owned(),
+            transport: None,
+            plain_http: false,
+        }
+    }
+
+    /// Sends the requests to `url` instead of the service, such as a relay adding TLS.
+    pub fn with_base_url(mut self, url: &str) -> Self {
+        self.base_url = url.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Fetches the URLs of the requests, key included, with `transport` instead of
+    /// [`crate::http::get`], such as a client speaking HTTPS. The URLs start with the base URL,
+    /// `https://` for the services.
+    pub fn with_transport<F>(mut self, transport: F) -> Self
+    where
+        F: Fn(&str) -> io::Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Allows sending the requests without a transport to a base URL that is not on a loopback
+    /// address, over plain HTTP even if the base URL is `https://`.
+    ///
+    /// **The key is then readable, and the responses can be forged, by anyone on the path.**
+    pub fn with_plain_http(mut self, allow: bool) -> Self {
+        self.plain_http = allow;
+        self
+    }
+
+    fn fetch(&self, url: &str) -> io::Result<Vec<u8>> {
+        if let Some(transport) = &self.transport {
+            return transport(url);
+        }
+        if self.plain_http {
+            return match url.strip_prefix("https://") {
+                Some(rest) => http::get(&format!("http://{}", rest)),
+                None => http::get(url),
+            };
+        }
+        if !is_loopback(url) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "refusing to send the key over plain HTTP without a transport",
+            ));
+        }
+        http::get(url)
+    }
+
+    /// Calls an endpoint of the service, such as `/v3/place/text`, with the key added to
+    /// `query`. Returns the response with its coordinates converted, or an error carrying the
+    /// message of the service.
+    ///
+    /// Coordinates of `query` are sent as given, in the system of the service. Without a
+    /// transport, requests to a base URL that is not on a loopback address fail with
+    /// [`io::ErrorKind::PermissionDenied`] unless plain HTTP is allowed.
+    pub fn request(&self, path: &str, query: &[(&str, &str)]) -> io::Result<String> {
+        let mut url = format!("{}{}?", self.base_url, path);
+        let key = match self.service {
+            Service::Amap => "key",
+            Service::Baidu => "ak",
+        };
+        url.push_str(&format!("{}={}", key, http::percent_encode(&self.key)));
+        if self.service == Service::Baidu {
+            url.push_str("&output=json");
+        }
+        for (name, value) in query {
+            url.push_str(&format!("&{}={}", name, http::percent_encode(value)));
+        }
+        let body = String::from_utf8(self.fetch(&url)?)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "response is not UTF-8"))?;
+
+        let document = Value::parse(&body).map_err(invalid_json)?;
+        let status = document.get("status");
+        let ok = match self.service {
+            Service::Amap => status.and_then(Value::as_str) == Some("1"),
+            Service::Baidu => status.and_then(Value::as_f64) == Some(0.0),
+        };
+        if !ok {
+            let message = ["info", "message", "msg"]
+                .iter()
+                .find_map(|key| document.get(key).and_then(Value::as_str))
+                .unwrap_or("unknown error");
+            return Err(io::Error::other(format!(
+                "{:?} geocoder: {}",
+                self.service, message
+            )));
+        }
+        convert_response(self.service, &body, self.system).map_err(invalid_json)
+    }
+
+    /// Finds the places at `address`, best first.
+    pub fn geocode(&self, address: &str) -> io::Result<Vec<Place>> {
+        let path = match self.service {
+            Service::Amap => "/v3/geocode/geo",
+            Service::Baidu => "/geocoding/v3/",
+        };
+        let body = self.request(path, &[("address", address)])?;
+        let document = Value::parse(&body).map_err(invalid_json)?;
+        let missing = || io::Error::new(io::ErrorKind::InvalidData, "unexpected response");
+        match self.service {
+            Service::Amap => {
+                let geocodes = match document.get("geocodes") {
+                    Some(Value::Array(geocodes)) => geocodes,
+                    _ => return Err(missing()),
+                };
+                let mut places = Vec::with_capacity(geocodes.len());
+                for geocode in geocodes {
+                    let location = geocode.get("location").and_then(Value::as_str);
+                    let (lat, lon) = location.and_then(parse_pair).ok_or_else(missing)?;
+                    let address = geocode.get("formatted_address").and_then(Value::as_str);
+                    places.push(Place {
+                        address: address.map(str::to_owned),
+                        lat,
+                        lon,
+                    });
+                }
+                Ok(places)
+            }
+            Service::Baidu => {
+                let location = document.get("result").and_then(|r| r.get("location"));
+                let get = |key| location.and_then(|l| l.get(key)).and_then(Value::as_f64);
+                match (get("lat"), get("lng")) {
+                    (Some(lat), Some(lon)) => Ok(vec![Place {
+                        address: None,
+                        lat,
+                        lon,
+                    }]),
+                    _ => Err(missing()),
+                }
+            }
+        }
+    }
+
+    /// Address of a coordinate of the caller, empty where the service has none.
+    pub fn reverse_geocode(&self, lat: f64, lon: f64) -> io::Result<String> {
+        let (lat, lon) = self.system.convert_to(self.service.system(), lat, lon);
+        let document = match self.service {
+            Service::Amap => {
+                let location = format!("{:.6},{:.6}", lon, lat);
+                let body = self.request("/v3/geocode/regeo", &[("location", &location)])?;
+                Value::parse(&body)
+                    .map_err(invalid_json)?
+                    .get("regeocode")
+                    .cloned()
+            }
+            Service::Baidu => {
+                let location = format!("{:.6},{:.6}", lat, lon);
+                let query = [("location", &location[..]), ("coordtype", "bd09ll")];
+                let body = self.request("/reverse_geocoding/v3/", &query)?;
+                Value::parse(&body)
+                    .map_err(invalid_json)?
+                    .get("result")
+                    .cloned()
+            }
+        };
+        // Amap writes an empty array for a missing address.
+        Ok(document
+            .as_ref()
+            .and_then(|d| d.get("formatted_address"))
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_owned())
+    }
+}
+
+/// Parses `"lng,lat"` into a latitude and a longitude.
+fn parse_pair(text: &str) -> Option<(f64, f64)> {
+    let (lon, lat) = text.split_once(',')?;
+    Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?))
+}
+
+/// Formats `x` with as many decimals as `original`, the spelling of the value it replaces.
+fn format_like(original: &str, x: f64) -> String {
+    if original.contains(['e', 'E']) {
+        return format_number(x);
+    }
+    let decimals = original.split('.').nth(1).map_or(0, str::len);
+    format!("{:.*}", decimals, x)
+}
+
+/// Converts the coordinates of a response of `service` into `to`, leaving the rest of the
+/// document as it is.
+///
+/// Coordinates of Amap are the `"lng,lat"` strings of the members named `location` or ending
+/// so, such as `entr_location`, and the `"lng,lat;lng,lat"` strings of `polyline` members. Those
+/// of Baidu are the objects with `lng` and `lat` numbers, and the `{"x":..,"y":..}` objects of
+/// `point` members.
+pub fn convert_response(
+    service: Service,
+    body: &str,
+    to: GeodeticSystem,
+) -> Result<String, JsonError> {
+    let mut document = Value::parse(body)?;
+    let converter = Converter::new(service.system(), to);
+    convert_value(service, &converter, None, &mut document);
+    Ok(document.to_string())
+}
+
+fn convert_value(service: Service, converter: &Converter, key: Option<&str>, value: &mut Value) {
+    match value {
+        Value::String(text) if service == Service::Amap => {
+            let key = key.unwrap_or("");
+            if !key.ends_with("location") && key != "polyline" {
+                return;
+            }
+            let mut converted = Vec::new();
+            for pair in text.split(';') {
+                let numbers = pair.split_once(',');
+                match (numbers, parse_pair(pair)) {
+                    (Some((lon_text, lat_text)), Some((lat, lon))) => {
+                        let (lat, lon) = converter.convert(lat, lon);
+                        converted.push(format!(
+                            "{},{}",
+                            format_like(lon_text.trim(), lon),
+                            format_like(lat_text.trim(), lat)
+                        ));
+                    }
+                    _ => return,
+                }
+            }
+            *text = converted.join(";");
+        }
+        Value::Array(items) => {
+            for item in items {
+                convert_value(service, converter, key, item);
+            }
+        }
+        Value::Object(members) => {
+            if service == Service::Baidu {
+                let (lat_key, lon_key) = match key {
+                    Some("point") => ("y", "x"),
+                    _ => ("lat", "lng"),
+                };
+                let find = |members: &[(String, Value)], name| {
+                    members
+                        .iter()
+                        .position(|(k, v)| k == name && v.as_f64().is_some())
+                };
+                if let (Some(i), Some(j)) = (find(members, lat_key), find(members, lon_key)) {
+                    let (lat, lon) = (
+                        members[i].1.as_f64().unwrap(),
+                        members[j].1.as_f64().unwrap(),
+                    );
+                    let (lat, lon) = converter.convert(lat, lon);
+                    for &(k, x) in &[(i, lat), (j, lon)] {
+                        if let Value::Number(text) = &mut members[k].1 {
+                            *text = format_like(text, x);
+                        }
+                    }
+                }
+            }
+            for (name, member) in members.iter_mut() {
+                convert_value(service, converter, Some(name), member);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Serves `body` to a single connection, sending back its request line.
+    fn serve(body: &'static str) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            (&stream).write_all(response.as_bytes()).unwrap();
+            sender.send(request).unwrap();
+        });
+        (format!("http://{}/", address), receiver)
+    }
+
+    #[test]
+    fn test_convert_response() {
+        let body = r#"{"status":"1","count":"1","geocodes":[{"formatted_address":"北京市朝阳区",
+            "location":"116.480881,39.989410","entr_location":[],"level":"门牌号"}],
+            "route":{"polyline":"116.4,39.9;116.41,39.91"}}"#;
+        let converted = convert_response(Service::Amap, body, Wgs84).unwrap();
+        let (lat, lon) = Gcj02.convert_to(Wgs84, 39.98941, 116.480881);
+        let location = format!(r#""location":"{:.6},{:.6}""#, lon, lat);
+        assert!(converted.contains(&location), "{}", converted);
+        assert!(converted.contains(r#""entr_location":[]"#));
+        assert!(converted.contains(r#""formatted_address":"北京市朝阳区""#));
+        let (lat, lon) = Gcj02.convert_to(Wgs84, 39.91, 116.41);
+        let polyline = format!(";{:.2},{:.2}\"", lon, lat);
+        assert!(converted.contains(&polyline), "{}", converted);
+
+        let body = r#"{"status":0,"result":{"location":{"lng":116.30815,"lat":40.05687},
+            "precise":1,"pois":[{"name":"a","point":{"x":116.3,"y":40.0}}]}}"#;
+        let converted = convert_response(Service::Baidu, body, Gcj02).unwrap();
+        let (lat, lon) = Bd09.convert_to(Gcj02, 40.05687, 116.30815);
+        let location = format!(r#""location":{{"lng":{:.5},"lat":{:.5}}}"#, lon, lat);
+        assert!(converted.contains(&location), "{}", converted);
+        assert!(converted.contains(r#""precise":1"#));
+        let (lat, lon) = Bd09.convert_to(Gcj02, 40.0, 116.3);
+        let point = format!(r#""point":{{"x":{:.1},"y":{:.1}}}"#, lon, lat);
+        assert!(converted.contains(&point), "{}", converted);
+
+        assert!(convert_response(Service::Baidu, "{", Wgs84).is_err());
+        let body = r#"{"status":"1","location":"not a pair"}"#;
+        assert_eq!(convert_response(Service::Amap, body, Wgs84).unwrap(), body);
+    }
+
+    #[test]
+    fn test_geocode() {
+        let (url, request) = serve(
+            r#"{"status":"1","info":"OK","geocodes":[{"formatted_address":"北京市东城区天安门",
+            "location":"116.397455,39.909187"}]}"#,
+        );
+        let geocoder = Geocoder::new(Service::Amap, "k&y", Wgs84).with_base_url(&url);
+        let places = geocoder.geocode("天安门").unwrap();
+        let request = request.recv().unwrap();
+        assert!(
+            request.starts_with("GET /v3/geocode/geo?key=k%26y&address=%E5%A4%A9"),
+            "{}",
+            request
+        );
+        let (lat, lon) = Gcj02.convert_to(Wgs84, 39.909187, 116.397455);
+        assert_eq!(places.len(), 1);
+        assert_eq!(places[0].address.as_deref(), Some("北京市东城区天安门"));
+        assert!((places[0].lat - lat).abs() < 1e-6 && (places[0].lon - lon).abs() < 1e-6);
+
+        let (url, request) = serve(r#"{"status":0,"result":{"formatted_address":"北京市海淀区"}}"#);
+        let geocoder = Geocoder::new(Service::Baidu, "key", Wgs84).with_base_url(&url);
+        assert_eq!(
+            geocoder.reverse_geocode(39.9, 116.4).unwrap(),
+            "北京市海淀区"
+        );
+        let (lat, lon) = Wgs84.convert_to(Bd09, 39.9, 116.4);
+        let location = format!("location={:.6}%2C{:.6}&coordtype=bd09ll", lat, lon);
+        let request = request.recv().unwrap();
+        assert!(request.contains("ak=key&output=json"), "{}", request);
+        assert!(request.contains(&location), "{}", request);
+
+        let (url, _) = serve(r#"{"status":"0","info":"INVALID_USER_KEY"}"#);
+        let geocoder = Geocoder::new(Service::Amap, "key", Wgs84).with_base_url(&url);
+        let error = geocoder.geocode("天安门").unwrap_err();
+        assert!(error.to_string().contains("INVALID_USER_KEY"), "{}", error);
+    }
+
+    #[test]
+    fn test_transport() {
+        let (sender, urls) = mpsc::channel();
+        let sender = std::sync::Mutex::new(sender);
+        let geocoder = Geocoder::new(Service::Baidu, "key", Bd09).with_transport(move |url| {
+            sender.lock().unwrap().send(url.to_owned()).unwrap();
+            Ok(br#"{"status":0,"result":{"location":{"lng":116.4,"lat":39.9}}}"#.to_vec())
+        });
+        let places = geocoder.geocode("天安门").unwrap();
+        assert_eq!((places[0].lat, places[0].lon), (39.9, 116.4));
+        let url = urls.recv().unwrap();
+        assert!(
+            url.starts_with("https://api.map.baidu.com/geocoding/v3/?ak=key"),
+            "{}",
+            url
+        );
+        assert!(!format!("{:?}", geocoder).contains("key"));
+    }
+
+    #[test]
+    fn test_plain_http() {
+        for url in &[
+            "https://restapi.amap.com",
+            "http://example.com",
+            "http://127.0.0.1.example",
+        ] {
+            let geocoder = Geocoder::new(Service::Amap, "key", Wgs84).with_base_url(url);
+            let error = geocoder.geocode("天安门").unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        }
+        assert!(is_loopback("http://localhost:8080/"));
+        assert!(is_loopback("http://[::1]/"));
+        assert!(is_loopback("http://127.0.0.2"));
+    }
+}
@@ -0,0 +1,308 @@
+//! GeoJSON documents.
+//!
+//! Only the numbers of positions and bounding boxes are rewritten: whitespace, key order,
+//! properties and foreign members are kept byte for byte, so converted files diff cleanly against
+//! their sources. Bounding box corners are converted independently, and objects inside
+//! `properties` are never treated as geometries.
+use crate::format::Precision;
+use crate::json::{JsonError, Value};
+use crate::xml::apply_edits;
+use crate::Converter;
+use std::ops::Range;
+
+/// Options for [`convert`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoJsonOptions {
+    /// Decimal places of the converted coordinates, trailing zeros removed. `None` follows the
+    /// [`Precision`] of the converter. Six places are about 0.1 m.
+    pub precision: Option<usize>,
+}
+
+const GEOMETRY_TYPES: [&str; 6] = [
+    "Point",
+    "MultiPoint",
+    "LineString",
+    "MultiLineString",
+    "Polygon",
+    "MultiPolygon",
+];
+
+/// A coordinate pair as written: the longitude and latitude with the ranges of their text.
+type Pair = ((f64, Range<usize>), (f64, Range<usize>));
+
+/// Walks a document, tracking the index of the next number in the span list.
+struct Walker<'a, F> {
+    spans: &'a [Range<usize>],
+    next: usize,
+    /// Called with each position, its altitude if any, and whether it is a bounding box corner.
+    f: F,
+}
+
+impl<'a, F: FnMut(Pair, Option<f64>, bool)> Walker<'a, F> {
+    /// Skips a value, counting its numbers.
+    fn skip(&mut self, value: &Value) {
+        match value {
+            Value::Number(_) => self.next += 1,
+            Value::Array(items) => items.iter().for_each(|item| self.skip(item)),
+            Value::Object(members) => members.iter().for_each(|(_, v)| self.skip(v)),
+            _ => {}
+        }
+    }
+
+    fn pair(&self, items: &[Value], i: usize) -> Result<Pair, JsonError> {
+        let number = |k: usize| {
+            let span = self.spans[self.next + k].clone();
+            (items[k].as_f64().unwrap(), span)
+        };
+        match (items.get(i), items.get(i + 1)) {
+            (Some(Value::Number(_)), Some(Value::Number(_))) => Ok((number(i), number(i + 1))),
+            _ => Err(self.error("invalid position")),
+        }
+    }
+
+    fn error(&self, message: &'static str) -> JsonError {
+        JsonError {
+            offset: self.spans.get(self.next).map_or(0, |s| s.start),
+            message,
+        }
+    }
+
+    fn walk(&mut self, value: &Value) -> Result<(), JsonError> {
+        let members = match value {
+            Value::Array(items) => return items.iter().try_for_each(|item| self.walk(item)),
+            Value::Object(members) => members,
+            _ => {
+                self.skip(value);
+                return Ok(());
+            }
+        };
+        let kind = value.get("type").and_then(Value::as_str).unwrap_or("");
+        let geometry = GEOMETRY_TYPES.contains(&kind);
+        if !geometry && !["Feature", "FeatureCollection", "GeometryCollection"].contains(&kind) {
+            self.skip(value);
+            return Ok(());
+        }
+        for (key, member) in members {
+            match key.as_str() {
+                "coordinates" if geometry => self.positions(member)?,
+                "bbox" => self.bbox(member)?,
+                "geometry" | "features" | "geometries" => self.walk(member)?,
+                _ => self.skip(member),
+            }
+        }
+        Ok(())
+    }
+
+    fn positions(&mut self, value: &Value) -> Result<(), JsonError> {
+        match value {
+            Value::Array(items) if matches!(items.first(), Some(Value::Number(_))) => {
+                let pair = self.pair(items, 0)?;
+                let altitude = items.get(2).and_then(Value::as_f64);
+                (self.f)(pair, altitude, false);
+                self.skip(value);
+            }
+            Value::Array(items) => {
+                for item in items {
+                    self.positions(item)?;
+                }
+            }
+            _ => return Err(self.error("invalid position")),
+        }
+        Ok(())
+    }
+
+    fn bbox(&mut self, value: &Value) -> Result<(), JsonError> {
+        match value {
+            Value::Array(items)
+                if items.len() >= 4
+                    && items.len() % 2 == 0
+                    && items.iter().all(|i| matches!(i, Value::Number(_))) =>
+            {
+                let min = self.pair(items, 0)?;
+                let max = self.pair(items, items.len() / 2)?;
+                (self.f)(min, None, true);
+                (self.f)(max, None, true);
+                self.skip(value);
+                Ok(())
+            }
+            _ => Err(self.error("invalid bbox")),
+        }
+    }
+}
+
+fn for_each_pair<F: FnMut(Pair, Option<f64>, bool)>(input: &str, f: F) -> Result<(), JsonError> {
+    let (document, spans) = Value::parse_with_spans(input)?;
+    Walker {
+        spans: &spans,
+        next: 0,
+        f,
+    }
+    .walk(&document)
+}
+
+/// Reads the positions of a GeoJSON document as (latitude, longitude) pairs, in order.
+pub fn positions(input: &str) -> Result<Vec<(f64, f64)>, JsonError> {
+    let mut positions = Vec::new();
+    for_each_pair(input, |(lon, lat), _, bbox| {
+        if !bbox {
+            positions.push((lat.0, lon.0));
+        }
+    })?;
+    Ok(positions)
+}
+
+/// Same as [`positions`] with the altitude of each position, if any.
+pub fn positions_3d(input: &str) -> Result<Vec<(f64, f64, Option<f64>)>, JsonError> {
+    let mut positions = Vec::new();
+    for_each_pair(input, |(lon, lat), altitude, bbox| {
+        if !bbox {
+            positions.push((lat.0, lon.0, altitude));
+        }
+    })?;
+    Ok(positions)
+}
+
+/// Converts the positions and bounding boxes of a GeoJSON document.
+pub fn convert(
+    converter: &Converter,
+    input: &str,
+    options: &GeoJsonOptions,
+) -> Result<String, JsonError> {
+    let precision = options
+        .precision
+        .map_or(converter.precision(), Precision::Decimals);
+    let mut edits = Vec::new();
+    let mut rejected = None;
+    for_each_pair(input, |(lon, lat), _, _| {
+        match converter.convert_written(lat.0, lon.0) {
+            Ok(Some((lat_out, lon_out))) => {
+                edits.push((lat.1, precision.format(lat_out)));
+                edits.push((lon.1, precision.format(lon_out)));
+            }
+            Ok(None) => {}
+            Err(_) => {
+                rejected.get_or_insert(lon.1.start);
+            }
+        }
+    })?;
+    if let Some(offset) = rejected {
+        return Err(JsonError {
+            offset,
+            message: "non-finite coordinate",
+        });
+    }
+    Ok(apply_edits(input, edits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    const COLLECTION: &str = r#"{
+  "type": "FeatureCollection",
+  "bbox": [116.40, 39.90, 116.42, 39.92],
+  "features": [
+    {
+      "type": "Feature",
+      "id": 7,
+      "properties": {"height": 12.5, "shape": {"type": "Point", "coordinates": [1, 2]}},
+      "geometry": {"type": "Point", "coordinates": [116.4074, 39.9042, 44.0]}
+    },
+    {
+      "type": "Feature",
+      "properties": null,
+      "geometry": {
+        "type": "GeometryCollection",
+        "geometries": [
+          {"type": "LineString", "coordinates": [[116.40, 39.90], [116.41, 39.91]]},
+          {"type": "MultiPolygon", "coordinates": [[[[116.40, 39.90], [116.41, 39.90], [116.40, 39.90]]]]}
+        ]
+      }
+    }
+  ]
+}"#;
+
+    #[test]
+    fn test_positions() {
+        assert_eq!(
+            positions(COLLECTION).unwrap(),
+            [
+                (39.9042, 116.4074),
+                (39.90, 116.40),
+                (39.91, 116.41),
+                (39.90, 116.40),
+                (39.90, 116.41),
+                (39.90, 116.40),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = convert(&converter, COLLECTION, &GeoJsonOptions::default()).unwrap();
+        let expected: Vec<_> = positions(COLLECTION)
+            .unwrap()
+            .iter()
+            .map(|&(lat, lon)| converter.convert(lat, lon))
+            .collect();
+        assert_eq!(positions(&output).unwrap(), expected);
+
+        // Everything but the coordinates is unchanged, including the fake geometry.
+        assert_eq!(output.lines().count(), COLLECTION.lines().count());
+        for (a, b) in output.lines().zip(COLLECTION.lines()) {
+            if !a.contains("116.") {
+                assert_eq!(a, b);
+            }
+        }
+        assert!(output.contains(r#""coordinates": [1, 2]}},"#));
+        assert!(output.contains(", 44.0]}"));
+        assert_eq!(positions_3d(&output).unwrap()[0].2, Some(44.0));
+        assert_eq!(positions_3d(&output).unwrap()[1].2, None);
+
+        let (lat, lon) = converter.convert(39.90, 116.40);
+        assert!(output.contains(&format!("\"bbox\": [{}, {}, ", lon, lat)));
+    }
+
+    #[test]
+    fn test_precision() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let options = GeoJsonOptions { precision: Some(6) };
+        let input = r#"{"type":"Point","coordinates":[116.4074,39.9042]}"#;
+        let output = convert(&converter, input, &options).unwrap();
+        let (lat, lon) = converter.convert(39.9042, 116.4074);
+        let expected = format!(
+            r#"{{"type":"Point","coordinates":[{},{}]}}"#,
+            Precision::Decimals(6).format(lon),
+            Precision::Decimals(6).format(lat)
+        );
+        assert!(expected.len() < input.len() + 6);
+        assert_eq!(output, expected);
+
+        // The options take precedence over the converter.
+        let converter = converter.with_precision(Precision::Significant(4));
+        assert_eq!(convert(&converter, input, &options).unwrap(), expected);
+        let output = convert(&converter, input, &GeoJsonOptions::default()).unwrap();
+        assert_eq!(output, r#"{"type":"Point","coordinates":[116.4,39.9]}"#);
+    }
+
+    #[test]
+    fn test_errors() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let error = |input| convert(&converter, input, &GeoJsonOptions::default()).unwrap_err();
+        assert_eq!(
+            error(r#"{"type":"Point","coordinates":[1]}"#).message,
+            "invalid position"
+        );
+        assert_eq!(
+            error(r#"{"type":"Point","coordinates":[1,2],"bbox":[1,2,3]}"#).message,
+            "invalid bbox"
+        );
+        assert_eq!(
+            error(r#"{"type":"Point","coordinates":[1e999,2]}"#).message,
+            "non-finite coordinate"
+        );
+        assert!(positions("[1,").is_err());
+    }
+}
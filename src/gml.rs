@@ -0,0 +1,307 @@
+//! Geography Markup Language (GML) geometries.
+//!
+//! The coordinates of `<pos>`, `<posList>`, `<coordinates>` and envelope corners are rewritten
+//! number by number, so whitespace, heights and everything around the geometries are kept byte
+//! for byte.
+//!
+//! Axis order follows the nearest `srsName`: latitude first, as EPSG:4326 defines and INSPIRE
+//! requires, except for CRS84 and the short `EPSG:4326` form, which are longitude first by
+//! convention. Without any `srsName`, latitude comes first.
+use crate::xml::{apply_edits, convert_pair, local_name, Event, Reader, XmlError};
+use crate::Converter;
+use std::ops::Range;
+
+/// A geometry read from a document, with (latitude, longitude) pairs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point((f64, f64)),
+    LineString(Vec<(f64, f64)>),
+    /// The exterior ring followed by the interior rings.
+    Polygon(Vec<Vec<(f64, f64)>>),
+}
+
+/// A position as written: the latitude and longitude with the ranges of their text.
+struct Position {
+    lat: (f64, Range<usize>),
+    lon: (f64, Range<usize>),
+}
+
+/// An element enclosing coordinates, with the axis order and dimension it sets for its children.
+struct Scope<'a> {
+    name: &'a str,
+    lat_first: bool,
+    dimension: Option<usize>,
+}
+
+/// What the scan reports to its caller.
+enum Part<'a> {
+    Start(&'a str),
+    Positions(Vec<Position>),
+    End(&'a str),
+}
+
+/// Whether a CRS name puts latitude first.
+fn lat_first(srs_name: &str) -> bool {
+    let srs_name = srs_name.to_ascii_lowercase();
+    !(srs_name.contains("crs84") || srs_name.starts_with("epsg:"))
+}
+
+/// Splits `text` into numbers with their absolute ranges, given its position in the document.
+fn numbers(
+    text: &str,
+    offset: usize,
+    separators: &[char],
+) -> Result<Vec<(f64, Range<usize>)>, XmlError> {
+    let is_separator = |c: char| c.is_whitespace() || separators.contains(&c);
+    let mut numbers = Vec::new();
+    let mut start = None;
+    for (i, c) in text
+        .char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+    {
+        match (start, is_separator(c)) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                let value = text[s..i].parse().map_err(|_| XmlError {
+                    offset: offset + s,
+                    message: "invalid coordinate",
+                })?;
+                numbers.push((value, offset + s..offset + i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    Ok(numbers)
+}
+
+/// Calls `f` on the geometry elements and positions of the document, in order.
+fn scan<'a, F>(input: &'a str, mut f: F) -> Result<(), XmlError>
+where
+    F: FnMut(Part<'a>),
+{
+    let mut scopes: Vec<Scope> = Vec::new();
+    // The separators of the current `<coordinates>` element.
+    let mut separators = (',', ' ');
+    for event in Reader::new(input) {
+        let (event, range) = event?;
+        match event {
+            Event::Start { name, attributes } => {
+                let name = local_name(name);
+                let parent = scopes.last();
+                let lat_first = match attributes.get("srsName") {
+                    Some((srs_name, _)) => lat_first(srs_name),
+                    None => parent.is_none_or(|p| p.lat_first),
+                };
+                let dimension = match attributes.get("srsDimension") {
+                    Some((dimension, _)) => {
+                        Some(dimension.trim().parse().map_err(|_| XmlError {
+                            offset: range.start,
+                            message: "invalid dimension",
+                        })?)
+                    }
+                    None => parent.and_then(|p| p.dimension),
+                };
+                if name == "coordinates" {
+                    let separator = |attribute, default| match attributes.get(attribute) {
+                        Some((s, _)) => s.chars().next().unwrap_or(default),
+                        None => default,
+                    };
+                    separators = (separator("cs", ','), separator("ts", ' '));
+                }
+                scopes.push(Scope {
+                    name,
+                    lat_first,
+                    dimension,
+                });
+                f(Part::Start(name));
+            }
+            Event::Text(text) => {
+                let scope = match scopes.last() {
+                    Some(scope) => scope,
+                    None => continue,
+                };
+                let (values, dimension) = match scope.name {
+                    "pos" | "lowerCorner" | "upperCorner" => {
+                        let values = numbers(text, range.start, &[])?;
+                        let dimension = scope.dimension.unwrap_or(values.len());
+                        (values, dimension)
+                    }
+                    "posList" => (
+                        numbers(text, range.start, &[])?,
+                        scope.dimension.unwrap_or(2),
+                    ),
+                    "coordinates" => {
+                        let (cs, ts) = separators;
+                        let values = numbers(text, range.start, &[cs, ts])?;
+                        let first = text.trim().split(ts).next().unwrap_or("");
+                        (values, first.split(cs).count())
+                    }
+                    _ => continue,
+                };
+                if values.is_empty() {
+                    continue;
+                }
+                if dimension < 2 || values.len() % dimension != 0 {
+                    return Err(XmlError {
+                        offset: range.start,
+                        message: "invalid coordinate",
+                    });
+                }
+                let lat_first = scope.lat_first;
+                let positions = values
+                    .chunks(dimension)
+                    .map(|tuple| {
+                        let (a, b) = (tuple[0].clone(), tuple[1].clone());
+                        let (lat, lon) = if lat_first { (a, b) } else { (b, a) };
+                        Position { lat, lon }
+                    })
+                    .collect();
+                f(Part::Positions(positions));
+            }
+            Event::End { name } => {
+                scopes.pop();
+                f(Part::End(local_name(name)));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reads the points, line strings and polygons of a document, in order. The members of
+/// multi-geometries are returned individually.
+pub fn geometries(input: &str) -> Result<Vec<Geometry>, XmlError> {
+    let mut geometries = Vec::new();
+    let mut current: Option<Geometry> = None;
+    scan(input, |part| match part {
+        Part::Start("Point") => current = Some(Geometry::Point((f64::NAN, f64::NAN))),
+        Part::Start("LineString") => current = Some(Geometry::LineString(Vec::new())),
+        Part::Start("Polygon") => current = Some(Geometry::Polygon(Vec::new())),
+        Part::Start("LinearRing") => {
+            if let Some(Geometry::Polygon(rings)) = &mut current {
+                rings.push(Vec::new());
+            }
+        }
+        Part::Positions(positions) => {
+            let mut pairs = positions.iter().map(|p| (p.lat.0, p.lon.0));
+            match &mut current {
+                Some(Geometry::Point(point)) => *point = pairs.next().unwrap_or(*point),
+                Some(Geometry::LineString(points)) => points.extend(pairs),
+                Some(Geometry::Polygon(rings)) => {
+                    if let Some(ring) = rings.last_mut() {
+                        ring.extend(pairs);
+                    }
+                }
+                None => {}
+            }
+        }
+        Part::End("Point" | "LineString" | "Polygon") => geometries.extend(current.take()),
+        _ => {}
+    })?;
+    Ok(geometries)
+}
+
+/// Converts the coordinates of a GML document.
+pub fn convert(converter: &Converter, input: &str) -> Result<String, XmlError> {
+    let mut edits = Vec::new();
+    let mut result = Ok(());
+    scan(input, |part| {
+        if let Part::Positions(positions) = part {
+            for p in positions {
+                if result.is_ok() {
+                    result = convert_pair(converter, &p.lat, &p.lon, &mut edits);
+                }
+            }
+        }
+    })?;
+    result?;
+    Ok(apply_edits(input, edits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    const FEATURES: &str = r#"<wfs:FeatureCollection xmlns:gml="http://www.opengis.net/gml/3.2">
+  <gml:Point srsName="http://www.opengis.net/def/crs/EPSG/0/4326">
+    <gml:pos>39.9042 116.4074</gml:pos>
+  </gml:Point>
+  <gml:LineString srsName="urn:ogc:def:crs:OGC:1.3:CRS84" srsDimension="3">
+    <gml:posList>
+      116.40 39.90 45.5
+      116.41 39.91 46.0
+    </gml:posList>
+  </gml:LineString>
+  <gml:Polygon>
+    <gml:exterior><gml:LinearRing>
+      <gml:posList>39.90 116.40 39.90 116.41 39.91 116.41 39.90 116.40</gml:posList>
+    </gml:LinearRing></gml:exterior>
+    <gml:interior><gml:LinearRing>
+      <gml:coordinates srsName="EPSG:4326">116.402,39.902 116.404,39.902 116.402,39.904
+        116.402,39.902</gml:coordinates>
+    </gml:LinearRing></gml:interior>
+  </gml:Polygon>
+</wfs:FeatureCollection>"#;
+
+    #[test]
+    fn test_geometries() {
+        let geometries = geometries(FEATURES).unwrap();
+        assert_eq!(geometries.len(), 3);
+        assert_eq!(geometries[0], Geometry::Point((39.9042, 116.4074)));
+        assert_eq!(
+            geometries[1],
+            Geometry::LineString(vec![(39.90, 116.40), (39.91, 116.41)])
+        );
+        match &geometries[2] {
+            Geometry::Polygon(rings) => {
+                assert_eq!(rings.len(), 2);
+                assert_eq!(rings[0][2], (39.91, 116.41));
+                assert_eq!(rings[1][1], (39.902, 116.404));
+                assert_eq!(rings[1].len(), 4);
+            }
+            g => panic!("unexpected {:?}", g),
+        }
+    }
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = convert(&converter, FEATURES).unwrap();
+        let convert_all = |points: &[(f64, f64)]| -> Vec<_> {
+            points
+                .iter()
+                .map(|&(lat, lon)| converter.convert(lat, lon))
+                .collect()
+        };
+        let (input, output_geometries) =
+            (geometries(FEATURES).unwrap(), geometries(&output).unwrap());
+        for (input, output) in input.iter().zip(&output_geometries) {
+            let expected = match input {
+                Geometry::Point(p) => Geometry::Point(converter.convert(p.0, p.1)),
+                Geometry::LineString(points) => Geometry::LineString(convert_all(points)),
+                Geometry::Polygon(rings) => {
+                    Geometry::Polygon(rings.iter().map(|r| convert_all(r)).collect())
+                }
+            };
+            assert_eq!(*output, expected);
+        }
+
+        // Heights and line breaks are kept.
+        assert!(output.contains(" 45.5\n      "));
+        assert!(output.contains(" 46.0\n    </gml:posList>"));
+        assert_eq!(output.lines().count(), FEATURES.lines().count());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input| geometries(input).unwrap_err().message;
+        assert_eq!(error("<pos>1 x</pos>"), "invalid coordinate");
+        assert_eq!(error("<posList>1 2 3</posList>"), "invalid coordinate");
+        assert_eq!(
+            error(r#"<posList srsDimension="a">1 2</posList>"#),
+            "invalid dimension"
+        );
+    }
+}
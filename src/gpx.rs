@@ -0,0 +1,178 @@
+//! GPS Exchange Format (GPX) files.
+//!
+//! The `lat` and `lon` attributes of waypoints, route points and track points are rewritten, as
+//! well as the corners of `<bounds>`; times, elevations and extensions are kept byte for byte.
+use crate::format::Precision;
+use crate::track::{parse_time, Track, TrackPoint};
+use crate::xml::{apply_edits, local_name, map_attribute_pairs, Event, Reader, XmlError};
+use crate::Converter;
+
+/// The latitude and longitude attributes of each element.
+fn pairs(name: &str) -> &'static [(&'static str, &'static str)] {
+    match name {
+        "wpt" | "rtept" | "trkpt" => &[("lat", "lon")],
+        "bounds" => &[("minlat", "minlon"), ("maxlat", "maxlon")],
+        _ => &[],
+    }
+}
+
+/// Reads the waypoints, route points and track points of a GPX document, in order.
+pub fn points(input: &str) -> Result<Vec<(f64, f64)>, XmlError> {
+    let mut points = Vec::new();
+    map_attribute_pairs(input, pairs, Precision::Shortest, |name, lat, lon| {
+        if name != "bounds" {
+            points.push((lat, lon));
+        }
+        Ok(None)
+    })?;
+    Ok(points)
+}
+
+/// Reads the track points of a GPX document with their elevations and times, in order.
+///
+/// The segments and tracks of the document are concatenated; see [`Track::split`] to break them at
+/// time gaps.
+pub fn track(input: &str) -> Result<Track, XmlError> {
+    let mut points = Vec::new();
+    let mut point: Option<TrackPoint> = None;
+    let mut field = None;
+    for event in Reader::new(input) {
+        let (event, range) = event?;
+        let invalid = |message| XmlError {
+            offset: range.start,
+            message,
+        };
+        match event {
+            Event::Start { name, attributes } | Event::Empty { name, attributes } => {
+                match local_name(name) {
+                    "trkpt" => {
+                        let coordinate = |name| -> Result<f64, XmlError> {
+                            let (value, _) =
+                                attributes.get(name).ok_or(invalid("missing coordinate"))?;
+                            value
+                                .trim()
+                                .parse()
+                                .map_err(|_| invalid("invalid coordinate"))
+                        };
+                        let p = TrackPoint::new(coordinate("lat")?, coordinate("lon")?);
+                        match event {
+                            Event::Empty { .. } => points.push(p),
+                            _ => point = Some(p),
+                        }
+                    }
+                    name @ ("ele" | "time") if point.is_some() => field = Some(name == "ele"),
+                    _ => {}
+                }
+            }
+            Event::Text(text) => {
+                if let (Some(p), Some(is_elevation)) = (point.as_mut(), field) {
+                    if is_elevation {
+                        let elevation = text
+                            .trim()
+                            .parse()
+                            .map_err(|_| invalid("invalid elevation"))?;
+                        p.elevation = Some(elevation);
+                    } else {
+                        p.time = Some(parse_time(text).ok_or(invalid("invalid time"))?);
+                    }
+                }
+            }
+            Event::End { name } => match local_name(name) {
+                "trkpt" => points.extend(point.take()),
+                "ele" | "time" => field = None,
+                _ => {}
+            },
+            Event::Other => {}
+        }
+    }
+    Ok(Track { points })
+}
+
+/// Converts the points and bounds of a GPX document.
+pub fn convert(converter: &Converter, input: &str) -> Result<String, XmlError> {
+    let edits = map_attribute_pairs(input, pairs, converter.precision(), |_, lat, lon| {
+        converter.convert_written(lat, lon)
+    })?;
+    Ok(apply_edits(input, edits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+    use crate::NonFinitePolicy;
+
+    const TRACK: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <metadata><bounds minlat="39.90" minlon="116.40" maxlat="39.91" maxlon="116.41"/></metadata>
+  <wpt lat="39.9042" lon="116.4074"><name>Start</name></wpt>
+  <trk><trkseg>
+    <trkpt lat="39.905" lon="116.405"><ele>50.2</ele><time>2020-01-01T00:00:00Z</time></trkpt>
+    <trkpt lon="116.406" lat="39.906"><ele>50.4</ele></trkpt>
+  </trkseg></trk>
+</gpx>
+"#;
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = convert(&converter, TRACK).unwrap();
+        let input = points(TRACK).unwrap();
+        assert_eq!(
+            input,
+            [(39.9042, 116.4074), (39.905, 116.405), (39.906, 116.406)]
+        );
+        let expected: Vec<_> = input
+            .iter()
+            .map(|&(lat, lon)| converter.convert(lat, lon))
+            .collect();
+        assert_eq!(points(&output).unwrap(), expected);
+        assert!(output.contains("<ele>50.2</ele><time>2020-01-01T00:00:00Z</time></trkpt>"));
+        let (lat, _) = converter.convert(39.90, 116.40);
+        assert!(output.contains(&format!("<bounds minlat=\"{}\"", lat)));
+
+        let precision = Precision::Decimals(5);
+        let output = convert(&converter.with_precision(precision), TRACK).unwrap();
+        let (lat, lon) = (
+            precision.format(expected[1].0),
+            precision.format(expected[1].1),
+        );
+        assert!(output.contains(&format!("<trkpt lat=\"{}\" lon=\"{}\">", lat, lon)));
+        assert!(output.contains("<ele>50.2</ele>"));
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let input = r#"<gpx><wpt lat="NaN" lon="116.4"/><wpt lat="39.9" lon="116.4"/></gpx>"#;
+        let converter = Converter::new(Gcj02, Wgs84);
+        assert_eq!(
+            convert(&converter, input).unwrap_err().message,
+            "non-finite coordinate"
+        );
+        let skip = converter.with_non_finite(NonFinitePolicy::Skip);
+        let output = convert(&skip, input).unwrap();
+        assert!(output.starts_with(r#"<gpx><wpt lat="NaN" lon="116.4"/>"#));
+        assert_eq!(points(&output).unwrap()[1], skip.convert(39.9, 116.4));
+    }
+
+    #[test]
+    fn test_track() {
+        let points = track(TRACK).unwrap().points;
+        assert_eq!(points.len(), 2);
+        let first = points[0];
+        assert_eq!(
+            (first.lat, first.lon, first.elevation),
+            (39.905, 116.405, Some(50.2))
+        );
+        assert_eq!(first.time, crate::track::parse_time("2020-01-01T00:00:00Z"));
+        assert_eq!(points[1].time, None);
+        assert_eq!(points[1].lon, 116.406);
+
+        let error = |input| track(input).unwrap_err().message;
+        assert_eq!(error(r#"<trkpt lat="1"/>"#), "missing coordinate");
+        assert_eq!(
+            error(r#"<trkpt lat="1" lon="2"><ele>x</ele></trkpt>"#),
+            "invalid elevation"
+        );
+    }
+}
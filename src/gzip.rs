@@ -0,0 +1,135 @@
+//! Gzip files, such as compressed exports of trajectories (`.geojson.gz`, `.gpx.gz`).
+//!
+//! Members are inflated and deflated with the codec of the [`crate::zip`] module, and checked
+//! against their CRC-32 and size. [`crate::zip::convert_entry`] and [`crate::transcode::read`]
+//! decompress their input with it, so that compressed files never have to be extracted first.
+use crate::zip::{crc32, deflate, inflate, MAX_INFLATED};
+use std::io;
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Whether the data starts like a gzip file.
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Decompresses a gzip file, concatenating its members, of at most [`MAX_INFLATED`] bytes.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    decompress_with_limit(data, MAX_INFLATED)
+}
+
+/// Decompresses a gzip file, failing with [`io::ErrorKind::InvalidData`] past `limit` bytes.
+pub fn decompress_with_limit(data: &[u8], limit: u64) -> io::Result<Vec<u8>> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+    let mut out = Vec::new();
+    let mut rest = data;
+    loop {
+        if rest.len() < 10 || !is_gzip(rest) || rest[2] != 8 {
+            return Err(invalid("invalid gzip header"));
+        }
+        let flags = rest[3];
+        let mut pos = 10;
+        if flags & FEXTRA != 0 {
+            let len = rest
+                .get(pos..pos + 2)
+                .ok_or_else(|| invalid("invalid gzip header"))?;
+            pos += 2 + usize::from(u16::from_le_bytes([len[0], len[1]]));
+        }
+        for &flag in &[FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                let end = rest
+                    .get(pos..)
+                    .and_then(|rest| rest.iter().position(|&b| b == 0));
+                pos += end.ok_or_else(|| invalid("invalid gzip header"))? + 1;
+            }
+        }
+        if flags & FHCRC != 0 {
+            pos += 2;
+        }
+        rest = rest
+            .get(pos..)
+            .ok_or_else(|| invalid("invalid gzip header"))?;
+        let member = inflate(&mut rest, limit - out.len() as u64)?;
+        if rest.len() < 8 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (crc, size) = (&rest[..4], &rest[4..8]);
+        if crc32(&member).to_le_bytes() != crc {
+            return Err(invalid("gzip checksum mismatch"));
+        }
+        if (member.len() as u32).to_le_bytes() != size {
+            return Err(invalid("gzip size mismatch"));
+        }
+        out.extend_from_slice(&member);
+        rest = &rest[8..];
+        if rest.is_empty() {
+            return Ok(out);
+        }
+    }
+}
+
+/// Compresses data into a gzip file of one member.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // Deflate, no flags, no time, no extra flags, unknown system.
+    let mut out = vec![MAGIC[0], MAGIC[1], 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend_from_slice(&deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decompress() {
+        // `printf tile | gzip -n`, with fixed Huffman codes.
+        let data = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x2b, 0xc9, 0xcc, 0x49,
+            0x05, 0x00, 0x04, 0xa9, 0x8f, 0x76, 0x04, 0x00, 0x00, 0x00,
+        ];
+        assert!(is_gzip(&data));
+        assert_eq!(decompress(&data).unwrap(), b"tile");
+        let twice = [&data[..], &data[..]].concat();
+        assert_eq!(decompress(&twice).unwrap(), b"tiletile");
+
+        assert!(decompress(&data[..4]).is_err());
+        assert!(decompress(&data[..20]).is_err());
+        let mut corrupt = data;
+        corrupt[16] ^= 1;
+        assert_eq!(
+            decompress(&corrupt).unwrap_err().to_string(),
+            "gzip checksum mismatch"
+        );
+        assert!(decompress(&[&data[..], b"x"].concat()).is_err());
+    }
+
+    #[test]
+    fn test_compress() {
+        let text = "[116.404,39.915],[116.405,39.916],".repeat(200);
+        for data in [&b""[..], b"a", text.as_bytes()] {
+            let compressed = compress(data);
+            assert!(is_gzip(&compressed));
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+        assert!(compress(text.as_bytes()).len() < text.len() / 10);
+
+        let bomb = compress(&vec![0; 1 << 20]);
+        assert!(bomb.len() < 10 << 10);
+        assert_eq!(
+            decompress_with_limit(&bomb, 1 << 20).unwrap().len(),
+            1 << 20
+        );
+        let error = decompress_with_limit(&bomb, (1 << 20) - 1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(decompress_with_limit(&[&bomb[..], &bomb[..]].concat(), 3 << 19).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! A minimal HTTP/1.1 client, enabled by the `http` feature, for the tile proxy and the geocoder
+//! clients.
+//!
+//! Requests go over plain TCP, as the crate has no dependencies to do TLS: HTTPS services are
+//! reached through a local relay, such as a reverse proxy terminating TLS.
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Timeout of connecting, and of each read and write of a request.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest response body accepted, far above tiles and geocoder answers.
+const MAX_BODY: usize = 64 << 20;
+/// Longest status, header or chunk size line accepted.
+const MAX_LINE: u64 = 8 << 10;
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn too_large() -> io::Error {
+    invalid("HTTP response too large")
+}
+
+/// Splits an authority into its host, without the brackets of an IPv6 address, and its port.
+pub(crate) fn split_authority(authority: &str) -> io::Result<(&str, u16)> {
+    let port = |port: &str| port.parse().map_err(|_| invalid("invalid port"));
+    if let Some(rest) = authority.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| invalid("invalid IPv6 address"))?;
+        return match rest {
+            "" => Ok((host, 80)),
+            _ => match rest.strip_prefix(':') {
+                Some(rest) => Ok((host, port(rest)?)),
+                None => Err(invalid("invalid IPv6 address")),
+            },
+        };
+    }
+    match authority.rsplit_once(':') {
+        Some((host, rest)) => Ok((host, port(rest)?)),
+        None => Ok((authority, 80)),
+    }
+}
+
+/// Connects to the first address of `host` answering within [`TIMEOUT`].
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "host has no address");
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Reads a line of at most [`MAX_LINE`] bytes, returning its length.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let length = reader.take(MAX_LINE).read_line(line)?;
+    if length as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(invalid("HTTP line too long"));
+    }
+    Ok(length)
+}
+
+/// Escapes `text` for a query string, keeping only the unreserved characters of RFC 3986.
+pub fn percent_encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for &byte in text.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(char::from(byte))
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// Downloads `url` with a plain HTTP/1.1 request; HTTPS is unsupported.
+///
+/// A 404 or 204 response is reported with [`io::ErrorKind::NotFound`], as tile servers answer so
+/// for empty tiles. Connecting, and each read and write, time out after 30 seconds, and bodies
+/// above 64 MiB are rejected with [`io::ErrorKind::InvalidData`].
+pub fn get(url: &str) -> io::Result<Vec<u8>> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only http:// URLs are supported",
+        )
+    })?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = split_authority(authority)?;
+    let mut stream = connect(host, port)?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: undrift_gps\r\n\
+         Accept-Encoding: identity\r\nConnection: close\r\n\r\n",
+        path, authority
+    )?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    read_line(&mut reader, &mut line)?;
+    let status: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("invalid HTTP response"))?;
+    let (mut length, mut chunked) = (None, false);
+    loop {
+        line.clear();
+        if read_line(&mut reader, &mut line)? == 0 {
+            return Err(invalid("truncated HTTP response"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(
+                    value
+                        .parse()
+                        .map_err(|_| invalid("invalid HTTP response"))?,
+                );
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            read_line(&mut reader, &mut line)?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| invalid("invalid chunk"))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            let end = start
+                .checked_add(size)
+                .filter(|&end| end <= MAX_BODY)
+                .ok_or_else(too_large)?;
+            body.resize(end, 0);
+            reader.read_exact(&mut body[start..])?;
+            line.clear();
+            read_line(&mut reader, &mut line)?;
+        }
+    } else if let Some(length) = length {
+        if length > MAX_BODY {
+            return Err(too_large());
+        }
+        body.resize(length, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        reader.take(MAX_BODY as u64 + 1).read_to_end(&mut body)?;
+        if body.len() > MAX_BODY {
+            return Err(too_large());
+        }
+    }
+
+    match status {
+        200 => Ok(body),
+        204 | 404 => Err(io::Error::new(io::ErrorKind::NotFound, "no tile upstream")),
+        _ => Err(io::Error::other(format!("upstream responded {}", status))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves `response` to a single connection, returning the URL to request.
+    fn serve(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            (&stream).write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{}/path?q=1", address)
+    }
+
+    #[test]
+    fn test_get() {
+        let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello, world");
+        assert_eq!(get(&url).unwrap(), b"hello");
+        let url = serve(
+            "HTTP/1.1 200 OK\r\ntransfer-encoding: Chunked\r\n\r\n\
+             3;ext\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n",
+        );
+        assert_eq!(get(&url).unwrap(), b"hello");
+        let url = serve("HTTP/1.0 200 OK\r\n\r\nhello");
+        assert_eq!(get(&url).unwrap(), b"hello");
+
+        let url = serve("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(get(&url).unwrap_err().kind(), io::ErrorKind::NotFound);
+        let url = serve("HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n");
+        assert!(get(&url).unwrap_err().to_string().contains("500"));
+        assert!(get(&serve("garbage")).is_err());
+        assert!(get("https://tiles.example/0/0/0").is_err());
+
+        let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\nhello");
+        assert_eq!(get(&url).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let url = serve(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             2\r\nhe\r\nffffffffffffffff\r\nllo\r\n0\r\n\r\n",
+        );
+        assert_eq!(get(&url).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_split_authority() {
+        assert_eq!(
+            split_authority("tiles.example").unwrap(),
+            ("tiles.example", 80)
+        );
+        assert_eq!(
+            split_authority("127.0.0.1:8080").unwrap(),
+            ("127.0.0.1", 8080)
+        );
+        assert_eq!(split_authority("[::1]").unwrap(), ("::1", 80));
+        assert_eq!(
+            split_authority("[2001:db8::1]:8080").unwrap(),
+            ("2001:db8::1", 8080)
+        );
+        assert!(split_authority("[::1]8080").is_err());
+        assert!(split_authority("host:port").is_err());
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(percent_encode("a-b_c.d~e"), "a-b_c.d~e");
+        assert_eq!(percent_encode("北京 1&2"), "%E5%8C%97%E4%BA%AC%201%262");
+    }
+}
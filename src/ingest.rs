@@ -0,0 +1,518 @@
+//! A conversion service for position streams, enabled by the `ingest` feature.
+//!
+//! Each connection carries NDJSON, one object per line, whose latitude and longitude members are
+//! converted before the line is forwarded downstream; the other members are kept. It can be
+//! inserted in a telemetry pipeline as is:
+//!
+//! ```no_run
+//! use std::net::{TcpListener, TcpStream};
+//! use undrift_gps::ingest::{IngestOptions, Server};
+//! use undrift_gps::{Converter, GeodeticSystem::*};
+//!
+//! let server = Server::new(Converter::new(Gcj02, Wgs84), IngestOptions::default());
+//! let listener = TcpListener::bind("127.0.0.1:7000").unwrap();
+//! server.serve_tcp(listener, || TcpStream::connect("127.0.0.1:7001")).unwrap();
+//! ```
+//!
+//! Lines go from the socket to the conversion through a queue of [`IngestOptions::queue_len`]
+//! lines. When the downstream is slower than the input, the queue fills up and the socket is no
+//! longer read, so that TCP flow control slows the sender down instead of the memory growing.
+use crate::json::Value;
+use crate::Converter;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// Settings of the conversion of a stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngestOptions {
+    /// Member holding the latitude.
+    pub lat_key: String,
+    /// Member holding the longitude.
+    pub lon_key: String,
+    /// Number of lines read ahead of the conversion, at least one.
+    pub queue_len: usize,
+    /// Longest line accepted, in bytes with its newline. A longer line fails the stream, rather
+    /// than being buffered whole.
+    pub max_line_len: usize,
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        IngestOptions {
+            lat_key: "lat".to_owned(),
+            lon_key: "lon".to_owned(),
+            queue_len: 1024,
+            max_line_len: 1 << 20,
+        }
+    }
+}
+
+/// Counts of the lines of a stream, empty lines excluded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestStats {
+    pub lines: u64,
+    /// Lines forwarded with converted coordinates.
+    pub converted: u64,
+    /// Lines dropped: invalid JSON, missing or non-numeric coordinates, or non-finite ones
+    /// rejected by the [`crate::NonFinitePolicy`]. Non-finite coordinates it lets through are
+    /// forwarded unchanged.
+    pub rejected: u64,
+}
+
+/// Converts an NDJSON stream until its end, writing one line at a time and flushing whenever no
+/// more input is ready.
+///
+/// The input is read by another thread. If writing fails, that thread stops once its current read
+/// returns, which the call waits for: [`Server`] shuts its sockets down to interrupt it.
+pub fn convert_ndjson<R, W>(
+    converter: &Converter,
+    reader: R,
+    writer: W,
+    options: &IngestOptions,
+) -> io::Result<IngestStats>
+where
+    R: Read + Send,
+    W: Write,
+{
+    convert_stopping(converter, reader, writer, options, || {})
+}
+
+/// Same as [`convert_ndjson`], calling `stop` when the conversion fails, before waiting for the
+/// reading thread.
+fn convert_stopping<R, W>(
+    converter: &Converter,
+    reader: R,
+    writer: W,
+    options: &IngestOptions,
+    stop: impl FnOnce(),
+) -> io::Result<IngestStats>
+where
+    R: Read + Send,
+    W: Write,
+{
+    let (sender, receiver) = mpsc::sync_channel::<io::Result<String>>(options.queue_len.max(1));
+    let limit = (options.max_line_len as u64).saturating_add(1);
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut reader = BufReader::new(reader);
+            loop {
+                let mut line = String::new();
+                let line = match (&mut reader).take(limit).read_line(&mut line) {
+                    Ok(0) => break,
+                    Ok(n) if n as u64 == limit => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "line longer than max_line_len",
+                    )),
+                    Ok(_) => Ok(line),
+                    Err(e) => Err(e),
+                };
+                let failed = line.is_err();
+                if sender.send(line).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        // The receiver is dropped when forwarding returns, so that the reading thread stops
+        // instead of waiting for room in the queue.
+        let result = forward(converter, receiver, writer, options);
+        if result.is_err() {
+            stop();
+        }
+        result
+    })
+}
+
+/// Converts and writes the lines of the queue until it is closed.
+fn forward<W: Write>(
+    converter: &Converter,
+    receiver: Receiver<io::Result<String>>,
+    mut writer: W,
+    options: &IngestOptions,
+) -> io::Result<IngestStats> {
+    let mut stats = IngestStats::default();
+    loop {
+        let line = match receiver.try_recv() {
+            Ok(line) => line?,
+            Err(TryRecvError::Empty) => {
+                writer.flush()?;
+                match receiver.recv() {
+                    Ok(line) => line?,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        let text = line.trim_end_matches(&['\n', '\r'][..]);
+        if text.trim().is_empty() {
+            continue;
+        }
+        stats.lines += 1;
+        match convert_line(converter, text, options) {
+            Some((output, converted)) => {
+                stats.converted += converted as u64;
+                writer.write_all(output.as_bytes())?;
+            }
+            None => stats.rejected += 1,
+        }
+    }
+    writer.flush()?;
+    Ok(stats)
+}
+
+/// Converts a line, returning it with its newline and whether it was changed, or `None` if it is
+/// rejected.
+fn convert_line(
+    converter: &Converter,
+    text: &str,
+    options: &IngestOptions,
+) -> Option<(String, bool)> {
+    let mut value = Value::parse(text).ok()?;
+    let lat = value.get(&options.lat_key)?.as_f64()?;
+    let lon = value.get(&options.lon_key)?.as_f64()?;
+    match converter.convert_written(lat, lon).ok()? {
+        Some((lat, lon)) => {
+            *value.get_mut(&options.lat_key)? = Value::number(lat);
+            *value.get_mut(&options.lon_key)? = Value::number(lon);
+            Some((format!("{}\n", value), true))
+        }
+        None => Some((format!("{}\n", text), false)),
+    }
+}
+
+/// Sockets which can be shut down from another handle, interrupting a blocked read.
+trait Socket: Read + Send + Sized {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// Pause after failing to accept a connection, such as when out of file descriptors.
+const ACCEPT_PAUSE: Duration = Duration::from_millis(100);
+
+/// Converts the streams of incoming connections, each forwarded to its own downstream.
+pub struct Server {
+    converter: Converter,
+    options: IngestOptions,
+}
+
+impl Server {
+    pub fn new(converter: Converter, options: IngestOptions) -> Self {
+        Server { converter, options }
+    }
+
+    /// Serves the connections of `listener`, each in its own thread, opening its downstream with
+    /// `downstream`.
+    ///
+    /// A failing connection or downstream closes that connection only, and is logged to the
+    /// standard error. So are failures to accept a connection, after which accepting goes on
+    /// after a short pause.
+    pub fn serve_tcp<F, W>(&self, listener: TcpListener, downstream: F) -> io::Result<()>
+    where
+        F: Fn() -> io::Result<W> + Sync,
+        W: Write,
+    {
+        self.serve(listener.incoming(), downstream)
+    }
+
+    /// Same as [`Server::serve_tcp`] for a Unix socket.
+    #[cfg(unix)]
+    pub fn serve_unix<F, W>(&self, listener: UnixListener, downstream: F) -> io::Result<()>
+    where
+        F: Fn() -> io::Result<W> + Sync,
+        W: Write,
+    {
+        self.serve(listener.incoming(), downstream)
+    }
+
+    fn serve<S, F, W>(
+        &self,
+        incoming: impl Iterator<Item = io::Result<S>>,
+        downstream: F,
+    ) -> io::Result<()>
+    where
+        S: Socket,
+        F: Fn() -> io::Result<W> + Sync,
+        W: Write,
+    {
+        let downstream = &downstream;
+        thread::scope(|scope| {
+            for stream in incoming {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("ingest: cannot accept a connection: {}", e);
+                        thread::sleep(ACCEPT_PAUSE);
+                        continue;
+                    }
+                };
+                scope.spawn(move || {
+                    if let Err(e) = self.connection(stream, downstream) {
+                        eprintln!("ingest: connection closed: {}", e);
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+
+    /// Converts the stream of a connection, shutting the socket down if it fails.
+    fn connection<S, F, W>(&self, stream: S, downstream: &F) -> io::Result<IngestStats>
+    where
+        S: Socket,
+        F: Fn() -> io::Result<W>,
+        W: Write,
+    {
+        let writer = match downstream() {
+            Ok(writer) => BufWriter::new(writer),
+            Err(e) => {
+                let _ = stream.shutdown();
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("cannot open the downstream: {}", e),
+                ));
+            }
+        };
+        let socket = stream.try_clone()?;
+        convert_stopping(&self.converter, stream, writer, &self.options, || {
+            let _ = socket.shutdown();
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let input = "{\"id\":1,\"lat\":39.9,\"lon\":116.4}\n\n\
+                     {\"id\":2,\"lat\":39.9}\n\
+                     not json\r\n\
+                     {\"id\":3,\"lat\":1e999,\"lon\":116.4}\n";
+        let mut output = Vec::new();
+        let options = IngestOptions::default();
+        let stats = convert_ndjson(&converter, Cursor::new(input), &mut output, &options).unwrap();
+        assert_eq!(
+            stats,
+            IngestStats {
+                lines: 4,
+                converted: 1,
+                rejected: 3,
+            }
+        );
+        let (lat, lon) = converter.convert(39.9, 116.4);
+        let expected = format!("{{\"id\":1,\"lat\":{},\"lon\":{}}}\n", lat, lon);
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let options = IngestOptions {
+            lat_key: "y".to_owned(),
+            lon_key: "x".to_owned(),
+            queue_len: 1,
+            ..IngestOptions::default()
+        };
+        let mut output = Vec::new();
+        let input = Cursor::new("{\"x\":116.4,\"y\":39.9}");
+        convert_ndjson(&converter, input, &mut output, &options).unwrap();
+        let expected = format!("{{\"x\":{},\"y\":{}}}\n", lon, lat);
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    /// A source of identical lines counting how many were read.
+    struct Lines {
+        read: Arc<AtomicUsize>,
+        remaining: usize,
+    }
+
+    impl Read for Lines {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+            self.remaining -= 1;
+            self.read.fetch_add(1, Ordering::SeqCst);
+            let line = b"{\"lat\":39.9,\"lon\":116.4}\n";
+            buf[..line.len()].copy_from_slice(line);
+            Ok(line.len())
+        }
+    }
+
+    /// A sink checking that the input is never read too far ahead of the output.
+    struct Slow {
+        read: Arc<AtomicUsize>,
+        written: usize,
+        ahead: usize,
+    }
+
+    impl Write for Slow {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written += 1;
+            thread::sleep(std::time::Duration::from_millis(1));
+            self.ahead = self
+                .ahead
+                .max(self.read.load(Ordering::SeqCst) - self.written);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let read = Arc::new(AtomicUsize::new(0));
+        let input = Lines {
+            read: read.clone(),
+            remaining: 100,
+        };
+        let mut output = Slow {
+            read,
+            written: 0,
+            ahead: 0,
+        };
+        let options = IngestOptions {
+            queue_len: 4,
+            ..IngestOptions::default()
+        };
+        let converter = Converter::new(Gcj02, Wgs84);
+        let stats = convert_ndjson(&converter, input, &mut output, &options).unwrap();
+        assert_eq!(stats.converted, 100);
+        // The queue, plus the line waiting to enter it.
+        assert!(output.ahead <= 4 + 1, "{}", output.ahead);
+    }
+
+    /// A sink failing every write.
+    struct Failing;
+
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "downstream closed",
+            ))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failing_writer() {
+        let input = Lines {
+            read: Arc::new(AtomicUsize::new(0)),
+            remaining: 100,
+        };
+        let options = IngestOptions {
+            queue_len: 1,
+            ..IngestOptions::default()
+        };
+        let converter = Converter::new(Gcj02, Wgs84);
+        let error = convert_ndjson(&converter, input, Failing, &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_max_line_len() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let options = IngestOptions {
+            max_line_len: 25,
+            ..IngestOptions::default()
+        };
+        // 24 bytes and the newline.
+        let input = "{\"lat\":39.9,\"lon\":116.4}\n";
+        let stats = convert_ndjson(&converter, Cursor::new(input), Vec::new(), &options);
+        assert_eq!(stats.unwrap().converted, 1);
+        let input = Cursor::new(format!("{}{}", input, input.replace("39.9", "39.90")));
+        let error = convert_ndjson(&converter, input, Vec::new(), &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_serve_failing_downstream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let server = Server::new(Converter::new(Wgs84, Gcj02), IngestOptions::default());
+            server.serve_tcp(listener, || Ok(Failing))
+        });
+
+        // The client keeps its side open: the server closes the connection after the failed
+        // write, even though no more input comes.
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"{\"lat\":39.9,\"lon\":116.4}\n").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        assert_eq!(client.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_serve_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        thread::spawn(move || {
+            let server = Server::new(Converter::new(Wgs84, Gcj02), IngestOptions::default());
+            server.serve_tcp(listener, || Ok(Sink(sink.clone())))
+        });
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"{\"lat\":39.9,\"lon\":116.4}\n").unwrap();
+        drop(client);
+        let (lat, lon) = Converter::new(Wgs84, Gcj02).convert(39.9, 116.4);
+        let expected = format!("{{\"lat\":{},\"lon\":{}}}\n", lat, lon);
+        for _ in 0..500 {
+            if !received.lock().unwrap().is_empty() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(*received.lock().unwrap(), expected.as_bytes());
+    }
+
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Keyhole Markup Language (KML) files.
+//!
+//! The `<coordinates>` of placemarks, written as `lon,lat[,alt]` tuples, and the `<gx:coord>` of
+//! tracks, written as `lon lat [alt]`, are rewritten number by number; altitudes, styles and
+//! descriptions are kept byte for byte. The `<LatLonBox>` of ground overlays is left unchanged.
+use crate::xml::{apply_edits, convert_pair, local_name, Event, Reader, XmlError};
+use crate::Converter;
+use std::ops::Range;
+
+/// A parsed position: the latitude and longitude with the ranges of their text.
+struct Position {
+    lat: (f64, Range<usize>),
+    lon: (f64, Range<usize>),
+    /// The range of the altitude text, if any.
+    alt: Option<Range<usize>>,
+}
+
+/// Parses the positions in the text of a `<coordinates>` or `<gx:coord>` element, starting at
+/// `offset` in the document.
+fn parse_positions(
+    text: &str,
+    offset: usize,
+    tuple_separator: fn(char) -> bool,
+    separator: char,
+) -> Result<Vec<Position>, XmlError> {
+    let mut positions = Vec::new();
+    let mut rest = text;
+    let mut base = offset;
+    while let Some(start) = rest.find(|c: char| !tuple_separator(c)) {
+        let end = rest[start..]
+            .find(tuple_separator)
+            .map_or(rest.len(), |i| start + i);
+        let tuple_start = base + start;
+        let mut numbers = Vec::new();
+        let mut pos = 0;
+        let tuple = &rest[start..end];
+        for part in tuple.split(separator) {
+            let trimmed = part.trim();
+            let at = tuple_start + pos + (part.len() - part.trim_start().len());
+            numbers.push((trimmed, at..at + trimmed.len()));
+            pos += part.len() + separator.len_utf8();
+        }
+        if numbers.len() < 2 {
+            return Err(XmlError {
+                offset: tuple_start,
+                message: "invalid coordinate",
+            });
+        }
+        let number = |(text, range): &(&str, Range<usize>)| {
+            text.parse()
+                .map(|x| (x, range.clone()))
+                .map_err(|_| XmlError {
+                    offset: range.start,
+                    message: "invalid coordinate",
+                })
+        };
+        positions.push(Position {
+            lon: number(&numbers[0])?,
+            lat: number(&numbers[1])?,
+            alt: numbers.get(2).map(|(_, range)| range.clone()),
+        });
+        rest = &rest[end..];
+        base += end;
+    }
+    Ok(positions)
+}
+
+/// Calls `f` on every position of the document, in order.
+fn for_each_position<F>(input: &str, mut f: F) -> Result<(), XmlError>
+where
+    F: FnMut(Position),
+{
+    let mut element = None;
+    for event in Reader::new(input) {
+        let (event, range) = event?;
+        match event {
+            Event::Start { name, .. } => element = Some(name),
+            Event::End { .. } => element = None,
+            Event::Text(text) => {
+                let positions = match element.map(local_name) {
+                    Some("coordinates") => {
+                        parse_positions(text, range.start, char::is_whitespace, ',')?
+                    }
+                    Some("coord") => parse_positions(text, range.start, |c| c == '\n', ' ')?,
+                    _ => continue,
+                };
+                positions.into_iter().for_each(&mut f);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reads the positions of a KML document, in order.
+pub fn positions(input: &str) -> Result<Vec<(f64, f64)>, XmlError> {
+    let mut positions = Vec::new();
+    for_each_position(input, |p| positions.push((p.lat.0, p.lon.0)))?;
+    Ok(positions)
+}
+
+/// Same as [`positions`] with the altitude of each position, if any.
+pub fn positions_3d(input: &str) -> Result<Vec<(f64, f64, Option<f64>)>, XmlError> {
+    let mut positions = Vec::new();
+    let mut error = None;
+    for_each_position(input, |p| {
+        let alt = p.alt.and_then(|range| {
+            let parsed = input[range.clone()].parse().map_err(|_| XmlError {
+                offset: range.start,
+                message: "invalid altitude",
+            });
+            parsed.map_err(|e| error = error.take().or(Some(e))).ok()
+        });
+        positions.push((p.lat.0, p.lon.0, alt));
+    })?;
+    match error {
+        Some(error) => Err(error),
+        None => Ok(positions),
+    }
+}
+
+/// Converts the positions of a KML document.
+pub fn convert(converter: &Converter, input: &str) -> Result<String, XmlError> {
+    let mut edits = Vec::new();
+    let mut result = Ok(());
+    for_each_position(input, |p| {
+        if result.is_ok() {
+            result = convert_pair(converter, &p.lat, &p.lon, &mut edits);
+        }
+    })?;
+    result?;
+    Ok(apply_edits(input, edits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    const DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+  <Document>
+    <Placemark><name>Gate</name><Point><coordinates>116.4074,39.9042,10</coordinates></Point></Placemark>
+    <Placemark>
+      <LineString><coordinates>
+        116.40,39.90 116.41,39.91
+      </coordinates></LineString>
+    </Placemark>
+    <Placemark><gx:Track>
+      <when>2020-01-01T00:00:00Z</when>
+      <gx:coord>116.42 39.92 55</gx:coord>
+    </gx:Track></Placemark>
+  </Document>
+</kml>
+"#;
+
+    #[test]
+    fn test_convert() {
+        let input = positions(DOCUMENT).unwrap();
+        assert_eq!(
+            input,
+            [
+                (39.9042, 116.4074),
+                (39.90, 116.40),
+                (39.91, 116.41),
+                (39.92, 116.42)
+            ]
+        );
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = convert(&converter, DOCUMENT).unwrap();
+        let expected: Vec<_> = input
+            .iter()
+            .map(|&(lat, lon)| converter.convert(lat, lon))
+            .collect();
+        assert_eq!(positions(&output).unwrap(), expected);
+
+        let (lat, lon) = expected[0];
+        assert!(output.contains(&format!("<coordinates>{},{},10</coordinates>", lon, lat)));
+        let (lat, lon) = expected[3];
+        assert!(output.contains(&format!("<gx:coord>{} {} 55</gx:coord>", lon, lat)));
+
+        let altitudes: Vec<_> = positions_3d(&output).unwrap().iter().map(|p| p.2).collect();
+        assert_eq!(altitudes, [Some(10.0), None, None, Some(55.0)]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input| positions(input).unwrap_err().message;
+        assert_eq!(
+            error("<coordinates>116.4</coordinates>"),
+            "invalid coordinate"
+        );
+        assert_eq!(
+            error("<coordinates>116.4,x</coordinates>"),
+            "invalid coordinate"
+        );
+        assert_eq!(
+            positions_3d("<coordinates>116.4,39.9,x</coordinates>")
+                .unwrap_err()
+                .message,
+            "invalid altitude"
+        );
+    }
+}
@@ -1,10 +1,89 @@
+// Lets the code generated by the derive macro name this crate from its own tests.
+#[cfg(feature = "derive")]
+extern crate self as undrift_gps;
+
 use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
 
+pub mod audit;
 pub mod baidu_mercator;
+pub mod basemap;
+pub mod batch;
+pub mod bulk;
+pub mod circle;
+pub mod converter;
+pub mod correction;
+pub mod datum;
+pub mod distance;
+pub mod drift_field;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fit;
+#[cfg(feature = "embedded")]
+pub mod fixed;
+pub mod format;
+pub mod gauss_kruger;
+#[cfg(feature = "http")]
+pub mod geocoder;
+pub mod geojson;
+pub mod georef;
+pub mod gml;
+pub mod gpx;
+pub mod gzip;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "ingest")]
+pub mod ingest;
+mod json;
+pub mod kml;
+pub mod maidenhead;
+pub mod mvt;
+pub mod offset_grid;
+pub mod osm;
+pub mod pipeline;
+pub mod plus_code;
+mod png;
+pub mod point;
+pub mod precise;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+pub mod road_match;
+pub mod s2;
+pub mod simplify;
+pub mod spec;
+pub mod srt;
+pub mod stream;
+pub mod takeout;
+pub mod tcx;
+pub mod topojson;
+pub mod track;
+pub mod transcode;
+pub mod transform;
+pub mod ubx;
+pub mod validate;
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod web_mercator;
+mod xml;
+pub mod xmp;
+pub mod zip;
+
+pub use converter::{
+    Algorithm, ConvertCoords, Converter, Float, NonFinitePolicy, MAX_OBFUSCATED_LAT,
+};
+pub use error::Error;
+pub use json::JsonError;
+#[cfg(feature = "derive")]
+pub use undrift_gps_derive::ConvertCoords;
+pub use xml::XmlError;
 
 const PI_X: f64 = std::f64::consts::PI * 3000.0 / 180.0;
 
-fn wgs_encrypt(x: f64, y: f64) -> (f64, f64) {
+/// The distortion as originally written, kept unchanged for [`Algorithm::V1Classic`].
+pub(crate) fn wgs_encrypt_v1(x: f64, y: f64) -> (f64, f64) {
     let r = 20.0 * (6.0 * PI * y).sin() + 20.0 * (2.0 * PI * y).sin();
 
     let x_p = 20.0 * (PI * x).sin() + 40.0 * (PI / 3.0 * x).sin();
@@ -30,12 +109,156 @@ fn wgs_encrypt(x: f64, y: f64) -> (f64, f64) {
     (x_t, y_t)
 }
 
-fn is_inside_china(lat: f64, lon: f64) -> bool {
-    (0.8293..=55.8271).contains(&lat) && (72.004..=137.8347).contains(&lon)
+/// Sines of the angles used by `wgs_encrypt`, derived from as few trigonometric calls as possible.
+///
+/// Returns the sines of `v * PI / 12`, `v * PI / 30`, `v * PI / 3` and `v * PI`, then the cosine of
+/// `v * PI`.
+fn sin_multiples(v: f64) -> (f64, f64, f64, f64, f64) {
+    let (s12, c12) = (PI / 12.0 * v).sin_cos();
+    let s30 = (PI / 30.0 * v).sin();
+    derive_multiples(s12, c12, s30)
 }
 
-/// Convert a WGS-84 coordinate into GCJ-02
-pub fn wgs_to_gcj(lat: f64, lon: f64) -> (f64, f64) {
+/// Same as `sin_multiples` with polynomial sines, see `approx_sin_cos`.
+#[inline(always)]
+fn approx_sin_multiples(v: f64) -> (f64, f64, f64, f64, f64) {
+    let (s12, c12) = approx_sin_cos(PI / 12.0 * v);
+    let (s30, _) = approx_sin_cos(PI / 30.0 * v);
+    derive_multiples(s12, c12, s30)
+}
+
+#[inline(always)]
+fn derive_multiples(s12: f64, c12: f64, s30: f64) -> (f64, f64, f64, f64, f64) {
+    // Double angle twice to reach PI / 3, then triple angle to reach PI.
+    let (s6, c6) = (2.0 * s12 * c12, 1.0 - 2.0 * s12 * s12);
+    let (s3, c3) = (2.0 * s6 * c6, 1.0 - 2.0 * s6 * s6);
+    let (s1, c1) = (s3 * (3.0 - 4.0 * s3 * s3), c3 * (4.0 * c3 * c3 - 3.0));
+    (s12, s30, s3, s1, c1)
+}
+
+/// Sine and cosine from Taylor polynomials after reducing the angle to [-PI / 2, PI / 2].
+///
+/// The error is below 1e-6 for angles of a few hundred radians, more accurate than the distortion
+/// needs.
+#[inline(always)]
+fn approx_sin_cos(a: f64) -> (f64, f64) {
+    // Rounding through an integer cast, as `f64::round` and `%` may be library calls.
+    let k = (a / PI + 0.5f64.copysign(a)) as i64;
+    let r = a - k as f64 * PI;
+    let r2 = r * r;
+    // Taylor coefficients, highest degree first.
+    const SIN: [f64; 6] = [
+        -1.0 / 39916800.0,
+        1.0 / 362880.0,
+        -1.0 / 5040.0,
+        1.0 / 120.0,
+        -1.0 / 6.0,
+        1.0,
+    ];
+    const COS: [f64; 6] = [
+        -1.0 / 3628800.0,
+        1.0 / 40320.0,
+        -1.0 / 720.0,
+        1.0 / 24.0,
+        -0.5,
+        1.0,
+    ];
+    let sin = r * SIN.iter().fold(0.0, |acc, c| acc * r2 + c);
+    let cos = COS.iter().fold(0.0, |acc, c| acc * r2 + c);
+    if k % 2 == 0 {
+        (sin, cos)
+    } else {
+        (-sin, -cos)
+    }
+}
+
+fn wgs_encrypt(x: f64, y: f64) -> (f64, f64) {
+    encrypt_with(sin_multiples, x, y)
+}
+
+/// The distortion with polynomial sines, for [`Algorithm::FastApprox`].
+#[inline(always)]
+fn wgs_encrypt_approx(x: f64, y: f64) -> (f64, f64) {
+    encrypt_with(approx_sin_multiples, x, y)
+}
+
+#[inline(always)]
+fn encrypt_with(sin_multiples: fn(f64) -> (f64, f64, f64, f64, f64), x: f64, y: f64) -> (f64, f64) {
+    let (x_s12, x_s30, x_s3, x_s1, _) = sin_multiples(x);
+    let (y_s12, y_s30, y_s3, y_s1, y_c1) = sin_multiples(y);
+    let y_s2 = 2.0 * y_s1 * y_c1;
+    let y_s6 = y_s2 * (3.0 - 4.0 * y_s2 * y_s2);
+    let r = 20.0 * y_s6 + 20.0 * y_s2;
+
+    let x_p = 20.0 * x_s1 + 40.0 * x_s3;
+    let x_q = 160.0 * x_s12 + 320.0 * x_s30;
+    let x_t = -100.0
+        + 2.0 * y
+        + 3.0 * x
+        + 0.2 * x * x
+        + 0.1 * x * y
+        + 0.2 * y.abs().sqrt()
+        + 2.0 / 3.0 * (r + x_p + x_q);
+
+    let y_p = 20.0 * y_s1 + 40.0 * y_s3;
+    let y_q = 150.0 * y_s12 + 300.0 * y_s30;
+    let y_t = 300.0
+        + y
+        + 2.0 * x
+        + 0.1 * y * y
+        + 0.1 * x * y
+        + 0.1 * y.abs().sqrt()
+        + 2.0 / 3.0 * (r + y_p + y_q);
+
+    (x_t, y_t)
+}
+
+/// The rectangle in which `wgs_to_gcj` applies the obfuscation.
+///
+/// These are the bounds used by the widely deployed reference implementations of GCJ-02 (such
+/// as eviltransform), so that coordinates just outside mainland China convert identically.
+pub const CHINA_BBOX: BoundingBox = BoundingBox {
+    min_lat: 0.8293,
+    min_lon: 72.004,
+    max_lat: 55.8271,
+    max_lon: 137.8347,
+};
+
+/// Check whether a coordinate is subject to the GCJ-02 obfuscation
+///
+/// This is a coarse rectangle (see [`CHINA_BBOX`]) rather than the actual border: it also covers
+/// parts of neighbouring countries, but matches what other implementations do.
+pub fn is_in_china(lat: f64, lon: f64) -> bool {
+    CHINA_BBOX.contains(lat, lon)
+}
+
+/// Offset added by GCJ-02 to a WGS-84 coordinate, regardless of the region.
+pub(crate) fn gcj_offset(lat: f64, lon: f64) -> (f64, f64) {
+    offset_with(wgs_encrypt, std_sin_cos, lat, lon)
+}
+
+/// Same as `gcj_offset` with the original distortion, see `wgs_encrypt_v1`.
+pub(crate) fn gcj_offset_v1(lat: f64, lon: f64) -> (f64, f64) {
+    offset_with(wgs_encrypt_v1, std_sin_cos, lat, lon)
+}
+
+/// Same as `gcj_offset` with polynomial sines everywhere, see `approx_sin_cos`.
+#[inline(always)]
+pub(crate) fn gcj_offset_approx(lat: f64, lon: f64) -> (f64, f64) {
+    offset_with(wgs_encrypt_approx, approx_sin_cos, lat, lon)
+}
+
+fn std_sin_cos(a: f64) -> (f64, f64) {
+    (a.sin(), a.cos())
+}
+
+#[inline(always)]
+fn offset_with(
+    encrypt: fn(f64, f64) -> (f64, f64),
+    sin_cos: fn(f64) -> (f64, f64),
+    lat: f64,
+    lon: f64,
+) -> (f64, f64) {
     /* Krasovsky 1940
        a = 6378245.0, 1/f = 298.3
        b = a * (1 - f)
@@ -44,61 +267,189 @@ pub fn wgs_to_gcj(lat: f64, lon: f64) -> (f64, f64) {
     const A: f64 = 6378245.0;
     const EE: f64 = 0.006_693_421_622_965_943;
 
-    if !is_inside_china(lat, lon) {
-        return (lat, lon);
-    }
+    let (lat_sin, lat_cos) = sin_cos(PI / 180.0 * lat);
+    let magic = 1.0 - EE * lat_sin.powi(2);
 
-    let lat_rad = PI / 180.0 * lat;
-    let magic = 1.0 - EE * lat_rad.sin().powi(2);
-
-    let (lat_t, lon_t) = wgs_encrypt(lat - 35.0, lon - 105.0);
+    let (lat_t, lon_t) = encrypt(lat - 35.0, lon - 105.0);
     let lat_d = (lat_t * 180.0) / (PI * A * (1.0 - EE) / (magic * magic.sqrt()));
-    let lon_d = (lon_t * 180.0) / (PI * A / magic.sqrt() * lat_rad.cos());
+    let lon_d = (lon_t * 180.0) / (PI * A / magic.sqrt() * lat_cos);
+    (lat_d, lon_d)
+}
+
+/// Convert a WGS-84 coordinate into GCJ-02
+pub fn wgs_to_gcj(lat: f64, lon: f64) -> (f64, f64) {
+    if !is_in_china(lat, lon) {
+        return (lat, lon);
+    }
 
+    let (lat_d, lon_d) = gcj_offset(lat, lon);
     (lat + lat_d, lon + lon_d)
 }
 
 /// Convert a GCJ-02 coordinate into WGS-84
 pub fn gcj_to_wgs(lat: f64, lon: f64) -> (f64, f64) {
-    let gcj = (lat, lon);
-    let mut wgs = gcj;
+    gcj_to_wgs_converged(lat, lon).0
+}
+
+/// Inverts `wgs_to_gcj` iteratively, also telling whether the iteration converged.
+///
+/// Whether the obfuscation applies is decided once from the GCJ-02 input, after which each round
+/// only evaluates the offset: the estimate is `gcj - offset(estimate)`.
+pub(crate) fn gcj_to_wgs_converged(lat: f64, lon: f64) -> ((f64, f64), bool) {
+    if !is_in_china(lat, lon) {
+        return ((lat, lon), true);
+    }
 
     const EPS: f64 = 1e-7;
     const MAX_ROUND: u32 = 10;
+    let mut wgs = (lat, lon);
     for _ in 0..MAX_ROUND {
-        let cur = wgs_to_gcj(wgs.0, wgs.1);
+        let (lat_d, lon_d) = gcj_offset(wgs.0, wgs.1);
+        let next = (lat - lat_d, lon - lon_d);
+        let converged = (next.0 - wgs.0).abs() < EPS && (next.1 - wgs.1).abs() < EPS;
+        wgs = next;
+        if converged {
+            return (wgs, true);
+        }
+    }
+
+    (wgs, false)
+}
+
+/// Tolerance in degrees of `invert`, also used by the wide kernels in [`bulk`].
+pub(crate) const INVERT_EPS: f64 = 1e-7;
+/// Round limit of `invert`.
+pub(crate) const INVERT_MAX_ROUND: u32 = 10;
+
+/// Inverts a forward transform by fixed-point iteration on its output, starting from `start`.
+///
+/// Each time the residual fails to decrease, which happens when the forward transform jumps at
+/// the edge of its region, the following steps are halved, see [`Damping`]. Converging inputs
+/// never trigger it, so their results are unchanged.
+pub(crate) fn invert<F>(forward: F, lat: f64, lon: f64, start: (f64, f64)) -> ((f64, f64), bool)
+where
+    F: Fn(f64, f64) -> (f64, f64),
+{
+    let gcj = (lat, lon);
+    let mut wgs = start;
+    let mut damping = Damping::new();
+
+    for _ in 0..INVERT_MAX_ROUND {
+        let cur = forward(wgs.0, wgs.1);
         let delta = ((gcj.0 - cur.0), (gcj.1 - cur.1));
-        if delta.0.abs() < EPS && delta.1.abs() < EPS {
-            break;
+        if delta.0.abs() < INVERT_EPS && delta.1.abs() < INVERT_EPS {
+            return (wgs, true);
         }
 
-        wgs.0 += delta.0;
-        wgs.1 += delta.1;
+        let factor = damping.update(delta);
+        wgs.0 += delta.0 * factor;
+        wgs.1 += delta.1 * factor;
+    }
+
+    (wgs, false)
+}
+
+/// Step factor of an inversion, halved whenever the residual stops decreasing.
+///
+/// Without it, an output just across the edge of the region, which has no exact preimage, makes
+/// the estimate jump back and forth between both sides; with it, the estimate settles next to
+/// the edge.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Damping {
+    last: f64,
+    factor: f64,
+}
+
+impl Damping {
+    pub(crate) fn new() -> Self {
+        Damping {
+            last: f64::INFINITY,
+            factor: 1.0,
+        }
     }
 
-    wgs
+    /// The factor to apply to the step `delta`, the residual of the current estimate.
+    pub(crate) fn update(&mut self, delta: (f64, f64)) -> f64 {
+        let residual = delta.0.abs().max(delta.1.abs());
+        if residual >= self.last {
+            self.factor *= 0.5;
+        }
+        self.last = residual;
+        self.factor
+    }
 }
 
 /// Convert a GCJ-02 coordinate into BD-09
+///
+/// The offset applies everywhere, unlike `wgs_to_gcj`; see [`Converter::with_bd_passthrough`] to
+/// limit it to a region. Results pushed over a pole or the antimeridian are brought back in
+/// range, see [`normalize`].
 pub fn gcj_to_bd(lat: f64, lon: f64) -> (f64, f64) {
     let z = (lon * lon + lat * lat).sqrt() + 0.00002 * (PI_X * lat).sin();
     let theta = lat.atan2(lon) + 0.000003 * (PI_X * lon).cos();
-    (z * theta.sin() + 0.006, z * theta.cos() + 0.0065)
+    normalize(z * theta.sin() + 0.006, z * theta.cos() + 0.0065)
 }
 
 /// Convert a BD-09 coordinate into GCJ-02
+///
+/// The offset applies everywhere, see [`gcj_to_bd`]. Results pushed over a pole or the
+/// antimeridian are brought back in range, see [`normalize`].
 pub fn bd_to_gcj(lat: f64, lon: f64) -> (f64, f64) {
     let (lat, lon) = (lat - 0.006, lon - 0.0065);
     let z = (lon * lon + lat * lat).sqrt() - 0.00002 * (PI_X * lat).sin();
     let theta = lat.atan2(lon) - 0.000003 * (PI_X * lon).cos();
-    (z * theta.sin(), z * theta.cos())
+    normalize(z * theta.sin(), z * theta.cos())
+}
+
+/// Brings a coordinate back in range, clamping the latitude to [-90, 90] and wrapping the
+/// longitude into [-180, 180]. Coordinates already in range, and NaN, are returned unchanged.
+///
+/// The offsets of BD-09, about 700 meters, push coordinates within that distance of a pole or of
+/// the antimeridian out of range. Clamping loses the meaningless longitude offsets at the poles.
+pub fn normalize(lat: f64, lon: f64) -> (f64, f64) {
+    let lon = if (-180.0..=180.0).contains(&lon) || !lon.is_finite() {
+        lon
+    } else {
+        (lon + 180.0).rem_euclid(360.0) - 180.0
+    };
+    (lat.clamp(-90.0, 90.0), lon)
 }
 
 /// Convert a BD-09 coordinate into WGS-84
+///
+/// The BD-09 offset is inverted more precisely than by [`bd_to_gcj`] first, see
+/// `bd_to_gcj_refined`, so that only the tolerance of [`gcj_to_wgs`] remains in the result.
+/// [`Converter::with_whole_chain`] inverts both offsets at once instead.
 pub fn bd_to_wgs(lat: f64, lon: f64) -> (f64, f64) {
-    let (lat, lon) = bd_to_gcj(lat, lon);
-    let (lat, lon) = gcj_to_wgs(lat, lon);
-    (lat, lon)
+    let (lat, lon) = bd_to_gcj_refined(lat, lon, INVERT_EPS);
+    gcj_to_wgs(lat, lon)
+}
+
+/// Same as [`bd_to_gcj`], corrected by fixed-point steps on [`gcj_to_bd`] until they are below
+/// `eps`, the tolerance of the inversion of GCJ-02 that follows.
+///
+/// The closed form is only exact to a few centimeters, an error which the inversion of GCJ-02
+/// would carry over and amplify. Each step shrinks the error fiftyfold, so that two steps are
+/// enough for [`INVERT_EPS`].
+pub(crate) fn bd_to_gcj_refined(lat: f64, lon: f64, eps: f64) -> (f64, f64) {
+    const MAX_ROUND: u32 = 6;
+    let mut gcj = bd_to_gcj(lat, lon);
+    for _ in 0..MAX_ROUND {
+        let bd = gcj_to_bd(gcj.0, gcj.1);
+        let mut lon_d = lon - bd.1;
+        // Across the antimeridian, the longitudes differ by a turn.
+        if lon_d > 180.0 {
+            lon_d -= 360.0;
+        } else if lon_d < -180.0 {
+            lon_d += 360.0;
+        }
+        let lat_d = lat - bd.0;
+        gcj = (gcj.0 + lat_d, gcj.1 + lon_d);
+        if lat_d.abs() < eps && lon_d.abs() < eps {
+            break;
+        }
+    }
+    normalize(gcj.0, gcj.1)
 }
 
 /// Convert a WGS-84 coordinate into BD-09
@@ -108,8 +459,119 @@ pub fn wgs_to_bd(lat: f64, lon: f64) -> (f64, f64) {
     (lat, lon)
 }
 
-/// Describes a coordinate system.
+/// Mean radius of the Earth, in meters.
+pub(crate) const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Great-circle distance between two points in meters.
+pub(crate) fn haversine((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = phi2 - phi1;
+    let d_lambda = (lon2 - lon1).to_radians();
+    let h = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+/// Displacement introduced by the GCJ-02 obfuscation, in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Drift {
+    /// Eastward component, negative when the shift is westward.
+    pub east: f64,
+    /// Northward component, negative when the shift is southward.
+    pub north: f64,
+    /// Length of the displacement.
+    pub magnitude: f64,
+}
+
+/// Measure the WGS-84 to GCJ-02 displacement at a WGS-84 coordinate
+pub fn drift_meters(lat: f64, lon: f64) -> Drift {
+    let (gcj_lat, gcj_lon) = wgs_to_gcj(lat, lon);
+    let east = haversine((lat, lon), (lat, gcj_lon)).copysign(gcj_lon - lon);
+    let north = haversine((lat, lon), (gcj_lat, lon)).copysign(gcj_lat - lat);
+    let magnitude = haversine((lat, lon), (gcj_lat, gcj_lon));
+    Drift {
+        east,
+        north,
+        magnitude,
+    }
+}
+
+/// A latitude/longitude aligned rectangle.
 #[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl BoundingBox {
+    /// Creates a bounding box from its south-west and north-east corners.
+    pub fn new(min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> Self {
+        BoundingBox {
+            min_lat,
+            min_lon,
+            max_lat,
+            max_lon,
+        }
+    }
+
+    /// Checks whether the coordinate lies within the box, edges included.
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        (self.min_lat..=self.max_lat).contains(&lat) && (self.min_lon..=self.max_lon).contains(&lon)
+    }
+
+    /// The box grown by `meters` on every side, or shrunk if negative.
+    ///
+    /// Longitudes are moved by the number of degrees spanning `meters` at the latitude of the box
+    /// farthest from the equator, so that the distance is at least `meters` everywhere. A box
+    /// shrunk beyond its size contains nothing.
+    pub fn buffered(&self, meters: f64) -> BoundingBox {
+        let degrees = (meters / EARTH_RADIUS).to_degrees();
+        let lat = self.min_lat.abs().max(self.max_lat.abs()).min(89.0);
+        let lon_degrees = degrees / lat.to_radians().cos();
+        BoundingBox::new(
+            (self.min_lat - degrees).max(-90.0),
+            self.min_lon - lon_degrees,
+            (self.max_lat + degrees).min(90.0),
+            self.max_lon + lon_degrees,
+        )
+    }
+
+    /// The box enclosing this one once converted, as for querying an index of another system.
+    ///
+    /// The offsets bend the edges of the box, so that points along them are converted every
+    /// 100 meters, and at most a thousand times per edge: the box is exact to a centimeter up to
+    /// 100 km wide, and to a meter for the whole of China.
+    pub fn converted(&self, converter: &Converter) -> BoundingBox {
+        const STEP: f64 = 100.0;
+        const MAX_SAMPLES: usize = 1000;
+        let corners = [
+            (self.min_lat, self.min_lon),
+            (self.min_lat, self.max_lon),
+            (self.max_lat, self.max_lon),
+            (self.max_lat, self.min_lon),
+        ];
+        let mut bounds =
+            BoundingBox::new(f64::INFINITY, f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        for (i, &from) in corners.iter().enumerate() {
+            let to = corners[(i + 1) % 4];
+            let samples = ((haversine(from, to) / STEP).ceil() as usize).clamp(1, MAX_SAMPLES);
+            for step in 0..samples {
+                let t = step as f64 / samples as f64;
+                let (lat, lon) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+                let (lat, lon) = converter.convert(lat, lon);
+                bounds.min_lat = bounds.min_lat.min(lat);
+                bounds.min_lon = bounds.min_lon.min(lon);
+                bounds.max_lat = bounds.max_lat.max(lat);
+                bounds.max_lon = bounds.max_lon.max(lon);
+            }
+        }
+        bounds
+    }
+}
+
+/// Describes a coordinate system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum GeodeticSystem {
     Wgs84,
     Gcj02,
@@ -117,7 +579,42 @@ pub enum GeodeticSystem {
 }
 
 impl GeodeticSystem {
+    /// Every supported system.
+    pub const ALL: [GeodeticSystem; 3] = [
+        GeodeticSystem::Wgs84,
+        GeodeticSystem::Gcj02,
+        GeodeticSystem::Bd09,
+    ];
+
+    /// Short human-readable name, such as "WGS-84".
+    pub fn name(self) -> &'static str {
+        match self {
+            GeodeticSystem::Wgs84 => "WGS-84",
+            GeodeticSystem::Gcj02 => "GCJ-02",
+            GeodeticSystem::Bd09 => "BD-09",
+        }
+    }
+
+    /// One-line description of the system and where it is found.
+    pub fn description(self) -> &'static str {
+        match self {
+            GeodeticSystem::Wgs84 => "World Geodetic System 1984, used by GPS",
+            GeodeticSystem::Gcj02 => {
+                "Obfuscated datum mandated in China, used by Amap, Tencent and Google in China"
+            }
+            GeodeticSystem::Bd09 => "Baidu's further obfuscation of GCJ-02, used by Baidu Maps",
+        }
+    }
+
+    /// Whether the system deliberately shifts coordinates from their true location.
+    pub fn is_obfuscated(self) -> bool {
+        self != GeodeticSystem::Wgs84
+    }
+
     /// Converts a coordinate to the target system.
+    ///
+    /// The input must be in range, with latitudes within [-90, 90] and longitudes within
+    /// [-180, 180], and so is the output; see [`Converter`] for the details.
     pub fn convert_to(self, target: Self, lat: f64, lon: f64) -> (f64, f64) {
         use GeodeticSystem::*;
         match (self, target) {
@@ -131,6 +628,71 @@ impl GeodeticSystem {
             _ => unreachable!(),
         }
     }
+
+    /// The conversion to the target system as a function, the same as `convert_to`.
+    ///
+    /// The pair of systems is matched once, here, instead of at every call, and the function
+    /// can be passed where a closure is expected, such as to `Iterator::map` or another thread.
+    pub fn compiled_to(
+        self,
+        target: Self,
+    ) -> impl Fn(f64, f64) -> (f64, f64) + Copy + Send + Sync + 'static {
+        use GeodeticSystem::*;
+        let convert: fn(f64, f64) -> (f64, f64) = match (self, target) {
+            (x, y) if x == y => |lat, lon| (lat, lon),
+            (Wgs84, Gcj02) => wgs_to_gcj,
+            (Wgs84, Bd09) => wgs_to_bd,
+            (Gcj02, Wgs84) => gcj_to_wgs,
+            (Gcj02, Bd09) => gcj_to_bd,
+            (Bd09, Wgs84) => bd_to_wgs,
+            (Bd09, Gcj02) => bd_to_gcj,
+            _ => unreachable!(),
+        };
+        convert
+    }
+
+    /// Same as `convert_to` with an altitude, which is passed through unchanged.
+    pub fn convert_to_3d(self, target: Self, lat: f64, lon: f64, alt: f64) -> (f64, f64, f64) {
+        let (lat, lon) = self.convert_to(target, lat, lon);
+        (lat, lon, alt)
+    }
+}
+
+impl fmt::Display for GeodeticSystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Error returned when parsing an unknown coordinate system name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSystemError(String);
+
+impl fmt::Display for ParseSystemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown coordinate system: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseSystemError {}
+
+impl FromStr for GeodeticSystem {
+    type Err = ParseSystemError;
+
+    /// Parses a system name case-insensitively, accepting the aliases found in common APIs.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized: String = s
+            .chars()
+            .filter(|c| !matches!(c, '-' | '_' | ' '))
+            .flat_map(char::to_lowercase)
+            .collect();
+        match normalized.as_str() {
+            "wgs84" | "wgs" | "gps" | "epsg:4326" => Ok(GeodeticSystem::Wgs84),
+            "gcj02" | "gcj" | "gcj02ll" | "mars" => Ok(GeodeticSystem::Gcj02),
+            "bd09" | "bd" | "bd09ll" | "baidu" => Ok(GeodeticSystem::Bd09),
+            _ => Err(ParseSystemError(s.to_owned())),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -171,4 +733,150 @@ mod test {
     fn gcj_to_wgs() {
         loc_assert(super::gcj_to_wgs(39.0, 116.0), (38.999133, 115.994002));
     }
+
+    #[test]
+    fn bd_to_wgs() {
+        // The round trip is within the tolerance of the inversion, without the error of the
+        // closed-form inverse of BD-09, near the antimeridian too.
+        for &(lat, lon) in &[
+            (39.0, 116.0),
+            (22.3, 114.2),
+            (48.8566, 2.3522),
+            (10.0, 179.999),
+        ] {
+            let bd = super::wgs_to_bd(lat, lon);
+            let (wgs_lat, wgs_lon) = super::bd_to_wgs(bd.0, bd.1);
+            let (x, y) = super::wgs_to_bd(wgs_lat, wgs_lon);
+            assert!((x - bd.0).abs() < 1e-7 && (y - bd.1).abs() < 1e-7);
+
+            let (gcj_lat, gcj_lon) = super::bd_to_gcj_refined(bd.0, bd.1, 1e-12);
+            let (x, y) = super::gcj_to_bd(gcj_lat, gcj_lon);
+            assert!((x - bd.0).abs() < 1e-12 && (y - bd.1).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn drift_meters() {
+        let drift = super::drift_meters(39.0, 116.0);
+        assert!((drift.north - 98.5).abs() < 1.0, "north = {}", drift.north);
+        assert!((drift.east - 520.1).abs() < 1.0, "east = {}", drift.east);
+        let expected = drift.east.hypot(drift.north);
+        assert!((drift.magnitude - expected).abs() < 0.1);
+
+        let outside = super::drift_meters(48.8566, 2.3522);
+        assert_eq!(outside.magnitude, 0.0);
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(super::normalize(39.0, 116.0), (39.0, 116.0));
+        assert_eq!(super::normalize(90.5, 540.0), (90.0, -180.0));
+        assert_eq!(super::normalize(-91.0, -181.0), (-90.0, 179.0));
+        assert_eq!(super::normalize(0.0, 181.0), (0.0, -179.0));
+        assert!(super::normalize(f64::NAN, f64::INFINITY).0.is_nan());
+        for &(lat, lon) in &[(90.0, 180.0), (-90.0, -180.0), (0.0, 180.0), (45.0, -180.0)] {
+            let (a, b) = super::gcj_to_bd(lat, lon);
+            assert!(
+                a.abs() <= 90.0 && b.abs() <= 180.0,
+                "gcj_to_bd({}, {})",
+                lat,
+                lon
+            );
+            let (a, b) = super::bd_to_gcj(lat, lon);
+            assert!(
+                a.abs() <= 90.0 && b.abs() <= 180.0,
+                "bd_to_gcj({}, {})",
+                lat,
+                lon
+            );
+        }
+    }
+
+    #[test]
+    fn is_in_china() {
+        assert!(super::is_in_china(39.9042, 116.4074));
+        assert!(super::is_in_china(22.3193, 114.1694));
+        assert!(!super::is_in_china(48.8566, 2.3522));
+        assert!(!super::is_in_china(-33.8688, 151.2093));
+    }
+
+    #[test]
+    fn buffered() {
+        let b = super::CHINA_BBOX;
+        let grown = b.buffered(1000.0);
+        assert!(grown.contains(b.max_lat + 0.008, b.max_lon + 0.015));
+        assert!(!grown.contains(b.max_lat + 0.01, b.max_lon));
+        let shrunk = b.buffered(-1000.0);
+        assert!(!shrunk.contains(b.min_lat + 0.008, b.min_lon + 0.1));
+        assert!(shrunk.buffered(1001.0).contains(b.min_lat, b.min_lon));
+        assert!(!b.buffered(-1e7).contains(30.0, 100.0));
+    }
+
+    #[test]
+    fn converted() {
+        use super::{BoundingBox, Converter, GeodeticSystem::*};
+        let b = BoundingBox::new(39.8, 116.2, 40.0, 116.5);
+        let to_gcj = Converter::new(Wgs84, Gcj02);
+        let gcj = b.converted(&to_gcj);
+        // Every converted point of the box is inside, and the edges are reached.
+        let mut reached = BoundingBox::new(90.0, 180.0, -90.0, -180.0);
+        for i in 0..=200 {
+            for j in 0..=200 {
+                let lat = b.min_lat + (b.max_lat - b.min_lat) * f64::from(i) / 200.0;
+                let lon = b.min_lon + (b.max_lon - b.min_lon) * f64::from(j) / 200.0;
+                let (lat, lon) = to_gcj.convert(lat, lon);
+                assert!(gcj.buffered(0.01).contains(lat, lon), "{} {}", lat, lon);
+                reached = BoundingBox::new(
+                    reached.min_lat.min(lat),
+                    reached.min_lon.min(lon),
+                    reached.max_lat.max(lat),
+                    reached.max_lon.max(lon),
+                );
+            }
+        }
+        assert!(reached.buffered(0.01).contains(gcj.min_lat, gcj.min_lon));
+        assert!(reached.buffered(0.01).contains(gcj.max_lat, gcj.max_lon));
+        assert_eq!(b.converted(&Converter::new(Wgs84, Wgs84)), b);
+    }
+
+    #[test]
+    fn system_metadata() {
+        use super::GeodeticSystem;
+        let names: Vec<_> = GeodeticSystem::ALL.iter().map(|s| s.name()).collect();
+        assert_eq!(names, ["WGS-84", "GCJ-02", "BD-09"]);
+        assert!(!GeodeticSystem::Wgs84.is_obfuscated());
+        assert!(GeodeticSystem::Bd09.is_obfuscated());
+        assert!(!GeodeticSystem::Gcj02.description().is_empty());
+    }
+
+    #[test]
+    fn system_from_str() {
+        use super::GeodeticSystem;
+        for system in &GeodeticSystem::ALL {
+            assert_eq!(system.to_string().parse(), Ok(*system));
+        }
+        assert_eq!("bd09ll".parse(), Ok(GeodeticSystem::Bd09));
+        assert_eq!("GCJ_02".parse(), Ok(GeodeticSystem::Gcj02));
+        assert_eq!("EPSG:4326".parse(), Ok(GeodeticSystem::Wgs84));
+        assert!("mercator".parse::<GeodeticSystem>().is_err());
+    }
+
+    #[test]
+    fn compiled_to() {
+        use super::GeodeticSystem;
+        for &from in &GeodeticSystem::ALL {
+            for &to in &GeodeticSystem::ALL {
+                let convert = from.compiled_to(to);
+                for &(lat, lon) in &[(39.9, 116.4), (22.3, 114.2), (48.8566, 2.3522)] {
+                    assert_eq!(convert(lat, lon), from.convert_to(to, lat, lon));
+                }
+            }
+        }
+        let convert = GeodeticSystem::Wgs84.compiled_to(GeodeticSystem::Gcj02);
+        let points: Vec<_> = [(39.9, 116.4)]
+            .iter()
+            .map(|&(lat, lon)| convert(lat, lon))
+            .collect();
+        assert_eq!(points, [super::wgs_to_gcj(39.9, 116.4)]);
+    }
 }
@@ -0,0 +1,168 @@
+//! Maidenhead grid locators, as used in amateur radio.
+//!
+//! A locator is made of pairs of characters, each pair dividing the square of the previous one:
+//! fields of 20° by 10° (`A` to `R`), squares of 2° by 1° (`0` to `9`), subsquares of 5' by
+//! 2.5' (`a` to `x`), then extended squares and subsquares. Logging software reads locators as
+//! WGS-84; [`convert`] moves a locator computed from a GCJ-02 map to the right square.
+use crate::Converter;
+use std::fmt;
+
+/// Divisions of the longitude and latitude by each pair, and whether the pair is a letter.
+const PAIRS: [(u32, bool); 5] = [(18, true), (10, false), (24, true), (10, false), (24, true)];
+
+/// Error returned for a string that is not a locator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocatorError;
+
+impl fmt::Display for LocatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid Maidenhead locator")
+    }
+}
+
+impl std::error::Error for LocatorError {}
+
+/// The square a locator designates, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Square {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+    /// Number of pairs of the locator.
+    pub pairs: usize,
+}
+
+impl Square {
+    /// The center of the square, as latitude and longitude.
+    pub fn center(&self) -> (f64, f64) {
+        (
+            (self.south + self.north) / 2.0,
+            (self.west + self.east) / 2.0,
+        )
+    }
+}
+
+/// Encodes a location as a locator of 1 to 5 pairs, such as `"OM89ev"` for 3 pairs in Beijing.
+///
+/// Fields are written in upper case and subsquares in lower case, as is customary.
+pub fn encode(lat: f64, lon: f64, pairs: usize) -> String {
+    assert!((1..=PAIRS.len()).contains(&pairs), "invalid locator length");
+    let mut lon = (lon + 180.0).rem_euclid(360.0);
+    let mut lat = (lat + 90.0).clamp(0.0, 180.0);
+    let (mut width, mut height) = (360.0, 180.0);
+    let mut locator = String::with_capacity(2 * pairs);
+    for (i, &(base, letter)) in PAIRS[..pairs].iter().enumerate() {
+        width /= f64::from(base);
+        height /= f64::from(base);
+        let index = |value: f64, size: f64| ((value / size).floor() as u32).min(base - 1);
+        let (x, y) = (index(lon, width), index(lat, height));
+        lon -= f64::from(x) * width;
+        lat -= f64::from(y) * height;
+        for &n in &[x, y] {
+            let c = match (letter, i) {
+                (true, 0) => b'A' + n as u8,
+                (true, _) => b'a' + n as u8,
+                (false, _) => b'0' + n as u8,
+            };
+            locator.push(char::from(c));
+        }
+    }
+    locator
+}
+
+/// Decodes a locator of 1 to 5 pairs into its square, in any case.
+pub fn decode(locator: &str) -> Result<Square, LocatorError> {
+    let bytes = locator.as_bytes();
+    if bytes.is_empty() || bytes.len() % 2 != 0 || bytes.len() > 2 * PAIRS.len() {
+        return Err(LocatorError);
+    }
+    let (mut west, mut south) = (0.0, 0.0);
+    let (mut width, mut height) = (360.0, 180.0);
+    for (pair, &(base, letter)) in bytes.chunks(2).zip(&PAIRS) {
+        width /= f64::from(base);
+        height /= f64::from(base);
+        let index = |c: u8| {
+            let n = if letter {
+                c.to_ascii_uppercase().wrapping_sub(b'A')
+            } else {
+                c.wrapping_sub(b'0')
+            };
+            Some(u32::from(n)).filter(|&n| n < base)
+        };
+        let (x, y) = match (index(pair[0]), index(pair[1])) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return Err(LocatorError),
+        };
+        west += f64::from(x) * width;
+        south += f64::from(y) * height;
+    }
+    Ok(Square {
+        south: south - 90.0,
+        west: west - 180.0,
+        north: south + height - 90.0,
+        east: west + width - 180.0,
+        pairs: bytes.len() / 2,
+    })
+}
+
+/// Converts a locator to another system: the center of its square is converted, and encoded
+/// again with as many pairs.
+pub fn convert(converter: &Converter, locator: &str) -> Result<String, LocatorError> {
+    let square = decode(locator)?;
+    let (lat, lon) = square.center();
+    let (lat, lon) = converter.convert(lat, lon);
+    Ok(encode(lat, lon, square.pairs))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_encode() {
+        // The station of the ARRL in Newington, and Munich.
+        assert_eq!(encode(41.714775, -72.727260, 3), "FN31pr");
+        assert_eq!(encode(48.1466, 11.6083, 3), "JN58td");
+        assert_eq!(encode(39.9042, 116.4074, 2), "OM89");
+        assert_eq!(encode(39.9042, 116.4074, 5), "OM89ev87va");
+        assert_eq!(encode(90.0, 180.0, 1), "AR");
+        assert_eq!(encode(-90.0, -180.0, 3), "AA00aa");
+    }
+
+    #[test]
+    fn test_decode() {
+        let square = decode("fn31PR").unwrap();
+        assert_eq!(square.pairs, 3);
+        let (lat, lon) = square.center();
+        assert!((lat - 41.729_166).abs() < 1e-5 && (lon - (-72.708_333)).abs() < 1e-5);
+        assert!(square.south <= 41.714775 && 41.714775 < square.north);
+        assert!(square.west <= -72.727260 && -72.727260 < square.east);
+        for locator in &["OM89ev87va", "OM", "AA00aa"] {
+            let square = decode(locator).unwrap();
+            let (lat, lon) = square.center();
+            assert_eq!(
+                encode(lat, lon, square.pairs).to_uppercase(),
+                locator.to_uppercase()
+            );
+        }
+        for locator in &["", "O", "SM", "OMA9", "OM89ez", "OM89ev87vaxx"] {
+            assert_eq!(decode(locator), Err(LocatorError), "{}", locator);
+        }
+    }
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let locator = encode(39.9042, 116.4074, 5);
+        let converted = convert(&converter, &locator).unwrap();
+        let (lat, lon) = decode(&locator).unwrap().center();
+        let (lat, lon) = converter.convert(lat, lon);
+        assert_eq!(converted, encode(lat, lon, 5));
+        assert_ne!(converted, locator);
+        // Fields are far larger than the drift.
+        assert_eq!(convert(&converter, "OM89").unwrap(), "OM89");
+        assert_eq!(convert(&converter, "OM8"), Err(LocatorError));
+    }
+}
@@ -0,0 +1,769 @@
+//! Mapbox Vector Tiles (MVT).
+//!
+//! [`reproject`] converts the geometries of a tile set and re-bins them: each feature is written
+//! to every tile of the same zoom its converted bounds touch, with the keys and values of the
+//! layers re-indexed. Everything else is copied as encoded, including feature IDs and unknown
+//! fields.
+//!
+//! Features are moved but not clipped, so parts beyond the tile buffer are left for the renderer
+//! to clip. Features that the source tiles already duplicated across their buffers stay
+//! duplicated. Tiles must be uncompressed: those stored gzipped, as in most MBTiles files, have to
+//! be inflated first. Tile addresses follow the XYZ scheme, with y growing southward.
+use crate::web_mercator::{latlon_to_xy, xy_to_latlon, RADIUS};
+use crate::Converter;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::f64::consts::PI;
+use std::fmt;
+
+/// The address of a tile in the XYZ scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TileId {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    /// The tile at a TMS address, where rows grow northward, as stored in MBTiles.
+    pub fn from_tms(z: u8, column: u32, row: u32) -> Self {
+        TileId {
+            z,
+            x: column,
+            y: ((1u64 << z) - 1 - u64::from(row)) as u32,
+        }
+    }
+
+    /// The TMS row of the tile.
+    pub fn tms_row(&self) -> u32 {
+        ((1u64 << self.z) - 1 - u64::from(self.y)) as u32
+    }
+
+    /// The Bing Maps quadkey of the tile: one digit per zoom level, from the quadrant at zoom 1.
+    pub fn quadkey(&self) -> String {
+        (1..=self.z)
+            .rev()
+            .map(|level| {
+                let bit = 1u32 << (level - 1);
+                let digit = u8::from(self.x & bit != 0) | u8::from(self.y & bit != 0) << 1;
+                char::from(b'0' + digit)
+            })
+            .collect()
+    }
+
+    /// The tile of a quadkey, or `None` if it has other digits than 0 to 3 or more than 32.
+    pub fn from_quadkey(key: &str) -> Option<Self> {
+        if key.len() > 32 {
+            return None;
+        }
+        let (mut x, mut y) = (0u32, 0u32);
+        for c in key.bytes() {
+            let digit = match c {
+                b'0'..=b'3' => u32::from(c - b'0'),
+                _ => return None,
+            };
+            x = x << 1 | digit & 1;
+            y = y << 1 | digit >> 1;
+        }
+        Some(TileId {
+            z: key.len() as u8,
+            x,
+            y,
+        })
+    }
+}
+
+/// Error returned for a malformed tile, with the byte offset where it was detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MvtError {
+    pub tile: TileId,
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for MvtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid vector tile {}/{}/{} at byte {}: {}",
+            self.tile.z, self.tile.x, self.tile.y, self.offset, self.message
+        )
+    }
+}
+
+impl std::error::Error for MvtError {}
+
+/// Error of a decoder, with the offset in the tile.
+type DecodeError = (usize, &'static str);
+
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+/// Default extent of a layer, as defined by the specification.
+const DEFAULT_EXTENT: u32 = 4096;
+
+/// Most tiles a feature may be written to, so that a malformed tile cannot fill the memory.
+const MAX_FEATURE_TILES: i64 = 1 << 16;
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_varint(out, u64::from(field << 3 | 2));
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_packed(out: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::with_capacity(values.len());
+    for &v in values {
+        write_varint(&mut packed, u64::from(v));
+    }
+    write_bytes(out, field, &packed);
+}
+
+fn zigzag(n: i64) -> u32 {
+    ((n << 1) ^ (n >> 63)) as u32
+}
+
+fn unzigzag(n: u32) -> i64 {
+    i64::from(n >> 1) ^ -i64::from(n & 1)
+}
+
+/// A protocol buffer field: its number, its value, and its whole encoding.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterates over the fields of a message starting at `base` in the tile.
+struct Fields<'a> {
+    data: &'a [u8],
+    pos: usize,
+    base: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8], base: usize) -> Self {
+        Fields { data, pos: 0, base }
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, Value<'a>, &'a [u8], usize), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.data.len() {
+            return None;
+        }
+        let start = self.pos;
+        let truncated = Err((self.base + start, "truncated field"));
+        let key = match read_varint(self.data, &mut self.pos) {
+            Some(key) => key,
+            None => return Some(truncated),
+        };
+        let payload = self.base + self.pos;
+        let value = match key & 7 {
+            0 => read_varint(self.data, &mut self.pos).map(Value::Varint),
+            1 | 5 => {
+                self.pos += if key & 7 == 1 { 8 } else { 4 };
+                Some(Value::Fixed).filter(|_| self.pos <= self.data.len())
+            }
+            2 => read_varint(self.data, &mut self.pos).and_then(|len| {
+                let start = self.pos;
+                self.pos = self.pos.checked_add(len as usize)?;
+                self.data.get(start..self.pos).map(Value::Bytes)
+            }),
+            _ => return Some(Err((self.base + start, "unsupported wire type"))),
+        };
+        match value {
+            Some(Value::Bytes(bytes)) => {
+                let offset = self.base + self.pos - bytes.len();
+                Some(Ok((
+                    (key >> 3) as u32,
+                    Value::Bytes(bytes),
+                    &self.data[start..self.pos],
+                    offset,
+                )))
+            }
+            Some(value) => Some(Ok((
+                (key >> 3) as u32,
+                value,
+                &self.data[start..self.pos],
+                payload,
+            ))),
+            None => {
+                self.pos = self.data.len();
+                Some(truncated)
+            }
+        }
+    }
+}
+
+/// Reads a packed repeated field of 32-bit varints.
+fn unpack(bytes: &[u8], offset: usize) -> Result<Vec<u32>, DecodeError> {
+    let mut values = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let value = read_varint(bytes, &mut pos).ok_or((offset + pos, "truncated field"))?;
+        values.push(value as u32);
+    }
+    Ok(values)
+}
+
+/// A geometry command with its points, in layer coordinates.
+#[derive(Debug, Clone, PartialEq)]
+struct Command {
+    id: u32,
+    count: u32,
+    points: Vec<[i64; 2]>,
+}
+
+fn decode_geometry(values: &[u32], offset: usize) -> Result<Vec<Command>, DecodeError> {
+    let invalid = Err((offset, "invalid geometry"));
+    let mut commands = Vec::new();
+    let mut cursor = [0i64, 0];
+    let mut i = 0;
+    while i < values.len() {
+        let (id, count) = (values[i] & 7, values[i] >> 3);
+        i += 1;
+        let mut points = Vec::new();
+        match id {
+            MOVE_TO | LINE_TO => {
+                let params = values.get(i..i + 2 * count as usize);
+                for pair in params.into_iter().flat_map(|p| p.chunks(2)) {
+                    cursor = [cursor[0] + unzigzag(pair[0]), cursor[1] + unzigzag(pair[1])];
+                    points.push(cursor);
+                }
+                if points.len() != count as usize {
+                    return invalid;
+                }
+                i += 2 * count as usize;
+            }
+            CLOSE_PATH => {}
+            _ => return invalid,
+        }
+        commands.push(Command { id, count, points });
+    }
+    Ok(commands)
+}
+
+fn encode_geometry(commands: &[Command], origin: [i64; 2]) -> Vec<u32> {
+    let mut values = Vec::new();
+    let mut cursor = origin;
+    for command in commands {
+        values.push(command.id | command.count << 3);
+        for p in &command.points {
+            values.push(zigzag(p[0] - cursor[0]));
+            values.push(zigzag(p[1] - cursor[1]));
+            cursor = *p;
+        }
+    }
+    values
+}
+
+struct Feature<'a> {
+    /// Fields other than the tags and the geometry, as encoded.
+    other: Vec<&'a [u8]>,
+    tags: Vec<u32>,
+    geometry: Vec<Command>,
+}
+
+struct Layer<'a> {
+    name: &'a [u8],
+    extent: u32,
+    /// Fields other than the name, features, keys, values and extent, as encoded.
+    other: Vec<&'a [u8]>,
+    keys: Vec<&'a [u8]>,
+    values: Vec<&'a [u8]>,
+    features: Vec<Feature<'a>>,
+}
+
+fn decode_feature(data: &[u8], base: usize) -> Result<Feature<'_>, DecodeError> {
+    let mut feature = Feature {
+        other: Vec::new(),
+        tags: Vec::new(),
+        geometry: Vec::new(),
+    };
+    for field in Fields::new(data, base) {
+        match field? {
+            (2, Value::Bytes(bytes), _, offset) => feature.tags = unpack(bytes, offset)?,
+            (4, Value::Bytes(bytes), _, offset) => {
+                feature.geometry = decode_geometry(&unpack(bytes, offset)?, offset)?
+            }
+            (_, _, raw, _) => feature.other.push(raw),
+        }
+    }
+    if feature.tags.len() % 2 != 0 {
+        return Err((base, "invalid tags"));
+    }
+    Ok(feature)
+}
+
+fn decode_layer(data: &[u8], base: usize) -> Result<Layer<'_>, DecodeError> {
+    let mut layer = Layer {
+        name: &[],
+        extent: DEFAULT_EXTENT,
+        other: Vec::new(),
+        keys: Vec::new(),
+        values: Vec::new(),
+        features: Vec::new(),
+    };
+    for field in Fields::new(data, base) {
+        match field? {
+            (1, Value::Bytes(name), _, _) => layer.name = name,
+            (2, Value::Bytes(bytes), _, offset) => {
+                layer.features.push(decode_feature(bytes, offset)?)
+            }
+            (3, Value::Bytes(key), _, _) => layer.keys.push(key),
+            (4, Value::Bytes(value), _, _) => layer.values.push(value),
+            (5, Value::Varint(extent), _, offset) => {
+                layer.extent = u32::try_from(extent)
+                    .ok()
+                    .filter(|&e| e > 0)
+                    .ok_or((offset, "invalid extent"))?
+            }
+            (_, _, raw, _) => layer.other.push(raw),
+        }
+    }
+    for feature in &layer.features {
+        let (keys, values) = (layer.keys.len() as u32, layer.values.len() as u32);
+        if feature
+            .tags
+            .chunks(2)
+            .any(|t| t[0] >= keys || t[1] >= values)
+        {
+            return Err((base, "invalid tags"));
+        }
+    }
+    Ok(layer)
+}
+
+fn decode_tile(data: &[u8]) -> Result<Vec<Layer<'_>>, DecodeError> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        return Err((0, "compressed tile"));
+    }
+    let mut layers = Vec::new();
+    for field in Fields::new(data, 0) {
+        if let (3, Value::Bytes(bytes), _, offset) = field? {
+            layers.push(decode_layer(bytes, offset)?);
+        }
+    }
+    Ok(layers)
+}
+
+/// Converts a point in world coordinates, i.e. layer coordinates over the whole zoom level.
+fn convert_point(converter: &Converter, size: f64, p: [i64; 2]) -> [i64; 2] {
+    let circumference = 2.0 * PI * RADIUS;
+    let x = (p[0] as f64 / size - 0.5) * circumference;
+    let y = (0.5 - p[1] as f64 / size) * circumference;
+    let (lat, lon) = xy_to_latlon(x, y);
+    let (lat, lon) = converter.convert(lat, lon);
+    let (x, y) = latlon_to_xy(lat, lon);
+    [
+        ((x / circumference + 0.5) * size).round() as i64,
+        ((0.5 - y / circumference) * size).round() as i64,
+    ]
+}
+
+/// A layer being assembled for an output tile.
+struct OutputLayer<'a> {
+    name: &'a [u8],
+    extent: u32,
+    other: Vec<&'a [u8]>,
+    keys: Vec<&'a [u8]>,
+    values: Vec<&'a [u8]>,
+    key_index: HashMap<&'a [u8], u32>,
+    value_index: HashMap<&'a [u8], u32>,
+    features: Vec<Vec<u8>>,
+}
+
+impl<'a> OutputLayer<'a> {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for raw in &self.other {
+            out.extend_from_slice(raw);
+        }
+        write_bytes(&mut out, 1, self.name);
+        for feature in &self.features {
+            write_bytes(&mut out, 2, feature);
+        }
+        for key in &self.keys {
+            write_bytes(&mut out, 3, key);
+        }
+        for value in &self.values {
+            write_bytes(&mut out, 4, value);
+        }
+        write_varint(&mut out, 5 << 3);
+        write_varint(&mut out, u64::from(self.extent));
+        out
+    }
+}
+
+/// Index of an entry in a table, adding it if needed.
+fn intern<'a>(
+    table: &mut Vec<&'a [u8]>,
+    index: &mut HashMap<&'a [u8], u32>,
+    entry: &'a [u8],
+) -> u32 {
+    *index.entry(entry).or_insert_with(|| {
+        table.push(entry);
+        table.len() as u32 - 1
+    })
+}
+
+/// Converts the features of a tile set and re-bins them into the tiles they land in.
+///
+/// A feature is written to every tile whose area, grown by `buffer` layer units on each side,
+/// its converted bounds intersect. Only tiles receiving features are returned, sorted by address.
+pub fn reproject(
+    converter: &Converter,
+    tiles: &[(TileId, &[u8])],
+    buffer: u32,
+) -> Result<Vec<(TileId, Vec<u8>)>, MvtError> {
+    let mut output: BTreeMap<TileId, Vec<OutputLayer>> = BTreeMap::new();
+    for &(tile, data) in tiles {
+        let error = |(offset, message)| MvtError {
+            tile,
+            offset,
+            message,
+        };
+        // Columns and rows are 32-bit, which bounds the zoom.
+        if tile.z > 32 {
+            return Err(error((0, "invalid zoom")));
+        }
+        let tiles_per_side = 1i64 << tile.z;
+        for layer in decode_tile(data).map_err(error)? {
+            let extent = i64::from(layer.extent);
+            let origin = [i64::from(tile.x) * extent, i64::from(tile.y) * extent];
+            let size = tiles_per_side
+                .checked_mul(extent)
+                .ok_or_else(|| error((0, "invalid extent")))? as f64;
+            for feature in &layer.features {
+                let mut geometry = feature.geometry.clone();
+                let (mut min, mut max) = ([i64::MAX; 2], [i64::MIN; 2]);
+                for p in geometry.iter_mut().flat_map(|c| c.points.iter_mut()) {
+                    *p = convert_point(converter, size, [p[0] + origin[0], p[1] + origin[1]]);
+                    min = [min[0].min(p[0]), min[1].min(p[1])];
+                    max = [max[0].max(p[0]), max[1].max(p[1])];
+                }
+                // Features without points stay where they are.
+                if min[0] > max[0] {
+                    min = origin;
+                    max = origin;
+                }
+
+                let b = i64::from(buffer);
+                let first = |m: i64| (-(b.saturating_sub(m).div_euclid(extent)) - 1).max(0);
+                let last =
+                    |m: i64| (m.saturating_add(b).div_euclid(extent)).min(tiles_per_side - 1);
+                let (columns, rows) = (
+                    last(max[0]) - first(min[0]) + 1,
+                    last(max[1]) - first(min[1]) + 1,
+                );
+                if columns > 0 && rows > 0 && columns.saturating_mul(rows) > MAX_FEATURE_TILES {
+                    return Err(error((0, "feature spans too many tiles")));
+                }
+                for x in first(min[0])..=last(max[0]) {
+                    for y in first(min[1])..=last(max[1]) {
+                        let id = TileId {
+                            z: tile.z,
+                            x: x as u32,
+                            y: y as u32,
+                        };
+                        let layers = output.entry(id).or_default();
+                        let index = match layers.iter().position(|l| l.name == layer.name) {
+                            Some(index) => index,
+                            None => {
+                                layers.push(OutputLayer {
+                                    name: layer.name,
+                                    extent: layer.extent,
+                                    other: layer.other.clone(),
+                                    keys: Vec::new(),
+                                    values: Vec::new(),
+                                    key_index: HashMap::new(),
+                                    value_index: HashMap::new(),
+                                    features: Vec::new(),
+                                });
+                                layers.len() - 1
+                            }
+                        };
+                        let out = &mut layers[index];
+                        if out.extent != layer.extent {
+                            return Err(error((
+                                0,
+                                "layers with the same name have different extents",
+                            )));
+                        }
+
+                        let mut tags = Vec::with_capacity(feature.tags.len());
+                        for pair in feature.tags.chunks(2) {
+                            let key = layer.keys[pair[0] as usize];
+                            let value = layer.values[pair[1] as usize];
+                            tags.push(intern(&mut out.keys, &mut out.key_index, key));
+                            tags.push(intern(&mut out.values, &mut out.value_index, value));
+                        }
+                        let mut encoded = Vec::new();
+                        for raw in &feature.other {
+                            encoded.extend_from_slice(raw);
+                        }
+                        if !tags.is_empty() {
+                            write_packed(&mut encoded, 2, &tags);
+                        }
+                        let origin = [x * extent, y * extent];
+                        write_packed(&mut encoded, 4, &encode_geometry(&geometry, origin));
+                        out.features.push(encoded);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(output
+        .into_iter()
+        .map(|(id, layers)| {
+            let mut tile = Vec::new();
+            for layer in &layers {
+                write_bytes(&mut tile, 3, &layer.encode());
+            }
+            (id, tile)
+        })
+        .collect())
+}
+
+/// Builds a single output tile of [`reproject`] from the source tiles around it.
+///
+/// The sources should cover the neighbours the drift can move features from, about a kilometer
+/// of ground. As each call only depends on its inputs, a driver over a tile archive can process
+/// the tiles one by one and resume after the last one it wrote. An empty result means no feature
+/// lands in the tile.
+pub fn reproject_tile(
+    converter: &Converter,
+    target: TileId,
+    sources: &[(TileId, &[u8])],
+    buffer: u32,
+) -> Result<Vec<u8>, MvtError> {
+    let output = reproject(converter, sources, buffer)?;
+    Ok(output
+        .into_iter()
+        .find(|(id, _)| *id == target)
+        .map_or_else(Vec::new, |(_, tile)| tile))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    fn encode_tile(
+        name: &str,
+        keys: &[&str],
+        values: &[&str],
+        features: &[(Vec<u32>, Vec<u32>)],
+    ) -> Vec<u8> {
+        let mut layer = Vec::new();
+        write_varint(&mut layer, 15 << 3);
+        write_varint(&mut layer, 2);
+        write_bytes(&mut layer, 1, name.as_bytes());
+        for (tags, geometry) in features {
+            let mut feature = Vec::new();
+            write_packed(&mut feature, 2, tags);
+            write_varint(&mut feature, 3 << 3);
+            write_varint(&mut feature, 1);
+            write_packed(&mut feature, 4, geometry);
+            write_bytes(&mut layer, 2, &feature);
+        }
+        for key in keys {
+            write_bytes(&mut layer, 3, key.as_bytes());
+        }
+        for value in values {
+            let mut encoded = Vec::new();
+            write_bytes(&mut encoded, 1, value.as_bytes());
+            write_bytes(&mut layer, 4, &encoded);
+        }
+        let mut tile = Vec::new();
+        write_bytes(&mut tile, 3, &layer);
+        tile
+    }
+
+    fn point(x: i64, y: i64) -> Vec<u32> {
+        vec![MOVE_TO | 1 << 3, zigzag(x), zigzag(y)]
+    }
+
+    /// Latitude and longitude of the first point of a feature.
+    fn latlon(tile: TileId, layer: &Layer, feature: &Feature) -> (f64, f64) {
+        let size = (1u64 << tile.z) as f64 * f64::from(layer.extent);
+        let p = feature.geometry[0].points[0];
+        let circumference = 2.0 * PI * RADIUS;
+        let x = ((p[0] + i64::from(tile.x * layer.extent)) as f64 / size - 0.5) * circumference;
+        let y = (0.5 - (p[1] + i64::from(tile.y * layer.extent)) as f64 / size) * circumference;
+        xy_to_latlon(x, y)
+    }
+
+    #[test]
+    fn test_geometry() {
+        let values = [
+            MOVE_TO | 1 << 3,
+            zigzag(5),
+            zigzag(-3),
+            LINE_TO | 2 << 3,
+            2,
+            4,
+            6,
+            8,
+            CLOSE_PATH | 1 << 3,
+        ];
+        let commands = decode_geometry(&values, 0).unwrap();
+        assert_eq!(commands[1].points, [[6, -1], [9, 3]]);
+        assert_eq!(encode_geometry(&commands, [0, 0]), values);
+        assert!(decode_geometry(&values[..5], 0).is_err());
+    }
+
+    #[test]
+    fn test_reproject() {
+        // Beijing at zoom 14; WGS-84 lies about 500 m west of GCJ-02, a quarter of a tile.
+        let z = 14;
+        let west = TileId {
+            z,
+            x: 13490,
+            y: 6208,
+        };
+        let east = TileId { x: 13491, ..west };
+        let tiles = [
+            (
+                west,
+                encode_tile("poi", &["name"], &["a"], &[(vec![0, 0], point(3000, 2000))]),
+            ),
+            (
+                east,
+                encode_tile(
+                    "poi",
+                    &["kind", "name"],
+                    &["x", "b"],
+                    &[(vec![1, 1, 0, 0], point(500, 2000))],
+                ),
+            ),
+        ];
+        let input: Vec<_> = tiles.iter().map(|(id, data)| (*id, &data[..])).collect();
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = reproject(&converter, &input, 0).unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].0, west);
+
+        let layers = decode_tile(&output[0].1).unwrap();
+        assert_eq!(layers.len(), 1);
+        let layer = &layers[0];
+        assert_eq!(layer.name, b"poi");
+        assert_eq!(layer.features.len(), 2);
+        assert_eq!(layer.keys, [&b"name"[..], b"kind"]);
+        // The second feature's tags now point into the merged tables.
+        assert_eq!(layer.features[1].tags, [0, 1, 1, 2]);
+        assert_eq!(layer.features[1].other, [&[3 << 3, 1][..]]);
+
+        let source = decode_tile(&tiles[1].1).unwrap();
+        let (lat, lon) = latlon(east, &source[0], &source[0].features[0]);
+        let expected = converter.convert(lat, lon);
+        let actual = latlon(west, layer, &layer.features[1]);
+        // One layer unit is about 0.6 m at this zoom.
+        assert!((actual.0 - expected.0).abs() < 1e-5 && (actual.1 - expected.1).abs() < 1e-5);
+
+        // A buffer keeps a copy of a feature near the edge in the neighbouring tile.
+        let output = reproject(&converter, &input, 4096).unwrap();
+        assert!(output.iter().any(|(id, _)| *id == east));
+
+        let tile = reproject_tile(&converter, west, &input, 4096).unwrap();
+        assert_eq!(decode_tile(&tile).unwrap()[0].features.len(), 2);
+        assert!(
+            reproject_tile(&converter, TileId { x: 0, ..west }, &input, 0)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_tms() {
+        let tile = TileId::from_tms(14, 13490, 10175);
+        assert_eq!(
+            tile,
+            TileId {
+                z: 14,
+                x: 13490,
+                y: 6208
+            }
+        );
+        assert_eq!(tile.tms_row(), 10175);
+        assert_eq!(TileId::from_tms(0, 0, 0).tms_row(), 0);
+    }
+
+    #[test]
+    fn test_quadkey() {
+        // The example of the Bing Maps documentation.
+        let tile = TileId { z: 3, x: 3, y: 5 };
+        assert_eq!(tile.quadkey(), "213");
+        assert_eq!(TileId::from_quadkey("213"), Some(tile));
+        let world = TileId { z: 0, x: 0, y: 0 };
+        assert_eq!(
+            (world.quadkey(), TileId::from_quadkey("")),
+            (String::new(), Some(world))
+        );
+        let corner = TileId {
+            z: 32,
+            x: u32::MAX,
+            y: 0,
+        };
+        assert_eq!(TileId::from_quadkey(&corner.quadkey()), Some(corner));
+        assert_eq!(TileId::from_quadkey("0124"), None);
+        assert_eq!(TileId::from_quadkey(&"0".repeat(33)), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let tile = TileId { z: 0, x: 0, y: 0 };
+        let error = |data: &[u8]| {
+            reproject(&converter, &[(tile, data)], 0)
+                .unwrap_err()
+                .message
+        };
+        assert_eq!(error(&[0x1f, 0x8b, 8]), "compressed tile");
+        assert_eq!(error(&[0x1a, 10, 0x12]), "truncated field");
+        let bad_tags = encode_tile("l", &[], &[], &[(vec![0, 0], point(1, 1))]);
+        assert_eq!(error(&bad_tags), "invalid tags");
+
+        let deep = TileId { z: 40, x: 0, y: 0 };
+        let tile = encode_tile("l", &[], &[], &[(vec![], point(1, 1))]);
+        assert_eq!(
+            reproject(&converter, &[(deep, &tile)], 0)
+                .unwrap_err()
+                .message,
+            "invalid zoom"
+        );
+        let z16 = TileId { z: 16, x: 0, y: 0 };
+        assert_eq!(
+            reproject(&converter, &[(z16, &tile)], u32::MAX)
+                .unwrap_err()
+                .message,
+            "feature spans too many tiles"
+        );
+    }
+}
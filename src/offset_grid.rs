@@ -0,0 +1,286 @@
+//! Offset grids for web clients, which correct coordinates approximately by interpolating a
+//! precomputed table instead of running the conversion. See `js/offset_grid.js` for a reader,
+//! usable from Leaflet or OpenLayers without the WebAssembly module.
+//!
+//! Offsets are stored in units of 1e-7 degrees, about a centimeter, in two encodings:
+//!
+//! - JSON: `{"from":"GCJ-02","to":"WGS-84","bbox":[west,south,east,north],"cols":C,"rows":R,
+//!   "unit":1e-7,"offsets":[dlat,dlon,...]}`;
+//! - binary, little-endian: the magic `UGRD`, a version byte of 1, the indices in
+//!   [`GeodeticSystem::ALL`] of the two systems and a zero byte, the bounding box as four `f64`
+//!   in the same order, the columns and rows as `u32`, then an `i32` pair per node, 8 bytes each.
+//!
+//! Nodes are ordered row by row from north to south, as in [`crate::drift_field`].
+use crate::json::Value;
+use crate::{BoundingBox, GeodeticSystem};
+use std::convert::TryFrom;
+use std::io;
+
+/// Degrees per stored unit.
+const UNIT: f64 = 1e-7;
+const MAGIC: &[u8; 4] = b"UGRD";
+const VERSION: u8 = 1;
+/// Size of the binary header.
+const HEADER: usize = 48;
+
+/// The offsets of a conversion at the nodes of a regular grid covering a bounding box.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OffsetGrid {
+    pub from: GeodeticSystem,
+    pub to: GeodeticSystem,
+    /// Area covered in `from`, whose corners are nodes.
+    pub bbox: BoundingBox,
+    pub cols: usize,
+    pub rows: usize,
+    /// `(dlat, dlon)` added to a coordinate in `from` at each node, in units of 1e-7 degrees.
+    pub offsets: Vec<(i32, i32)>,
+}
+
+impl OffsetGrid {
+    /// Samples the conversion from `from` to `to` over `bbox`, with nodes at most `resolution`
+    /// degrees apart.
+    ///
+    /// A resolution of 0.05 degrees keeps the interpolation within about 3 m; the periodic terms
+    /// of GCJ-02 have wavelengths of a third of a degree, so halving the resolution divides the
+    /// error by four. See [`OffsetGrid::max_error_meters`].
+    pub fn sample(
+        from: GeodeticSystem,
+        to: GeodeticSystem,
+        bbox: BoundingBox,
+        resolution: f64,
+    ) -> Self {
+        let count = |span: f64| (span / resolution).ceil().max(1.0) as usize + 1;
+        let (rows, cols) = (
+            count(bbox.max_lat - bbox.min_lat),
+            count(bbox.max_lon - bbox.min_lon),
+        );
+        let mut offsets = Vec::with_capacity(rows * cols);
+        for row in 0..rows {
+            for col in 0..cols {
+                let (lat, lon) = node(&bbox, rows, cols, row, col);
+                let (to_lat, to_lon) = from.convert_to(to, lat, lon);
+                let units = |d: f64| (d / UNIT).round() as i32;
+                offsets.push((units(to_lat - lat), units(to_lon - lon)));
+            }
+        }
+        OffsetGrid {
+            from,
+            to,
+            bbox,
+            cols,
+            rows,
+            offsets,
+        }
+    }
+
+    /// Converts a coordinate approximately, interpolating the offsets of the nodes around it.
+    ///
+    /// Coordinates outside the bounding box are returned unchanged.
+    pub fn apply(&self, lat: f64, lon: f64) -> (f64, f64) {
+        let b = &self.bbox;
+        if !(b.min_lat..=b.max_lat).contains(&lat) || !(b.min_lon..=b.max_lon).contains(&lon) {
+            return (lat, lon);
+        }
+        let v = (b.max_lat - lat) / (b.max_lat - b.min_lat) * (self.rows - 1) as f64;
+        let u = (lon - b.min_lon) / (b.max_lon - b.min_lon) * (self.cols - 1) as f64;
+        let (row, col) = (
+            (v as usize).min(self.rows - 2),
+            (u as usize).min(self.cols - 2),
+        );
+        let (fy, fx) = (v - row as f64, u - col as f64);
+        let at = |row: usize, col: usize| self.offsets[row * self.cols + col];
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let mut offset = [0.0; 2];
+        for (i, offset) in offset.iter_mut().enumerate() {
+            let get = |row, col| {
+                let (dlat, dlon) = at(row, col);
+                f64::from(if i == 0 { dlat } else { dlon })
+            };
+            let upper = lerp(get(row, col), get(row, col + 1), fx);
+            let lower = lerp(get(row + 1, col), get(row + 1, col + 1), fx);
+            *offset = lerp(upper, lower, fy) * UNIT;
+        }
+        (lat + offset[0], lon + offset[1])
+    }
+
+    /// Largest distance in meters between [`OffsetGrid::apply`] and the exact conversion at the
+    /// cell centers, where the interpolation is the furthest from the nodes.
+    pub fn max_error_meters(&self) -> f64 {
+        let mut max: f64 = 0.0;
+        for row in 0..self.rows - 1 {
+            for col in 0..self.cols - 1 {
+                let (north, west) = node(&self.bbox, self.rows, self.cols, row, col);
+                let (south, east) = node(&self.bbox, self.rows, self.cols, row + 1, col + 1);
+                let (lat, lon) = ((north + south) / 2.0, (west + east) / 2.0);
+                let exact = self.from.convert_to(self.to, lat, lon);
+                max = max.max(crate::haversine(self.apply(lat, lon), exact));
+            }
+        }
+        max
+    }
+
+    /// Encodes the grid as JSON.
+    pub fn to_json(&self) -> String {
+        let b = &self.bbox;
+        let integer = |x: i64| Value::Number(x.to_string());
+        let offsets = self
+            .offsets
+            .iter()
+            .flat_map(|&(dlat, dlon)| vec![integer(dlat.into()), integer(dlon.into())])
+            .collect();
+        let document = Value::Object(vec![
+            (
+                "from".to_owned(),
+                Value::String(self.from.name().to_owned()),
+            ),
+            ("to".to_owned(), Value::String(self.to.name().to_owned())),
+            (
+                "bbox".to_owned(),
+                Value::Array(
+                    [b.min_lon, b.min_lat, b.max_lon, b.max_lat]
+                        .iter()
+                        .map(|&x| Value::number(x))
+                        .collect(),
+                ),
+            ),
+            ("cols".to_owned(), integer(self.cols as i64)),
+            ("rows".to_owned(), integer(self.rows as i64)),
+            ("unit".to_owned(), Value::Number("1e-7".to_owned())),
+            ("offsets".to_owned(), Value::Array(offsets)),
+        ]);
+        document.to_string()
+    }
+
+    /// Encodes the grid in the binary format.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let index = |system| {
+            GeodeticSystem::ALL
+                .iter()
+                .position(|&s| s == system)
+                .unwrap() as u8
+        };
+        let b = &self.bbox;
+        let mut out = Vec::with_capacity(HEADER + self.offsets.len() * 8);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&[VERSION, index(self.from), index(self.to), 0]);
+        for x in &[b.min_lon, b.min_lat, b.max_lon, b.max_lat] {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.cols as u32).to_le_bytes());
+        out.extend_from_slice(&(self.rows as u32).to_le_bytes());
+        for &(dlat, dlon) in &self.offsets {
+            out.extend_from_slice(&dlat.to_le_bytes());
+            out.extend_from_slice(&dlon.to_le_bytes());
+        }
+        out
+    }
+
+    /// Decodes a grid of the binary format.
+    pub fn from_binary(data: &[u8]) -> io::Result<Self> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        if data.len() < HEADER || &data[..4] != MAGIC || data[4] != VERSION {
+            return Err(invalid("not an offset grid"));
+        }
+        let system = |i: u8| GeodeticSystem::ALL.get(usize::from(i)).copied();
+        let (from, to) = match (system(data[5]), system(data[6])) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Err(invalid("invalid system")),
+        };
+        let f64_at = |i: usize| f64::from_le_bytes(<[u8; 8]>::try_from(&data[i..i + 8]).unwrap());
+        let u32_at = |i: usize| u32::from_le_bytes(<[u8; 4]>::try_from(&data[i..i + 4]).unwrap());
+        let bbox = BoundingBox::new(f64_at(16), f64_at(8), f64_at(32), f64_at(24));
+        let (cols, rows) = (u32_at(40) as usize, u32_at(44) as usize);
+        let nodes = &data[HEADER..];
+        if cols < 2 || rows < 2 || Some(nodes.len()) != cols.checked_mul(rows).map(|n| n * 8) {
+            return Err(invalid("invalid grid size"));
+        }
+        let offsets = nodes
+            .chunks(8)
+            .map(|node| {
+                let i32_at =
+                    |i: usize| i32::from_le_bytes(<[u8; 4]>::try_from(&node[i..i + 4]).unwrap());
+                (i32_at(0), i32_at(4))
+            })
+            .collect();
+        Ok(OffsetGrid {
+            from,
+            to,
+            bbox,
+            cols,
+            rows,
+            offsets,
+        })
+    }
+}
+
+/// Latitude and longitude of a node.
+fn node(bbox: &BoundingBox, rows: usize, cols: usize, row: usize, col: usize) -> (f64, f64) {
+    let lat = bbox.max_lat - (bbox.max_lat - bbox.min_lat) * row as f64 / (rows - 1) as f64;
+    let lon = bbox.min_lon + (bbox.max_lon - bbox.min_lon) * col as f64 / (cols - 1) as f64;
+    (lat, lon)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    fn beijing() -> BoundingBox {
+        BoundingBox::new(39.4, 115.9, 40.4, 117.0)
+    }
+
+    #[test]
+    fn test_apply() {
+        let grid = OffsetGrid::sample(Wgs84, Gcj02, beijing(), 0.05);
+        assert_eq!((grid.rows, grid.cols), (21, 23));
+        let error = grid.max_error_meters();
+        assert!(error < 3.0, "{}", error);
+        let finer = OffsetGrid::sample(Wgs84, Gcj02, beijing(), 0.01).max_error_meters();
+        assert!(finer < 0.1 && finer < error / 10.0, "{}", finer);
+
+        // Nodes are exact up to the unit, and the corners are nodes.
+        let (lat, lon) = (40.4, 115.9);
+        let exact = Wgs84.convert_to(Gcj02, lat, lon);
+        assert!(crate::haversine(grid.apply(lat, lon), exact) < 0.02);
+        assert_eq!(grid.apply(39.3, 116.4), (39.3, 116.4));
+
+        let inverse = OffsetGrid::sample(Gcj02, Wgs84, beijing(), 0.05);
+        let (lat, lon) = inverse.apply(39.9, 116.4);
+        let exact = Gcj02.convert_to(Wgs84, 39.9, 116.4);
+        assert!(crate::haversine((lat, lon), exact) < 3.0);
+    }
+
+    #[test]
+    fn test_json() {
+        let grid = OffsetGrid::sample(Gcj02, Bd09, beijing(), 0.5);
+        let json = Value::parse(&grid.to_json()).unwrap();
+        assert_eq!(json.get("from").unwrap().as_str(), Some("GCJ-02"));
+        assert_eq!(json.get("to").unwrap().as_str(), Some("BD-09"));
+        assert_eq!(json.get("cols").unwrap().as_f64(), Some(4.0));
+        assert_eq!(json.get("unit").unwrap().as_f64(), Some(1e-7));
+        match json.get("bbox") {
+            Some(Value::Array(bbox)) => assert_eq!(bbox[1].as_f64(), Some(39.4)),
+            other => panic!("{:?}", other),
+        }
+        match json.get("offsets") {
+            Some(Value::Array(offsets)) => {
+                assert_eq!(offsets.len(), grid.offsets.len() * 2);
+                assert_eq!(offsets[1].as_f64(), Some(f64::from(grid.offsets[0].1)));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_binary() {
+        let grid = OffsetGrid::sample(Wgs84, Gcj02, beijing(), 0.1);
+        let data = grid.to_binary();
+        assert_eq!(data.len(), HEADER + grid.offsets.len() * 8);
+        assert_eq!(OffsetGrid::from_binary(&data).unwrap(), grid);
+        assert!(OffsetGrid::from_binary(&data[..data.len() - 1]).is_err());
+        assert!(OffsetGrid::from_binary(&data[..HEADER - 1]).is_err());
+        let mut other = data.clone();
+        other[5] = 3;
+        assert!(OffsetGrid::from_binary(&other).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! OpenStreetMap XML files, including osmChange files.
+//!
+//! The `lat` and `lon` attributes of nodes are rewritten; IDs, tags, ways and relations are kept
+//! byte for byte. The corners of `<bounds>` are converted independently, which is close enough for
+//! the rectangle of an extract.
+//!
+//! The binary PBF format is not supported: its blocks are zlib-compressed protocol buffers, which
+//! would need a decoder for both.
+use crate::format::Precision;
+use crate::xml::{apply_edits, map_attribute_pairs, XmlError};
+use crate::Converter;
+
+/// The latitude and longitude attributes of each element.
+fn pairs(name: &str) -> &'static [(&'static str, &'static str)] {
+    match name {
+        // Deleted nodes in osmChange files have no coordinates, and are skipped.
+        "node" => &[("lat", "lon")],
+        "bounds" => &[("minlat", "minlon"), ("maxlat", "maxlon")],
+        _ => &[],
+    }
+}
+
+/// Reads the node coordinates of an OSM XML document, in order.
+pub fn nodes(input: &str) -> Result<Vec<(f64, f64)>, XmlError> {
+    let mut nodes = Vec::new();
+    map_attribute_pairs(input, pairs, Precision::Shortest, |name, lat, lon| {
+        if name == "node" {
+            nodes.push((lat, lon));
+        }
+        Ok(None)
+    })?;
+    Ok(nodes)
+}
+
+/// Converts the node coordinates and bounds of an OSM XML document.
+pub fn convert(converter: &Converter, input: &str) -> Result<String, XmlError> {
+    let edits = map_attribute_pairs(input, pairs, converter.precision(), |_, lat, lon| {
+        converter.convert_written(lat, lon)
+    })?;
+    Ok(apply_edits(input, edits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    const EXTRACT: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6" generator="test">
+  <bounds minlat="39.9" minlon="116.3" maxlat="40.0" maxlon="116.5"/>
+  <node id="1" lat="39.9042" lon="116.4074" version="1"/>
+  <node id="2" version="3" lon="116.41" lat="39.91">
+    <tag k="name" v="Tian'anmen &amp; Square"/>
+  </node>
+  <way id="10"><nd ref="1"/><nd ref="2"/><tag k="highway" v="primary"/></way>
+  <relation id="20"><member type="way" ref="10" role=""/></relation>
+</osm>
+"#;
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = convert(&converter, EXTRACT).unwrap();
+        let expected: Vec<_> = [(39.9042, 116.4074), (39.91, 116.41)]
+            .iter()
+            .map(|&(lat, lon)| converter.convert(lat, lon))
+            .collect();
+        assert_eq!(nodes(&output).unwrap(), expected);
+        assert_eq!(
+            nodes(EXTRACT).unwrap(),
+            [(39.9042, 116.4074), (39.91, 116.41)]
+        );
+
+        let (lat, _) = converter.convert(39.9, 116.3);
+        assert!(output.contains(&format!("<bounds minlat=\"{}\"", lat)));
+        assert!(output.contains(r#"<node id="2" version="3" lon=""#));
+        let tail = &EXTRACT[EXTRACT.find("    <tag").unwrap()..];
+        assert!(output.ends_with(tail));
+    }
+
+    #[test]
+    fn test_osm_change() {
+        let change = r#"<osmChange><delete><node id="1" version="2"/></delete></osmChange>"#;
+        let converter = Converter::new(Gcj02, Wgs84);
+        assert_eq!(convert(&converter, change).unwrap(), change);
+        let invalid = r#"<osm><node id="1" lat="x" lon="1"/></osm>"#;
+        assert!(convert(&converter, invalid).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+//! A builder gathering the options of a conversion in one value.
+//!
+//! ```
+//! use undrift_gps::pipeline::Pipeline;
+//! use undrift_gps::transform::Transform;
+//! use undrift_gps::GeodeticSystem::*;
+//!
+//! let pipeline = Pipeline::from(Bd09).densify(50.0).unwrap().to(Wgs84).strict();
+//! let line = pipeline.convert_line(&[(39.90, 116.40), (39.91, 116.41)]).unwrap();
+//! let (lat, lon) = pipeline.apply(39.90, 116.40);
+//! ```
+use crate::batch::{convert_point, Failure, FailureReason};
+use crate::distance::sample_every_meters;
+use crate::simplify::{simplify_indices, Simplification};
+use crate::spec::{Boundary, PipelineSpec, Projection};
+use crate::transform::{BaiduMercator, Transform, WebMercator};
+use crate::{Algorithm, Converter, Error, GeodeticSystem, NonFinitePolicy};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Error returned for an option out of its range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PipelineError {
+    /// A densification length that is not positive and finite.
+    Densify(f64),
+    /// A simplification tolerance that is not positive and finite.
+    Simplify(f64),
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PipelineError::Densify(x) => write!(f, "invalid densification length {}", x),
+            PipelineError::Simplify(x) => write!(f, "invalid simplification tolerance {}", x),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+pub(crate) fn is_length(x: f64) -> bool {
+    x > 0.0 && x.is_finite()
+}
+
+/// A conversion with its options, usable as a [`Transform`].
+///
+/// The builder methods can be called in any order; the target defaults to the source.
+#[derive(Clone)]
+pub struct Pipeline {
+    spec: PipelineSpec,
+    converter: Converter,
+    simplify: Option<(f64, Simplification)>,
+    strict: bool,
+    non_finite: NonFinitePolicy,
+}
+
+impl From<GeodeticSystem> for Pipeline {
+    fn from(source: GeodeticSystem) -> Self {
+        Pipeline {
+            spec: PipelineSpec::new(source, source),
+            converter: Converter::new(source, source),
+            simplify: None,
+            strict: false,
+            non_finite: NonFinitePolicy::default(),
+        }
+    }
+}
+
+impl TryFrom<PipelineSpec> for Pipeline {
+    type Error = Error;
+
+    /// Fails if the densification length of the spec is not positive and finite.
+    fn try_from(spec: PipelineSpec) -> Result<Self, Error> {
+        let densify = spec.densify;
+        let pipeline = Pipeline::from(spec.source).with_spec(|s| *s = spec);
+        match densify {
+            Some(max_meters) => pipeline.densify(max_meters),
+            None => Ok(pipeline),
+        }
+    }
+}
+
+impl Pipeline {
+    fn with_spec(mut self, f: impl FnOnce(&mut PipelineSpec)) -> Self {
+        f(&mut self.spec);
+        self.converter = self.spec.converter().with_non_finite(self.non_finite);
+        self
+    }
+
+    /// Sets the target system.
+    pub fn to(self, target: GeodeticSystem) -> Self {
+        self.with_spec(|spec| spec.target = target)
+    }
+
+    pub fn algorithm(self, algorithm: Algorithm) -> Self {
+        self.with_spec(|spec| spec.algorithm = algorithm)
+    }
+
+    pub fn boundary(self, boundary: Boundary) -> Self {
+        self.with_spec(|spec| spec.boundary = boundary)
+    }
+
+    /// Grows the boundary by `meters`, or shrinks it if negative, see [`PipelineSpec::buffer`].
+    pub fn buffer(self, meters: f64) -> Self {
+        self.with_spec(|spec| spec.buffer = meters)
+    }
+
+    /// Projects the converted coordinates, which are then `(x, y)` in meters.
+    pub fn project(self, projection: Projection) -> Self {
+        self.with_spec(|spec| spec.projection = Some(projection))
+    }
+
+    /// Makes [`Pipeline::convert_line`] insert points on the great circles so that no segment is
+    /// longer than `max_meters`, as straight lines in one system are curves in the other.
+    ///
+    /// Fails if `max_meters` is not positive and finite.
+    pub fn densify(mut self, max_meters: f64) -> Result<Self, Error> {
+        if !is_length(max_meters) {
+            return Err(PipelineError::Densify(max_meters).into());
+        }
+        self.spec.densify = Some(max_meters);
+        Ok(self)
+    }
+
+    /// Makes [`Pipeline::convert_line`] simplify the converted line within `tolerance` meters,
+    /// before projecting it, for web-facing outputs.
+    ///
+    /// Fails if `tolerance` is not positive and finite.
+    pub fn simplify(mut self, tolerance: f64, method: Simplification) -> Result<Self, Error> {
+        if !is_length(tolerance) {
+            return Err(PipelineError::Simplify(tolerance).into());
+        }
+        self.simplify = Some((tolerance, method));
+        Ok(self)
+    }
+
+    /// Rejects invalid inputs, non-convergent inversions and non-finite results instead of
+    /// passing them through. Non-finite inputs follow [`Pipeline::non_finite`].
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Selects how strict mode handles non-finite inputs: skipped points are left out of
+    /// [`Pipeline::convert_line`], and returned unchanged by the other methods.
+    pub fn non_finite(mut self, policy: NonFinitePolicy) -> Self {
+        self.non_finite = policy;
+        self.converter = self.converter.with_non_finite(policy);
+        self
+    }
+
+    /// The options as a spec, which can be saved and loaded; simplification and strictness are
+    /// not part of it.
+    pub fn spec(&self) -> &PipelineSpec {
+        &self.spec
+    }
+
+    fn project_point(&self, lat: f64, lon: f64) -> (f64, f64) {
+        match self.spec.projection {
+            None => (lat, lon),
+            Some(Projection::WebMercator) => WebMercator.apply(lat, lon),
+            Some(Projection::BaiduMercator) => BaiduMercator.apply(lat, lon),
+        }
+    }
+
+    /// Converts a point without projecting it, returning `None` if it is skipped.
+    fn convert(&self, lat: f64, lon: f64) -> Result<Option<(f64, f64)>, FailureReason> {
+        let (lat, lon) = if self.strict {
+            let mut point = (lat, lon);
+            if !convert_point(&self.converter, &mut point, lat, lon, None)? {
+                return Ok(None);
+            }
+            point
+        } else {
+            self.converter.convert(lat, lon)
+        };
+        Ok(Some((lat, lon)))
+    }
+
+    /// Converts a point, failing only in strict mode.
+    pub fn try_apply(&self, lat: f64, lon: f64) -> Result<(f64, f64), FailureReason> {
+        Ok(match self.convert(lat, lon)? {
+            Some((lat, lon)) => self.project_point(lat, lon),
+            None => (lat, lon),
+        })
+    }
+
+    /// Converts a line, densified first and simplified last if enabled. Failing points are
+    /// reported with their index in the input.
+    pub fn convert_line(&self, points: &[(f64, f64)]) -> Result<Vec<(f64, f64)>, Failure> {
+        let mut line = Vec::with_capacity(points.len());
+        for (index, &(lat, lon)) in points.iter().enumerate() {
+            let failure = |reason| Failure { index, reason };
+            let samples = match (self.spec.densify, index.checked_sub(1)) {
+                // NaN coordinates are left to the conversion of the point itself.
+                (Some(max), Some(prev)) => sample_every_meters(points[prev], (lat, lon), max),
+                _ => None,
+            };
+            if let Some(samples) = samples {
+                for &(a, b) in &samples[1..samples.len() - 1] {
+                    line.extend(self.convert(a, b).map_err(failure)?);
+                }
+            }
+            line.extend(self.convert(lat, lon).map_err(failure)?);
+        }
+        if let Some((tolerance, method)) = self.simplify {
+            let kept = simplify_indices(&line, tolerance, method);
+            line = kept.into_iter().map(|i| line[i]).collect();
+        }
+        Ok(line
+            .into_iter()
+            .map(|(lat, lon)| self.project_point(lat, lon))
+            .collect())
+    }
+}
+
+impl Transform for Pipeline {
+    /// Converts a point; in strict mode, failing points become NaN.
+    fn apply(&self, lat: f64, lon: f64) -> (f64, f64) {
+        self.try_apply(lat, lon).unwrap_or((f64::NAN, f64::NAN))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::haversine;
+    use crate::validate::Issue;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_builder() {
+        let pipeline = Pipeline::from(Bd09).to(Wgs84).algorithm(Algorithm::Exact);
+        let converter = Converter::new(Bd09, Wgs84).with_algorithm(Algorithm::Exact);
+        assert_eq!(pipeline.apply(39.9, 116.4), converter.convert(39.9, 116.4));
+        assert_eq!(Pipeline::from(Gcj02).apply(39.9, 116.4), (39.9, 116.4));
+
+        let projected = Pipeline::from(Gcj02)
+            .project(Projection::WebMercator)
+            .to(Wgs84);
+        let (lat, lon) = Converter::new(Gcj02, Wgs84).convert(39.9, 116.4);
+        assert_eq!(projected.apply(39.9, 116.4), WebMercator.apply(lat, lon));
+        assert_eq!(projected.spec().projection, Some(Projection::WebMercator));
+    }
+
+    #[test]
+    fn test_strict() {
+        let lenient = Pipeline::from(Gcj02).to(Wgs84);
+        assert_eq!(lenient.try_apply(100.0, 116.4).map(|_| ()), Ok(()));
+        let strict = lenient.strict();
+        assert_eq!(
+            strict.try_apply(100.0, 116.4),
+            Err(FailureReason::Invalid(Issue::LatitudeOutOfRange))
+        );
+        assert!(strict.apply(f64::NAN, 116.4).0.is_nan());
+        assert_eq!(
+            strict.convert_line(&[(39.9, 116.4), (f64::NAN, 116.4)]),
+            Err(Failure {
+                index: 1,
+                reason: FailureReason::Invalid(Issue::NotFinite)
+            })
+        );
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let line = [(39.9, 116.4), (f64::NAN, 116.4), (39.91, 116.41)];
+        let skip = Pipeline::from(Gcj02)
+            .strict()
+            .non_finite(NonFinitePolicy::Skip)
+            .to(Wgs84);
+        assert_eq!(skip.converter.non_finite(), NonFinitePolicy::Skip);
+        assert_eq!(skip.convert_line(&line).unwrap().len(), 2);
+        assert!(skip.try_apply(f64::NAN, 116.4).unwrap().0.is_nan());
+        let propagate = skip.non_finite(NonFinitePolicy::Propagate);
+        assert_eq!(propagate.convert_line(&line).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_densify() {
+        let pipeline = Pipeline::from(Gcj02).densify(50.0).unwrap().to(Wgs84);
+        // About 140 meters apart, so split in 3 segments.
+        let line = pipeline
+            .convert_line(&[(39.90, 116.40), (39.901, 116.401), (39.901, 116.401)])
+            .unwrap();
+        assert_eq!(line.len(), 4 + 1);
+        assert_eq!(line[3], pipeline.apply(39.901, 116.401));
+        for pair in line.windows(2) {
+            assert!(haversine(pair[0], pair[1]) < 50.0);
+        }
+
+        // Across the antimeridian, the short way.
+        let pipeline = Pipeline::from(Wgs84).densify(50.0).unwrap();
+        let line = pipeline
+            .convert_line(&[(10.0, 179.999), (10.0, -179.999)])
+            .unwrap();
+        assert_eq!(line.len(), 6);
+        assert!(line.iter().all(|&(_, lon)| lon.abs() > 179.99));
+    }
+
+    #[test]
+    fn test_spec() {
+        let spec =
+            PipelineSpec::from_toml("source = \"gcj02\"\ntarget = \"wgs84\"\ndensify = 50.0");
+        let pipeline = Pipeline::try_from(spec.unwrap()).unwrap();
+        let line = [(39.90, 116.40), (39.901, 116.401)];
+        assert_eq!(pipeline.convert_line(&line).unwrap().len(), 4);
+        assert_eq!(pipeline.spec().densify, Some(50.0));
+
+        let mut spec = PipelineSpec::new(Gcj02, Wgs84);
+        spec.densify = Some(-1.0);
+        assert!(Pipeline::try_from(spec).is_err());
+    }
+
+    #[test]
+    fn test_invalid_lengths() {
+        for &x in &[0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let e = Pipeline::from(Gcj02).densify(x).err().unwrap();
+            let source = std::error::Error::source(&e).unwrap();
+            assert!(matches!(
+                source.downcast_ref::<PipelineError>(),
+                Some(PipelineError::Densify(_))
+            ));
+            let e = Pipeline::from(Gcj02)
+                .simplify(x, Simplification::Visvalingam)
+                .err()
+                .unwrap();
+            assert!(e
+                .to_string()
+                .starts_with("invalid simplification tolerance"));
+        }
+    }
+
+    #[test]
+    fn test_simplify() {
+        let line: Vec<_> = (0..50)
+            .map(|i| (39.9, 116.4 + f64::from(i) * 0.001))
+            .collect();
+        let pipeline = Pipeline::from(Gcj02)
+            .simplify(10.0, Simplification::DouglasPeucker)
+            .unwrap()
+            .to(Wgs84);
+        let simplified = pipeline.convert_line(&line).unwrap();
+        let ends = [pipeline.apply(39.9, 116.4), pipeline.apply(39.9, 116.449)];
+        assert_eq!(simplified.len(), 2);
+        for (a, b) in simplified.iter().zip(&ends) {
+            assert!((a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9);
+        }
+
+        // Simplified in meters before the projection.
+        let projected = pipeline.project(Projection::WebMercator);
+        let simplified = projected.convert_line(&line).unwrap();
+        let end = projected.apply(39.9, 116.449);
+        assert_eq!(simplified.len(), 2);
+        assert!((simplified[1].0 - end.0).abs() < 1e-3 && (simplified[1].1 - end.1).abs() < 1e-3);
+    }
+}
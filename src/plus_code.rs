@@ -0,0 +1,302 @@
+//! Open Location Codes, known as Plus Codes.
+//!
+//! Codes are encoded and decoded as the reference implementation does, in integer units so that
+//! the digits do not depend on rounding. A code read from a GCJ-02 map designates a GCJ-02 area:
+//! [`convert`] moves its center to another system and encodes it again at the same length. Short
+//! codes, such as `"9G8F+6X"`, are recovered with a nearby point first, see [`recover_nearest`].
+use crate::Converter;
+use std::fmt;
+
+const ALPHABET: &[u8; 20] = b"23456789CFGHJMPQRVWX";
+const SEPARATOR: char = '+';
+const SEPARATOR_POSITION: usize = 8;
+const PADDING: char = '0';
+const PAIR_LENGTH: usize = 10;
+const MAX_LENGTH: usize = 15;
+const GRID_ROWS: i64 = 5;
+const GRID_COLUMNS: i64 = 4;
+/// Units per degree of the last digit of latitude, `8000 * 5^5`.
+const LAT_PRECISION: i64 = 25_000_000;
+/// Units per degree of the last digit of longitude, `8000 * 4^5`.
+const LON_PRECISION: i64 = 8_192_000;
+
+/// Error returned for a string that is not a usable code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlusCodeError {
+    /// The string is not a valid code.
+    Invalid,
+    /// The code is short, and must be recovered with a reference location first.
+    Short,
+}
+
+impl fmt::Display for PlusCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlusCodeError::Invalid => f.write_str("invalid plus code"),
+            PlusCodeError::Short => f.write_str("short plus code without a reference location"),
+        }
+    }
+}
+
+impl std::error::Error for PlusCodeError {}
+
+/// The area a code designates, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodeArea {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+    /// Number of digits of the code, padding excluded.
+    pub length: usize,
+}
+
+impl CodeArea {
+    /// The center of the area, as latitude and longitude.
+    pub fn center(&self) -> (f64, f64) {
+        (
+            ((self.south + self.north) / 2.0).min(90.0),
+            ((self.west + self.east) / 2.0).min(180.0),
+        )
+    }
+}
+
+fn digit(c: u8) -> Option<i64> {
+    ALPHABET
+        .iter()
+        .position(|&d| d == c.to_ascii_uppercase())
+        .map(|i| i as i64)
+}
+
+/// Encodes a location as a code of `length` digits: 2, 4, 6, 8, or from 10 to 15.
+///
+/// Ten digits are an area of about 14 by 14 meters, and each further digit divides it by 20.
+pub fn encode(lat: f64, lon: f64, length: usize) -> String {
+    assert!(
+        (2..=MAX_LENGTH).contains(&length) && (length >= PAIR_LENGTH || length % 2 == 0),
+        "invalid plus code length"
+    );
+    let mut lat = ((lat * LAT_PRECISION as f64).floor() as i64 + 90 * LAT_PRECISION)
+        .clamp(0, 180 * LAT_PRECISION - 1);
+    let mut lon = ((lon * LON_PRECISION as f64).floor() as i64 + 180 * LON_PRECISION)
+        .rem_euclid(360 * LON_PRECISION);
+
+    let mut digits = Vec::with_capacity(MAX_LENGTH);
+    if length > PAIR_LENGTH {
+        for _ in PAIR_LENGTH..MAX_LENGTH {
+            let index = (lat % GRID_ROWS) * GRID_COLUMNS + lon % GRID_COLUMNS;
+            digits.push(ALPHABET[index as usize]);
+            lat /= GRID_ROWS;
+            lon /= GRID_COLUMNS;
+        }
+    } else {
+        lat /= GRID_ROWS.pow(5);
+        lon /= GRID_COLUMNS.pow(5);
+    }
+    for _ in 0..PAIR_LENGTH / 2 {
+        digits.push(ALPHABET[(lon % 20) as usize]);
+        digits.push(ALPHABET[(lat % 20) as usize]);
+        lat /= 20;
+        lon /= 20;
+    }
+    digits.reverse();
+
+    let mut code: String = digits[..length].iter().map(|&d| char::from(d)).collect();
+    if length < SEPARATOR_POSITION {
+        code.extend(std::iter::repeat_n(PADDING, SEPARATOR_POSITION - length));
+    }
+    code.insert(SEPARATOR_POSITION, SEPARATOR);
+    code
+}
+
+/// Checks the syntax of a code, full or short, returning whether it is full.
+fn check(code: &str) -> Result<bool, PlusCodeError> {
+    let invalid = Err(PlusCodeError::Invalid);
+    let bytes = code.as_bytes();
+    let separator = match code.find(SEPARATOR) {
+        Some(i) if code.rfind(SEPARATOR) == Some(i) => i,
+        _ => return invalid,
+    };
+    if separator > SEPARATOR_POSITION || separator % 2 != 0 {
+        return invalid;
+    }
+    let (head, tail) = (&code[..separator], &code[separator + 1..]);
+    if tail.len() == 1 || tail.len() > MAX_LENGTH - SEPARATOR_POSITION {
+        return invalid;
+    }
+    if let Some(padding) = head.find(PADDING) {
+        // Padding ends the code, from an even position.
+        if separator != SEPARATOR_POSITION || padding == 0 || padding % 2 != 0 {
+            return invalid;
+        }
+        if !head[padding..].bytes().all(|b| b == b'0') || !tail.is_empty() {
+            return invalid;
+        }
+    }
+    let digits = head.bytes().take_while(|&b| b != b'0').chain(tail.bytes());
+    if digits.map(digit).any(|d| d.is_none()) {
+        return invalid;
+    }
+    if separator < SEPARATOR_POSITION {
+        return Ok(false);
+    }
+    // The first digits cannot go beyond 90 degrees of latitude and 180 of longitude.
+    match (digit(bytes[0]), digit(bytes[1])) {
+        (Some(lat), Some(lon)) if lat * 20 < 180 && lon * 20 < 360 => Ok(true),
+        _ => invalid,
+    }
+}
+
+/// Decodes a full code into its area.
+pub fn decode(code: &str) -> Result<CodeArea, PlusCodeError> {
+    if !check(code)? {
+        return Err(PlusCodeError::Short);
+    }
+    let digits: Vec<i64> = code.bytes().filter_map(digit).take(MAX_LENGTH).collect();
+    let (mut lat, mut lon) = (0, 0);
+    let (mut lat_place, mut lon_place) = (400 * LAT_PRECISION, 400 * LON_PRECISION);
+    for pair in digits.chunks(2).take(PAIR_LENGTH / 2) {
+        lat_place /= 20;
+        lon_place /= 20;
+        lat += pair[0] * lat_place;
+        lon += pair[1] * lon_place;
+    }
+    for &d in digits.iter().skip(PAIR_LENGTH) {
+        lat_place /= GRID_ROWS;
+        lon_place /= GRID_COLUMNS;
+        lat += d / GRID_COLUMNS * lat_place;
+        lon += d % GRID_COLUMNS * lon_place;
+    }
+    let lat_degrees = |units: i64| (units - 90 * LAT_PRECISION) as f64 / LAT_PRECISION as f64;
+    let lon_degrees = |units: i64| (units - 180 * LON_PRECISION) as f64 / LON_PRECISION as f64;
+    Ok(CodeArea {
+        south: lat_degrees(lat),
+        west: lon_degrees(lon),
+        north: lat_degrees(lat + lat_place),
+        east: lon_degrees(lon + lon_place),
+        length: digits.len(),
+    })
+}
+
+/// Recovers the full code of a short one, nearest to a reference location. Full codes are
+/// returned as they are, in upper case.
+pub fn recover_nearest(code: &str, lat: f64, lon: f64) -> Result<String, PlusCodeError> {
+    if check(code)? {
+        return Ok(code.to_ascii_uppercase());
+    }
+    let missing = SEPARATOR_POSITION - code.find(SEPARATOR).unwrap_or(0);
+    // Size in degrees of the area the missing digits designate.
+    let resolution = 20f64.powi(2 - (missing / 2) as i32);
+    let half = resolution / 2.0;
+    let lat = lat.clamp(-90.0, 90.0);
+
+    let prefix = encode(lat, lon, PAIR_LENGTH);
+    let full = prefix[..missing].to_owned() + &code.to_ascii_uppercase();
+    let area = decode(&full)?;
+    let (mut center_lat, mut center_lon) = area.center();
+    // The nearest of the candidates one area away in each direction.
+    if lat + half < center_lat && center_lat - resolution >= -90.0 {
+        center_lat -= resolution;
+    } else if lat - half > center_lat && center_lat + resolution <= 90.0 {
+        center_lat += resolution;
+    }
+    if lon + half < center_lon {
+        center_lon -= resolution;
+    } else if lon - half > center_lon {
+        center_lon += resolution;
+    }
+    Ok(encode(center_lat, center_lon, area.length))
+}
+
+/// Converts a full code to another system: the center of its area is converted, and encoded
+/// again with as many digits.
+pub fn convert(converter: &Converter, code: &str) -> Result<String, PlusCodeError> {
+    let area = decode(code)?;
+    let (lat, lon) = area.center();
+    let (lat, lon) = converter.convert(lat, lon);
+    Ok(encode(lat, lon, area.length))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_encode() {
+        // Test data of the reference implementation.
+        let cases: &[(f64, f64, usize, &str)] = &[
+            (20.375, 2.775, 6, "7FG49Q00+"),
+            (20.3700625, 2.7821875, 10, "7FG49QCJ+2V"),
+            (20.3701125, 2.782234375, 11, "7FG49QCJ+2VX"),
+            (20.3701135, 2.78223535156, 13, "7FG49QCJ+2VXGJ"),
+            (47.0000625, 8.0000625, 10, "8FVC2222+22"),
+            (-41.2730625, 174.7859375, 10, "4VCPPQGP+Q9"),
+            (0.5, 179.5, 4, "6VGX0000+"),
+            (-89.5, -179.5, 4, "22220000+"),
+            (90.0, 1.0, 4, "CFX30000+"),
+            (92.0, 1.0, 4, "CFX30000+"),
+            (1.0, 180.0, 4, "62H20000+"),
+            (1.0, 181.0, 4, "62H30000+"),
+            (90.0, 1.0, 10, "CFX3X2X2+X2"),
+        ];
+        for &(lat, lon, length, code) in cases {
+            assert_eq!(encode(lat, lon, length), code);
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let area = decode("7FG49QCJ+2VX").unwrap();
+        assert_eq!(area.length, 11);
+        assert_eq!((area.south, area.north), (20.3701, 20.370125));
+        assert_eq!((area.west, area.east), (2.78221875, 2.78225));
+        assert_eq!(encode(area.south, area.west, 11), "7FG49QCJ+2VX");
+        let area = decode("7fg49q00+").unwrap();
+        assert_eq!((area.south, area.west, area.length), (20.35, 2.75, 6));
+
+        for code in &[
+            "7FG49Q0+",
+            "7FG49Q00+2V",
+            "7FG49QCJ+2",
+            "7FG49QCJ++2V",
+            "F2000000+",
+            "WC00",
+            "",
+        ] {
+            assert_eq!(decode(code), Err(PlusCodeError::Invalid), "{}", code);
+        }
+        assert_eq!(decode("9QCJ+2VX"), Err(PlusCodeError::Short));
+    }
+
+    #[test]
+    fn test_recover_nearest() {
+        assert_eq!(
+            recover_nearest("9G8F+6X", 47.4, 8.6).unwrap(),
+            "8FVC9G8F+6X"
+        );
+        assert_eq!(
+            recover_nearest("8fvc9g8f+6x", 0.0, 0.0).unwrap(),
+            "8FVC9G8F+6X"
+        );
+        // Across a boundary of the reference area.
+        assert_eq!(
+            recover_nearest("2222+22", 46.9999, 7.9999).unwrap(),
+            "8FVC2222+22"
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let code = encode(39.9042, 116.4074, 11);
+        let converted = convert(&converter, &code).unwrap();
+        assert_eq!(converted.len(), code.len());
+        let (lat, lon) = decode(&code).unwrap().center();
+        let (lat, lon) = converter.convert(lat, lon);
+        let area = decode(&converted).unwrap();
+        assert!(area.south <= lat && lat < area.north && area.west <= lon && lon < area.east);
+        assert_ne!(converted, code);
+        assert_eq!(convert(&converter, "9QCJ+2VX"), Err(PlusCodeError::Short));
+    }
+}
@@ -0,0 +1,244 @@
+//! A minimal PNG codec for the raster tiles of the `proxy` feature and the drift field heatmaps.
+//!
+//! Non-interlaced images with 8 bits per channel are read, in every color type, and written as
+//! RGBA.
+// Only the `proxy` feature reads images.
+#![cfg_attr(not(feature = "proxy"), allow(dead_code))]
+use crate::zip::{crc32, crc32_continue, deflate, inflate};
+use std::io;
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// An image as RGBA pixels, row by row from the top.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    /// A transparent image.
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            rgba: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        [
+            self.rgba[i],
+            self.rgba[i + 1],
+            self.rgba[i + 2],
+            self.rgba[i + 3],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, pixel: [u8; 4]) {
+        let i = (y * self.width + x) * 4;
+        self.rgba[i..i + 4].copy_from_slice(&pixel);
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = (
+        (p - i16::from(a)).abs(),
+        (p - i16::from(b)).abs(),
+        (p - i16::from(c)).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Decodes a PNG file.
+pub(crate) fn decode(data: &[u8]) -> io::Result<Image> {
+    if !data.starts_with(SIGNATURE) {
+        return Err(invalid("not a PNG file"));
+    }
+    let mut pos = SIGNATURE.len();
+    let (mut header, mut palette, mut alpha, mut compressed) = (None, &[][..], &[][..], Vec::new());
+    loop {
+        let chunk = data
+            .get(pos..pos + 8)
+            .ok_or_else(|| invalid("truncated PNG"))?;
+        let len = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+        let body = data
+            .get(pos + 8..(pos + 8).saturating_add(len))
+            .ok_or_else(|| invalid("truncated PNG"))?;
+        match &chunk[4..8] {
+            b"IHDR" if body.len() == 13 => header = Some(body),
+            b"PLTE" => palette = body,
+            b"tRNS" => alpha = body,
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+    let header = header.ok_or_else(|| invalid("missing PNG header"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color, interlace) = (header[8], header[9], header[12]);
+    let channels = match color {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid("invalid PNG color type")),
+    };
+    if depth != 8 || interlace != 0 {
+        return Err(invalid("unsupported PNG layout"));
+    }
+    if width == 0 || height == 0 || width.saturating_mul(height) > 1 << 26 {
+        return Err(invalid("invalid PNG size"));
+    }
+
+    // The zlib header is two bytes, and the checksum is left unchecked.
+    let stride = width * channels;
+    let size = height * (stride + 1);
+    let raw = inflate(&mut compressed.get(2..).unwrap_or(&[]), size as u64)?;
+    if raw.len() < size {
+        return Err(invalid("truncated PNG data"));
+    }
+    let mut pixels = vec![0u8; height * stride];
+    for y in 0..height {
+        let line = &raw[y * (stride + 1)..(y + 1) * (stride + 1)];
+        let (filter, line) = (line[0], &line[1..]);
+        for i in 0..stride {
+            let a = if i >= channels {
+                pixels[y * stride + i - channels]
+            } else {
+                0
+            };
+            let b = if y > 0 {
+                pixels[(y - 1) * stride + i]
+            } else {
+                0
+            };
+            let c = if y > 0 && i >= channels {
+                pixels[(y - 1) * stride + i - channels]
+            } else {
+                0
+            };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(invalid("invalid PNG filter")),
+            };
+            pixels[y * stride + i] = line[i].wrapping_add(predicted);
+        }
+    }
+
+    let mut image = Image::new(width, height);
+    for (i, p) in pixels.chunks(channels).enumerate() {
+        let rgba = match color {
+            0 => [p[0], p[0], p[0], 255],
+            2 => [p[0], p[1], p[2], 255],
+            3 => {
+                let entry = usize::from(p[0]);
+                let rgb = palette
+                    .get(entry * 3..entry * 3 + 3)
+                    .ok_or_else(|| invalid("invalid PNG palette index"))?;
+                [
+                    rgb[0],
+                    rgb[1],
+                    rgb[2],
+                    alpha.get(entry).copied().unwrap_or(255),
+                ]
+            }
+            4 => [p[0], p[0], p[0], p[1]],
+            _ => [p[0], p[1], p[2], p[3]],
+        };
+        image.rgba[i * 4..i * 4 + 4].copy_from_slice(&rgba);
+    }
+    Ok(image)
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc32_continue(crc32(kind), data).to_be_bytes());
+}
+
+/// Encodes an image as an RGBA PNG file.
+pub(crate) fn encode(image: &Image) -> Vec<u8> {
+    let stride = image.width * 4;
+    let mut raw = Vec::with_capacity(image.height * (stride + 1));
+    for line in image.rgba.chunks(stride.max(1)) {
+        // Filter type "Sub", which suits the flat areas of map tiles.
+        raw.push(1);
+        raw.extend(line.iter().enumerate().map(|(i, &v)| {
+            let left = if i >= 4 { line[i - 4] } else { 0 };
+            v.wrapping_sub(left)
+        }));
+    }
+    let mut zlib = vec![0x78, 0x01];
+    zlib.extend_from_slice(&deflate(&raw));
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(image.width as u32).to_be_bytes());
+    header.extend_from_slice(&(image.height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &zlib);
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut image = Image::new(7, 5);
+        for y in 0..5 {
+            for x in 0..7 {
+                image.set_pixel(x, y, [x as u8 * 30, y as u8 * 50, 7, 255 - x as u8]);
+            }
+        }
+        assert_eq!(decode(&encode(&image)).unwrap(), image);
+        assert!(decode(b"GIF89a").is_err());
+        assert!(decode(&encode(&image)[..40]).is_err());
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let field = crate::drift_field::DriftField::sample(crate::CHINA_BBOX, 3, 2);
+        let mut png = Vec::new();
+        field.write_png(&mut png).unwrap();
+        let image = decode(&png).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.pixel(0, 0)[3], 255);
+    }
+}
@@ -0,0 +1,262 @@
+//! Coordinates whose components are checked once, when they are created.
+//!
+//! [`Coordinate`] carries its system at run time, while [`Point`] carries it in its type, so that
+//! mixing systems is a compile error:
+//!
+//! ```
+//! use undrift_gps::point::{system, Point};
+//!
+//! let gcj: Point<system::Gcj02> = Point::from_degrees(39.9, 116.4).unwrap();
+//! let wgs = gcj.into_system::<system::Wgs84>();
+//! ```
+use crate::validate::Issue;
+use crate::{Converter, GeodeticSystem};
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A latitude in degrees, finite and within [-90, 90].
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Latitude(f64);
+
+/// A longitude in degrees, finite and within [-180, 180].
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Longitude(f64);
+
+impl Latitude {
+    pub fn new(degrees: f64) -> Result<Self, Issue> {
+        if !degrees.is_finite() {
+            Err(Issue::NotFinite)
+        } else if !(-90.0..=90.0).contains(&degrees) {
+            Err(Issue::LatitudeOutOfRange)
+        } else {
+            Ok(Latitude(degrees))
+        }
+    }
+
+    pub fn degrees(self) -> f64 {
+        self.0
+    }
+}
+
+impl Longitude {
+    pub fn new(degrees: f64) -> Result<Self, Issue> {
+        if !degrees.is_finite() {
+            Err(Issue::NotFinite)
+        } else if !(-180.0..=180.0).contains(&degrees) {
+            Err(Issue::LongitudeOutOfRange)
+        } else {
+            Ok(Longitude(degrees))
+        }
+    }
+
+    pub fn degrees(self) -> f64 {
+        self.0
+    }
+}
+
+macro_rules! impl_conversions {
+    ($name:ident) => {
+        impl TryFrom<f64> for $name {
+            type Error = Issue;
+
+            fn try_from(degrees: f64) -> Result<Self, Issue> {
+                $name::new(degrees)
+            }
+        }
+
+        impl From<$name> for f64 {
+            fn from(x: $name) -> f64 {
+                x.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+impl_conversions!(Latitude);
+impl_conversions!(Longitude);
+
+/// A valid coordinate tagged with its system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinate {
+    pub system: GeodeticSystem,
+    pub lat: Latitude,
+    pub lon: Longitude,
+}
+
+impl Coordinate {
+    pub fn new(system: GeodeticSystem, lat: Latitude, lon: Longitude) -> Self {
+        Coordinate { system, lat, lon }
+    }
+
+    /// Validates raw degrees, reporting the first issue found.
+    pub fn from_degrees(system: GeodeticSystem, lat: f64, lon: f64) -> Result<Self, Issue> {
+        Ok(Coordinate::new(
+            system,
+            Latitude::new(lat)?,
+            Longitude::new(lon)?,
+        ))
+    }
+
+    /// Converts the coordinate with the default settings.
+    pub fn convert_to(self, target: GeodeticSystem) -> Self {
+        self.convert_with(&Converter::new(self.system, target))
+    }
+
+    /// Converts the coordinate with `converter`, whose source system must be the one of the
+    /// coordinate.
+    ///
+    /// Results pushed out of range, which happens next to the poles and the antimeridian, are
+    /// brought back by the converter.
+    pub fn convert_with(self, converter: &Converter) -> Self {
+        assert_eq!(converter.source(), self.system, "mismatched source system");
+        let (lat, lon) = converter.convert(self.lat.0, self.lon.0);
+        Coordinate::new(converter.target(), Latitude(lat), Longitude(lon))
+    }
+}
+
+/// A system known at compile time.
+pub trait System: Copy {
+    const SYSTEM: GeodeticSystem;
+}
+
+/// Marker types of the systems, for [`Point`].
+pub mod system {
+    use super::System;
+    use crate::GeodeticSystem;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Wgs84;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Gcj02;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Bd09;
+
+    impl System for Wgs84 {
+        const SYSTEM: GeodeticSystem = GeodeticSystem::Wgs84;
+    }
+
+    impl System for Gcj02 {
+        const SYSTEM: GeodeticSystem = GeodeticSystem::Gcj02;
+    }
+
+    impl System for Bd09 {
+        const SYSTEM: GeodeticSystem = GeodeticSystem::Bd09;
+    }
+}
+
+/// A valid coordinate in the system `S`, the same size as two `f64`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point<S: System> {
+    lat: Latitude,
+    lon: Longitude,
+    system: PhantomData<S>,
+}
+
+impl<S: System> Point<S> {
+    pub fn new(lat: Latitude, lon: Longitude) -> Self {
+        Point {
+            lat,
+            lon,
+            system: PhantomData,
+        }
+    }
+
+    /// Validates raw degrees, reporting the first issue found.
+    pub fn from_degrees(lat: f64, lon: f64) -> Result<Self, Issue> {
+        Ok(Point::new(Latitude::new(lat)?, Longitude::new(lon)?))
+    }
+
+    pub fn lat(self) -> Latitude {
+        self.lat
+    }
+
+    pub fn lon(self) -> Longitude {
+        self.lon
+    }
+
+    /// Converts the point with the default settings, see [`Coordinate::convert_to`].
+    pub fn into_system<T: System>(self) -> Point<T> {
+        let converted = Coordinate::from(self).convert_to(T::SYSTEM);
+        Point::new(converted.lat, converted.lon)
+    }
+}
+
+impl<S: System> From<Point<S>> for Coordinate {
+    fn from(point: Point<S>) -> Self {
+        Coordinate::new(S::SYSTEM, point.lat, point.lon)
+    }
+}
+
+impl<S: System> TryFrom<Coordinate> for Point<S> {
+    /// The coordinate itself, when it is in another system.
+    type Error = Coordinate;
+
+    fn try_from(coordinate: Coordinate) -> Result<Self, Coordinate> {
+        if coordinate.system == S::SYSTEM {
+            Ok(Point::new(coordinate.lat, coordinate.lon))
+        } else {
+            Err(coordinate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_new() {
+        assert_eq!(Latitude::new(39.9).map(f64::from), Ok(39.9));
+        assert_eq!(Latitude::new(90.5), Err(Issue::LatitudeOutOfRange));
+        assert_eq!(Latitude::try_from(f64::NAN), Err(Issue::NotFinite));
+        assert_eq!(Longitude::new(-180.0).map(Longitude::degrees), Ok(-180.0));
+        assert_eq!(Longitude::new(180.1), Err(Issue::LongitudeOutOfRange));
+        assert_eq!(
+            Coordinate::from_degrees(Wgs84, 39.9, f64::INFINITY),
+            Err(Issue::NotFinite)
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let gcj = Coordinate::from_degrees(Gcj02, 39.9, 116.4).unwrap();
+        let wgs = gcj.convert_to(Wgs84);
+        assert_eq!(wgs.system, Wgs84);
+        assert_eq!(
+            (wgs.lat.degrees(), wgs.lon.degrees()),
+            Converter::new(Gcj02, Wgs84).convert(39.9, 116.4)
+        );
+
+        let pole = Coordinate::from_degrees(Gcj02, 90.0, 180.0).unwrap();
+        let bd = pole.convert_to(Bd09);
+        assert!(bd.lat.degrees() <= 90.0 && bd.lon.degrees().abs() <= 180.0);
+    }
+
+    #[test]
+    fn test_point() {
+        assert_eq!(
+            std::mem::size_of::<Point<system::Bd09>>(),
+            std::mem::size_of::<(f64, f64)>()
+        );
+        let bd: Point<system::Bd09> = Point::from_degrees(39.9, 116.4).unwrap();
+        let wgs = bd.into_system::<system::Wgs84>();
+        let expected = Coordinate::from(bd).convert_to(Wgs84);
+        assert_eq!(Coordinate::from(wgs), expected);
+        assert_eq!(Point::<system::Wgs84>::try_from(expected), Ok(wgs));
+        assert_eq!(Point::<system::Gcj02>::try_from(expected), Err(expected));
+        assert_eq!(
+            Point::<system::Gcj02>::from_degrees(91.0, 0.0),
+            Err(Issue::LatitudeOutOfRange)
+        );
+    }
+}
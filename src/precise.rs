@@ -0,0 +1,159 @@
+//! Extended-precision inversion of GCJ-02, for validating the `f64` algorithms.
+//!
+//! The position and the residual of the iteration are carried as double-double numbers, an
+//! unevaluated sum of two `f64` giving about 32 significant digits. The offset itself is still
+//! evaluated in `f64` with the original distortion of [`crate::Algorithm::V1Classic`]: it is below
+//! 0.01 degrees inside China, so its rounding error is far below the resolution of an `f64`
+//! latitude or longitude, and the result is the exact inverse of that offset to within the
+//! reported residual.
+use crate::{gcj_offset_v1, is_in_china};
+use std::fmt;
+use std::ops::{Add, Sub};
+
+/// A double-double number, the unevaluated sum `hi + lo` with `|lo|` at most half an ulp of `hi`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DoubleDouble {
+    pub hi: f64,
+    pub lo: f64,
+}
+
+/// Sum of two numbers with its exact rounding error (Knuth's two-sum).
+fn two_sum(a: f64, b: f64) -> DoubleDouble {
+    let hi = a + b;
+    let b_virtual = hi - a;
+    let lo = (a - (hi - b_virtual)) + (b - b_virtual);
+    DoubleDouble { hi, lo }
+}
+
+impl DoubleDouble {
+    pub fn new(x: f64) -> Self {
+        DoubleDouble { hi: x, lo: 0.0 }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.hi + self.lo
+    }
+}
+
+impl Add for DoubleDouble {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let s = two_sum(self.hi, other.hi);
+        let t = two_sum(self.lo, other.lo);
+        let s = two_sum(s.hi, s.lo + t.hi);
+        two_sum(s.hi, s.lo + t.lo)
+    }
+}
+
+impl Add<f64> for DoubleDouble {
+    type Output = Self;
+
+    fn add(self, other: f64) -> Self {
+        self + DoubleDouble::new(other)
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self + DoubleDouble {
+            hi: -other.hi,
+            lo: -other.lo,
+        }
+    }
+}
+
+impl fmt::Display for DoubleDouble {
+    /// Formats the parts as `hi+lo`, which reads back exactly.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{:+e}", self.hi, self.lo)
+    }
+}
+
+/// The result of [`gcj_to_wgs`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreciseInverse {
+    pub lat: DoubleDouble,
+    pub lon: DoubleDouble,
+    /// Largest absolute difference in degrees between the input and the forward transform of the
+    /// result, evaluated in double-double.
+    pub residual: f64,
+    /// Rounds of iteration done.
+    pub rounds: u32,
+}
+
+/// Converts a GCJ-02 coordinate into WGS-84, iterating until the residual stops decreasing.
+pub fn gcj_to_wgs(lat: f64, lon: f64) -> PreciseInverse {
+    const MAX_ROUND: u32 = 100;
+
+    let gcj = (DoubleDouble::new(lat), DoubleDouble::new(lon));
+    let mut best = PreciseInverse {
+        lat: gcj.0,
+        lon: gcj.1,
+        residual: 0.0,
+        rounds: 0,
+    };
+    if !is_in_china(lat, lon) {
+        return best;
+    }
+
+    best.residual = f64::INFINITY;
+    let mut wgs = gcj;
+    for round in 0..MAX_ROUND {
+        let (lat_d, lon_d) = gcj_offset_v1(wgs.0.hi, wgs.1.hi);
+        let delta = (gcj.0 - (wgs.0 + lat_d), gcj.1 - (wgs.1 + lon_d));
+        let residual = delta.0.to_f64().abs().max(delta.1.to_f64().abs());
+        if residual >= best.residual {
+            break;
+        }
+        best = PreciseInverse {
+            lat: wgs.0,
+            lon: wgs.1,
+            residual,
+            rounds: round,
+        };
+        if residual == 0.0 {
+            break;
+        }
+        wgs = (wgs.0 + delta.0, wgs.1 + delta.1);
+    }
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Algorithm;
+    use crate::Converter;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_double_double() {
+        let x = DoubleDouble::new(1.0) + 1e-20;
+        assert_eq!(x, DoubleDouble { hi: 1.0, lo: 1e-20 });
+        assert_eq!((x - DoubleDouble::new(1.0)).to_f64(), 1e-20);
+        assert_eq!(x.to_string(), "1+1e-20");
+    }
+
+    #[test]
+    fn test_gcj_to_wgs() {
+        let converter = Converter::new(Gcj02, Wgs84).with_algorithm(Algorithm::Exact);
+        for &(lat, lon) in &[
+            (39.9042, 116.4074),
+            (31.2304, 121.4737),
+            (22.5431, 114.0579),
+        ] {
+            let precise = gcj_to_wgs(lat, lon);
+            assert!(precise.residual < 1e-20, "{:?}", precise);
+            let (exact_lat, exact_lon) = converter.convert(lat, lon);
+            assert!((precise.lat.to_f64() - exact_lat).abs() < 1e-13);
+            assert!((precise.lon.to_f64() - exact_lon).abs() < 1e-13);
+        }
+
+        let outside = gcj_to_wgs(48.8566, 2.3522);
+        assert_eq!(outside.lat.to_f64(), 48.8566);
+        assert_eq!(outside.residual, 0.0);
+    }
+}
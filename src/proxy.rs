@@ -0,0 +1,553 @@
+//! Tile-correcting proxy, enabled by the `proxy` feature: serves the tiles of an upstream
+//! provider redrawn for another, such as GCJ-02 tiles of Amap lined up with WGS-84 overlays. See
+//! `src/bin/undrift-tile-proxy.rs` for the server binary.
+//!
+//! Each tile is built from the upstream tiles it overlaps, listed by [`overlapping_tiles`].
+//! Vector tiles have their features converted by [`mvt::reproject_tile`]. Raster tiles are
+//! resampled: every pixel is read, with bilinear filtering, where its position falls in the
+//! upstream tiles, which must be 256-pixel PNG files.
+//!
+//! ```no_run
+//! use std::net::TcpListener;
+//! use undrift_gps::basemap::TileProvider;
+//! use undrift_gps::proxy::{upstream, TileKind, TileProxy};
+//!
+//! let proxy = TileProxy::new(
+//!     TileProvider::Amap,
+//!     TileProvider::OpenStreetMap,
+//!     TileKind::Raster,
+//!     upstream("http://tiles.example/{z}/{x}/{y}.png"),
+//! );
+//! proxy.serve(TcpListener::bind("127.0.0.1:8080").unwrap()).unwrap();
+//! ```
+use crate::basemap::{overlapping_tiles, TileProvider, TILE_SIZE};
+use crate::gzip;
+use crate::http;
+use crate::mvt::{self, TileId};
+use crate::png::{self, Image};
+use crate::Converter;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Fetches an upstream tile, addressed as its provider does. A tile missing upstream is reported
+/// with [`io::ErrorKind::NotFound`], and served as transparent or empty.
+pub type Fetch = Box<dyn Fn(TileId) -> io::Result<Vec<u8>> + Send + Sync>;
+
+/// Content of the tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TileKind {
+    /// Mapbox vector tiles, possibly gzipped upstream.
+    Vector,
+    /// PNG images.
+    Raster,
+}
+
+impl TileKind {
+    fn content_type(self) -> &'static str {
+        match self {
+            TileKind::Vector => "application/vnd.mapbox-vector-tile",
+            TileKind::Raster => "image/png",
+        }
+    }
+}
+
+/// Serves the tiles of `upstream` in the system and addressing of `served`.
+pub struct TileProxy {
+    upstream: TileProvider,
+    served: TileProvider,
+    kind: TileKind,
+    fetch: Fetch,
+    buffer: u32,
+}
+
+/// Default buffer of vector tiles, in layer units.
+const BUFFER: u32 = 64;
+
+/// Cells per side of the grid of exact conversions of a raster tile, between which positions
+/// are interpolated.
+const GRID: usize = 16;
+
+/// Connections served at once; further ones wait in the backlog of the listener.
+const MAX_CONNECTIONS: usize = 64;
+/// Timeout of each read and write of a connection.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest request line and headers read, in bytes.
+const MAX_HEAD: u64 = 16 << 10;
+/// Pause after failing to accept a connection, such as when out of file descriptors.
+const ACCEPT_PAUSE: Duration = Duration::from_millis(100);
+/// Largest size a gzipped upstream tile is decompressed to.
+const MAX_TILE: u64 = 16 << 20;
+
+/// Count of the connections being served, waited on when it reaches [`MAX_CONNECTIONS`].
+struct Connections {
+    count: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Connections {
+    /// Waits for a free slot and takes it.
+    fn acquire(&self) -> Slot<'_> {
+        let mut count = self.count.lock().unwrap();
+        while *count >= MAX_CONNECTIONS {
+            count = self.freed.wait(count).unwrap();
+        }
+        *count += 1;
+        Slot(self)
+    }
+}
+
+/// A slot of [`Connections`], freed when dropped, even by a panicking thread.
+struct Slot<'a>(&'a Connections);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        *self.0.count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl TileProxy {
+    pub fn new(upstream: TileProvider, served: TileProvider, kind: TileKind, fetch: Fetch) -> Self {
+        TileProxy {
+            upstream,
+            served,
+            kind,
+            fetch,
+            buffer: BUFFER,
+        }
+    }
+
+    /// Sets the buffer of vector tiles around their area, in layer units.
+    pub fn with_buffer(mut self, buffer: u32) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    /// Builds `target`, addressed as the served provider does.
+    ///
+    /// Vector tiles of Baidu Maps, which are not Web Mercator tiles, are unsupported.
+    pub fn tile(&self, target: TileId) -> io::Result<Vec<u8>> {
+        let ids = overlapping_tiles(self.served, target, self.upstream, target.z);
+        let fetched = thread::scope(|scope| {
+            let handles: Vec<_> = ids
+                .iter()
+                .map(|&id| scope.spawn(move || (self.fetch)(id)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(io::Error::other("upstream fetch panicked")))
+                })
+                .collect::<Vec<_>>()
+        });
+        let mut sources = Vec::new();
+        for (id, data) in ids.into_iter().zip(fetched) {
+            match data {
+                Ok(data) => sources.push((id, data)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        match self.kind {
+            TileKind::Vector => self.vector_tile(target, sources),
+            TileKind::Raster => self.raster_tile(target, &sources),
+        }
+    }
+
+    fn vector_tile(&self, target: TileId, sources: Vec<(TileId, Vec<u8>)>) -> io::Result<Vec<u8>> {
+        if self.upstream == TileProvider::Baidu || self.served == TileProvider::Baidu {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "vector tiles of Baidu Maps are unsupported",
+            ));
+        }
+        // The XYZ address of a tile, as the tiles of Tencent count rows northward.
+        let xyz = |provider, tile: TileId| match provider {
+            TileProvider::Tencent => TileId::from_tms(tile.z, tile.x, tile.y),
+            _ => tile,
+        };
+        let mut data = Vec::with_capacity(sources.len());
+        for (id, tile) in sources {
+            let tile = if gzip::is_gzip(&tile) {
+                gzip::decompress_with_limit(&tile, MAX_TILE)?
+            } else {
+                tile
+            };
+            data.push((xyz(self.upstream, id), tile));
+        }
+        let sources: Vec<_> = data.iter().map(|(id, tile)| (*id, &tile[..])).collect();
+        let converter = Converter::new(self.upstream.system(), self.served.system());
+        let target = xyz(self.served, target);
+        mvt::reproject_tile(&converter, target, &sources, self.buffer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    fn raster_tile(&self, target: TileId, sources: &[(TileId, Vec<u8>)]) -> io::Result<Vec<u8>> {
+        let mut images = HashMap::new();
+        for (id, data) in sources {
+            let image = png::decode(data)?;
+            if (image.width, image.height) != (TILE_SIZE as usize, TILE_SIZE as usize) {
+                return Err(invalid("upstream tile is not 256 pixels wide"));
+            }
+            images.insert(*id, image);
+        }
+
+        // Upstream pixel positions of the grid, the conversions being too slow for every pixel.
+        let (z, up) = (target.z, self.upstream);
+        let (top, _) = self.served.rows(z, target.y);
+        let left = f64::from(target.x) * TILE_SIZE;
+        let step = TILE_SIZE / GRID as f64;
+        let mut grid = Vec::with_capacity((GRID + 1) * (GRID + 1));
+        for j in 0..=GRID {
+            for i in 0..=GRID {
+                let x = left + i as f64 * step;
+                let y = top + j as f64 * step;
+                let (lat, lon) = self.served.unpixel(z, x, y);
+                let (lat, lon) = self.served.system().convert_to(up.system(), lat, lon);
+                grid.push(up.pixel(z, lat, lon));
+            }
+        }
+
+        // A pixel of the upstream tiles, by its column and row across the tiles.
+        let texel = |x: f64, y: f64| {
+            let (column, row) = ((x / TILE_SIZE).floor(), up.row(z, y));
+            let max = up.max_index(z);
+            if column < 0.0 || row < 0.0 || column > max || row > max {
+                return [0; 4];
+            }
+            let (column, row) = (column as u32, row as u32);
+            let id = TileId {
+                z,
+                x: column,
+                y: row,
+            };
+            let (top, _) = up.rows(z, row);
+            let x = (x - f64::from(column) * TILE_SIZE).clamp(0.0, TILE_SIZE - 1.0);
+            let y = (y - top).clamp(0.0, TILE_SIZE - 1.0);
+            images
+                .get(&id)
+                .map_or([0; 4], |image: &Image| image.pixel(x as usize, y as usize))
+        };
+
+        let size = TILE_SIZE as usize;
+        let mut output = Image::new(size, size);
+        for py in 0..size {
+            let v = (py as f64 + 0.5) / step;
+            let (j, fy) = ((v as usize).min(GRID - 1), v.fract());
+            for px in 0..size {
+                let u = (px as f64 + 0.5) / step;
+                let (i, fx) = ((u as usize).min(GRID - 1), u.fract());
+                let at = |i: usize, j: usize| grid[j * (GRID + 1) + i];
+                let lerp = |a: (f64, f64), b: (f64, f64), t: f64| {
+                    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+                };
+                let upper = lerp(at(i, j), at(i + 1, j), fx);
+                let lower = lerp(at(i, j + 1), at(i + 1, j + 1), fx);
+                let (x, y) = lerp(upper, lower, fy);
+
+                // Bilinear filtering between the four nearest pixel centers.
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let mut pixel = [0.0; 4];
+                for &(dx, dy, weight) in &[
+                    (0.0, 0.0, (1.0 - tx) * (1.0 - ty)),
+                    (1.0, 0.0, tx * (1.0 - ty)),
+                    (0.0, 1.0, (1.0 - tx) * ty),
+                    (1.0, 1.0, tx * ty),
+                ] {
+                    let texel = texel(x0 + dx, y0 + dy);
+                    for (sum, &value) in pixel.iter_mut().zip(&texel) {
+                        *sum += f64::from(value) * weight;
+                    }
+                }
+                output.set_pixel(px, py, pixel.map(|value| value.round() as u8));
+            }
+        }
+        Ok(png::encode(&output))
+    }
+
+    /// Serves `GET /{z}/{x}/{y}` requests, with any extension, from the connections of
+    /// `listener`, each in its own thread.
+    ///
+    /// Unknown paths get a 404 response, failures upstream a 502 response, and requests whose
+    /// line and headers exceed 16 KiB a 431 response. Up to 64 connections are served at once,
+    /// each read and write timing out after 30 seconds. Failures to accept a connection are
+    /// logged to the standard error, after which accepting goes on after a short pause.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        let connections = Connections {
+            count: Mutex::new(0),
+            freed: Condvar::new(),
+        };
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("proxy: cannot accept a connection: {}", e);
+                        thread::sleep(ACCEPT_PAUSE);
+                        continue;
+                    }
+                };
+                let slot = connections.acquire();
+                scope.spawn(move || {
+                    let _slot = slot;
+                    stream.set_read_timeout(Some(TIMEOUT))?;
+                    stream.set_write_timeout(Some(TIMEOUT))?;
+                    self.respond(&stream)
+                });
+            }
+            Ok(())
+        })
+    }
+
+    fn respond(&self, stream: &TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.take(MAX_HEAD));
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // The head ends with an empty line, which a request cut by the limit lacks.
+        let mut line = String::new();
+        let complete = loop {
+            line.clear();
+            match reader.read_line(&mut line)? {
+                0 => break false,
+                1 | 2 if line.ends_with('\n') => break true,
+                _ => {}
+            }
+        };
+
+        let mut words = request.split_whitespace();
+        let (status, content_type, body) = match (words.next(), words.next().and_then(parse_path)) {
+            _ if !complete && reader.get_ref().limit() == 0 => (
+                "431 Request Header Fields Too Large",
+                "text/plain",
+                b"request too large".to_vec(),
+            ),
+            (Some("GET"), Some(tile)) if self.addressable(tile) => match self.tile(tile) {
+                Ok(body) => ("200 OK", self.kind.content_type(), body),
+                Err(e) => ("502 Bad Gateway", "text/plain", e.to_string().into_bytes()),
+            },
+            _ => ("404 Not Found", "text/plain", b"not a tile".to_vec()),
+        };
+        let mut writer = stream;
+        // Tiles are fetched across origins by web maps.
+        write!(
+            writer,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        )?;
+        writer.write_all(&body)?;
+        writer.flush()
+    }
+
+    fn addressable(&self, tile: TileId) -> bool {
+        let max = self.served.max_index(tile.z);
+        tile.z <= 30 && f64::from(tile.x) <= max && f64::from(tile.y) <= max
+    }
+}
+
+/// Parses `/{z}/{x}/{y}`, with an optional extension and query.
+fn parse_path(path: &str) -> Option<TileId> {
+    let path = path.split('?').next()?.strip_prefix('/')?;
+    let mut parts = path.split('/');
+    let z = parts.next()?.parse().ok()?;
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.split('.').next()?.parse().ok()?;
+    match parts.next() {
+        Some(_) => None,
+        None => Some(TileId { z, x, y }),
+    }
+}
+
+/// Fetches the tiles of an HTTP server with [`http::get`], from a URL template where `{z}`, `{x}`
+/// and `{y}` are replaced by the address of the tile.
+pub fn upstream(template: &str) -> Fetch {
+    let template = template.to_owned();
+    Box::new(move |tile| {
+        let url = template
+            .replace("{z}", &tile.z.to_string())
+            .replace("{x}", &tile.x.to_string())
+            .replace("{y}", &tile.y.to_string());
+        http::get(&url)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+    use std::sync::{Arc, Mutex};
+
+    fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    /// A tile with a layer of one point at `(x, y)` of its 4096 units.
+    fn point_tile(x: u32, y: u32) -> Vec<u8> {
+        let mut geometry = Vec::new();
+        for &value in &[9, x << 1, y << 1] {
+            varint(&mut geometry, u64::from(value));
+        }
+        let mut feature = vec![0x18, 1, 0x22];
+        varint(&mut feature, geometry.len() as u64);
+        feature.extend_from_slice(&geometry);
+        let mut layer = vec![0x78, 2, 0x0a, 1, b'p', 0x12];
+        varint(&mut layer, feature.len() as u64);
+        layer.extend_from_slice(&feature);
+        let mut tile = vec![0x1a];
+        varint(&mut tile, layer.len() as u64);
+        tile.extend_from_slice(&layer);
+        tile
+    }
+
+    fn solid(pixel: [u8; 4]) -> Vec<u8> {
+        let mut image = Image::new(256, 256);
+        for y in 0..256 {
+            for x in 0..256 {
+                image.set_pixel(x, y, pixel);
+            }
+        }
+        png::encode(&image)
+    }
+
+    #[test]
+    fn test_vector_tile() {
+        use TileProvider::*;
+        let (lat, lon) = (39.9, 116.4);
+        let source = Amap.tile(14, lat, lon);
+        let data = point_tile(2048, 2048);
+        let fetched = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&fetched);
+        let fetch: Fetch = Box::new(move |id| {
+            log.lock().unwrap().push(id);
+            if id == source {
+                Ok(data.clone())
+            } else {
+                Err(io::ErrorKind::NotFound.into())
+            }
+        });
+        let proxy = TileProxy::new(Amap, OpenStreetMap, TileKind::Vector, fetch);
+
+        let (lat, lon) = Amap.tile_center(source);
+        let (lat, lon) = Gcj02.convert_to(Wgs84, lat, lon);
+        let target = OpenStreetMap.tile(14, lat, lon);
+        let tile = proxy.tile(target).unwrap();
+        let sources = [(source, &point_tile(2048, 2048)[..])];
+        let expected =
+            mvt::reproject_tile(&Converter::new(Gcj02, Wgs84), target, &sources, BUFFER).unwrap();
+        assert!(!tile.is_empty());
+        assert_eq!(tile, expected);
+        let mut fetched = fetched.lock().unwrap().clone();
+        fetched.sort();
+        assert_eq!(fetched, overlapping_tiles(OpenStreetMap, target, Amap, 14));
+
+        let baidu = TileProxy::new(Baidu, OpenStreetMap, TileKind::Vector, upstream("x"));
+        assert!(baidu.tile(target).is_err());
+    }
+
+    #[test]
+    fn test_raster_tile() {
+        use TileProvider::*;
+        let identity = TileProxy::new(
+            Amap,
+            Amap,
+            TileKind::Raster,
+            Box::new(|id| {
+                let mut image = Image::new(256, 256);
+                for y in 0..256 {
+                    for x in 0..256 {
+                        image.set_pixel(x, y, [x as u8, y as u8, id.x as u8, 255]);
+                    }
+                }
+                Ok(png::encode(&image))
+            }),
+        );
+        let tile = Amap.tile(15, 39.9, 116.4);
+        let image = png::decode(&identity.tile(tile).unwrap()).unwrap();
+        assert_eq!(image.pixel(0, 0), [0, 0, tile.x as u8, 255]);
+        assert_eq!(image.pixel(200, 17), [200, 17, tile.x as u8, 255]);
+        assert_eq!(image.pixel(255, 255), [255, 255, tile.x as u8, 255]);
+
+        // Each upstream tile has its own color, and the center of the served tile lies in one.
+        let source = Gcj02.convert_to(Wgs84, 39.9, 116.4);
+        let target = OpenStreetMap.tile(15, source.0, source.1);
+        let (lat, lon) = OpenStreetMap.tile_center(target);
+        let (lat, lon) = Wgs84.convert_to(Gcj02, lat, lon);
+        let center = Amap.tile(15, lat, lon);
+        let color = |id: TileId| [id.x as u8, id.y as u8, 7, 255];
+        let shifted = TileProxy::new(
+            Amap,
+            OpenStreetMap,
+            TileKind::Raster,
+            Box::new(move |id| Ok(solid(color(id)))),
+        );
+        let image = png::decode(&shifted.tile(target).unwrap()).unwrap();
+        assert_eq!(image.pixel(128, 128), color(center));
+
+        // Missing tiles are transparent, and other failures are reported.
+        let missing = TileProxy::new(
+            Amap,
+            OpenStreetMap,
+            TileKind::Raster,
+            Box::new(|_| Err(io::ErrorKind::NotFound.into())),
+        );
+        let image = png::decode(&missing.tile(target).unwrap()).unwrap();
+        assert!(image.rgba.iter().all(|&v| v == 0));
+        let jpeg = TileProxy::new(
+            Amap,
+            OpenStreetMap,
+            TileKind::Raster,
+            Box::new(|_| Ok(b"\xff\xd8\xff".to_vec())),
+        );
+        assert!(jpeg.tile(target).is_err());
+    }
+
+    #[test]
+    fn test_serve() {
+        use TileProvider::*;
+        let proxy = TileProxy::new(
+            Amap,
+            Amap,
+            TileKind::Raster,
+            Box::new(|_| Ok(solid([1, 2, 3, 255]))),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || proxy.serve(listener));
+
+        let tile = http::get(&format!("http://{}/15/26978/12416.png?v=1", address)).unwrap();
+        assert_eq!(png::decode(&tile).unwrap().pixel(3, 3), [1, 2, 3, 255]);
+        let error = http::get(&format!("http://{}/15/26978", address)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(http::get(&format!("http://{}/1/2/0", address)).is_err());
+
+        let mut client = TcpStream::connect(address).unwrap();
+        // Exactly the limit, all read by the server, but without the empty line ending the head.
+        let mut long = String::from("GET /1/0/0 HTTP/1.1\r\nX-Padding: ");
+        long.push_str(&"a".repeat(MAX_HEAD as usize - long.len()));
+        client.write_all(long.as_bytes()).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 "), "{}", response);
+
+        assert_eq!(parse_path("/3/1/2.pbf"), Some(TileId { z: 3, x: 1, y: 2 }));
+        assert_eq!(parse_path("/3/1/2/4"), None);
+    }
+}
@@ -0,0 +1,272 @@
+//! Validation of a conversion against a road network, for large fleet datasets.
+//!
+//! Vehicle tracks converted into the system of an OpenStreetMap extract, WGS-84, run along its
+//! roads, while tracks left in GCJ-02 or BD-09 run hundreds of meters off them. [`validate`]
+//! measures the fraction of points near a road before and after the conversion: a rise is the
+//! signal that the conversion went the right way.
+use crate::track::Track;
+use crate::xml::{Event, Reader, XmlError};
+use crate::{Converter, EARTH_RADIUS};
+use std::collections::HashMap;
+
+/// Side of the cells of the spatial index, in degrees.
+const CELL: f64 = 0.005;
+
+/// Road segments with a grid index, in the system of the extract they come from.
+#[derive(Debug, Clone, Default)]
+pub struct RoadNetwork {
+    segments: Vec<((f64, f64), (f64, f64))>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+fn cell(lat: f64, lon: f64) -> (i32, i32) {
+    ((lat / CELL).floor() as i32, (lon / CELL).floor() as i32)
+}
+
+impl RoadNetwork {
+    /// Builds a network from segments between `(lat, lon)` endpoints.
+    pub fn from_segments(segments: Vec<((f64, f64), (f64, f64))>) -> Self {
+        let mut cells: HashMap<_, Vec<usize>> = HashMap::new();
+        for (i, &(a, b)) in segments.iter().enumerate() {
+            let (min, max) = (
+                cell(a.0.min(b.0), a.1.min(b.1)),
+                cell(a.0.max(b.0), a.1.max(b.1)),
+            );
+            for row in min.0..=max.0 {
+                for col in min.1..=max.1 {
+                    cells.entry((row, col)).or_default().push(i);
+                }
+            }
+        }
+        RoadNetwork { segments, cells }
+    }
+
+    /// Reads the ways tagged `highway` of an OSM XML extract.
+    ///
+    /// Nodes missing from the extract, as where ways cross its boundary, cut the ways.
+    pub fn from_osm(input: &str) -> Result<Self, XmlError> {
+        let mut nodes = HashMap::new();
+        let mut ways = Vec::new();
+        // Node references and highway tag of the way being read.
+        let mut way: Option<(Vec<i64>, bool)> = None;
+        for event in Reader::new(input) {
+            match event?.0 {
+                Event::Start { name, attributes } | Event::Empty { name, attributes } => {
+                    let get = |key| attributes.get(key).map(|(value, _)| value);
+                    match name {
+                        "node" => {
+                            let id = get("id").and_then(|id| id.parse::<i64>().ok());
+                            let lat = get("lat").and_then(|lat| lat.parse::<f64>().ok());
+                            let lon = get("lon").and_then(|lon| lon.parse::<f64>().ok());
+                            if let (Some(id), Some(lat), Some(lon)) = (id, lat, lon) {
+                                nodes.insert(id, (lat, lon));
+                            }
+                        }
+                        "way" => way = Some((Vec::new(), false)),
+                        "nd" => {
+                            let id = get("ref").and_then(|id| id.parse().ok());
+                            if let (Some((refs, _)), Some(id)) = (&mut way, id) {
+                                refs.push(id);
+                            }
+                        }
+                        "tag" if get("k") == Some("highway") => {
+                            if let Some((_, highway)) = &mut way {
+                                *highway = true;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Event::End { name: "way" } => {
+                    if let Some((refs, true)) = way.take() {
+                        ways.push(refs);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut segments = Vec::new();
+        for refs in ways {
+            for pair in refs.windows(2) {
+                if let (Some(&a), Some(&b)) = (nodes.get(&pair[0]), nodes.get(&pair[1])) {
+                    segments.push((a, b));
+                }
+            }
+        }
+        Ok(RoadNetwork::from_segments(segments))
+    }
+
+    /// Number of road segments.
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Distance in meters from a point to the nearest road, or `None` if none is within `max`.
+    pub fn distance(&self, lat: f64, lon: f64, max: f64) -> Option<f64> {
+        if !lat.is_finite() || !lon.is_finite() {
+            return None;
+        }
+        // Meters per degree around the point, where the segments are projected.
+        let m = EARTH_RADIUS.to_radians();
+        let (my, mx) = (m, m * lat.to_radians().cos());
+        let (dlat, dlon) = (max / my, max / mx.max(1.0));
+        let (min_cell, max_cell) = (cell(lat - dlat, lon - dlon), cell(lat + dlat, lon + dlon));
+        let mut best: Option<f64> = None;
+        for row in min_cell.0..=max_cell.0 {
+            for col in min_cell.1..=max_cell.1 {
+                for &i in self.cells.get(&(row, col)).into_iter().flatten() {
+                    let (a, b) = self.segments[i];
+                    let a = ((a.1 - lon) * mx, (a.0 - lat) * my);
+                    let b = ((b.1 - lon) * mx, (b.0 - lat) * my);
+                    let d = distance_to_segment(a, b);
+                    if d <= max && best.is_none_or(|best| d < best) {
+                        best = Some(d);
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Fraction of `points` within `threshold` meters of a road, 0 without points.
+    pub fn match_rate(&self, points: impl IntoIterator<Item = (f64, f64)>, threshold: f64) -> f64 {
+        let (mut matched, mut total) = (0usize, 0usize);
+        for (lat, lon) in points {
+            total += 1;
+            if self.distance(lat, lon, threshold).is_some() {
+                matched += 1;
+            }
+        }
+        if total == 0 {
+            0.0
+        } else {
+            matched as f64 / total as f64
+        }
+    }
+}
+
+/// Distance from the origin to the segment between `a` and `b`, in a plane.
+fn distance_to_segment(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 > 0.0 {
+        (-(a.0 * dx + a.1 * dy) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (a.0 + t * dx).hypot(a.1 + t * dy)
+}
+
+/// Fractions of the points of a dataset near the road network, before and after conversion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchReport {
+    pub points: usize,
+    pub threshold: f64,
+    pub before: f64,
+    pub after: f64,
+}
+
+impl MatchReport {
+    /// Whether the conversion brought more points onto roads than it took off.
+    pub fn improved(&self) -> bool {
+        self.after > self.before
+    }
+}
+
+/// Converts `tracks` and compares how many of their points lie within `threshold` meters of
+/// `network`, whose system must be the target of `converter`.
+///
+/// A threshold of 20 to 30 meters covers the width of roads and the noise of receivers, and
+/// stays well below the drift.
+pub fn validate(
+    network: &RoadNetwork,
+    converter: &Converter,
+    tracks: &[Track],
+    threshold: f64,
+) -> MatchReport {
+    let points = || {
+        tracks
+            .iter()
+            .flat_map(|t| t.points.iter())
+            .map(|p| (p.lat, p.lon))
+    };
+    let converted: Vec<_> = tracks.iter().map(|t| t.convert(converter)).collect();
+    let after = converted
+        .iter()
+        .flat_map(|t| t.points.iter())
+        .map(|p| (p.lat, p.lon));
+    MatchReport {
+        points: points().count(),
+        threshold,
+        before: network.match_rate(points(), threshold),
+        after: network.match_rate(after, threshold),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::track::TrackPoint;
+    use crate::GeodeticSystem::*;
+
+    const EXTRACT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="39.9000" lon="116.3900"/>
+  <node id="2" lat="39.9000" lon="116.4100"/>
+  <node id="3" lat="39.9200" lon="116.4100"/>
+  <node id="4" lat="39.9500" lon="116.3000"/>
+  <way id="10">
+    <nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="99"/>
+    <tag k="highway" v="primary"/>
+  </way>
+  <way id="11">
+    <nd ref="3"/><nd ref="4"/>
+    <tag k="waterway" v="river"/>
+  </way>
+</osm>"#;
+
+    #[test]
+    fn test_network() {
+        let network = RoadNetwork::from_osm(EXTRACT).unwrap();
+        assert_eq!(network.len(), 2);
+        // A tenth of a thousandth of a degree of latitude is 11 meters.
+        let d = network.distance(39.9001, 116.4, 50.0).unwrap();
+        assert!((d - 11.12).abs() < 0.01, "{}", d);
+        let d = network.distance(39.91, 116.4101, 50.0).unwrap();
+        assert!((d - 8.53).abs() < 0.01, "{}", d);
+        assert_eq!(network.distance(39.9001, 116.4, 10.0), None);
+        // Beyond the end of the road, and along the river, which is not a road.
+        assert_eq!(network.distance(39.9, 116.389, 50.0), None);
+        assert_eq!(network.distance(39.935, 116.355, 50.0), None);
+        assert_eq!(network.distance(f64::NAN, 116.4, 50.0), None);
+        assert!(RoadNetwork::from_osm("<osm><way>").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let network = RoadNetwork::from_osm(EXTRACT).unwrap();
+        // A vehicle driving along the road, recorded in GCJ-02.
+        let points = (0..=40)
+            .map(|i| {
+                let (lat, lon) = Wgs84.convert_to(Gcj02, 39.9, 116.39 + f64::from(i) * 0.0005);
+                TrackPoint::new(lat, lon)
+            })
+            .collect();
+        let tracks = [Track { points }];
+        let report = validate(&network, &Converter::new(Gcj02, Wgs84), &tracks, 25.0);
+        assert_eq!(report.points, 41);
+        assert!(report.before < 0.1, "{:?}", report);
+        assert_eq!(report.after, 1.0);
+        assert!(report.improved());
+
+        let wrong = validate(&network, &Converter::new(Wgs84, Gcj02), &tracks, 25.0);
+        assert!(wrong.after < 0.1, "{:?}", wrong);
+        assert!(!wrong.improved());
+        assert_eq!(network.match_rate(Vec::new(), 25.0), 0.0);
+    }
+}
@@ -0,0 +1,305 @@
+//! S2 cell IDs, for location platforms indexed on the S2 geometry library.
+//!
+//! Cells are computed as the reference implementation does: the sphere is projected on the six
+//! faces of a cube with the quadratic transform, and each face is divided along a Hilbert curve
+//! down to level 30, about a centimeter. Converting a cell moves it by hundreds of meters, to
+//! other cells of the same level: [`convert_cell`] gives the cell of the converted center, and
+//! [`convert_covering`] every cell the converted cell overlaps.
+use crate::Converter;
+use std::fmt;
+
+const MAX_LEVEL: u8 = 30;
+const MAX_SIZE: u32 = 1 << MAX_LEVEL;
+const SWAP: u8 = 1;
+const INVERT: u8 = 2;
+/// The (i, j) quadrant of each position along the curve, by orientation.
+const POS_TO_IJ: [[u8; 4]; 4] = [[0, 1, 3, 2], [0, 2, 3, 1], [3, 2, 0, 1], [3, 1, 0, 2]];
+/// The position along the curve of each (i, j) quadrant, by orientation.
+const IJ_TO_POS: [[u8; 4]; 4] = [[0, 1, 3, 2], [0, 3, 1, 2], [2, 3, 1, 0], [2, 1, 3, 0]];
+/// How the orientation of a sub-cell differs from its parent's, by position.
+const POS_TO_ORIENTATION: [u8; 4] = [SWAP, 0, 0, INVERT | SWAP];
+
+/// An S2 cell, identified by its face, its position along the Hilbert curve and its level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CellId(pub u64);
+
+fn st_to_uv(s: f64) -> f64 {
+    if s >= 0.5 {
+        (4.0 * s * s - 1.0) / 3.0
+    } else {
+        (1.0 - 4.0 * (1.0 - s) * (1.0 - s)) / 3.0
+    }
+}
+
+fn uv_to_st(u: f64) -> f64 {
+    if u >= 0.0 {
+        0.5 * (1.0 + 3.0 * u).sqrt()
+    } else {
+        1.0 - 0.5 * (1.0 - 3.0 * u).sqrt()
+    }
+}
+
+/// The face and (u, v) coordinates of a point given in degrees.
+fn latlon_to_face_uv(lat: f64, lon: f64) -> (u8, f64, f64) {
+    let (lat, lon) = (lat.to_radians(), lon.to_radians());
+    let (x, y, z) = (lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin());
+    let face = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+        if x < 0.0 {
+            3
+        } else {
+            0
+        }
+    } else if y.abs() >= z.abs() {
+        if y < 0.0 {
+            4
+        } else {
+            1
+        }
+    } else if z < 0.0 {
+        5
+    } else {
+        2
+    };
+    let (u, v) = match face {
+        0 => (y / x, z / x),
+        1 => (-x / y, z / y),
+        2 => (-x / z, -y / z),
+        3 => (z / x, y / x),
+        4 => (z / y, -x / y),
+        _ => (-y / z, -x / z),
+    };
+    (face, u, v)
+}
+
+/// The point in degrees at (u, v) on a face.
+fn face_uv_to_latlon(face: u8, u: f64, v: f64) -> (f64, f64) {
+    let (x, y, z) = match face {
+        0 => (1.0, u, v),
+        1 => (-u, 1.0, v),
+        2 => (-u, -v, 1.0),
+        3 => (-1.0, -v, -u),
+        4 => (v, -1.0, -u),
+        _ => (v, u, -1.0),
+    };
+    (z.atan2(x.hypot(y)).to_degrees(), y.atan2(x).to_degrees())
+}
+
+fn st_to_ij(s: f64) -> u32 {
+    (s * f64::from(MAX_SIZE))
+        .floor()
+        .clamp(0.0, f64::from(MAX_SIZE - 1)) as u32
+}
+
+fn ij_to_st(i: f64) -> f64 {
+    i / f64::from(MAX_SIZE)
+}
+
+impl CellId {
+    /// The leaf cell of position `(i, j)` on a face.
+    fn from_face_ij(face: u8, i: u32, j: u32) -> Self {
+        let mut orientation = face & SWAP;
+        let mut pos = 0u64;
+        for k in (0..MAX_LEVEL).rev() {
+            let ij = ((i >> k) & 1) << 1 | ((j >> k) & 1);
+            let p = IJ_TO_POS[orientation as usize][ij as usize];
+            pos = pos << 2 | u64::from(p);
+            orientation ^= POS_TO_ORIENTATION[p as usize];
+        }
+        CellId((u64::from(face) << 60 | pos) << 1 | 1)
+    }
+
+    /// The face and the position of the first leaf cell.
+    fn to_face_ij(self) -> (u8, u32, u32) {
+        let face = self.face();
+        let first = self.0 - (self.lsb() - 1);
+        let mut orientation = face & SWAP;
+        let (mut i, mut j) = (0, 0);
+        for k in (0..MAX_LEVEL).rev() {
+            let p = (first >> (2 * k + 1)) & 3;
+            let ij = POS_TO_IJ[orientation as usize][p as usize];
+            i |= u32::from(ij >> 1) << k;
+            j |= u32::from(ij & 1) << k;
+            orientation ^= POS_TO_ORIENTATION[p as usize];
+        }
+        (face, i, j)
+    }
+
+    fn lsb(self) -> u64 {
+        self.0 & self.0.wrapping_neg()
+    }
+
+    /// The cell of level `level`, from 0 to 30, containing a point.
+    pub fn from_latlon(lat: f64, lon: f64, level: u8) -> Self {
+        assert!(level <= MAX_LEVEL, "S2 levels go up to 30");
+        let (face, u, v) = latlon_to_face_uv(lat, lon);
+        let leaf = CellId::from_face_ij(face, st_to_ij(uv_to_st(u)), st_to_ij(uv_to_st(v)));
+        leaf.parent(level)
+    }
+
+    /// Whether the ID designates a cell: a face below 6 and a level from 0 to 30.
+    pub fn is_valid(self) -> bool {
+        self.face() < 6 && self.lsb() & 0x1555_5555_5555_5555 != 0
+    }
+
+    pub fn face(self) -> u8 {
+        (self.0 >> 61) as u8
+    }
+
+    pub fn level(self) -> u8 {
+        MAX_LEVEL - (self.0.trailing_zeros() / 2) as u8
+    }
+
+    /// The cell of level `level`, at most the level of this one, containing it.
+    pub fn parent(self, level: u8) -> Self {
+        assert!(level <= self.level(), "the parent must be at a lower level");
+        let lsb = 1u64 << (2 * (MAX_LEVEL - level));
+        CellId((self.0 & lsb.wrapping_neg()) | lsb)
+    }
+
+    /// The point at `(s, t)` in the cell, from (0, 0) at its first corner to (1, 1).
+    fn point(self, s: f64, t: f64) -> (f64, f64) {
+        let (face, i, j) = self.to_face_ij();
+        let size = f64::from(1u32 << (MAX_LEVEL - self.level()));
+        let mask = !((1u32 << (MAX_LEVEL - self.level())) - 1);
+        let (i, j) = (f64::from(i & mask), f64::from(j & mask));
+        let u = st_to_uv(ij_to_st(i + s * size));
+        let v = st_to_uv(ij_to_st(j + t * size));
+        face_uv_to_latlon(face, u, v)
+    }
+
+    /// The center of the cell, as latitude and longitude.
+    pub fn center(self) -> (f64, f64) {
+        self.point(0.5, 0.5)
+    }
+
+    /// The corners of the cell, in counterclockwise order.
+    pub fn vertices(self) -> [(f64, f64); 4] {
+        [
+            self.point(0.0, 0.0),
+            self.point(1.0, 0.0),
+            self.point(1.0, 1.0),
+            self.point(0.0, 1.0),
+        ]
+    }
+
+    /// The token of the cell: its ID in hexadecimal without the trailing zeros.
+    pub fn token(self) -> String {
+        if self.0 == 0 {
+            return "X".to_owned();
+        }
+        let hex = format!("{:016x}", self.0);
+        hex.trim_end_matches('0').to_owned()
+    }
+
+    /// Reads a token, returning `None` if it does not designate a cell.
+    pub fn from_token(token: &str) -> Option<Self> {
+        if token.is_empty() || token.len() > 16 || !token.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let id = u64::from_str_radix(token, 16).ok()? << (4 * (16 - token.len()));
+        Some(CellId(id)).filter(|cell| cell.is_valid())
+    }
+}
+
+impl fmt::Display for CellId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.token())
+    }
+}
+
+/// The cell of the same level containing the converted center of `cell`.
+pub fn convert_cell(converter: &Converter, cell: CellId) -> CellId {
+    let (lat, lon) = cell.center();
+    let (lat, lon) = converter.convert(lat, lon);
+    CellId::from_latlon(lat, lon, cell.level())
+}
+
+/// The cells of the same level that the converted cell overlaps, in order.
+///
+/// The converted cell is taken as the quadrilateral through its converted corners, edge midpoints
+/// and center, which takes the drift across the cell into account down to level 0.
+pub fn convert_covering(converter: &Converter, cell: CellId) -> Vec<CellId> {
+    // Just inside the corners, so that a cell moved onto the grid does not cover its neighbors.
+    const EDGE: f64 = 1e-9;
+    let mut cells = Vec::new();
+    for &s in &[EDGE, 0.5, 1.0 - EDGE] {
+        for &t in &[EDGE, 0.5, 1.0 - EDGE] {
+            let (lat, lon) = cell.point(s, t);
+            let (lat, lon) = converter.convert(lat, lon);
+            cells.push(CellId::from_latlon(lat, lon, cell.level()));
+        }
+    }
+    cells.sort_unstable();
+    cells.dedup();
+    cells
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_cell_id() {
+        // The reference values of the S2 library.
+        assert_eq!(
+            CellId::from_latlon(0.0, 0.0, 30),
+            CellId(0x1000_0000_0000_0001)
+        );
+        let faces: Vec<_> = [(0.0, 0.0), (0.0, 90.0), (90.0, 0.0), (0.0, 180.0)]
+            .iter()
+            .chain(&[(0.0, -90.0), (-90.0, 0.0)])
+            .map(|&(lat, lon)| CellId::from_latlon(lat, lon, 0).token())
+            .collect();
+        assert_eq!(faces, ["1", "3", "5", "7", "9", "b"]);
+
+        let leaf = CellId::from_latlon(39.9042, 116.4074, 30);
+        assert!(leaf.is_valid());
+        assert_eq!((leaf.face(), leaf.level()), (1, 30));
+        let (lat, lon) = leaf.center();
+        assert!((lat - 39.9042).abs() < 1e-7 && (lon - 116.4074).abs() < 1e-7);
+        for level in 0..=30 {
+            let cell = CellId::from_latlon(39.9042, 116.4074, level);
+            assert_eq!(cell, leaf.parent(level));
+            assert_eq!(cell.level(), level);
+            let (lat, lon) = cell.center();
+            assert_eq!(CellId::from_latlon(lat, lon, level), cell);
+            assert_eq!(CellId::from_token(&cell.token()), Some(cell));
+        }
+
+        let cell = leaf.parent(12);
+        for &(lat, lon) in &cell.vertices() {
+            let (center_lat, center_lon) = cell.center();
+            assert!((lat - center_lat).abs() < 0.02 && (lon - center_lon).abs() < 0.02);
+        }
+        assert_eq!(CellId::from_token("X"), None);
+        assert_eq!(CellId::from_token("d"), None);
+        assert!(!CellId(0x1000_0000_0000_0002).is_valid());
+    }
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        // Cells of about 150 meters and 10 kilometers.
+        for &level in &[16, 10] {
+            let cell = CellId::from_latlon(39.9042, 116.4074, level);
+            let converted = convert_cell(&converter, cell);
+            assert_eq!(converted.level(), level);
+            let (lat, lon) = cell.center();
+            let (lat, lon) = converter.convert(lat, lon);
+            assert_eq!(converted, CellId::from_latlon(lat, lon, level));
+
+            let covering = convert_covering(&converter, cell);
+            assert!(covering.contains(&converted));
+            assert!(covering.len() <= 4, "{:?}", covering);
+            assert!(covering.iter().all(|c| c.level() == level));
+        }
+
+        let cell = CellId::from_latlon(39.9042, 116.4074, 16);
+        assert_ne!(convert_cell(&converter, cell), cell);
+
+        // No drift outside of China.
+        let cell = CellId::from_latlon(48.8566, 2.3522, 16);
+        assert_eq!(convert_covering(&converter, cell), [cell]);
+    }
+}
@@ -0,0 +1,197 @@
+//! Simplification of converted lines, for web-facing outputs.
+//!
+//! Tolerances are in meters, measured in a plane tangent to the Earth around each point, so that
+//! they mean the same anywhere. See [`crate::pipeline::Pipeline::simplify`] to convert and
+//! simplify in one pass, and [`crate::track::Track::simplify`].
+use crate::EARTH_RADIUS;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Algorithm of the simplification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Simplification {
+    /// Ramer-Douglas-Peucker: keeps the points further than the tolerance from the simplified
+    /// line, which bounds the error.
+    DouglasPeucker,
+    /// Visvalingam-Whyatt: removes the points whose triangle with their neighbors is smaller
+    /// than the square of the tolerance, keeping the shape better at the same number of points.
+    Visvalingam,
+}
+
+/// Position in meters of `q` relative to `p`.
+fn offset(p: (f64, f64), q: (f64, f64)) -> (f64, f64) {
+    let m = EARTH_RADIUS.to_radians();
+    ((q.1 - p.1) * m * p.0.to_radians().cos(), (q.0 - p.0) * m)
+}
+
+/// Distance in meters from `p` to the segment between `a` and `b`.
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (a, b) = (offset(p, a), offset(p, b));
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 > 0.0 {
+        (-(a.0 * dx + a.1 * dy) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (a.0 + t * dx).hypot(a.1 + t * dy)
+}
+
+/// Area in square meters of the triangle of `p` and its neighbors `a` and `b`.
+fn triangle_area(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (a, b) = (offset(p, a), offset(p, b));
+    (a.0 * b.1 - a.1 * b.0).abs() / 2.0
+}
+
+/// Indices of the points kept by the simplification of `points`, in order.
+///
+/// The endpoints are always kept. Lines with a non-finite point are kept whole.
+pub fn simplify_indices(
+    points: &[(f64, f64)],
+    tolerance: f64,
+    method: Simplification,
+) -> Vec<usize> {
+    let finite = points.iter().all(|p| p.0.is_finite() && p.1.is_finite());
+    if points.len() < 3 || !finite || tolerance.is_nan() || tolerance <= 0.0 {
+        return (0..points.len()).collect();
+    }
+    // Douglas-Peucker adds points to the endpoints, and Visvalingam removes points from all.
+    let mut keep = vec![method == Simplification::Visvalingam; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    match method {
+        Simplification::DouglasPeucker => {
+            let mut ranges = vec![(0, points.len() - 1)];
+            while let Some((first, last)) = ranges.pop() {
+                let (a, b) = (points[first], points[last]);
+                let farthest = (first + 1..last)
+                    .map(|i| (i, segment_distance(points[i], a, b)))
+                    .fold((first, 0.0), |max, d| if d.1 > max.1 { d } else { max });
+                if farthest.1 > tolerance {
+                    keep[farthest.0] = true;
+                    ranges.push((first, farthest.0));
+                    ranges.push((farthest.0, last));
+                }
+            }
+        }
+        Simplification::Visvalingam => {
+            let threshold = tolerance * tolerance;
+            let n = points.len();
+            let mut prev: Vec<usize> = (0..n).map(|i| i.saturating_sub(1)).collect();
+            let mut next: Vec<usize> = (1..=n).collect();
+            let mut area = vec![f64::INFINITY; n];
+            // Areas are non-negative, so that their bits order as they do.
+            let mut heap = BinaryHeap::new();
+            for i in 1..n - 1 {
+                area[i] = triangle_area(points[i], points[i - 1], points[i + 1]);
+                heap.push(Reverse((area[i].to_bits(), i)));
+            }
+            while let Some(Reverse((bits, i))) = heap.pop() {
+                if bits != area[i].to_bits() || !keep[i] {
+                    continue;
+                }
+                if area[i] >= threshold {
+                    break;
+                }
+                keep[i] = false;
+                let (p, q) = (prev[i], next[i]);
+                next[p] = q;
+                prev[q] = p;
+                // Neighbors take at least the area of the removed point, so that the areas grow
+                // as points are removed.
+                for &j in &[p, q] {
+                    if j != 0 && j != n - 1 {
+                        let new = triangle_area(points[j], points[prev[j]], points[next[j]]);
+                        area[j] = new.max(area[i]);
+                        heap.push(Reverse((area[j].to_bits(), j)));
+                    }
+                }
+            }
+        }
+    }
+    (0..points.len()).filter(|&i| keep[i]).collect()
+}
+
+/// Simplifies a line within `tolerance` meters.
+pub fn simplify(points: &[(f64, f64)], tolerance: f64, method: Simplification) -> Vec<(f64, f64)> {
+    simplify_indices(points, tolerance, method)
+        .into_iter()
+        .map(|i| points[i])
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A zigzag of about 110 meters per step along a parallel, swinging by `amplitude` meters.
+    fn zigzag(n: usize, amplitude: f64) -> Vec<(f64, f64)> {
+        let degrees = (amplitude / EARTH_RADIUS).to_degrees();
+        (0..n)
+            .map(|i| {
+                let swing = if i % 2 == 0 { 0.0 } else { degrees };
+                (39.9 + swing, 116.4 + i as f64 * 0.0013)
+            })
+            .collect()
+    }
+
+    /// Largest distance from a point of `line` to the part of the simplified line replacing it.
+    fn deviation(line: &[(f64, f64)], indices: &[usize]) -> f64 {
+        let mut max: f64 = 0.0;
+        for pair in indices.windows(2) {
+            for &p in &line[pair[0]..=pair[1]] {
+                max = max.max(segment_distance(p, line[pair[0]], line[pair[1]]));
+            }
+        }
+        max
+    }
+
+    #[test]
+    fn test_douglas_peucker() {
+        let line = zigzag(21, 5.0);
+        let simplified = simplify(&line, 10.0, Simplification::DouglasPeucker);
+        assert_eq!(simplified, [line[0], line[20]]);
+        let indices = simplify_indices(&line, 4.0, Simplification::DouglasPeucker);
+        assert!(indices.len() > 2 && indices.len() < 21, "{:?}", indices);
+        assert!(deviation(&line, &indices) <= 4.0);
+
+        // A corner is kept, and the error is bounded by the tolerance.
+        let mut corner = zigzag(11, 2.0);
+        let lon = corner[10].1;
+        corner.extend((1..11).map(|i| (39.9 + i as f64 * 0.001, lon)));
+        let indices = simplify_indices(&corner, 10.0, Simplification::DouglasPeucker);
+        assert_eq!(indices, [0, 10, 20]);
+        assert!(deviation(&corner, &indices) <= 10.0);
+    }
+
+    #[test]
+    fn test_visvalingam() {
+        let line = zigzag(21, 5.0);
+        // Triangles of 5 meters by 220 meters have an area of about 560 square meters.
+        let simplified = simplify_indices(&line, 25.0, Simplification::Visvalingam);
+        assert_eq!(simplified, [0, 20]);
+        let kept = simplify_indices(&line, 15.0, Simplification::Visvalingam);
+        assert_eq!(kept.len(), 21);
+
+        let mut corner = zigzag(11, 2.0);
+        let lon = corner[10].1;
+        corner.extend((1..11).map(|i| (39.9 + i as f64 * 0.001, lon)));
+        let indices = simplify_indices(&corner, 30.0, Simplification::Visvalingam);
+        assert_eq!(indices, [0, 10, 20]);
+    }
+
+    #[test]
+    fn test_degenerate() {
+        for &method in &[Simplification::DouglasPeucker, Simplification::Visvalingam] {
+            assert!(simplify(&[], 10.0, method).is_empty());
+            let two = [(39.9, 116.4), (39.9, 116.4)];
+            assert_eq!(simplify(&two, 10.0, method), two);
+            let same = [(39.9, 116.4); 5];
+            assert_eq!(simplify(&same, 10.0, method), [same[0], same[4]]);
+            let nan = [(39.9, 116.4), (f64::NAN, 116.4), (39.9, 116.41)];
+            assert_eq!(simplify(&nan, 10.0, method).len(), 3);
+            let line = zigzag(5, 5.0);
+            assert_eq!(simplify(&line, 0.0, method), line);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Declarative descriptions of conversion pipelines.
+//!
+//! A spec can be written in JSON or in a flat subset of TOML:
+//!
+//! ```toml
+//! source = "bd09"
+//! target = "wgs84"
+//! algorithm = "exact"                 # optional, see `Algorithm`
+//! boundary = [18.0, 73.0, 54.0, 135.0] # optional: "china", "everywhere" or a bbox
+//! buffer = 500.0                      # optional: meters around the boundary, see `buffer`
+//! projection = "web-mercator"         # optional: "web-mercator" or "baidu-mercator"
+//! densify = 50.0                      # optional: meters between points of lines, see `densify`
+//! ```
+use crate::json::{JsonError, Value};
+use crate::pipeline::is_length;
+use crate::transform::{BaiduMercator, Transform, WebMercator};
+use crate::{Algorithm, BoundingBox, Converter, GeodeticSystem, CHINA_BBOX};
+use std::fmt;
+
+/// Where the GCJ-02 obfuscation is applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    /// The built-in rectangle, see [`crate::CHINA_BBOX`].
+    China,
+    /// Everywhere on the globe.
+    Everywhere,
+    /// A custom rectangle.
+    BBox(BoundingBox),
+}
+
+/// Projection applied after the conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    WebMercator,
+    BaiduMercator,
+}
+
+/// A conversion pipeline description.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineSpec {
+    pub source: GeodeticSystem,
+    pub target: GeodeticSystem,
+    pub algorithm: Algorithm,
+    pub boundary: Boundary,
+    /// Meters by which a rectangular boundary is grown, so that points near it and just outside
+    /// are obfuscated too; a negative buffer shrinks it instead. Zero by default.
+    pub buffer: f64,
+    pub projection: Option<Projection>,
+    /// The longest segment, in meters, of the lines converted by
+    /// [`crate::pipeline::Pipeline::convert_line`]; points are converted as they are.
+    pub densify: Option<f64>,
+}
+
+/// Error returned for an invalid spec.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpecError {
+    Json(JsonError),
+    /// A TOML line that is not a `key = value` pair, with its 1-based number.
+    Syntax(usize),
+    UnknownKey(String),
+    InvalidValue(String),
+    MissingKey(&'static str),
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpecError::Json(e) => write!(f, "{}", e),
+            SpecError::Syntax(line) => write!(f, "invalid syntax on line {}", line),
+            SpecError::UnknownKey(key) => write!(f, "unknown key {:?}", key),
+            SpecError::InvalidValue(key) => write!(f, "invalid value for {:?}", key),
+            SpecError::MissingKey(key) => write!(f, "missing key {:?}", key),
+        }
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+impl From<JsonError> for SpecError {
+    fn from(e: JsonError) -> Self {
+        SpecError::Json(e)
+    }
+}
+
+impl PipelineSpec {
+    /// Creates a spec with the default options.
+    pub fn new(source: GeodeticSystem, target: GeodeticSystem) -> Self {
+        PipelineSpec {
+            source,
+            target,
+            algorithm: Algorithm::default(),
+            boundary: Boundary::China,
+            buffer: 0.0,
+            projection: None,
+            densify: None,
+        }
+    }
+
+    /// Reads a spec from a JSON object.
+    pub fn from_json(s: &str) -> Result<Self, SpecError> {
+        match Value::parse(s)? {
+            Value::Object(members) => Self::from_members(members),
+            _ => Err(SpecError::Syntax(1)),
+        }
+    }
+
+    /// Reads a spec from flat TOML.
+    pub fn from_toml(s: &str) -> Result<Self, SpecError> {
+        let mut members = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or(SpecError::Syntax(i + 1))?;
+            // TOML strings and arrays of numbers are valid JSON.
+            let value = Value::parse(value.trim()).map_err(|_| SpecError::Syntax(i + 1))?;
+            members.push((key.trim().to_owned(), value));
+        }
+        Self::from_members(members)
+    }
+
+    fn from_members(members: Vec<(String, Value)>) -> Result<Self, SpecError> {
+        let mut source = None;
+        let mut target = None;
+        let mut spec = PipelineSpec::new(GeodeticSystem::Wgs84, GeodeticSystem::Wgs84);
+        for (key, value) in members {
+            let invalid = || SpecError::InvalidValue(key.clone());
+            let text = value.as_str();
+            match key.as_str() {
+                "source" => source = Some(text.and_then(|s| s.parse().ok()).ok_or_else(invalid)?),
+                "target" => target = Some(text.and_then(|s| s.parse().ok()).ok_or_else(invalid)?),
+                "algorithm" => {
+                    spec.algorithm = text.and_then(|s| s.parse().ok()).ok_or_else(invalid)?
+                }
+                "boundary" => spec.boundary = parse_boundary(&value).ok_or_else(invalid)?,
+                "buffer" => {
+                    spec.buffer = value
+                        .as_f64()
+                        .filter(|x| x.is_finite())
+                        .ok_or_else(invalid)?
+                }
+                "projection" => {
+                    spec.projection = Some(match text {
+                        Some("web-mercator") => Projection::WebMercator,
+                        Some("baidu-mercator") => Projection::BaiduMercator,
+                        _ => return Err(invalid()),
+                    })
+                }
+                "densify" => {
+                    spec.densify = Some(
+                        value
+                            .as_f64()
+                            .filter(|&x| is_length(x))
+                            .ok_or_else(invalid)?,
+                    )
+                }
+                _ => return Err(SpecError::UnknownKey(key)),
+            }
+        }
+        spec.source = source.ok_or(SpecError::MissingKey("source"))?;
+        spec.target = target.ok_or(SpecError::MissingKey("target"))?;
+        Ok(spec)
+    }
+
+    fn members(&self) -> Vec<(&'static str, Value)> {
+        let string = |s: &str| Value::String(s.to_owned());
+        let mut members = vec![
+            ("source", string(&system_key(self.source))),
+            ("target", string(&system_key(self.target))),
+            ("algorithm", string(self.algorithm.key())),
+        ];
+        members.push((
+            "boundary",
+            match self.boundary {
+                Boundary::China => string("china"),
+                Boundary::Everywhere => string("everywhere"),
+                Boundary::BBox(b) => Value::Array(
+                    [b.min_lat, b.min_lon, b.max_lat, b.max_lon]
+                        .iter()
+                        .map(|&x| Value::number(x))
+                        .collect(),
+                ),
+            },
+        ));
+        if self.buffer != 0.0 {
+            members.push(("buffer", Value::number(self.buffer)));
+        }
+        if let Some(projection) = self.projection {
+            let name = match projection {
+                Projection::WebMercator => "web-mercator",
+                Projection::BaiduMercator => "baidu-mercator",
+            };
+            members.push(("projection", string(name)));
+        }
+        if let Some(densify) = self.densify {
+            members.push(("densify", Value::number(densify)));
+        }
+        members
+    }
+
+    /// Writes the spec as a JSON object.
+    pub fn to_json(&self) -> String {
+        let members = self.members();
+        let object = members
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v))
+            .collect();
+        Value::Object(object).to_string()
+    }
+
+    /// Writes the spec as flat TOML.
+    pub fn to_toml(&self) -> String {
+        self.members()
+            .into_iter()
+            .map(|(key, value)| format!("{} = {}\n", key, value))
+            .collect()
+    }
+
+    /// Creates the converter described by the spec, ignoring the projection.
+    pub fn converter(&self) -> Converter {
+        let converter = Converter::new(self.source, self.target).with_algorithm(self.algorithm);
+        let bbox = match self.boundary {
+            Boundary::China if self.buffer == 0.0 => return converter,
+            Boundary::China => CHINA_BBOX,
+            Boundary::Everywhere => return converter.with_region(|_, _| true),
+            Boundary::BBox(bbox) => bbox,
+        }
+        .buffered(self.buffer);
+        converter.with_region(move |lat, lon| bbox.contains(lat, lon))
+    }
+
+    /// Creates the whole pipeline described by the spec.
+    pub fn build(&self) -> Box<dyn Transform + Send + Sync> {
+        let converter = self.converter();
+        match self.projection {
+            None => Box::new(converter),
+            Some(Projection::WebMercator) => Box::new(converter.then(WebMercator)),
+            Some(Projection::BaiduMercator) => Box::new(converter.then(BaiduMercator)),
+        }
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn system_key(system: GeodeticSystem) -> String {
+    system.name().replace('-', "").to_lowercase()
+}
+
+fn parse_boundary(value: &Value) -> Option<Boundary> {
+    match value {
+        Value::String(s) if s == "china" => Some(Boundary::China),
+        Value::String(s) if s == "everywhere" => Some(Boundary::Everywhere),
+        Value::Array(items) if items.len() == 4 => {
+            let v: Option<Vec<f64>> = items.iter().map(Value::as_f64).collect();
+            let v = v?;
+            Some(Boundary::BBox(BoundingBox::new(v[0], v[1], v[2], v[3])))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use GeodeticSystem::*;
+
+    #[test]
+    fn test_toml() {
+        let spec = PipelineSpec::from_toml(
+            r#"
+            # Baidu to GPS, projected for the web.
+            source = "bd09ll"
+            target = "wgs84"
+            algorithm = "exact"
+            boundary = [18.0, 73.0, 54.0, 135.0]
+            buffer = -250.5
+            projection = "web-mercator" # trailing comment
+            densify = 25.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(spec.source, Bd09);
+        assert_eq!(spec.target, Wgs84);
+        assert_eq!(spec.algorithm, Algorithm::Exact);
+        assert_eq!(
+            spec.boundary,
+            Boundary::BBox(BoundingBox::new(18.0, 73.0, 54.0, 135.0))
+        );
+        assert_eq!(spec.buffer, -250.5);
+        assert_eq!(spec.projection, Some(Projection::WebMercator));
+        assert_eq!(spec.densify, Some(25.0));
+        assert_eq!(PipelineSpec::from_toml(&spec.to_toml()), Ok(spec));
+    }
+
+    #[test]
+    fn test_buffer() {
+        // About 500 meters north of the northern edge.
+        let (lat, lon) = (CHINA_BBOX.max_lat + 0.0045, 120.0);
+        let mut spec = PipelineSpec::new(Wgs84, Gcj02);
+        assert_eq!(spec.converter().convert(lat, lon), (lat, lon));
+        spec.buffer = 1000.0;
+        assert_ne!(spec.converter().convert(lat, lon), (lat, lon));
+        assert!(spec.to_json().contains("\"buffer\":1000"));
+        spec.boundary = Boundary::Everywhere;
+        assert_ne!(spec.converter().convert(-30.0, 0.0), (-30.0, 0.0));
+    }
+
+    #[test]
+    fn test_json() {
+        let spec = PipelineSpec::from_json(r#"{"source": "gcj02", "target": "bd09"}"#).unwrap();
+        assert_eq!(spec, PipelineSpec::new(Gcj02, Bd09));
+        assert_eq!(PipelineSpec::from_json(&spec.to_json()), Ok(spec.clone()));
+
+        let pipeline = spec.build();
+        assert_eq!(pipeline.apply(39.0, 116.0), crate::gcj_to_bd(39.0, 116.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            PipelineSpec::from_toml("source = \"wgs84\""),
+            Err(SpecError::MissingKey("target"))
+        );
+        assert_eq!(
+            PipelineSpec::from_toml("source = wgs84"),
+            Err(SpecError::Syntax(1))
+        );
+        assert_eq!(
+            PipelineSpec::from_json(r#"{"source": "wgs84", "target": "gcj02", "x": 1}"#),
+            Err(SpecError::UnknownKey("x".to_owned()))
+        );
+        assert_eq!(
+            PipelineSpec::from_json(r#"{"source": "utm", "target": "gcj02"}"#),
+            Err(SpecError::InvalidValue("source".to_owned()))
+        );
+        for densify in &["0", "-5", "\"50\"", "1e999"] {
+            let toml = format!(
+                "source = \"wgs84\"\ntarget = \"gcj02\"\ndensify = {}",
+                densify
+            );
+            assert_eq!(
+                PipelineSpec::from_toml(&toml),
+                Err(SpecError::InvalidValue("densify".to_owned()))
+            );
+        }
+    }
+}
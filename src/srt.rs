@@ -0,0 +1,247 @@
+//! DJI SRT files, the subtitle tracks of telemetry recorded along with drone footage.
+//!
+//! Two layouts of the subtitle text are recognized: the `[latitude: 22.543210] [longitude:
+//! 113.945678]` tags of recent models, the longitude being spelled `longtitude` by some firmware,
+//! and the `GPS(113.9456,22.5432,18)` of older ones, longitude first. The numbers are rewritten in
+//! place; timings, camera settings and altitudes are kept byte for byte.
+use crate::xml::apply_edits;
+use crate::Converter;
+use std::fmt;
+use std::ops::Range;
+
+/// Error returned for a malformed position, with the byte offset of its subtitle or number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrtError {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for SrtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid SRT at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for SrtError {}
+
+/// A number of the text, with its byte range.
+type Number = (f64, Range<usize>);
+
+/// Finds the value after `[name:` in `text`, with its range shifted by `base`.
+fn tag(text: &str, names: &[&str], base: usize) -> Result<Option<Number>, SrtError> {
+    for name in names {
+        if let Some(start) = text.find(&format!("[{}", name)) {
+            let after = start + 1 + name.len();
+            let rest = text[after..].trim_start();
+            let rest = match rest.strip_prefix(':') {
+                Some(rest) => rest,
+                // Another tag starting with the same name, such as `[latitude_ref: N]`.
+                None => continue,
+            };
+            let value_start = text.len() - rest.trim_start().len();
+            let value_end = text[value_start..]
+                .find(|c: char| c == ']' || c.is_whitespace())
+                .map_or(text.len(), |end| value_start + end);
+            return number(text, value_start..value_end, base).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Parses the number of `text` at `range`, shifting the range by `base`.
+fn number(text: &str, range: Range<usize>, base: usize) -> Result<Number, SrtError> {
+    let value = text[range.clone()].parse().map_err(|_| SrtError {
+        offset: base + range.start,
+        message: "invalid coordinate",
+    })?;
+    Ok((value, base + range.start..base + range.end))
+}
+
+/// Finds the longitude then the latitude of `GPS(lon, lat, alt)` in `text`.
+fn gps(text: &str, base: usize) -> Result<Option<(Number, Number)>, SrtError> {
+    let start = match text.find("GPS") {
+        Some(start) if text[start + 3..].trim_start().starts_with('(') => start,
+        _ => return Ok(None),
+    };
+    let open = start + text[start..].find('(').unwrap() + 1;
+    let close = text[open..]
+        .find(')')
+        .map(|end| open + end)
+        .ok_or(SrtError {
+            offset: base + start,
+            message: "unterminated GPS",
+        })?;
+    let mut fields = Vec::new();
+    let mut field_start = open;
+    for (i, c) in text[open..close].char_indices() {
+        if c == ',' {
+            fields.push(field_start..open + i);
+            field_start = open + i + 1;
+        }
+    }
+    fields.push(field_start..close);
+    if fields.len() < 2 {
+        return Err(SrtError {
+            offset: base + start,
+            message: "missing coordinate",
+        });
+    }
+    let trimmed = |range: &Range<usize>| {
+        let field = &text[range.clone()];
+        let start = range.start + (field.len() - field.trim_start().len());
+        start..start + field.trim().len()
+    };
+    let lon = number(text, trimmed(&fields[0]), base)?;
+    let lat = number(text, trimmed(&fields[1]), base)?;
+    Ok(Some((lon, lat)))
+}
+
+/// Finds the position of each subtitle, in order.
+fn find(input: &str) -> Result<Vec<(Number, Number)>, SrtError> {
+    let mut positions = Vec::new();
+    let mut block_start = 0;
+    let mut offset = 0;
+    let mut lines = input.split_inclusive('\n').peekable();
+    while let Some(line) = lines.next() {
+        offset += line.len();
+        if !line.trim().is_empty() && lines.peek().is_some() {
+            continue;
+        }
+        let (base, block) = (block_start, &input[block_start..offset]);
+        block_start = offset;
+        let lat = tag(block, &["latitude"], base)?;
+        let lon = tag(block, &["longitude", "longtitude"], base)?;
+        let position = match (gps(block, base)?, lat, lon) {
+            (Some((lon, lat)), _, _) | (None, Some(lat), Some(lon)) => (lat, lon),
+            (None, None, None) => continue,
+            _ => {
+                return Err(SrtError {
+                    offset: base,
+                    message: "missing coordinate",
+                })
+            }
+        };
+        positions.push(position);
+    }
+    Ok(positions)
+}
+
+/// Reads the position of each subtitle of a DJI SRT file, in order.
+pub fn positions(input: &str) -> Result<Vec<(f64, f64)>, SrtError> {
+    Ok(find(input)?
+        .into_iter()
+        .map(|(lat, lon)| (lat.0, lon.0))
+        .collect())
+}
+
+/// Converts the positions of a DJI SRT file.
+///
+/// Frames without a fix, recorded at 0, 0, are outside every region and left unchanged.
+pub fn convert(converter: &Converter, input: &str) -> Result<String, SrtError> {
+    let mut edits = Vec::new();
+    for (lat, lon) in find(input)? {
+        let converted = converter
+            .convert_written(lat.0, lon.0)
+            .map_err(|_| SrtError {
+                offset: lat.1.start,
+                message: "non-finite coordinate",
+            })?;
+        if let Some((x, y)) = converted {
+            edits.push((lat.1, converter.precision().format(x)));
+            edits.push((lon.1, converter.precision().format(y)));
+        }
+    }
+    Ok(apply_edits(input, edits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    const TAGS: &str = "1\r\n\
+        00:00:00,000 --> 00:00:00,033\r\n\
+        <font size=\"28\">FrameCnt: 1, DiffTime: 33ms\r\n\
+        2023-05-01 10:00:00.123\r\n\
+        [iso: 100] [shutter: 1/100.0] [fnum: 2.8] [latitude: 39.906217] [longitude: 116.391276] \
+        [rel_alt: 1.300 abs_alt: 100.200] </font>\r\n\
+        \r\n\
+        2\r\n\
+        00:00:00,033 --> 00:00:00,066\r\n\
+        [latitude : 39.906300] [longtitude : 116.391300] [altitude: 100.2]\r\n";
+
+    const GPS: &str = "1\n\
+        00:00:00,000 --> 00:00:01,000\n\
+        HOME(116.3900,39.9000) 2017.08.05 14:11:51\n\
+        GPS(116.3913,39.9062,18) BAROMETER:1.3\n\
+        \n\
+        2\n\
+        00:00:01,000 --> 00:00:02,000\n\
+        F/2.8, SS 1000, ISO 100, EV 0, GPS (116.3914, 39.9063, 19), D 1.0m\n";
+
+    #[test]
+    fn test_positions() {
+        assert_eq!(
+            positions(TAGS).unwrap(),
+            [(39.906217, 116.391276), (39.9063, 116.3913)]
+        );
+        assert_eq!(
+            positions(GPS).unwrap(),
+            [(39.9062, 116.3913), (39.9063, 116.3914)]
+        );
+        assert_eq!(
+            positions("1\n00:00:00,000 --> 00:00:01,000\nhello\n"),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        for input in &[TAGS, GPS] {
+            let output = convert(&converter, input).unwrap();
+            let expected: Vec<_> = positions(input)
+                .unwrap()
+                .into_iter()
+                .map(|(lat, lon)| converter.convert(lat, lon))
+                .collect();
+            assert_eq!(positions(&output).unwrap(), expected);
+            assert_eq!(output.lines().count(), input.lines().count());
+        }
+        let output = convert(&converter, TAGS).unwrap();
+        assert!(output.contains("[rel_alt: 1.300 abs_alt: 100.200] </font>\r\n\r\n2\r\n"));
+        let output = convert(&converter, GPS).unwrap();
+        assert!(output.contains("HOME(116.3900,39.9000)") && output.contains(",18) BAROMETER:1.3"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            positions("1\n[latitude: 39.9] [rel_alt: 1.0]\n\n"),
+            Err(SrtError {
+                offset: 0,
+                message: "missing coordinate"
+            })
+        );
+        assert_eq!(
+            positions("1\n[latitude: x] [longitude: 116.4]\n")
+                .unwrap_err()
+                .message,
+            "invalid coordinate"
+        );
+        assert_eq!(
+            positions("1\nGPS(116.39\n").unwrap_err().message,
+            "unterminated GPS"
+        );
+        assert_eq!(
+            convert(
+                &Converter::new(Gcj02, Wgs84),
+                "[latitude: 1e999] [longitude: 116.4]"
+            ),
+            Err(SrtError {
+                offset: 11,
+                message: "non-finite coordinate"
+            })
+        );
+    }
+}
@@ -0,0 +1,508 @@
+//! Conversion of large files in parallel with bounded memory.
+//!
+//! The input goes through three stages connected by channels: a thread reads it in chunks, a pool
+//! of threads converts them, and the calling thread writes them in their original order. Reading,
+//! converting and writing overlap, so that neither the disk nor the processors wait for the
+//! other. Chunks are read only when a buffer is free, so at most
+//! `2 * (threads + queue_len) * chunk_size` bytes are buffered and dumps far larger than the
+//! memory can be processed at the speed of the disk. Each point is converted on its own, so the
+//! output is the same, byte for byte, whatever the number of threads and the chunk size.
+//!
+//! Memory-mapping is not used: it is not available in the standard library, and its gain over
+//! large sequential reads is small for a single pass over the data.
+use crate::Converter;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// Settings of a file conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamOptions {
+    /// Number of threads converting chunks. Reading and writing take one thread each, as a stream
+    /// is read and written in order.
+    pub threads: usize,
+    /// Size of the chunks in bytes. CSV chunks are extended to the end of their last line.
+    pub chunk_size: usize,
+    /// Number of chunks, beyond one per thread, that can be read ahead of the conversion or wait
+    /// to be written.
+    pub queue_len: usize,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        StreamOptions {
+            threads,
+            chunk_size: 1 << 20,
+            queue_len: threads,
+        }
+    }
+}
+
+/// Layout of a CSV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvLayout {
+    /// Index of the latitude column.
+    pub lat: usize,
+    /// Index of the longitude column.
+    pub lon: usize,
+    pub delimiter: u8,
+    /// Whether the first line is a header, copied as is.
+    pub has_header: bool,
+}
+
+impl CsvLayout {
+    /// A layout with the coordinates in the given columns, the other settings being the defaults.
+    /// Fails with [`io::ErrorKind::InvalidInput`] if both columns are the same.
+    pub fn new(lat: usize, lon: usize) -> io::Result<Self> {
+        let layout = CsvLayout {
+            lat,
+            lon,
+            ..Default::default()
+        };
+        layout.check()?;
+        Ok(layout)
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if self.lat == self.lon {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "latitude and longitude in the same column",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for CsvLayout {
+    fn default() -> Self {
+        CsvLayout {
+            lat: 0,
+            lon: 1,
+            delimiter: b',',
+            has_header: false,
+        }
+    }
+}
+
+/// Size of a binary record: the latitude then the longitude, as little-endian `f64`.
+pub const RECORD_SIZE: usize = 16;
+
+/// Converts a file of binary records, see [`RECORD_SIZE`], returning the number of records.
+pub fn convert_binary<R: Read + Send, W: Write>(
+    converter: &Converter,
+    reader: R,
+    writer: W,
+    options: &StreamOptions,
+) -> io::Result<u64> {
+    let chunk_size = (options.chunk_size / RECORD_SIZE).max(1) * RECORD_SIZE;
+    let read_chunk = |reader: &mut R, buf: &mut Vec<u8>| -> io::Result<()> {
+        reader.take(chunk_size as u64).read_to_end(buf)?;
+        if buf.len() % RECORD_SIZE != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated record",
+            ));
+        }
+        Ok(())
+    };
+    let process = |input: &[u8], _: u64, output: &mut Vec<u8>| -> io::Result<u64> {
+        for record in input.chunks_exact(RECORD_SIZE) {
+            let (lat, lon) = record.split_at(8);
+            let lat = f64::from_le_bytes(lat.try_into().unwrap());
+            let lon = f64::from_le_bytes(lon.try_into().unwrap());
+            let (lat, lon) = match converter.convert_written(lat, lon) {
+                Ok(Some(converted)) => converted,
+                Ok(None) => (lat, lon),
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "non-finite coordinate",
+                    ))
+                }
+            };
+            output.extend_from_slice(&lat.to_le_bytes());
+            output.extend_from_slice(&lon.to_le_bytes());
+        }
+        Ok((input.len() / RECORD_SIZE) as u64)
+    };
+    run(reader, writer, options, read_chunk, process)
+}
+
+/// Converts the coordinate columns of a CSV file, returning the number of converted lines.
+///
+/// The other columns and empty lines are copied unchanged. Delimiters inside double quotes are
+/// not column boundaries, but the coordinate columns themselves must not be quoted. A layout with
+/// both coordinates in the same column fails with [`io::ErrorKind::InvalidInput`].
+pub fn convert_csv<R: BufRead + Send, W: Write>(
+    converter: &Converter,
+    mut reader: R,
+    mut writer: W,
+    layout: &CsvLayout,
+    options: &StreamOptions,
+) -> io::Result<u64> {
+    layout.check()?;
+    let mut first_line = 1;
+    if layout.has_header {
+        let mut header = Vec::new();
+        reader.read_until(b'\n', &mut header)?;
+        writer.write_all(&header)?;
+        first_line += 1;
+    }
+
+    let read_chunk = |reader: &mut R, buf: &mut Vec<u8>| -> io::Result<()> {
+        reader.take(options.chunk_size as u64).read_to_end(buf)?;
+        if !buf.is_empty() && !buf.ends_with(b"\n") {
+            reader.read_until(b'\n', buf)?;
+        }
+        Ok(())
+    };
+    let process = |input: &[u8], line: u64, output: &mut Vec<u8>| -> io::Result<u64> {
+        let mut count = 0;
+        for (i, text) in input.split_inclusive(|&b| b == b'\n').enumerate() {
+            match convert_line(converter, layout, text, output) {
+                Some(converted) => count += converted as u64,
+                None => {
+                    let line = first_line + line + i as u64;
+                    let message = format!("invalid coordinate on line {}", line);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+                }
+            }
+        }
+        Ok(count)
+    };
+    run(reader, writer, options, read_chunk, process)
+}
+
+/// Converts a line into `output`, telling whether it had coordinates, or returns `None` if they
+/// are invalid.
+fn convert_line(
+    converter: &Converter,
+    layout: &CsvLayout,
+    line: &[u8],
+    output: &mut Vec<u8>,
+) -> Option<bool> {
+    let content = line.strip_suffix(b"\n").unwrap_or(line);
+    let content = content.strip_suffix(b"\r").unwrap_or(content);
+    if content.is_empty() {
+        output.extend_from_slice(line);
+        return Some(false);
+    }
+
+    let fields = split_fields(content, layout.delimiter);
+    let number = |i: usize| -> Option<f64> {
+        let &(start, end) = fields.get(i)?;
+        std::str::from_utf8(&content[start..end])
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    let (lat, lon) = match converter.convert_written(number(layout.lat)?, number(layout.lon)?) {
+        Ok(Some(converted)) => converted,
+        Ok(None) => {
+            output.extend_from_slice(line);
+            return Some(false);
+        }
+        Err(_) => return None,
+    };
+
+    let mut copied = 0;
+    let mut columns = [(layout.lat, lat), (layout.lon, lon)];
+    columns.sort_by_key(|&(i, _)| i);
+    for &(i, value) in &columns {
+        let (start, end) = fields[i];
+        output.extend_from_slice(&line[copied..start]);
+        output.extend_from_slice(converter.precision().format(value).as_bytes());
+        copied = end;
+    }
+    output.extend_from_slice(&line[copied..]);
+    Some(true)
+}
+
+/// Byte ranges of the fields of a line.
+fn split_fields(line: &[u8], delimiter: u8) -> Vec<(usize, usize)> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, &b) in line.iter().enumerate() {
+        if b == b'"' {
+            quoted = !quoted;
+        } else if b == delimiter && !quoted {
+            fields.push((start, i));
+            start = i + 1;
+        }
+    }
+    fields.push((start, line.len()));
+    fields
+}
+
+/// A chunk going through the stages, with its position in the input.
+struct Chunk {
+    index: u64,
+    /// Number of lines in the previous chunks.
+    lines: u64,
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+/// Reads chunks with `read_chunk`, converts them concurrently with `process` and writes them.
+///
+/// `process` receives the chunk, the number of lines in the previous chunks and the output
+/// buffer, and returns the number of records converted.
+fn run<R, W, C, P>(
+    mut reader: R,
+    mut writer: W,
+    options: &StreamOptions,
+    mut read_chunk: C,
+    process: P,
+) -> io::Result<u64>
+where
+    R: Send,
+    W: Write,
+    C: FnMut(&mut R, &mut Vec<u8>) -> io::Result<()> + Send,
+    P: Fn(&[u8], u64, &mut Vec<u8>) -> io::Result<u64> + Sync,
+{
+    let threads = options.threads.max(1);
+    // The buffers circulate from the reader to the writer and back, so that they are reused and
+    // the reader waits while they are all in use.
+    let (free_sender, free) = mpsc::channel();
+    for _ in 0..threads + options.queue_len {
+        free_sender.send((Vec::new(), Vec::new())).unwrap();
+    }
+    let (work_sender, work) = mpsc::channel::<Chunk>();
+    let work = Mutex::new(work);
+    let (done_sender, done) = mpsc::channel::<(Chunk, io::Result<u64>)>();
+
+    thread::scope(|scope| {
+        // Returning early drops the channels, which stops the other stages.
+        let (free_sender, done) = (free_sender, done);
+        let read_error = done_sender.clone();
+        scope.spawn(move || {
+            let mut lines = 0;
+            for index in 0.. {
+                let (mut input, output) = match free.recv() {
+                    Ok(buffers) => buffers,
+                    Err(_) => break,
+                };
+                input.clear();
+                let result = read_chunk(&mut reader, &mut input);
+                let chunk = Chunk {
+                    index,
+                    lines,
+                    input,
+                    output,
+                };
+                match result {
+                    Err(e) => {
+                        let _ = read_error.send((chunk, Err(e)));
+                        break;
+                    }
+                    Ok(()) if chunk.input.is_empty() => break,
+                    Ok(()) => {
+                        lines += chunk.input.iter().filter(|&&b| b == b'\n').count() as u64;
+                        if work_sender.send(chunk).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        for _ in 0..threads {
+            let (work, process, done_sender) = (&work, &process, done_sender.clone());
+            scope.spawn(move || loop {
+                let received = work.lock().unwrap().recv();
+                let mut chunk = match received {
+                    Ok(chunk) => chunk,
+                    Err(_) => break,
+                };
+                chunk.output.clear();
+                let result = process(&chunk.input, chunk.lines, &mut chunk.output);
+                if done_sender.send((chunk, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(done_sender);
+
+        // Chunks finished ahead of their turn wait here; they hold buffers, so they are bounded.
+        let mut pending = HashMap::new();
+        let mut next = 0;
+        let mut total = 0;
+        for (chunk, result) in done {
+            pending.insert(chunk.index, (chunk, result));
+            while let Some((chunk, result)) = pending.remove(&next) {
+                total += result?;
+                writer.write_all(&chunk.output)?;
+                next += 1;
+                let _ = free_sender.send((chunk.input, chunk.output));
+            }
+        }
+        writer.flush()?;
+        Ok(total)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::format::Precision;
+    use crate::GeodeticSystem::*;
+    use crate::NonFinitePolicy;
+
+    const SMALL: StreamOptions = StreamOptions {
+        threads: 3,
+        chunk_size: 40,
+        queue_len: 1,
+    };
+
+    #[test]
+    fn test_binary() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let points: Vec<(f64, f64)> = (0..20).map(|i| (30.0 + i as f64, 110.0)).collect();
+        let mut input = Vec::new();
+        for &(lat, lon) in &points {
+            input.extend_from_slice(&lat.to_le_bytes());
+            input.extend_from_slice(&lon.to_le_bytes());
+        }
+
+        let mut output = Vec::new();
+        let count = convert_binary(&converter, &input[..], &mut output, &SMALL).unwrap();
+        assert_eq!(count, 20);
+        for (record, &(lat, lon)) in output.chunks(RECORD_SIZE).zip(&points) {
+            let expected = converter.convert(lat, lon);
+            assert_eq!(&record[..8], &expected.0.to_le_bytes());
+            assert_eq!(&record[8..], &expected.1.to_le_bytes());
+        }
+
+        let result = convert_binary(&converter, &input[..20], &mut Vec::new(), &SMALL);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        // A failing writer stops the other stages, with chunks still to read.
+        let mut full = [0; RECORD_SIZE * 3];
+        let result = convert_binary(&converter, &input[..], &mut full[..], &SMALL);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let converter = Converter::new(Bd09, Wgs84);
+        let points: Vec<(f64, f64)> = (0..200)
+            .map(|i| (20.0 + i as f64 * 0.17, 75.0 + i as f64 * 0.31))
+            .collect();
+        let mut binary = Vec::new();
+        let mut csv = String::new();
+        for &(lat, lon) in &points {
+            binary.extend_from_slice(&lat.to_le_bytes());
+            binary.extend_from_slice(&lon.to_le_bytes());
+            csv += &format!("{},{}\n", lat, lon);
+        }
+
+        let run = |options: &StreamOptions| {
+            let mut binary_output = Vec::new();
+            convert_binary(&converter, &binary[..], &mut binary_output, options).unwrap();
+            let mut csv_output = Vec::new();
+            let layout = CsvLayout::default();
+            convert_csv(
+                &converter,
+                csv.as_bytes(),
+                &mut csv_output,
+                &layout,
+                options,
+            )
+            .unwrap();
+            (binary_output, csv_output)
+        };
+        let expected = run(&StreamOptions {
+            threads: 1,
+            chunk_size: 1 << 20,
+            queue_len: 0,
+        });
+        for &threads in &[1, 2, 5] {
+            for &chunk_size in &[16, 40, 333] {
+                for &queue_len in &[0, 3] {
+                    let options = StreamOptions {
+                        threads,
+                        chunk_size,
+                        queue_len,
+                    };
+                    assert!(run(&options) == expected, "{:?}", options);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let converter = Converter::new(Wgs84, Gcj02);
+        let layout = CsvLayout {
+            lat: 2,
+            lon: 1,
+            delimiter: b',',
+            has_header: true,
+        };
+        let mut input = String::from("id,lon,lat,name\n");
+        let mut expected = input.clone();
+        for i in 0..30 {
+            let (lat, lon) = (30.0 + i as f64 * 0.1, 116.0);
+            input += &format!("{},{},{},\"a, b\"\r\n", i, lon, lat);
+            let (lat, lon) = converter.convert(lat, lon);
+            expected += &format!("{},{},{},\"a, b\"\r\n", i, lon, lat);
+        }
+        input += "\n";
+        expected += "\n";
+
+        let mut output = Vec::new();
+        let count =
+            convert_csv(&converter, input.as_bytes(), &mut output, &layout, &SMALL).unwrap();
+        assert_eq!(count, 30);
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let input = "id,lon,lat\n1,116,39\n2,116,x\n";
+        let error = convert_csv(&converter, input.as_bytes(), Vec::new(), &layout, &SMALL);
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "invalid coordinate on line 3"
+        );
+
+        let input = "id,lon,lat\n1,116,NaN\n";
+        let propagate = converter.with_non_finite(NonFinitePolicy::Propagate);
+        let mut output = Vec::new();
+        let count = convert_csv(&propagate, input.as_bytes(), &mut output, &layout, &SMALL);
+        assert_eq!(count.unwrap(), 0);
+        assert_eq!(output, input.as_bytes());
+
+        let input = "id,lon,lat\n1,116.4074,39.9042\n";
+        let precision = Precision::Decimals(6);
+        let rounded = Converter::new(Wgs84, Gcj02).with_precision(precision);
+        let mut output = Vec::new();
+        convert_csv(&rounded, input.as_bytes(), &mut output, &layout, &SMALL).unwrap();
+        let (lat, lon) = rounded.convert(39.9042, 116.4074);
+        let expected = format!(
+            "id,lon,lat\n1,{},{}\n",
+            precision.format(lon),
+            precision.format(lat)
+        );
+        assert!(expected.len() < input.len() + 6);
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_csv_layout() {
+        assert_eq!(CsvLayout::new(2, 1).unwrap().lon, 1);
+        let error = CsvLayout::new(1, 1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let converter = Converter::new(Wgs84, Gcj02);
+        let layout = CsvLayout {
+            lon: 0,
+            ..Default::default()
+        };
+        let result = convert_csv(&converter, &b"39,116\n"[..], Vec::new(), &layout, &SMALL);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,247 @@
+//! Location History of Google Takeout.
+//!
+//! Google records positions in China on GCJ-02, and exports them as they are, so that tracks drift
+//! from WGS-84 maps by hundreds of meters there. Both kinds of files of the export are supported:
+//! `Records.json`, the raw `locations`, and the monthly files of Semantic Location History, the
+//! `timelineObjects` with their visits, candidate places, waypoints and raw paths.
+//!
+//! Positions are pairs of integers in ten-millionths of a degree, such as `latitudeE7` and
+//! `longitudeE7`. Only their numbers are rewritten: timestamps, activities and the formatting
+//! of the file are kept byte for byte. Points outside China are left as they are by the
+//! conversion from GCJ-02:
+//!
+//! ```
+//! use undrift_gps::{takeout, Converter, GeodeticSystem::*};
+//!
+//! let records = r#"{"locations": [
+//!     {"latitudeE7": 399042000, "longitudeE7": 1164074000, "timestamp": "2020-01-01T08:00:00Z"},
+//!     {"latitudeE7": 488566000, "longitudeE7": 23522000, "timestamp": "2020-02-01T08:00:00Z"}
+//! ]}"#;
+//! let fixed = takeout::convert(&Converter::new(Gcj02, Wgs84), records).unwrap();
+//! assert!(fixed.contains(r#""latitudeE7": 488566000, "longitudeE7": 23522000"#));
+//! ```
+use crate::json::{JsonError, Value};
+use crate::xml::apply_edits;
+use crate::Converter;
+use std::fmt;
+use std::ops::Range;
+
+/// Error returned for a file that is not a readable Location History.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TakeoutError {
+    Json(JsonError),
+    /// Valid JSON that is not a Location History.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for TakeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TakeoutError::Json(e) => write!(f, "{}", e),
+            TakeoutError::Invalid(message) => write!(f, "invalid Location History: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TakeoutError {}
+
+impl From<JsonError> for TakeoutError {
+    fn from(e: JsonError) -> Self {
+        TakeoutError::Json(e)
+    }
+}
+
+/// Keys of the latitudes and longitudes of the files, in E7.
+const PAIRS: [(&str, &str); 3] = [
+    ("latitudeE7", "longitudeE7"),
+    ("latE7", "lngE7"),
+    ("centerLatE7", "centerLngE7"),
+];
+
+/// A latitude and longitude in E7, with the ranges of their text.
+type Pair = ((i64, Range<usize>), (i64, Range<usize>));
+
+/// Walks a document, tracking the index of the next number in the span list.
+struct Walker<'a, F> {
+    spans: &'a [Range<usize>],
+    next: usize,
+    f: F,
+}
+
+impl<'a, F: FnMut(Pair)> Walker<'a, F> {
+    fn walk(&mut self, value: &Value) -> Result<(), TakeoutError> {
+        match value {
+            Value::Number(_) => self.next += 1,
+            Value::Array(items) => {
+                for item in items {
+                    self.walk(item)?;
+                }
+            }
+            Value::Object(members) => {
+                // Text and index in the span list of each number of the object itself.
+                let mut numbers = Vec::new();
+                for (key, value) in members {
+                    if let Value::Number(text) = value {
+                        numbers.push((key.as_str(), text.as_str(), self.next));
+                    }
+                    self.walk(value)?;
+                }
+                let spans = self.spans;
+                let e7 = |name: &str| {
+                    let &(_, text, index) = numbers.iter().find(|(key, _, _)| *key == name)?;
+                    Some(
+                        text.parse::<i64>()
+                            .map(|e7| (e7, spans[index].clone()))
+                            .map_err(|_| TakeoutError::Invalid("coordinate not in E7")),
+                    )
+                };
+                for &(lat, lon) in &PAIRS {
+                    if let (Some(lat), Some(lon)) = (e7(lat), e7(lon)) {
+                        (self.f)((lat?, lon?));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Calls `f` with every position of a Location History, in order.
+fn for_each_pair(input: &str, f: impl FnMut(Pair)) -> Result<(), TakeoutError> {
+    let (document, spans) = Value::parse_with_spans(input)?;
+    if document.get("locations").is_none() && document.get("timelineObjects").is_none() {
+        return Err(TakeoutError::Invalid("no locations or timelineObjects"));
+    }
+    Walker {
+        spans: &spans,
+        next: 0,
+        f,
+    }
+    .walk(&document)
+}
+
+/// Reads the positions of a Location History as (latitude, longitude) pairs in degrees. The
+/// locations of `Records.json` come in order.
+pub fn positions(input: &str) -> Result<Vec<(f64, f64)>, TakeoutError> {
+    let mut positions = Vec::new();
+    for_each_pair(input, |((lat, _), (lon, _))| {
+        positions.push((lat as f64 / 1e7, lon as f64 / 1e7));
+    })?;
+    Ok(positions)
+}
+
+/// Converts the positions of a Location History, written back in E7.
+pub fn convert(converter: &Converter, input: &str) -> Result<String, TakeoutError> {
+    let mut edits = Vec::new();
+    for_each_pair(input, |((lat, lat_span), (lon, lon_span))| {
+        let (lat_out, lon_out) = converter.convert(lat as f64 / 1e7, lon as f64 / 1e7);
+        let e7 = |x: f64| format!("{}", (x * 1e7).round() as i64);
+        if (lat_out, lon_out) != (lat as f64 / 1e7, lon as f64 / 1e7) {
+            edits.push((lat_span, e7(lat_out)));
+            edits.push((lon_span, e7(lon_out)));
+        }
+    })?;
+    Ok(apply_edits(input, edits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    const RECORDS: &str = r#"{
+  "locations": [{
+    "latitudeE7": 399042000,
+    "longitudeE7": 1164074000,
+    "accuracy": 16,
+    "activity": [{
+      "activity": [{"type": "STILL", "confidence": 100}],
+      "timestamp": "2020-01-01T08:00:01.123Z"
+    }],
+    "source": "WIFI",
+    "timestamp": "2020-01-01T08:00:00.000Z"
+  }, {
+    "latitudeE7": -338688000,
+    "longitudeE7": 1512093000,
+    "timestamp": "2020-02-01T08:00:00.000Z"
+  }]
+}"#;
+
+    const SEMANTIC: &str = r#"{"timelineObjects": [
+  {"activitySegment": {
+    "startLocation": {"latitudeE7": 399042000, "longitudeE7": 1164074000},
+    "endLocation": {"latitudeE7": 399100000, "longitudeE7": 1164100000},
+    "duration": {"startTimestamp": "2020-01-01T08:00:00Z", "endTimestamp": "2020-01-01T08:30:00Z"},
+    "distance": 1200,
+    "activityType": "WALKING",
+    "waypointPath": {"waypoints": [{"latE7": 399050000, "lngE7": 1164080000}]},
+    "simplifiedRawPath": {"points": [
+      {"latE7": 399060000, "lngE7": 1164090000, "accuracyMeters": 10,
+       "timestamp": "2020-01-01T08:10:00Z"}
+    ]}
+  }},
+  {"placeVisit": {
+    "location": {"latitudeE7": 399100000, "longitudeE7": 1164100000, "placeId": "ChIJ", "name": "Office"},
+    "otherCandidateLocations": [{"latitudeE7": 399110000, "longitudeE7": 1164110000}],
+    "centerLatE7": 399105000,
+    "centerLngE7": 1164105000
+  }}
+]}"#;
+
+    #[test]
+    fn test_positions() {
+        assert_eq!(
+            positions(RECORDS).unwrap(),
+            [(39.9042, 116.4074), (-33.8688, 151.2093)]
+        );
+        let semantic = positions(SEMANTIC).unwrap();
+        assert_eq!(semantic.len(), 7);
+        assert_eq!(semantic[2], (39.905, 116.408));
+        assert_eq!(semantic[6], (39.9105, 116.4105));
+    }
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        for input in &[RECORDS, SEMANTIC] {
+            let output = convert(&converter, input).unwrap();
+            for (original, converted) in positions(input)
+                .unwrap()
+                .into_iter()
+                .zip(positions(&output).unwrap())
+            {
+                let (lat, lon) = converter.convert(original.0, original.1);
+                assert!((converted.0 - lat).abs() <= 0.5e-7 && (converted.1 - lon).abs() <= 0.5e-7);
+            }
+        }
+
+        // Everything but the positions in China is kept as is.
+        let output = convert(&converter, RECORDS).unwrap();
+        assert_eq!(output.len(), RECORDS.len());
+        assert!(output.contains(r#""activity": [{"type": "STILL", "confidence": 100}],"#));
+        assert!(output.contains(r#""timestamp": "2020-01-01T08:00:00.000Z""#));
+        assert!(output.contains(r#""latitudeE7": -338688000,"#));
+        assert!(!output.contains("399042000"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        assert_eq!(
+            convert(&converter, r#"{"type": "FeatureCollection"}"#),
+            Err(TakeoutError::Invalid("no locations or timelineObjects"))
+        );
+        assert_eq!(
+            convert(
+                &converter,
+                r#"{"locations": [{"latitudeE7": 39.9, "longitudeE7": 1164074000}]}"#
+            ),
+            Err(TakeoutError::Invalid("coordinate not in E7"))
+        );
+        assert!(matches!(
+            convert(&converter, r#"{"locations": ["#),
+            Err(TakeoutError::Json(_))
+        ));
+    }
+}
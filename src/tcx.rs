@@ -0,0 +1,213 @@
+//! Garmin Training Center (TCX) activity and course files.
+//!
+//! Only the `<LatitudeDegrees>` and `<LongitudeDegrees>` of each `<Position>` are rewritten; heart
+//! rate, cadence, laps and everything else are kept byte for byte.
+use crate::track::{parse_time, Track, TrackPoint};
+use crate::xml::{apply_edits, convert_pair, local_name, trimmed, Event, Reader, XmlError};
+use crate::Converter;
+use std::ops::Range;
+
+/// A parsed `<Position>`: the latitude and longitude with the ranges of their text.
+struct Position {
+    lat: (f64, Range<usize>),
+    lon: (f64, Range<usize>),
+}
+
+/// Calls `f` on every position of the document, in order.
+fn for_each_position<F>(input: &str, mut f: F) -> Result<(), XmlError>
+where
+    F: FnMut(Position),
+{
+    let mut in_position = None;
+    let mut field = None;
+    let (mut lat, mut lon) = (None, None);
+    for event in Reader::new(input) {
+        let (event, range) = event?;
+        match event {
+            Event::Start { name, .. } => match local_name(name) {
+                "Position" => {
+                    in_position = Some(range.start);
+                    lat = None;
+                    lon = None;
+                }
+                "LatitudeDegrees" | "LongitudeDegrees" if in_position.is_some() => {
+                    field = Some(local_name(name) == "LatitudeDegrees")
+                }
+                _ => {}
+            },
+            Event::Text(text) => {
+                if let Some(is_lat) = field {
+                    let value = text.trim().parse().map_err(|_| XmlError {
+                        offset: range.start,
+                        message: "invalid coordinate",
+                    })?;
+                    let value = (value, trimmed(text, range));
+                    if is_lat {
+                        lat = Some(value);
+                    } else {
+                        lon = Some(value);
+                    }
+                }
+            }
+            Event::End { name } => match local_name(name) {
+                "LatitudeDegrees" | "LongitudeDegrees" => field = None,
+                "Position" => {
+                    let start = in_position.take().unwrap_or(range.start);
+                    match (lat.take(), lon.take()) {
+                        (Some(lat), Some(lon)) => f(Position { lat, lon }),
+                        _ => {
+                            return Err(XmlError {
+                                offset: start,
+                                message: "incomplete position",
+                            })
+                        }
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reads the positions of a TCX document, in order.
+pub fn positions(input: &str) -> Result<Vec<(f64, f64)>, XmlError> {
+    let mut positions = Vec::new();
+    for_each_position(input, |p| positions.push((p.lat.0, p.lon.0)))?;
+    Ok(positions)
+}
+
+/// Reads the trackpoints of a TCX document with their altitudes and times, in order, skipping
+/// those without a position.
+///
+/// The laps and activities of the document are concatenated.
+pub fn track(input: &str) -> Result<Track, XmlError> {
+    let mut points = Vec::new();
+    let mut point = TrackPoint::new(f64::NAN, f64::NAN);
+    let mut field = None;
+    for event in Reader::new(input) {
+        let (event, range) = event?;
+        match event {
+            Event::Start { name, .. } => match local_name(name) {
+                "Trackpoint" => point = TrackPoint::new(f64::NAN, f64::NAN),
+                name @ ("Time" | "AltitudeMeters" | "LatitudeDegrees" | "LongitudeDegrees") => {
+                    field = Some(name)
+                }
+                _ => {}
+            },
+            Event::Text(text) => {
+                let invalid = |message| XmlError {
+                    offset: range.start,
+                    message,
+                };
+                let number = |message| text.trim().parse().map_err(|_| invalid(message));
+                match field {
+                    Some("Time") => {
+                        point.time = Some(parse_time(text).ok_or(invalid("invalid time"))?)
+                    }
+                    Some("AltitudeMeters") => point.elevation = Some(number("invalid altitude")?),
+                    Some("LatitudeDegrees") => point.lat = number("invalid coordinate")?,
+                    Some(_) => point.lon = number("invalid coordinate")?,
+                    None => {}
+                }
+            }
+            Event::End { name } => match local_name(name) {
+                "Time" | "AltitudeMeters" | "LatitudeDegrees" | "LongitudeDegrees" => field = None,
+                "Trackpoint" if !point.lat.is_nan() && !point.lon.is_nan() => points.push(point),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    Ok(Track { points })
+}
+
+/// Converts the positions of a TCX document.
+pub fn convert(converter: &Converter, input: &str) -> Result<String, XmlError> {
+    let mut edits = Vec::new();
+    let mut result = Ok(());
+    for_each_position(input, |p| {
+        if result.is_ok() {
+            result = convert_pair(converter, &p.lat, &p.lon, &mut edits);
+        }
+    })?;
+    result?;
+    Ok(apply_edits(input, edits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    const ACTIVITY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities>
+    <Activity Sport="Running">
+      <Id>2020-01-01T00:00:00Z</Id>
+      <Lap StartTime="2020-01-01T00:00:00Z">
+        <TotalTimeSeconds>60.0</TotalTimeSeconds>
+        <Track>
+          <Trackpoint>
+            <Time>2020-01-01T00:00:00Z</Time>
+            <Position>
+              <LatitudeDegrees> 39.9042 </LatitudeDegrees>
+              <LongitudeDegrees>116.4074</LongitudeDegrees>
+            </Position>
+            <AltitudeMeters>44.5</AltitudeMeters>
+            <HeartRateBpm><Value>140</Value></HeartRateBpm>
+            <Cadence>85</Cadence>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2020-01-01T00:00:01Z</Time>
+            <HeartRateBpm><Value>141</Value></HeartRateBpm>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>
+"#;
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = convert(&converter, ACTIVITY).unwrap();
+        let (lat, lon) = converter.convert(39.9042, 116.4074);
+        assert_eq!(positions(&output).unwrap(), [(lat, lon)]);
+        assert!(output.contains(&format!("<LatitudeDegrees> {} </LatitudeDegrees>", lat)));
+
+        // Only the coordinates change.
+        fn unchanged(s: &str) -> Vec<&str> {
+            s.lines().filter(|l| !l.contains("Degrees")).collect()
+        }
+        assert_eq!(unchanged(&output), unchanged(ACTIVITY));
+    }
+
+    #[test]
+    fn test_track() {
+        let points = track(ACTIVITY).unwrap().points;
+        assert_eq!(points.len(), 1);
+        assert_eq!((points[0].lat, points[0].lon), (39.9042, 116.4074));
+        assert_eq!(points[0].elevation, Some(44.5));
+        assert_eq!(
+            points[0].time,
+            crate::track::parse_time("2020-01-01T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let incomplete = "<Position><LatitudeDegrees>1</LatitudeDegrees></Position>";
+        assert_eq!(
+            positions(incomplete).unwrap_err().message,
+            "incomplete position"
+        );
+        let invalid = "<Position><LatitudeDegrees>x</LatitudeDegrees></Position>";
+        assert_eq!(
+            positions(invalid).unwrap_err().message,
+            "invalid coordinate"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! TopoJSON topologies.
+//!
+//! Each arc is converted once, so boundaries shared between features stay shared. Quantized
+//! topologies are re-quantized with their original `transform`, keeping the file size and the
+//! precision of the input. Point and MultiPoint geometries are converted as well, and a top-level
+//! `bbox` is recomputed from the converted positions. Everything else, including properties and
+//! the arc references of the objects, is kept as is.
+use crate::format::Precision;
+use crate::json::{JsonError, Value};
+use crate::Converter;
+use std::fmt;
+
+/// Error returned for an invalid topology.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopoJsonError {
+    Json(JsonError),
+    /// Valid JSON that is not a usable topology.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for TopoJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TopoJsonError::Json(e) => write!(f, "{}", e),
+            TopoJsonError::Invalid(message) => write!(f, "invalid TopoJSON: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TopoJsonError {}
+
+impl From<JsonError> for TopoJsonError {
+    fn from(e: JsonError) -> Self {
+        TopoJsonError::Json(e)
+    }
+}
+
+/// The quantization of a topology.
+#[derive(Debug, Clone, Copy)]
+struct Quantization {
+    scale: [f64; 2],
+    translate: [f64; 2],
+}
+
+impl Quantization {
+    fn decode(&self, q: [f64; 2]) -> [f64; 2] {
+        [
+            q[0] * self.scale[0] + self.translate[0],
+            q[1] * self.scale[1] + self.translate[1],
+        ]
+    }
+
+    fn encode(&self, p: [f64; 2]) -> [f64; 2] {
+        [
+            ((p[0] - self.translate[0]) / self.scale[0]).round(),
+            ((p[1] - self.translate[1]) / self.scale[1]).round(),
+        ]
+    }
+}
+
+/// The first two numbers of an array, i.e. the x (longitude) and y (latitude) of a position.
+fn position(value: &Value) -> Option<[f64; 2]> {
+    match value {
+        Value::Array(items) if items.len() >= 2 => Some([items[0].as_f64()?, items[1].as_f64()?]),
+        _ => None,
+    }
+}
+
+/// Replaces the x and y of a position, keeping any further dimensions. Quantized positions, of
+/// no `precision`, are written as integers.
+fn set_position(value: &mut Value, p: [f64; 2], precision: Option<Precision>) {
+    let number = |x: f64| match precision {
+        Some(precision) => Value::Number(precision.format(x)),
+        None => Value::Number(format!("{}", x as i64)),
+    };
+    if let Some(items) = value.as_array_mut() {
+        items[0] = number(p[0]);
+        items[1] = number(p[1]);
+    }
+}
+
+fn invalid(message: &'static str) -> TopoJsonError {
+    TopoJsonError::Invalid(message)
+}
+
+fn parse_topology(input: &str) -> Result<(Value, Option<Quantization>), TopoJsonError> {
+    let topology = Value::parse(input)?;
+    if topology.get("type").and_then(Value::as_str) != Some("Topology") {
+        return Err(invalid("not a topology"));
+    }
+    let quantization = match topology.get("transform") {
+        None => None,
+        Some(transform) => {
+            let scale = transform.get("scale").and_then(position);
+            let translate = transform.get("translate").and_then(position);
+            match (scale, translate) {
+                (Some(scale), Some(translate)) if scale[0] != 0.0 && scale[1] != 0.0 => {
+                    Some(Quantization { scale, translate })
+                }
+                _ => return Err(invalid("invalid transform")),
+            }
+        }
+    };
+    Ok((topology, quantization))
+}
+
+/// Absolute positions of an arc, undoing the delta encoding of quantized topologies.
+fn decode_arc(
+    arc: &Value,
+    quantization: Option<Quantization>,
+) -> Result<Vec<[f64; 2]>, TopoJsonError> {
+    let items = match arc {
+        Value::Array(items) => items,
+        _ => return Err(invalid("invalid arc")),
+    };
+    let mut q = [0.0, 0.0];
+    items
+        .iter()
+        .map(|item| {
+            let p = position(item).ok_or_else(|| invalid("invalid position"))?;
+            Ok(match quantization {
+                Some(quantization) => {
+                    q = [q[0] + p[0], q[1] + p[1]];
+                    quantization.decode(q)
+                }
+                None => p,
+            })
+        })
+        .collect()
+}
+
+/// Reads the arcs of a topology as (latitude, longitude) pairs.
+pub fn arcs(input: &str) -> Result<Vec<Vec<(f64, f64)>>, TopoJsonError> {
+    let (topology, quantization) = parse_topology(input)?;
+    let arcs = match topology.get("arcs") {
+        Some(Value::Array(arcs)) => arcs,
+        _ => return Err(invalid("missing arcs")),
+    };
+    arcs.iter()
+        .map(|arc| {
+            let positions = decode_arc(arc, quantization)?;
+            Ok(positions.iter().map(|p| (p[1], p[0])).collect())
+        })
+        .collect()
+}
+
+/// Converts the points of a geometry object and its children, collecting the results.
+fn convert_geometry(
+    converter: &Converter,
+    geometry: &mut Value,
+    quantization: Option<Quantization>,
+    converted: &mut Vec<[f64; 2]>,
+) -> Result<(), TopoJsonError> {
+    let mut convert_position = |value: &mut Value| {
+        let p = position(value).ok_or_else(|| invalid("invalid position"))?;
+        let p = quantization.map_or(p, |quantization| quantization.decode(p));
+        let (lat, lon) = match converter.convert_written(p[1], p[0]) {
+            Ok(Some(converted)) => converted,
+            Ok(None) => return Ok(()),
+            Err(_) => return Err(invalid("non-finite coordinate")),
+        };
+        converted.push([lon, lat]);
+        let p = quantization.map_or([lon, lat], |quantization| quantization.encode([lon, lat]));
+        let precision = match quantization {
+            Some(_) => None,
+            None => Some(converter.precision()),
+        };
+        set_position(value, p, precision);
+        Ok::<_, TopoJsonError>(())
+    };
+    match geometry.get("type").and_then(Value::as_str) {
+        Some("Point") => {
+            if let Some(coordinates) = geometry.get_mut("coordinates") {
+                convert_position(coordinates)?;
+            }
+        }
+        Some("MultiPoint") => {
+            if let Some(positions) = geometry
+                .get_mut("coordinates")
+                .and_then(Value::as_array_mut)
+            {
+                for p in positions {
+                    convert_position(p)?;
+                }
+            }
+        }
+        Some("GeometryCollection") => {
+            if let Some(geometries) = geometry.get_mut("geometries").and_then(Value::as_array_mut) {
+                for geometry in geometries {
+                    convert_geometry(converter, geometry, quantization, converted)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Converts the arcs and points of a topology.
+pub fn convert(converter: &Converter, input: &str) -> Result<String, TopoJsonError> {
+    let (mut topology, quantization) = parse_topology(input)?;
+    let mut converted = Vec::new();
+
+    let arcs = topology
+        .get_mut("arcs")
+        .and_then(Value::as_array_mut)
+        .ok_or_else(|| invalid("missing arcs"))?;
+    for arc in arcs.iter_mut() {
+        let positions = decode_arc(arc, quantization)?;
+        let mut previous = [0.0, 0.0];
+        // decode_arc checked that every item is a position.
+        for (item, p) in arc.as_array_mut().unwrap().iter_mut().zip(positions) {
+            let (lat, lon) = match converter.convert_written(p[1], p[0]) {
+                Ok(Some(converted)) => converted,
+                // Quantized positions are always finite.
+                Ok(None) => continue,
+                Err(_) => return Err(invalid("non-finite coordinate")),
+            };
+            converted.push([lon, lat]);
+            match quantization {
+                Some(quantization) => {
+                    let q = quantization.encode([lon, lat]);
+                    set_position(item, [q[0] - previous[0], q[1] - previous[1]], None);
+                    previous = q;
+                }
+                None => set_position(item, [lon, lat], Some(converter.precision())),
+            }
+        }
+    }
+
+    if let Some(Value::Object(objects)) = topology.get_mut("objects") {
+        for (_, object) in objects.iter_mut() {
+            convert_geometry(converter, object, quantization, &mut converted)?;
+        }
+    }
+
+    if let (Some(Value::Array(bbox)), false) = (topology.get_mut("bbox"), converted.is_empty()) {
+        if bbox.len() == 4 {
+            let mut bounds = [
+                f64::INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::NEG_INFINITY,
+            ];
+            for p in &converted {
+                bounds = [
+                    bounds[0].min(p[0]),
+                    bounds[1].min(p[1]),
+                    bounds[2].max(p[0]),
+                    bounds[3].max(p[1]),
+                ];
+            }
+            *bbox = bounds
+                .iter()
+                .map(|&x| Value::Number(converter.precision().format(x)))
+                .collect();
+        }
+    }
+    Ok(topology.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    // Two arcs meeting at (116.41, 39.91), bounding a polygon, and a point.
+    const QUANTIZED: &str = r#"{
+        "type": "Topology",
+        "bbox": [116.4, 39.9, 116.42, 39.92],
+        "transform": {"scale": [0.0001, 0.0001], "translate": [116.4, 39.9]},
+        "objects": {
+            "district": {"type": "Polygon", "arcs": [[0, 1]], "properties": {"name": "a"}},
+            "office": {"type": "Point", "coordinates": [150, 50]}
+        },
+        "arcs": [[[0, 0], [100, 0], [0, 100]], [[100, 100], [-100, 100], [0, -200]]]
+    }"#;
+
+    #[test]
+    fn test_quantized() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = convert(&converter, QUANTIZED).unwrap();
+        let (input_arcs, output_arcs) = (arcs(QUANTIZED).unwrap(), arcs(&output).unwrap());
+        assert_eq!(input_arcs[0][2], input_arcs[1][0]);
+        for (input, output) in input_arcs
+            .iter()
+            .flatten()
+            .zip(output_arcs.iter().flatten())
+        {
+            let (lat, lon) = converter.convert(input.0, input.1);
+            assert!((output.0 - lat).abs() <= 0.00005 && (output.1 - lon).abs() <= 0.00005);
+        }
+        // The shared vertex is still shared.
+        assert_eq!(output_arcs[0][2], output_arcs[1][0]);
+
+        // Positions stay integral, and the objects keep their arc references.
+        let topology = Value::parse(&output).unwrap();
+        let district = topology.get("objects").unwrap().get("district").unwrap();
+        assert_eq!(district.get("arcs").unwrap().to_string(), "[[0,1]]");
+        assert!(!topology.get("arcs").unwrap().to_string().contains('.'));
+
+        let office = topology.get("objects").unwrap().get("office").unwrap();
+        let (lat, lon) = converter.convert(39.905, 116.415);
+        let q = position(office.get("coordinates").unwrap()).unwrap();
+        assert_eq!(
+            q,
+            [
+                ((lon - 116.4) / 0.0001).round(),
+                ((lat - 39.9) / 0.0001).round()
+            ]
+        );
+
+        // WGS-84 lies south-west of GCJ-02 around Beijing.
+        let bbox = position(topology.get("bbox").unwrap()).unwrap();
+        assert!(bbox[0] < 116.4 && bbox[1] < 39.9);
+    }
+
+    #[test]
+    fn test_unquantized() {
+        let input =
+            r#"{"type":"Topology","objects":{},"arcs":[[[116.4,39.9,12],[116.41,39.91,13]]]}"#;
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = convert(&converter, input).unwrap();
+        let expected: Vec<_> = [(39.9, 116.4), (39.91, 116.41)]
+            .iter()
+            .map(|&(lat, lon)| converter.convert(lat, lon))
+            .collect();
+        assert_eq!(arcs(&output).unwrap(), [expected]);
+        assert!(output.contains(",12],["));
+    }
+
+    #[test]
+    fn test_errors() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let error = |input| convert(&converter, input).unwrap_err();
+        assert_eq!(
+            error(r#"{"type":"FeatureCollection"}"#),
+            TopoJsonError::Invalid("not a topology")
+        );
+        assert_eq!(
+            error(r#"{"type":"Topology","transform":{"scale":[0,1],"translate":[0,0]},"arcs":[]}"#),
+            TopoJsonError::Invalid("invalid transform")
+        );
+        assert_eq!(
+            error(r#"{"type":"Topology","arcs":[[[1]]]}"#),
+            TopoJsonError::Invalid("invalid position")
+        );
+        assert!(matches!(error("{"), TopoJsonError::Json(_)));
+    }
+}
@@ -0,0 +1,340 @@
+//! Timestamped tracks, as read from the GPX, TCX and FIT files.
+use crate::simplify::{simplify_indices, Simplification};
+use crate::{BoundingBox, Converter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A point of a track.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPoint {
+    pub lat: f64,
+    pub lon: f64,
+    /// Elevation in meters, if recorded.
+    pub elevation: Option<f64>,
+    /// Time of the fix, if recorded.
+    pub time: Option<SystemTime>,
+}
+
+impl TrackPoint {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        TrackPoint {
+            lat,
+            lon,
+            elevation: None,
+            time: None,
+        }
+    }
+}
+
+/// A sequence of points in recording order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    pub points: Vec<TrackPoint>,
+}
+
+impl Track {
+    /// Converts the positions, keeping elevations and times.
+    ///
+    /// Each inversion starts from the offset of the previous point, which barely changes along a
+    /// track.
+    pub fn convert(&self, converter: &Converter) -> Track {
+        let mut warm = None;
+        let points = self
+            .points
+            .iter()
+            .map(|p| {
+                let ((lat, lon), _) = converter.convert_warm(p.lat, p.lon, &mut warm);
+                TrackPoint { lat, lon, ..*p }
+            })
+            .collect();
+        Track { points }
+    }
+
+    /// Keeps the points of a simplification within `tolerance` meters, with their elevations
+    /// and times.
+    pub fn simplify(&self, tolerance: f64, method: Simplification) -> Track {
+        let positions: Vec<_> = self.points.iter().map(|p| (p.lat, p.lon)).collect();
+        let points = simplify_indices(&positions, tolerance, method)
+            .into_iter()
+            .map(|i| self.points[i])
+            .collect();
+        Track { points }
+    }
+
+    /// Splits the track where consecutive points are more than `max_gap` apart in time.
+    ///
+    /// Points without a time never start a new segment, and neither do points going back in time.
+    pub fn split(&self, max_gap: Duration) -> Vec<Track> {
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut last_time = None;
+        for point in &self.points {
+            let gap = match (last_time, point.time) {
+                (Some(last), Some(time)) => time.duration_since(last).is_ok_and(|d| d > max_gap),
+                _ => false,
+            };
+            match tracks.last_mut() {
+                Some(track) if !gap => track.points.push(*point),
+                _ => tracks.push(Track {
+                    points: vec![*point],
+                }),
+            }
+            last_time = point.time.or(last_time);
+        }
+        tracks
+    }
+
+    /// The smallest box containing every point, unless the track is empty.
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        let first = self.points.first()?;
+        let init = BoundingBox::new(first.lat, first.lon, first.lat, first.lon);
+        Some(self.points.iter().fold(init, |b, p| {
+            BoundingBox::new(
+                b.min_lat.min(p.lat),
+                b.min_lon.min(p.lon),
+                b.max_lat.max(p.lat),
+                b.max_lon.max(p.lon),
+            )
+        }))
+    }
+
+    /// Time between the first and the last timed points.
+    pub fn duration(&self) -> Option<Duration> {
+        let mut times = self.points.iter().filter_map(|p| p.time);
+        let first = times.next()?;
+        times
+            .next_back()
+            .unwrap_or(first)
+            .duration_since(first)
+            .ok()
+    }
+}
+
+impl From<Vec<TrackPoint>> for Track {
+    fn from(points: Vec<TrackPoint>) -> Self {
+        Track { points }
+    }
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Date of the proleptic Gregorian calendar `days` after 1970-01-01, inverting
+/// [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Formats a time in UTC as ISO 8601, such as `2020-01-01T00:00:00.5Z`, which [`parse_time`]
+/// reads back.
+pub(crate) fn format_time(time: SystemTime) -> String {
+    let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                nanos => (-(d.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    };
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let second = seconds.rem_euclid(86_400);
+    let mut text = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        second / 3600,
+        second / 60 % 60,
+        second % 60
+    );
+    if nanos != 0 {
+        let fraction = format!("{:09}", nanos);
+        text.push('.');
+        text.push_str(fraction.trim_end_matches('0'));
+    }
+    text.push('Z');
+    text
+}
+
+/// Parses an ISO 8601 date and time such as `2020-01-01T08:00:00.5+08:00`, as used by GPX and TCX.
+///
+/// A missing time zone is taken as UTC.
+pub(crate) fn parse_time(s: &str) -> Option<SystemTime> {
+    let s = s.trim();
+    let b = s.as_bytes();
+    if !s.is_ascii() || b.len() < 19 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' {
+        return None;
+    }
+    if !matches!(b[10], b'T' | b' ') || b[16] != b':' {
+        return None;
+    }
+    let number = |text: &str| -> Option<i64> {
+        if text.bytes().all(|c| c.is_ascii_digit()) {
+            text.parse().ok()
+        } else {
+            None
+        }
+    };
+    let (year, month, day) = (number(&s[0..4])?, number(&s[5..7])?, number(&s[8..10])?);
+    let (hour, minute, second) = (
+        number(&s[11..13])?,
+        number(&s[14..16])?,
+        number(&s[17..19])?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    if second > 60 {
+        return None;
+    }
+
+    let mut rest = &s[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        nanos = format!("{:0<9}", &fraction[..digits.min(9)]).parse().ok()?;
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "" | "Z" => 0,
+        _ => {
+            let b = rest.as_bytes();
+            let sign = match b.first() {
+                Some(b'+') => 1,
+                Some(b'-') => -1,
+                _ => return None,
+            };
+            if b.len() != 6 || b[3] != b':' {
+                return None;
+            }
+            let (h, m) = (number(&rest[1..3])?, number(&rest[4..6])?);
+            sign * (h * 3600 + m * 60)
+        }
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    let time = if seconds >= 0 {
+        UNIX_EPOCH + Duration::from_secs(seconds as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+    };
+    Some(time + Duration::from_nanos(nanos))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    fn at(seconds: u64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    fn sample() -> Track {
+        let point = |lat, lon, time| TrackPoint {
+            elevation: Some(50.0),
+            time,
+            ..TrackPoint::new(lat, lon)
+        };
+        Track::from(vec![
+            point(39.90, 116.40, at(1000)),
+            point(39.91, 116.41, at(1010)),
+            point(39.92, 116.39, None),
+            point(39.93, 116.42, at(2000)),
+        ])
+    }
+
+    #[test]
+    fn test_convert() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let track = sample();
+        let converted = track.convert(&converter);
+        for (a, b) in track.points.iter().zip(&converted.points) {
+            let (lat, lon) = converter.convert(a.lat, a.lon);
+            assert!((b.lat - lat).abs() < 1e-6 && (b.lon - lon).abs() < 1e-6);
+            assert_eq!((a.elevation, a.time), (b.elevation, b.time));
+        }
+    }
+
+    #[test]
+    fn test_split_and_bounds() {
+        let track = sample();
+        let parts = track.split(Duration::from_secs(60));
+        assert_eq!(
+            parts.iter().map(|t| t.points.len()).collect::<Vec<_>>(),
+            [3, 1]
+        );
+        assert_eq!(track.split(Duration::from_secs(3600)).len(), 1);
+        assert_eq!(parts[0].duration(), Some(Duration::from_secs(10)));
+        assert_eq!(
+            track.bounds(),
+            Some(BoundingBox::new(39.90, 116.39, 39.93, 116.42))
+        );
+        assert_eq!(Track::default().bounds(), None);
+        assert!(Track::default().split(Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn test_simplify() {
+        let track = sample();
+        let simplified = track.simplify(10.0, Simplification::DouglasPeucker);
+        assert_eq!(simplified.points, track.points);
+        let straight = Track::from(
+            (0..10)
+                .map(|i| TrackPoint {
+                    time: at(i),
+                    ..TrackPoint::new(39.9, 116.4 + i as f64 * 0.001)
+                })
+                .collect::<Vec<_>>(),
+        );
+        let simplified = straight.simplify(1.0, Simplification::Visvalingam);
+        assert_eq!(simplified.points, [straight.points[0], straight.points[9]]);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1970-01-01T00:00:00Z"), at(0));
+        assert_eq!(parse_time("2020-01-01T00:00:00Z"), at(1_577_836_800));
+        assert_eq!(parse_time("2020-01-01T08:00:00+08:00"), at(1_577_836_800));
+        assert_eq!(
+            parse_time("2020-01-01T00:00:00.25"),
+            Some(UNIX_EPOCH + Duration::from_millis(1_577_836_800_250))
+        );
+        assert_eq!(parse_time("2020-13-01T00:00:00Z"), None);
+        assert_eq!(parse_time("2020-01-01"), None);
+        assert_eq!(parse_time("2020-01-01T00:00:00+0800"), None);
+    }
+
+    #[test]
+    fn test_format_time() {
+        for text in &[
+            "1970-01-01T00:00:00Z",
+            "2020-02-29T23:59:59.25Z",
+            "1969-12-31T23:59:59.5Z",
+            "1600-03-01T12:00:00Z",
+        ] {
+            assert_eq!(format_time(parse_time(text).unwrap()), *text);
+        }
+    }
+}
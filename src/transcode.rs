@@ -0,0 +1,371 @@
+//! Conversion from one file format to another, together with the coordinate conversion.
+//!
+//! The other modules rewrite documents in place, keeping everything but the coordinates byte for
+//! byte. Here the input is read as a [`Track`], converted, and written anew in the output format,
+//! so that, for instance, a GPX recording in GCJ-02 becomes a GeoJSON line in WGS-84 in one pass:
+//!
+//! ```
+//! use undrift_gps::transcode::{transcode, FileFormat};
+//! use undrift_gps::{Converter, GeodeticSystem::*};
+//!
+//! let gpx = r#"<gpx><trk><trkseg><trkpt lat="39.905" lon="116.405"/></trkseg></trk></gpx>"#;
+//! let converter = Converter::new(Gcj02, Wgs84);
+//! let geojson = transcode(&converter, FileFormat::Gpx, FileFormat::GeoJson, gpx.as_bytes());
+//! assert!(String::from_utf8(geojson.unwrap()).unwrap().contains("\"Point\""));
+//! ```
+//!
+//! Only the positions, elevations and times of the track make it through: names, extensions and
+//! the structure of segments are dropped. Times are read from GPX, TCX and FIT, and written to
+//! GPX, KML and GeoJSON. Gzipped inputs are decompressed; outputs can be compressed with
+//! [`crate::gzip::compress`].
+use crate::format::Precision;
+use crate::gzip;
+use crate::json::format_number;
+use crate::track::{format_time, Track, TrackPoint};
+use crate::Converter;
+use std::io;
+
+/// File formats a track can be read from, and written to unless noted otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileFormat {
+    Gpx,
+    /// Read only.
+    Tcx,
+    /// Read only.
+    Fit,
+    Kml,
+    GeoJson,
+}
+
+impl FileFormat {
+    /// The format of a file by the extension of its name, as in [`crate::zip::convert_entry`],
+    /// looking through a `.gz` extension.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = match name.len().checked_sub(3) {
+            Some(end) if name[end..].eq_ignore_ascii_case(".gz") => &name[..end],
+            _ => name,
+        };
+        let (_, extension) = name.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "gpx" => Some(FileFormat::Gpx),
+            "tcx" => Some(FileFormat::Tcx),
+            "fit" => Some(FileFormat::Fit),
+            "kml" => Some(FileFormat::Kml),
+            "geojson" => Some(FileFormat::GeoJson),
+            _ => None,
+        }
+    }
+
+    /// Whether [`write`] supports the format.
+    pub fn writable(self) -> bool {
+        !matches!(self, FileFormat::Tcx | FileFormat::Fit)
+    }
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "format cannot be written")
+}
+
+fn invalid<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Reads the points of a file as a track, decompressing it first if it is gzipped.
+///
+/// KML and GeoJSON documents give all their positions in order, with their altitudes.
+pub fn read(format: FileFormat, data: &[u8]) -> io::Result<Track> {
+    if gzip::is_gzip(data) {
+        return read(format, &gzip::decompress(data)?);
+    }
+    let text = || std::str::from_utf8(data).map_err(|_| invalid("file is not UTF-8"));
+    let from_positions = |positions: Vec<(f64, f64, Option<f64>)>| {
+        let points = positions
+            .into_iter()
+            .map(|(lat, lon, elevation)| TrackPoint {
+                elevation,
+                ..TrackPoint::new(lat, lon)
+            })
+            .collect();
+        Track { points }
+    };
+    match format {
+        FileFormat::Gpx => crate::gpx::track(text()?).map_err(invalid),
+        FileFormat::Tcx => crate::tcx::track(text()?).map_err(invalid),
+        FileFormat::Fit => crate::fit::track(data).map_err(invalid),
+        FileFormat::Kml => crate::kml::positions_3d(text()?)
+            .map(from_positions)
+            .map_err(invalid),
+        FileFormat::GeoJson => crate::geojson::positions_3d(text()?)
+            .map(from_positions)
+            .map_err(invalid),
+    }
+}
+
+fn write_gpx(track: &Track, precision: Precision) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<gpx version=\"1.1\" creator=\"undrift_gps\" ",
+        "xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+        "  <trk><trkseg>\n",
+    ));
+    for p in &track.points {
+        out += &format!(
+            "    <trkpt lat=\"{}\" lon=\"{}\">",
+            precision.format(p.lat),
+            precision.format(p.lon)
+        );
+        if let Some(elevation) = p.elevation {
+            out += &format!("<ele>{}</ele>", format_number(elevation));
+        }
+        if let Some(time) = p.time {
+            out += &format!("<time>{}</time>", format_time(time));
+        }
+        out += "</trkpt>\n";
+    }
+    out + "  </trkseg></trk>\n</gpx>\n"
+}
+
+/// A KML or GeoJSON position: longitude, latitude and the elevation if any.
+fn position(p: &TrackPoint, separator: &str, precision: Precision) -> String {
+    let mut text = precision.format(p.lon) + separator + &precision.format(p.lat);
+    if let Some(elevation) = p.elevation {
+        text += separator;
+        text += &format_number(elevation);
+    }
+    text
+}
+
+/// Writes a `<gx:Track>` when every point has a time, and a point or line otherwise.
+fn write_kml(track: &Track, precision: Precision) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<kml xmlns=\"http://www.opengis.net/kml/2.2\" ",
+        "xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n",
+        "  <Document>\n",
+    ));
+    let points = &track.points;
+    if !points.is_empty() && points.iter().all(|p| p.time.is_some()) {
+        out += "    <Placemark><gx:Track>\n";
+        for time in points.iter().filter_map(|p| p.time) {
+            out += &format!("      <when>{}</when>\n", format_time(time));
+        }
+        for p in points {
+            out += &format!(
+                "      <gx:coord>{}</gx:coord>\n",
+                position(p, " ", precision)
+            );
+        }
+        out += "    </gx:Track></Placemark>\n";
+    } else if let [p] = &points[..] {
+        out += &format!(
+            "    <Placemark><Point><coordinates>{}</coordinates></Point></Placemark>\n",
+            position(p, ",", precision)
+        );
+    } else if !points.is_empty() {
+        out += "    <Placemark><LineString><coordinates>\n";
+        for p in points {
+            out += &format!("      {}\n", position(p, ",", precision));
+        }
+        out += "    </coordinates></LineString></Placemark>\n";
+    }
+    out + "  </Document>\n</kml>\n"
+}
+
+/// Writes a feature collection of a point or line, with the times in a `coordTimes` property
+/// when some points have one.
+fn write_geojson(track: &Track, precision: Precision) -> String {
+    let points = &track.points;
+    let geometry = match &points[..] {
+        [] => return "{\"type\":\"FeatureCollection\",\"features\":[]}\n".to_owned(),
+        [p] => format!(
+            "{{\"type\":\"Point\",\"coordinates\":[{}]}}",
+            position(p, ",", precision)
+        ),
+        _ => {
+            let positions: Vec<_> = points
+                .iter()
+                .map(|p| format!("[{}]", position(p, ",", precision)))
+                .collect();
+            format!(
+                "{{\"type\":\"LineString\",\"coordinates\":[{}]}}",
+                positions.join(",")
+            )
+        }
+    };
+    let properties = if points.iter().any(|p| p.time.is_some()) {
+        let times: Vec<_> = points
+            .iter()
+            .map(|p| match p.time {
+                Some(time) => format!("\"{}\"", format_time(time)),
+                None => "null".to_owned(),
+            })
+            .collect();
+        format!("{{\"coordTimes\":[{}]}}", times.join(","))
+    } else {
+        "{}".to_owned()
+    };
+    format!(
+        concat!(
+            "{{\"type\":\"FeatureCollection\",\"features\":[",
+            "{{\"type\":\"Feature\",\"properties\":{},\"geometry\":{}}}]}}\n"
+        ),
+        properties, geometry
+    )
+}
+
+/// Writes a track in a format, failing with [`io::ErrorKind::Unsupported`] for the read-only
+/// ones.
+pub fn write(format: FileFormat, track: &Track) -> io::Result<Vec<u8>> {
+    write_with(format, track, Precision::Shortest)
+}
+
+fn write_with(format: FileFormat, track: &Track, precision: Precision) -> io::Result<Vec<u8>> {
+    let text = match format {
+        FileFormat::Gpx => write_gpx(track, precision),
+        FileFormat::Kml => write_kml(track, precision),
+        FileFormat::GeoJson => write_geojson(track, precision),
+        FileFormat::Tcx | FileFormat::Fit => return Err(unsupported()),
+    };
+    Ok(text.into_bytes())
+}
+
+/// Reads a file in one format, converts its points and writes them in another, with the
+/// precision of the converter.
+pub fn transcode(
+    converter: &Converter,
+    from: FileFormat,
+    to: FileFormat,
+    data: &[u8],
+) -> io::Result<Vec<u8>> {
+    if !to.writable() {
+        return Err(unsupported());
+    }
+    write_with(
+        to,
+        &read(from, data)?.convert(converter),
+        converter.precision(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::track::parse_time;
+    use crate::GeodeticSystem::*;
+
+    fn sample() -> Track {
+        let point = |lat, lon, elevation, time: &str| TrackPoint {
+            elevation,
+            time: parse_time(time),
+            ..TrackPoint::new(lat, lon)
+        };
+        Track::from(vec![
+            point(39.905, 116.405, Some(50.5), "2020-01-01T00:00:00Z"),
+            point(39.906, 116.406, None, "2020-01-01T00:00:01.5Z"),
+            point(39.907, 116.407, Some(51.0), ""),
+        ])
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let track = sample();
+        let gpx = write(FileFormat::Gpx, &track).unwrap();
+        assert_eq!(read(FileFormat::Gpx, &gpx).unwrap(), track);
+
+        // KML and GeoJSON are read back without the times.
+        let untimed = |track: &Track| {
+            let points = track.points.iter().map(|p| TrackPoint { time: None, ..*p });
+            Track::from(points.collect::<Vec<_>>())
+        };
+        for &format in &[FileFormat::Kml, FileFormat::GeoJson] {
+            let written = write(format, &track).unwrap();
+            assert_eq!(read(format, &written).unwrap(), untimed(&track));
+        }
+        let kml = write(FileFormat::Kml, &untimed(&track)).unwrap();
+        assert!(String::from_utf8(kml).unwrap().contains("<LineString>"));
+        let timed = Track::from(track.points[..2].to_vec());
+        let kml = String::from_utf8(write(FileFormat::Kml, &timed).unwrap()).unwrap();
+        assert!(kml.contains("<when>2020-01-01T00:00:01.5Z</when>"));
+        assert_eq!(
+            read(FileFormat::Kml, kml.as_bytes()).unwrap(),
+            untimed(&timed)
+        );
+
+        let geojson = String::from_utf8(write(FileFormat::GeoJson, &track).unwrap()).unwrap();
+        assert!(geojson
+            .contains(r#""coordTimes":["2020-01-01T00:00:00Z","2020-01-01T00:00:01.5Z",null]"#));
+        for &format in &[FileFormat::Gpx, FileFormat::Kml, FileFormat::GeoJson] {
+            let written = write(format, &Track::default()).unwrap();
+            assert_eq!(read(format, &written).unwrap(), Track::default());
+        }
+    }
+
+    #[test]
+    fn test_transcode() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let track = sample();
+        let gpx = write(FileFormat::Gpx, &track).unwrap();
+        let geojson = transcode(&converter, FileFormat::Gpx, FileFormat::GeoJson, &gpx).unwrap();
+        let output = read(FileFormat::GeoJson, &geojson).unwrap();
+        let expected = track.convert(&converter);
+        for (a, b) in output.points.iter().zip(&expected.points) {
+            assert_eq!((a.lat, a.lon, a.elevation), (b.lat, b.lon, b.elevation));
+        }
+        assert_eq!(output.points.len(), 3);
+        let rounded = converter.clone().with_precision(Precision::Significant(6));
+        let kml = transcode(&rounded, FileFormat::Gpx, FileFormat::Kml, &gpx).unwrap();
+        let (lat, lon) = converter.convert(39.905, 116.405);
+        let expected = format!(
+            "      {},{},50.5\n",
+            Precision::Decimals(3).format(lon),
+            Precision::Decimals(4).format(lat)
+        );
+        let kml = String::from_utf8(kml).unwrap();
+        assert!(kml.contains(&expected));
+        let compressed = gzip::compress(&gpx);
+        let from_gzip = transcode(
+            &converter,
+            FileFormat::Gpx,
+            FileFormat::GeoJson,
+            &compressed,
+        );
+        assert_eq!(from_gzip.unwrap(), geojson);
+
+        let error = |from, to, data: &[u8]| transcode(&converter, from, to, data).unwrap_err();
+        assert_eq!(
+            error(FileFormat::Gpx, FileFormat::Fit, &gpx).kind(),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(
+            error(
+                FileFormat::Kml,
+                FileFormat::Gpx,
+                b"<coordinates>x</coordinates>"
+            )
+            .kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            error(FileFormat::GeoJson, FileFormat::Gpx, b"\xff").kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(FileFormat::from_name("ride.GPX"), Some(FileFormat::Gpx));
+        assert_eq!(
+            FileFormat::from_name("a.b.geojson"),
+            Some(FileFormat::GeoJson)
+        );
+        assert_eq!(FileFormat::from_name("ride.fit"), Some(FileFormat::Fit));
+        assert_eq!(FileFormat::from_name("ride.gpx.GZ"), Some(FileFormat::Gpx));
+        assert_eq!(FileFormat::from_name("ride.gz"), None);
+        assert_eq!(FileFormat::from_name("gpx"), None);
+        assert_eq!(FileFormat::from_name("notes.txt"), None);
+        assert!(FileFormat::Kml.writable() && !FileFormat::Tcx.writable());
+    }
+}
@@ -0,0 +1,269 @@
+//! Composable coordinate transforms.
+//!
+//! A [`Transform`] maps a pair of numbers to another. For geodetic systems the pairs are
+//! `(lat, lon)` in degrees; for projections they are `(x, y)` in meters. Transforms chain with
+//! [`Transform::then`], so a whole pipeline can be passed around as a single value:
+//!
+//! ```
+//! use undrift_gps::transform::{Transform, WebMercator};
+//! use undrift_gps::{Converter, GeodeticSystem};
+//!
+//! let pipeline = Converter::new(GeodeticSystem::Bd09, GeodeticSystem::Wgs84).then(WebMercator);
+//! let (x, y) = pipeline.apply(39.91, 116.41);
+//! ```
+use crate::{baidu_mercator, web_mercator, Converter, GeodeticSystem};
+
+/// A mapping between two coordinate spaces.
+pub trait Transform {
+    /// Transforms one coordinate pair.
+    fn apply(&self, a: f64, b: f64) -> (f64, f64);
+
+    /// Chains another transform after this one.
+    fn then<T: Transform>(self, next: T) -> Chain<Self, T>
+    where
+        Self: Sized,
+    {
+        Chain(self, next)
+    }
+}
+
+impl<T: Transform + ?Sized> Transform for &T {
+    fn apply(&self, a: f64, b: f64) -> (f64, f64) {
+        (**self).apply(a, b)
+    }
+}
+
+impl<T: Transform + ?Sized> Transform for Box<T> {
+    fn apply(&self, a: f64, b: f64) -> (f64, f64) {
+        (**self).apply(a, b)
+    }
+}
+
+impl Transform for Converter {
+    fn apply(&self, lat: f64, lon: f64) -> (f64, f64) {
+        self.convert(lat, lon)
+    }
+}
+
+/// Two transforms applied one after the other, created by [`Transform::then`].
+#[derive(Debug, Clone)]
+pub struct Chain<A, B>(pub A, pub B);
+
+impl<A: Transform, B: Transform> Transform for Chain<A, B> {
+    fn apply(&self, a: f64, b: f64) -> (f64, f64) {
+        let (a, b) = self.0.apply(a, b);
+        self.1.apply(a, b)
+    }
+}
+
+/// A transform defined by a function, created by [`from_fn`].
+#[derive(Debug, Clone, Copy)]
+pub struct FnTransform<F>(F);
+
+impl<F: Fn(f64, f64) -> (f64, f64)> Transform for FnTransform<F> {
+    fn apply(&self, a: f64, b: f64) -> (f64, f64) {
+        (self.0)(a, b)
+    }
+}
+
+/// Wraps a function, such as [`crate::wgs_to_gcj`], into a transform.
+pub fn from_fn<F: Fn(f64, f64) -> (f64, f64)>(f: F) -> FnTransform<F> {
+    FnTransform(f)
+}
+
+/// Projection of `(lat, lon)` to Web Mercator `(x, y)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebMercator;
+
+impl Transform for WebMercator {
+    fn apply(&self, lat: f64, lon: f64) -> (f64, f64) {
+        web_mercator::latlon_to_xy(lat, lon)
+    }
+}
+
+/// Inverse of [`WebMercator`], from `(x, y)` to `(lat, lon)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebMercatorInverse;
+
+impl Transform for WebMercatorInverse {
+    fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        web_mercator::xy_to_latlon(x, y)
+    }
+}
+
+/// Projection of BD-09 `(lat, lon)` to Baidu-Mercator `(x, y)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaiduMercator;
+
+impl Transform for BaiduMercator {
+    fn apply(&self, lat: f64, lon: f64) -> (f64, f64) {
+        baidu_mercator::latlon_to_xy(lat, lon)
+    }
+}
+
+/// Inverse of [`BaiduMercator`], from `(x, y)` to BD-09 `(lat, lon)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaiduMercatorInverse;
+
+impl Transform for BaiduMercatorInverse {
+    fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        baidu_mercator::xy_to_latlon(x, y)
+    }
+}
+
+/// A plane in meters: a geodetic system drawn with the projection of its web maps.
+///
+/// GCJ-02 web tiles, such as Amap's and Tencent's, apply the Web Mercator formulas to GCJ-02
+/// coordinates. Their plane has the extent and units of EPSG:3857 but is shifted by the drift,
+/// hundreds of meters inside China, so it is a system of its own here rather than EPSG:3857.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProjectedSystem {
+    /// EPSG:3857, WGS-84 in Web Mercator.
+    WebMercator,
+    /// GCJ-02 in Web Mercator, the plane of Amap and Tencent tiles.
+    GcjWebMercator,
+    /// BD-09 in Baidu Mercator, the plane of Baidu tiles.
+    BaiduMercator,
+}
+
+impl ProjectedSystem {
+    /// Every variant.
+    pub const ALL: [ProjectedSystem; 3] = [
+        ProjectedSystem::WebMercator,
+        ProjectedSystem::GcjWebMercator,
+        ProjectedSystem::BaiduMercator,
+    ];
+
+    /// The system of the coordinates projected onto the plane.
+    pub fn geodetic(self) -> GeodeticSystem {
+        match self {
+            ProjectedSystem::WebMercator => GeodeticSystem::Wgs84,
+            ProjectedSystem::GcjWebMercator => GeodeticSystem::Gcj02,
+            ProjectedSystem::BaiduMercator => GeodeticSystem::Bd09,
+        }
+    }
+
+    /// Projects `(lat, lon)` in [`ProjectedSystem::geodetic`] to `(x, y)`.
+    pub fn project(self, lat: f64, lon: f64) -> (f64, f64) {
+        match self {
+            ProjectedSystem::WebMercator | ProjectedSystem::GcjWebMercator => {
+                web_mercator::latlon_to_xy(lat, lon)
+            }
+            ProjectedSystem::BaiduMercator => baidu_mercator::latlon_to_xy(lat, lon),
+        }
+    }
+
+    /// Inverse of [`ProjectedSystem::project`].
+    pub fn unproject(self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            ProjectedSystem::WebMercator | ProjectedSystem::GcjWebMercator => {
+                web_mercator::xy_to_latlon(x, y)
+            }
+            ProjectedSystem::BaiduMercator => baidu_mercator::xy_to_latlon(x, y),
+        }
+    }
+
+    /// Converts `(x, y)` to the plane of `target`.
+    pub fn convert_to(self, target: ProjectedSystem, x: f64, y: f64) -> (f64, f64) {
+        if self == target {
+            return (x, y);
+        }
+        let (lat, lon) = self.unproject(x, y);
+        let (lat, lon) = self.geodetic().convert_to(target.geodetic(), lat, lon);
+        target.project(lat, lon)
+    }
+
+    /// The WGS-84 `(lat, lon)` of `(x, y)`.
+    pub fn to_wgs84(self, x: f64, y: f64) -> (f64, f64) {
+        let (lat, lon) = self.unproject(x, y);
+        self.geodetic().convert_to(GeodeticSystem::Wgs84, lat, lon)
+    }
+
+    /// The `(x, y)` of a WGS-84 `(lat, lon)`.
+    pub fn from_wgs84(self, lat: f64, lon: f64) -> (f64, f64) {
+        let (lat, lon) = GeodeticSystem::Wgs84.convert_to(self.geodetic(), lat, lon);
+        self.project(lat, lon)
+    }
+}
+
+/// Conversion of `(x, y)` from the first plane to the second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reproject(pub ProjectedSystem, pub ProjectedSystem);
+
+impl Transform for Reproject {
+    fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        self.0.convert_to(self.1, x, y)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+    use crate::{bd_to_wgs, gcj_to_bd, wgs_to_gcj};
+
+    #[test]
+    fn test_chain() {
+        let pipeline = from_fn(wgs_to_gcj).then(from_fn(gcj_to_bd));
+        assert_eq!(pipeline.apply(39.0, 116.0), crate::wgs_to_bd(39.0, 116.0));
+
+        let to_mercator = from_fn(bd_to_wgs).then(WebMercator);
+        let (lat, lon) = bd_to_wgs(39.0, 116.0);
+        assert_eq!(
+            to_mercator.apply(39.0, 116.0),
+            web_mercator::latlon_to_xy(lat, lon)
+        );
+
+        let converter = Converter::new(Bd09, Wgs84);
+        let (lat, lon) = converter.convert(39.0, 116.0);
+        assert_eq!(
+            converter.then(WebMercator).apply(39.0, 116.0),
+            web_mercator::latlon_to_xy(lat, lon)
+        );
+    }
+
+    #[test]
+    fn test_dynamic() {
+        let pipelines: Vec<Box<dyn Transform>> = vec![
+            Box::new(BaiduMercatorInverse.then(Converter::new(Bd09, Gcj02))),
+            Box::new(from_fn(|a, b| (b, a))),
+        ];
+        let (lat, lon) = baidu_mercator::xy_to_latlon(12949156.80, 4817450.00);
+        assert_eq!(
+            pipelines[0].apply(12949156.80, 4817450.00),
+            crate::bd_to_gcj(lat, lon)
+        );
+        let negate = from_fn(|a, b| (a, -b));
+        assert_eq!((&pipelines[1]).then(negate).apply(1.0, 2.0), (2.0, -1.0));
+    }
+
+    #[test]
+    fn test_projected_system() {
+        use ProjectedSystem::*;
+        let (x, y) = GcjWebMercator.project(39.9, 116.4);
+        // The same numbers are another place in EPSG:3857.
+        let (lat, lon) = WebMercator.to_wgs84(x, y);
+        assert!((lat - 39.9).abs() < 1e-9 && (lon - 116.4).abs() < 1e-9);
+        let wgs = GcjWebMercator.to_wgs84(x, y);
+        assert_eq!(wgs, crate::gcj_to_wgs(lat, lon));
+        let (p, q) = Reproject(GcjWebMercator, WebMercator).apply(x, y);
+        assert_eq!((p, q), WebMercator.project(wgs.0, wgs.1));
+        assert!((p - x).hypot(q - y) > 500.0);
+
+        for &from in &ProjectedSystem::ALL {
+            for &to in &ProjectedSystem::ALL {
+                let (a, b) = from.from_wgs84(39.9, 116.4);
+                let (c, d) = from.convert_to(to, a, b);
+                let (lat, lon) = to.to_wgs84(c, d);
+                assert!((lat - 39.9).abs() < 1e-6 && (lon - 116.4).abs() < 1e-6);
+            }
+        }
+
+        // Outside China, the GCJ-02 plane is EPSG:3857.
+        let paris = GcjWebMercator.project(48.86, 2.35);
+        assert_eq!(
+            GcjWebMercator.convert_to(WebMercator, paris.0, paris.1),
+            paris
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! u-blox UBX binary messages.
+//!
+//! A frame is made of the sync characters `0xb5 0x62`, the class and id of the message, the
+//! little-endian length of the payload, the payload, and a Fletcher checksum. The positions of
+//! NAV-PVT, NAV-POSLLH and NAV-HPPOSLLH are rewritten in place, and the checksums updated; the
+//! other messages and the bytes between frames, such as NMEA sentences, are kept.
+//!
+//! Bridge firmware converts each chunk read from the receiver, keeping the frame cut by the end
+//! of the chunk for the next one:
+//!
+//! ```
+//! use undrift_gps::{ubx, Converter, GeodeticSystem::*};
+//!
+//! let converter = Converter::new(Wgs84, Gcj02);
+//! let mut buffer = Vec::new();
+//! # let chunks: Vec<Vec<u8>> = vec![b"$GPGGA,...*47\r\n\xb5\x62\x01".to_vec()];
+//! for chunk in chunks {
+//!     buffer.extend_from_slice(&chunk);
+//!     let converted = ubx::convert(&converter, &mut buffer);
+//!     // Send buffer[..converted.consumed] to the host.
+//!     buffer.drain(..converted.consumed);
+//! }
+//! assert_eq!(buffer, b"\xb5\x62\x01");
+//! ```
+use crate::Converter;
+use std::fmt;
+use std::ops::Range;
+
+const SYNC: [u8; 2] = [0xb5, 0x62];
+
+/// Bytes of a frame around its payload.
+const OVERHEAD: usize = 8;
+
+/// Error returned for an invalid frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UbxError {
+    /// The frame ends before the length of its header.
+    Truncated,
+    /// The frame does not start with the sync characters.
+    InvalidSync,
+    /// The frame is longer than its header says, or its navigation message is too short for
+    /// its position.
+    InvalidLength,
+    /// The checksum does not match the content.
+    InvalidChecksum,
+}
+
+impl fmt::Display for UbxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            UbxError::Truncated => "truncated UBX frame",
+            UbxError::InvalidSync => "missing UBX sync characters",
+            UbxError::InvalidLength => "invalid length of UBX frame",
+            UbxError::InvalidChecksum => "invalid UBX checksum",
+        })
+    }
+}
+
+impl std::error::Error for UbxError {}
+
+/// Where a message stores its position.
+struct Layout {
+    class: u8,
+    id: u8,
+    /// Offsets in the payload of the longitude and latitude, in 1e-7 degrees.
+    lon: usize,
+    lat: usize,
+    /// Offsets of the high-precision parts of the longitude and latitude, in 1e-9 degrees.
+    high_precision: Option<(usize, usize)>,
+    /// Offset and mask of the flag marking the position invalid.
+    invalid: Option<(usize, u8)>,
+}
+
+impl Layout {
+    /// Smallest payload holding the position.
+    fn size(&self) -> usize {
+        match self.high_precision {
+            Some((lon, lat)) => lon.max(lat) + 1,
+            None => self.lon.max(self.lat) + 4,
+        }
+    }
+
+    /// The position of a payload in degrees, unless marked invalid.
+    fn get(&self, payload: &[u8]) -> Option<(f64, f64)> {
+        if let Some((offset, mask)) = self.invalid {
+            if payload.get(offset).is_some_and(|&flags| flags & mask != 0) {
+                return None;
+            }
+        }
+        let read = |offset: usize, high_precision: Option<usize>| {
+            let e7 = f64::from(i32::from_le_bytes([
+                payload[offset],
+                payload[offset + 1],
+                payload[offset + 2],
+                payload[offset + 3],
+            ]));
+            match high_precision {
+                Some(hp) => (e7 * 100.0 + f64::from(payload[hp] as i8)) / 1e9,
+                None => e7 / 1e7,
+            }
+        };
+        let (lon_hp, lat_hp) = self.high_precision.unzip();
+        Some((read(self.lat, lat_hp), read(self.lon, lon_hp)))
+    }
+
+    fn set(&self, payload: &mut [u8], (lat, lon): (f64, f64)) {
+        let mut write = |offset: usize, high_precision: Option<usize>, degrees: f64| {
+            let e7 = match high_precision {
+                Some(hp) => {
+                    // Both parts have the sign of the value, the high-precision one within ±99.
+                    let e9 = (degrees * 1e9).round() as i64;
+                    payload[hp] = (e9 % 100) as i8 as u8;
+                    e9 / 100
+                }
+                None => (degrees * 1e7).round() as i64,
+            };
+            payload[offset..offset + 4].copy_from_slice(&(e7 as i32).to_le_bytes());
+        };
+        let (lon_hp, lat_hp) = self.high_precision.unzip();
+        write(self.lat, lat_hp, lat);
+        write(self.lon, lon_hp, lon);
+    }
+}
+
+const LAYOUTS: [Layout; 3] = [
+    // NAV-PVT, with invalidLlh in flags3.
+    Layout {
+        class: 0x01,
+        id: 0x07,
+        lon: 24,
+        lat: 28,
+        high_precision: None,
+        invalid: Some((78, 0x01)),
+    },
+    // NAV-POSLLH
+    Layout {
+        class: 0x01,
+        id: 0x02,
+        lon: 4,
+        lat: 8,
+        high_precision: None,
+        invalid: None,
+    },
+    // NAV-HPPOSLLH, with invalidLlh in its flags.
+    Layout {
+        class: 0x01,
+        id: 0x14,
+        lon: 8,
+        lat: 12,
+        high_precision: Some((24, 25)),
+        invalid: Some((3, 0x01)),
+    },
+];
+
+/// The 8-bit Fletcher checksum of the class, id, length and payload of a frame.
+pub fn checksum(data: &[u8]) -> [u8; 2] {
+    let (mut a, mut b) = (0u8, 0u8);
+    for &byte in data {
+        a = a.wrapping_add(byte);
+        b = b.wrapping_add(a);
+    }
+    [a, b]
+}
+
+/// Checks a frame, returning the layout of its message if it has a position.
+fn check(frame: &[u8]) -> Result<Option<&'static Layout>, UbxError> {
+    if frame.len() < OVERHEAD {
+        return Err(UbxError::Truncated);
+    }
+    if frame[..2] != SYNC {
+        return Err(UbxError::InvalidSync);
+    }
+    let size = usize::from(u16::from_le_bytes([frame[4], frame[5]]));
+    match frame.len().cmp(&(size + OVERHEAD)) {
+        std::cmp::Ordering::Less => return Err(UbxError::Truncated),
+        std::cmp::Ordering::Greater => return Err(UbxError::InvalidLength),
+        std::cmp::Ordering::Equal => {}
+    }
+    if checksum(&frame[2..frame.len() - 2]) != frame[frame.len() - 2..] {
+        return Err(UbxError::InvalidChecksum);
+    }
+    match LAYOUTS
+        .iter()
+        .find(|layout| (layout.class, layout.id) == (frame[2], frame[3]))
+    {
+        Some(layout) if size < layout.size() => Err(UbxError::InvalidLength),
+        layout => Ok(layout),
+    }
+}
+
+/// Converts the position of a complete frame in place, updating its checksum. Returns whether
+/// the frame had a valid position.
+pub fn convert_frame(converter: &Converter, frame: &mut [u8]) -> Result<bool, UbxError> {
+    let layout = match check(frame)? {
+        Some(layout) => layout,
+        None => return Ok(false),
+    };
+    let end = frame.len() - 2;
+    let payload = &mut frame[6..end];
+    let (lat, lon) = match layout.get(payload) {
+        Some(position) => position,
+        None => return Ok(false),
+    };
+    layout.set(payload, converter.convert(lat, lon));
+    let sum = checksum(&frame[2..end]);
+    frame[end..].copy_from_slice(&sum);
+    Ok(true)
+}
+
+/// The valid frames of a stream, and the length of the stream before a frame cut by its end.
+fn scan(data: &[u8]) -> (Vec<Range<usize>>, usize) {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = data[pos..]
+        .windows(2)
+        .position(|w| w == SYNC)
+        .map(|i| pos + i)
+    {
+        let rest = &data[start..];
+        let end = match rest.get(4..6) {
+            Some(&[a, b]) => start + OVERHEAD + usize::from(u16::from_le_bytes([a, b])),
+            _ => return (frames, start),
+        };
+        if end > data.len() {
+            return (frames, start);
+        }
+        // Sync characters inside other data are skipped by their checksum.
+        if check(&data[start..end]).is_ok() {
+            frames.push(start..end);
+            pos = end;
+        } else {
+            pos = start + 1;
+        }
+    }
+    // A last byte may start the sync characters of the next frame.
+    let consumed = if data[pos..].last() == Some(&SYNC[0]) {
+        data.len() - 1
+    } else {
+        data.len()
+    };
+    (frames, consumed)
+}
+
+/// Reads the valid positions of the frames of a stream, in order.
+pub fn positions(data: &[u8]) -> Vec<(f64, f64)> {
+    let (frames, _) = scan(data);
+    frames
+        .into_iter()
+        .filter_map(|frame| {
+            let layout = check(&data[frame.clone()]).ok()??;
+            layout.get(&data[frame.start + 6..frame.end - 2])
+        })
+        .collect()
+}
+
+/// The result of [`convert`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Converted {
+    /// Number of positions converted.
+    pub messages: usize,
+    /// Length of the data before a frame cut by its end, which is to be converted again with
+    /// the data that follows.
+    pub consumed: usize,
+}
+
+/// Converts the positions of the frames of a stream in place.
+///
+/// Bytes that are not valid frames, such as NMEA sentences or frames with a wrong checksum,
+/// are kept as they are.
+pub fn convert(converter: &Converter, data: &mut [u8]) -> Converted {
+    let (frames, consumed) = scan(data);
+    let mut messages = 0;
+    for frame in frames {
+        // Scanned frames are valid.
+        if convert_frame(converter, &mut data[frame]).unwrap() {
+            messages += 1;
+        }
+    }
+    Converted { messages, consumed }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    fn frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = SYNC.to_vec();
+        frame.extend_from_slice(&[class, id]);
+        frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        frame.extend_from_slice(payload);
+        let sum = checksum(&frame[2..]);
+        frame.extend_from_slice(&sum);
+        frame
+    }
+
+    fn pvt(lat: f64, lon: f64, invalid: bool) -> Vec<u8> {
+        let mut payload = [0u8; 92];
+        payload[20] = 3;
+        payload[24..28].copy_from_slice(&((lon * 1e7).round() as i32).to_le_bytes());
+        payload[28..32].copy_from_slice(&((lat * 1e7).round() as i32).to_le_bytes());
+        payload[32..36].copy_from_slice(&45_000i32.to_le_bytes());
+        payload[78] = u8::from(invalid);
+        frame(0x01, 0x07, &payload)
+    }
+
+    fn hpposllh(lat_e9: i64, lon_e9: i64) -> Vec<u8> {
+        let mut payload = [0u8; 36];
+        payload[8..12].copy_from_slice(&((lon_e9 / 100) as i32).to_le_bytes());
+        payload[12..16].copy_from_slice(&((lat_e9 / 100) as i32).to_le_bytes());
+        payload[24] = (lon_e9 % 100) as i8 as u8;
+        payload[25] = (lat_e9 % 100) as i8 as u8;
+        frame(0x01, 0x14, &payload)
+    }
+
+    #[test]
+    fn test_checksum() {
+        // UBX-CFG-PRT poll, as documented.
+        assert_eq!(checksum(&[0x06, 0x00, 0x00, 0x00]), [0x06, 0x18]);
+        assert_eq!(
+            frame(0x06, 0x00, &[]),
+            [0xb5, 0x62, 0x06, 0x00, 0, 0, 0x06, 0x18]
+        );
+    }
+
+    #[test]
+    fn test_convert_frame() {
+        let converter = Converter::new(Wgs84, Gcj02);
+        let mut message = pvt(39.9042, 116.4074, false);
+        assert_eq!(convert_frame(&converter, &mut message), Ok(true));
+        let (lat, lon) = converter.convert(39.9042, 116.4074);
+        let converted = positions(&message)[0];
+        assert!((converted.0 - lat).abs() <= 0.5e-7 && (converted.1 - lon).abs() <= 0.5e-7);
+        // The height and the rest of the payload are kept.
+        assert_eq!(message[6 + 32..6 + 36], 45_000i32.to_le_bytes());
+        assert_eq!(check(&message).map(|_| ()), Ok(()));
+
+        let mut invalid = pvt(39.9042, 116.4074, true);
+        let original = invalid.clone();
+        assert_eq!(convert_frame(&converter, &mut invalid), Ok(false));
+        assert_eq!(invalid, original);
+
+        // High precision positions keep their nanodegrees.
+        let mut message = hpposllh(39_904_212_345, -116_407_412_345);
+        assert_eq!(positions(&message), [(39.904_212_345, -116.407_412_345)]);
+        let mut to_gcj = hpposllh(39_904_212_345, 116_407_412_345);
+        convert_frame(&converter, &mut to_gcj).unwrap();
+        let (lat, lon) = converter.convert(39.904_212_345, 116.407_412_345);
+        let converted = positions(&to_gcj)[0];
+        assert!((converted.0 - lat).abs() <= 0.5e-9 && (converted.1 - lon).abs() <= 0.5e-9);
+        assert_eq!(convert_frame(&converter, &mut message), Ok(true));
+        assert_eq!(positions(&message), [(39.904_212_345, -116.407_412_345)]);
+
+        let mut other = frame(0x01, 0x03, &[0; 16]);
+        assert_eq!(convert_frame(&converter, &mut other), Ok(false));
+        let mut short = frame(0x01, 0x02, &[0; 8]);
+        assert_eq!(
+            convert_frame(&converter, &mut short),
+            Err(UbxError::InvalidLength)
+        );
+        let mut corrupt = pvt(39.9042, 116.4074, false);
+        corrupt[40] ^= 1;
+        assert_eq!(
+            convert_frame(&converter, &mut corrupt),
+            Err(UbxError::InvalidChecksum)
+        );
+        assert_eq!(
+            convert_frame(&converter, &mut corrupt[..50]),
+            Err(UbxError::Truncated)
+        );
+        assert_eq!(
+            convert_frame(&converter, &mut [0u8; 8]),
+            Err(UbxError::InvalidSync)
+        );
+    }
+
+    #[test]
+    fn test_stream() {
+        let converter = Converter::new(Wgs84, Gcj02);
+        let nmea = b"$GNGGA,080000.00,3954.252,N,11624.444,E,1,12,0.9,45.0,M,,,,*4B\r\n";
+        let mut corrupt = pvt(39.95, 116.45, false);
+        corrupt[40] ^= 1;
+        let mut data = nmea.to_vec();
+        data.extend_from_slice(&pvt(39.9042, 116.4074, false));
+        data.extend_from_slice(&corrupt);
+        data.extend_from_slice(&frame(0x01, 0x02, &[0; 28]));
+        data.extend_from_slice(nmea);
+        let complete = data.len();
+        data.extend_from_slice(&pvt(31.2, 121.5, false)[..30]);
+
+        let original = data.clone();
+        let converted = convert(&converter, &mut data);
+        assert_eq!(
+            converted,
+            Converted {
+                messages: 2,
+                consumed: complete
+            }
+        );
+        assert_eq!(data[..nmea.len()], nmea[..]);
+        assert_eq!(
+            data[complete - nmea.len()..],
+            original[complete - nmea.len()..]
+        );
+        let positions = positions(&data);
+        assert_eq!(positions.len(), 2);
+        let (lat, lon) = converter.convert(39.9042, 116.4074);
+        assert!((positions[0].0 - lat).abs() <= 0.5e-7 && (positions[0].1 - lon).abs() <= 0.5e-7);
+        // Null Island is outside China.
+        assert_eq!(positions[1], (0.0, 0.0));
+
+        let mut end = nmea.to_vec();
+        end.push(SYNC[0]);
+        assert_eq!(convert(&converter, &mut end).consumed, nmea.len());
+        assert_eq!(convert(&converter, &mut []).consumed, 0);
+    }
+}
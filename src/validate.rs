@@ -0,0 +1,84 @@
+//! Sanity checks for raw coordinates.
+use crate::is_in_china;
+
+/// A problem found in a coordinate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Issue {
+    /// A component is NaN or infinite.
+    NotFinite,
+    /// The latitude is outside of [-90, 90].
+    LatitudeOutOfRange,
+    /// The longitude is outside of [-180, 180].
+    LongitudeOutOfRange,
+    /// The coordinate is (0, 0), which receivers commonly emit when they have no fix.
+    NullIsland,
+    /// The latitude is out of range, but the coordinate is valid with latitude and longitude
+    /// exchanged.
+    LikelySwapped,
+}
+
+/// Checks a coordinate, returning every issue found.
+pub fn validate(lat: f64, lon: f64) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    if !lat.is_finite() || !lon.is_finite() {
+        issues.push(Issue::NotFinite);
+        return issues;
+    }
+
+    if !(-90.0..=90.0).contains(&lat) {
+        issues.push(Issue::LatitudeOutOfRange);
+    }
+    if !(-180.0..=180.0).contains(&lon) {
+        issues.push(Issue::LongitudeOutOfRange);
+    }
+    if lat.abs() < 1e-9 && lon.abs() < 1e-9 {
+        issues.push(Issue::NullIsland);
+    }
+    if is_likely_swapped(lat, lon) {
+        issues.push(Issue::LikelySwapped);
+    }
+    issues
+}
+
+/// Checks whether the coordinate is only valid with its components exchanged.
+///
+/// Coordinates valid either way are never flagged on their own: (78.2, 15.6) in Svalbard reads as
+/// a point of India when exchanged. See [`crate::batch::detect_swapped`] for datasets.
+pub fn is_likely_swapped(lat: f64, lon: f64) -> bool {
+    lat.abs() > 90.0 && lat.abs() <= 180.0 && lon.abs() <= 90.0
+}
+
+/// Checks whether the coordinate lies outside of China as given, but inside once exchanged.
+pub(crate) fn is_in_china_swapped(lat: f64, lon: f64) -> bool {
+    !is_in_china(lat, lon) && is_in_china(lon, lat)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(39.9, 116.4).is_empty());
+        assert!(validate(48.8566, 2.3522).is_empty());
+        assert_eq!(validate(f64::NAN, 116.4), vec![Issue::NotFinite]);
+        assert_eq!(validate(0.0, 0.0), vec![Issue::NullIsland]);
+        assert_eq!(
+            validate(116.4, 39.9),
+            vec![Issue::LatitudeOutOfRange, Issue::LikelySwapped]
+        );
+        assert_eq!(validate(10.0, 200.0), vec![Issue::LongitudeOutOfRange]);
+    }
+
+    #[test]
+    fn test_arctic() {
+        // Svalbard, Franz Josef Land and Novaya Zemlya are valid either way.
+        for &(lat, lon) in &[(78.2, 15.6), (80.6, 58.0), (74.0, 56.0)] {
+            assert!(!is_likely_swapped(lat, lon));
+            assert!(validate(lat, lon).is_empty());
+        }
+        // Svalbard even falls in China once exchanged.
+        assert!(is_in_china_swapped(78.2, 15.6));
+        assert!(!is_in_china_swapped(30.0, 80.0));
+    }
+}
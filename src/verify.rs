@@ -0,0 +1,695 @@
+//! Checks that integrators can run against this crate before relying on it.
+use crate::{
+    bd_to_gcj, gcj_to_bd, gcj_to_wgs, haversine, is_in_china, wgs_to_gcj, BoundingBox, Converter,
+    GeodeticSystem, CHINA_BBOX, EARTH_RADIUS,
+};
+
+/// Bound in meters of the WGS-84 to GCJ-02 to WGS-84 round trip inside [`crate::CHINA_BBOX`].
+///
+/// Two million samples stay below 0.06 mm.
+pub const WGS_ROUNDTRIP_BOUND: f64 = 0.001;
+/// Bound in meters of the GCJ-02 to BD-09 to GCJ-02 round trip inside [`crate::CHINA_BBOX`].
+///
+/// The BD-09 inverse is the closed-form approximation of [`crate::bd_to_gcj`], whose error grows
+/// with the latitude: two million samples stay below 0.24 m, reached in the far north-east.
+/// [`crate::bd_to_wgs`] refines it before inverting GCJ-02, and is not affected.
+pub const BD_ROUNDTRIP_BOUND: f64 = 0.3;
+
+/// Largest round-trip errors found by [`verify_roundtrip`], in meters, with where they occur.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RoundtripReport {
+    pub samples: usize,
+    /// Samples left out of the WGS-84 round trip because they are shifted across the edge of
+    /// [`crate::CHINA_BBOX`], where GCJ-02 cannot be inverted.
+    pub edge_samples: usize,
+    /// Error of WGS-84 to GCJ-02 to WGS-84, and the WGS-84 point where it is the largest.
+    pub wgs_error: f64,
+    pub wgs_worst: (f64, f64),
+    /// Error of GCJ-02 to BD-09 to GCJ-02, and the GCJ-02 point where it is the largest.
+    pub bd_error: f64,
+    pub bd_worst: (f64, f64),
+}
+
+impl RoundtripReport {
+    /// Whether both errors are within the documented bounds.
+    pub fn within_bounds(&self) -> bool {
+        self.wgs_error <= WGS_ROUNDTRIP_BOUND && self.bd_error <= BD_ROUNDTRIP_BOUND
+    }
+}
+
+/// Pseudo-random numbers from SplitMix64, so that runs are reproducible.
+struct SplitMix(u64);
+
+impl SplitMix {
+    /// A number uniformly distributed in [0, 1).
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Round-trips `samples` pseudo-random points of `bbox` through the free conversion functions.
+///
+/// The points are the same for the same arguments.
+pub fn verify_roundtrip(bbox: BoundingBox, samples: usize) -> RoundtripReport {
+    let mut rng = SplitMix(0x756e_6472_6966_7421);
+    let mut report = RoundtripReport {
+        samples,
+        edge_samples: 0,
+        wgs_error: 0.0,
+        wgs_worst: (f64::NAN, f64::NAN),
+        bd_error: 0.0,
+        bd_worst: (f64::NAN, f64::NAN),
+    };
+    for _ in 0..samples {
+        let lat = bbox.min_lat + (bbox.max_lat - bbox.min_lat) * rng.next();
+        let lon = bbox.min_lon + (bbox.max_lon - bbox.min_lon) * rng.next();
+
+        let (gcj_lat, gcj_lon) = wgs_to_gcj(lat, lon);
+        let error = haversine((lat, lon), gcj_to_wgs(gcj_lat, gcj_lon));
+        if is_in_china(lat, lon) != is_in_china(gcj_lat, gcj_lon) {
+            report.edge_samples += 1;
+        } else if error > report.wgs_error {
+            report.wgs_error = error;
+            report.wgs_worst = (lat, lon);
+        }
+
+        let (bd_lat, bd_lon) = gcj_to_bd(lat, lon);
+        let error = haversine((lat, lon), bd_to_gcj(bd_lat, bd_lon));
+        if error > report.bd_error {
+            report.bd_error = error;
+            report.bd_worst = (lat, lon);
+        }
+    }
+    report
+}
+
+/// Parts of [`CHINA_BBOX`] with their own bounds in [`max_error_meters`], split at 105° E and
+/// 42° N.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Area {
+    /// The whole rectangle.
+    China,
+    /// West of 105° E: Xinjiang, Tibet, Qinghai, Gansu, Sichuan and Yunnan.
+    Western,
+    /// East of 105° E and south of 42° N, where most of the population lives.
+    Eastern,
+    /// East of 105° E and north of 42° N: Inner Mongolia and the north-east.
+    NorthEastern,
+}
+
+impl Area {
+    pub const ALL: [Area; 4] = [
+        Area::China,
+        Area::Western,
+        Area::Eastern,
+        Area::NorthEastern,
+    ];
+
+    pub fn bbox(self) -> BoundingBox {
+        let b = CHINA_BBOX;
+        match self {
+            Area::China => b,
+            Area::Western => BoundingBox::new(b.min_lat, b.min_lon, b.max_lat, 105.0),
+            Area::Eastern => BoundingBox::new(b.min_lat, 105.0, 42.0, b.max_lon),
+            Area::NorthEastern => BoundingBox::new(42.0, 105.0, b.max_lat, b.max_lon),
+        }
+    }
+}
+
+/// Bound in meters of the error of `from.convert_to(to, ..)` for inputs in `area`, in the source
+/// system.
+///
+/// The error is how far the result is from converting back exactly to the input. The directions
+/// towards GCJ-02 and BD-09 define these systems, so their bound is zero, like converting to the
+/// same system. The other bounds have a margin of at least 25% over the largest errors of 400,000
+/// samples per area, which are below 0.06 mm to WGS-84 from GCJ-02 and 0.23 mm from BD-09, whose
+/// closed-form inverse is refined on the way. From BD-09 to GCJ-02, the closed form is kept: its
+/// errors reach 0.19 m in the west and east and 0.24 m in the north-east. Inputs converting out of
+/// [`CHINA_BBOX`], within a kilometer of its edges, are not covered.
+pub fn max_error_meters(from: GeodeticSystem, to: GeodeticSystem, area: Area) -> f64 {
+    use GeodeticSystem::*;
+    match (from, to) {
+        (Gcj02, Wgs84) | (Bd09, Wgs84) => WGS_ROUNDTRIP_BOUND,
+        (Bd09, Gcj02) => match area {
+            Area::Western | Area::Eastern => 0.25,
+            Area::China | Area::NorthEastern => BD_ROUNDTRIP_BOUND,
+        },
+        _ => 0.0,
+    }
+}
+
+/// Estimated error of a converted position, as an ellipse around it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorEllipse {
+    /// Semi-major axis in meters.
+    pub semi_major: f64,
+    /// Semi-minor axis in meters.
+    pub semi_minor: f64,
+    /// Direction of the major axis in degrees clockwise from north, in `[0, 180)`.
+    pub orientation: f64,
+}
+
+/// Estimates the error ellipse of `converter.convert(lat, lon)`.
+///
+/// The result is converted back by the forward transform, which defines the obfuscated systems
+/// exactly. The distance between the input and the result converted back is the residual of the
+/// algorithm, to which the tolerance at which the iteration stops, [`crate::Algorithm::tolerance`]
+/// on each axis, is added when the conversion inverts GCJ-02. A disk of that radius around the
+/// input is then mapped to the target system by the local derivatives of the inverse, giving the
+/// ellipse, which is conservative: the iteration usually ends well below its tolerance, so that
+/// the axes may exceed [`max_error_meters`]. Conversions towards GCJ-02 and BD-09, and positions
+/// left unchanged outside the region, are exact: their ellipse is empty. Non-finite inputs give
+/// NaN axes.
+pub fn error_ellipse(converter: &Converter, lat: f64, lon: f64) -> ErrorEllipse {
+    use GeodeticSystem::*;
+    let result = converter.convert(lat, lon);
+    let inverse = matches!(
+        (converter.source(), converter.target()),
+        (Gcj02, Wgs84) | (Bd09, Gcj02) | (Bd09, Wgs84)
+    );
+    if !inverse || result == (lat, lon) {
+        let empty = if lat.is_finite() && lon.is_finite() {
+            0.0
+        } else {
+            f64::NAN
+        };
+        return ErrorEllipse {
+            semi_major: empty,
+            semi_minor: empty,
+            orientation: 0.0,
+        };
+    }
+    let back = converter.reversed();
+    let forward = |lat, lon| back.convert(lat, lon);
+
+    // Derivatives of the forward transform at the result, in degrees per degree.
+    const STEP: f64 = 1e-6;
+    let (lat_p, lat_m) = (
+        forward(result.0 + STEP, result.1),
+        forward(result.0 - STEP, result.1),
+    );
+    let (lon_p, lon_m) = (
+        forward(result.0, result.1 + STEP),
+        forward(result.0, result.1 - STEP),
+    );
+    let j = [
+        [
+            (lat_p.0 - lat_m.0) / (2.0 * STEP),
+            (lon_p.0 - lon_m.0) / (2.0 * STEP),
+        ],
+        [
+            (lat_p.1 - lat_m.1) / (2.0 * STEP),
+            (lon_p.1 - lon_m.1) / (2.0 * STEP),
+        ],
+    ];
+    // Mapping from meters at the input to meters at the result: the inverse of the derivatives,
+    // scaled by the length of a degree along each axis.
+    let meters = |lat: f64| {
+        let m = EARTH_RADIUS.to_radians();
+        (m, m * lat.to_radians().cos())
+    };
+    let (source, target) = (meters(lat), meters(result.0));
+    let det = j[0][0] * j[1][1] - j[0][1] * j[1][0];
+    let m = [
+        [
+            j[1][1] / det * target.0 / source.0,
+            -j[0][1] / det * target.0 / source.1,
+        ],
+        [
+            -j[1][0] / det * target.1 / source.0,
+            j[0][0] / det * target.1 / source.1,
+        ],
+    ];
+
+    let residual = forward(result.0, result.1);
+    let residual = ((lat - residual.0) * source.0, (lon - residual.1) * source.1);
+    let tolerance = if converter.target() == Wgs84 {
+        let eps = converter.algorithm().tolerance();
+        (eps * source.0).hypot(eps * source.1)
+    } else {
+        0.0
+    };
+    let radius = residual.0.hypot(residual.1) + tolerance;
+
+    // Axes of the image of the unit disk, from the eigenvalues of m * m^T.
+    let a = m[0][0] * m[0][0] + m[0][1] * m[0][1];
+    let b = m[0][0] * m[1][0] + m[0][1] * m[1][1];
+    let c = m[1][0] * m[1][0] + m[1][1] * m[1][1];
+    let mean = (a + c) / 2.0;
+    let spread = ((a - c) / 2.0).hypot(b);
+    let orientation = 0.5 * (2.0 * b).atan2(a - c);
+    ErrorEllipse {
+        semi_major: (mean + spread).sqrt() * radius,
+        semi_minor: (mean - spread).max(0.0).sqrt() * radius,
+        orientation: orientation.to_degrees().rem_euclid(180.0),
+    }
+}
+
+/// A place whose location is known in the target system of a conversion, such as an office
+/// address, with the position a pipeline converted for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    /// Position output by the conversion.
+    pub converted: (f64, f64),
+    /// Known location in the target system.
+    pub known: (f64, f64),
+}
+
+/// What explains the position of a converted reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Finding {
+    /// The position is the known location, within the tolerance.
+    Match,
+    /// The position is the known location in another system: the conversion was skipped if it
+    /// is the source system, or went into the wrong system.
+    InSystem(GeodeticSystem),
+    /// The position is where converting in the wrong direction puts it, shifted twice over.
+    Reversed,
+    /// The position is where converting twice puts it.
+    ConvertedTwice,
+    /// None of the above.
+    Unexplained,
+}
+
+/// Outcome of [`check_references`] for one reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReferenceResult {
+    /// Distance in meters between the converted position and the known location.
+    pub displacement: f64,
+    pub finding: Finding,
+}
+
+/// Outcome of [`check_references`], with a result per reference, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceReport {
+    pub tolerance: f64,
+    pub results: Vec<ReferenceResult>,
+}
+
+impl ReferenceReport {
+    /// Whether every reference matches its known location.
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.finding == Finding::Match)
+    }
+
+    /// The most frequent finding apart from [`Finding::Match`], the likely mistake of the
+    /// pipeline, or `None` if it passed.
+    pub fn diagnosis(&self) -> Option<Finding> {
+        let mut counts: Vec<(Finding, usize)> = Vec::new();
+        for result in self.results.iter().filter(|r| r.finding != Finding::Match) {
+            match counts.iter_mut().find(|(f, _)| *f == result.finding) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((result.finding, 1)),
+            }
+        }
+        // The first of the most frequent, so that ties go to the earlier finding.
+        counts
+            .iter()
+            .rev()
+            .max_by_key(|(_, count)| *count)
+            .map(|&(finding, _)| finding)
+    }
+}
+
+/// Compares the positions a pipeline converted with `converter` against the known locations of
+/// the references, as a smoke test of its deployment: a position further than `tolerance` meters
+/// from its location is explained, when possible, by the usual mistakes of skipping the
+/// conversion, converting in the wrong direction, or converting twice.
+///
+/// The drift is hundreds of meters, so a tolerance of tens of meters tells the mistakes apart
+/// while allowing for references located by hand.
+pub fn check_references(
+    converter: &Converter,
+    references: &[Reference],
+    tolerance: f64,
+) -> ReferenceReport {
+    let target = converter.target();
+    let back = converter.reversed();
+    let results = references
+        .iter()
+        .map(|reference| {
+            let (lat, lon) = reference.known;
+            let near = |p: (f64, f64)| haversine(reference.converted, p) <= tolerance;
+            let displacement = haversine(reference.converted, reference.known);
+            let other = GeodeticSystem::ALL
+                .iter()
+                .copied()
+                .filter(|&s| s != target)
+                .find(|&s| near(target.convert_to(s, lat, lon)));
+            let input = back.convert(lat, lon);
+            let finding = if displacement <= tolerance {
+                Finding::Match
+            } else if let Some(system) = other {
+                Finding::InSystem(system)
+            } else if converter.source() != target && near(back.convert(input.0, input.1)) {
+                Finding::Reversed
+            } else if converter.source() != target && near(converter.convert(lat, lon)) {
+                Finding::ConvertedTwice
+            } else {
+                Finding::Unexplained
+            };
+            ReferenceResult {
+                displacement,
+                finding,
+            }
+        })
+        .collect();
+    ReferenceReport { tolerance, results }
+}
+
+/// The algorithms of eviltransform, the de-facto reference implementation of GCJ-02 and BD-09,
+/// transcribed from its published Go source.
+mod eviltransform {
+    use std::f64::consts::PI;
+
+    const EARTH_R: f64 = 6378137.0;
+    const EE: f64 = 0.006_693_421_622_965_943;
+    const X_PI: f64 = PI * 3000.0 / 180.0;
+
+    fn out_of_china(lat: f64, lng: f64) -> bool {
+        !(72.004..=137.8347).contains(&lng) || !(0.8293..=55.8271).contains(&lat)
+    }
+
+    fn transform(x: f64, y: f64) -> (f64, f64) {
+        let xy = x * y;
+        let abs_x = x.abs().sqrt();
+        let x_pi = x * PI;
+        let y_pi = y * PI;
+        let d = 20.0 * (6.0 * x_pi).sin() + 20.0 * (2.0 * x_pi).sin();
+        let mut lat = d;
+        let mut lng = d;
+        lat += 20.0 * y_pi.sin() + 40.0 * (y_pi / 3.0).sin();
+        lng += 20.0 * x_pi.sin() + 40.0 * (x_pi / 3.0).sin();
+        lat += 160.0 * (y_pi / 12.0).sin() + 320.0 * (y_pi / 30.0).sin();
+        lng += 150.0 * (x_pi / 12.0).sin() + 300.0 * (x_pi / 30.0).sin();
+        lat *= 2.0 / 3.0;
+        lng *= 2.0 / 3.0;
+        lat += -100.0 + 2.0 * x + 3.0 * y + 0.2 * y * y + 0.1 * xy + 0.2 * abs_x;
+        lng += 300.0 + x + 2.0 * y + 0.1 * x * x + 0.1 * xy + 0.1 * abs_x;
+        (lat, lng)
+    }
+
+    fn delta(lat: f64, lng: f64) -> (f64, f64) {
+        let (d_lat, d_lng) = transform(lng - 105.0, lat - 35.0);
+        let rad_lat = lat / 180.0 * PI;
+        let magic = 1.0 - EE * rad_lat.sin() * rad_lat.sin();
+        let sqrt_magic = magic.sqrt();
+        (
+            (d_lat * 180.0) / ((EARTH_R * (1.0 - EE)) / (magic * sqrt_magic) * PI),
+            (d_lng * 180.0) / (EARTH_R / sqrt_magic * rad_lat.cos() * PI),
+        )
+    }
+
+    pub(super) fn wgs_to_gcj(lat: f64, lng: f64) -> (f64, f64) {
+        if out_of_china(lat, lng) {
+            return (lat, lng);
+        }
+        let (d_lat, d_lng) = delta(lat, lng);
+        (lat + d_lat, lng + d_lng)
+    }
+
+    /// The bisection of `GCJtoWGSExact`, stopping within 1e-6 degrees.
+    pub(super) fn gcj_to_wgs_exact(gcj_lat: f64, gcj_lng: f64) -> (f64, f64) {
+        const INIT_DELTA: f64 = 0.01;
+        const THRESHOLD: f64 = 0.000001;
+        let (mut m_lat, mut m_lng) = (gcj_lat - INIT_DELTA, gcj_lng - INIT_DELTA);
+        let (mut p_lat, mut p_lng) = (gcj_lat + INIT_DELTA, gcj_lng + INIT_DELTA);
+        let mut wgs = (gcj_lat, gcj_lng);
+        for _ in 0..30 {
+            wgs = ((m_lat + p_lat) / 2.0, (m_lng + p_lng) / 2.0);
+            let (tmp_lat, tmp_lng) = wgs_to_gcj(wgs.0, wgs.1);
+            let (d_lat, d_lng) = (tmp_lat - gcj_lat, tmp_lng - gcj_lng);
+            if d_lat.abs() < THRESHOLD && d_lng.abs() < THRESHOLD {
+                return wgs;
+            }
+            if d_lat > 0.0 {
+                p_lat = wgs.0;
+            } else {
+                m_lat = wgs.0;
+            }
+            if d_lng > 0.0 {
+                p_lng = wgs.1;
+            } else {
+                m_lng = wgs.1;
+            }
+        }
+        wgs
+    }
+
+    pub(super) fn gcj_to_bd(gcj_lat: f64, gcj_lng: f64) -> (f64, f64) {
+        let (x, y) = (gcj_lng, gcj_lat);
+        let z = (x * x + y * y).sqrt() + 0.00002 * (y * X_PI).sin();
+        let theta = y.atan2(x) + 0.000003 * (x * X_PI).cos();
+        (z * theta.sin() + 0.006, z * theta.cos() + 0.0065)
+    }
+
+    pub(super) fn bd_to_gcj(bd_lat: f64, bd_lng: f64) -> (f64, f64) {
+        let (x, y) = (bd_lng - 0.0065, bd_lat - 0.006);
+        let z = (x * x + y * y).sqrt() - 0.00002 * (y * X_PI).sin();
+        let theta = y.atan2(x) - 0.000003 * (x * X_PI).cos();
+        (z * theta.sin(), z * theta.cos())
+    }
+}
+
+/// Largest differences in meters between this crate and eviltransform, per direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParityReport {
+    pub samples: usize,
+    /// Samples where the bisection of eviltransform does not converge, which happens at high
+    /// latitudes, left out of `gcj_to_wgs`.
+    pub reference_nonconvergent: usize,
+    pub wgs_to_gcj: f64,
+    pub gcj_to_wgs: f64,
+    pub gcj_to_bd: f64,
+    pub bd_to_gcj: f64,
+}
+
+impl ParityReport {
+    /// Whether every difference is within [`PARITY_TOLERANCE`].
+    pub fn within_tolerance(&self) -> bool {
+        [
+            self.wgs_to_gcj,
+            self.gcj_to_wgs,
+            self.gcj_to_bd,
+            self.bd_to_gcj,
+        ]
+        .iter()
+        .all(|&d| d <= PARITY_TOLERANCE)
+    }
+}
+
+/// Tolerance in meters of [`verify_against_reference`].
+///
+/// eviltransform stops its inversion within 1e-6 degrees, about 0.1 m, and takes the semi-major
+/// axis of WGS-84 instead of Krasovsky's to scale the offset, a relative difference of 1.3e-5.
+pub const PARITY_TOLERANCE: f64 = 0.2;
+
+/// Compares the free conversion functions with eviltransform on a 0.5 degree grid over
+/// [`crate::CHINA_BBOX`].
+///
+/// The reference is eviltransform's algorithm run in this crate, not recorded outputs of it; it
+/// is kept independent from the code it checks. The offsets agree within 1.2 cm, the BD-09
+/// formulas exactly, and the inversions within 0.16 m, the stopping threshold of eviltransform.
+pub fn verify_against_reference() -> ParityReport {
+    let mut report = ParityReport {
+        samples: 0,
+        reference_nonconvergent: 0,
+        wgs_to_gcj: 0.0,
+        gcj_to_wgs: 0.0,
+        gcj_to_bd: 0.0,
+        bd_to_gcj: 0.0,
+    };
+    let bbox = CHINA_BBOX;
+    let record = |max: &mut f64, a, b| *max = max.max(haversine(a, b));
+    let mut lat = bbox.min_lat.ceil();
+    while lat < bbox.max_lat {
+        let mut lon = bbox.min_lon.ceil();
+        while lon < bbox.max_lon {
+            report.samples += 1;
+            record(
+                &mut report.wgs_to_gcj,
+                wgs_to_gcj(lat, lon),
+                eviltransform::wgs_to_gcj(lat, lon),
+            );
+            let reference = eviltransform::gcj_to_wgs_exact(lat, lon);
+            let residual = eviltransform::wgs_to_gcj(reference.0, reference.1);
+            if (residual.0 - lat).abs().max((residual.1 - lon).abs()) < 1e-6 {
+                record(&mut report.gcj_to_wgs, gcj_to_wgs(lat, lon), reference);
+            } else {
+                report.reference_nonconvergent += 1;
+            }
+            record(
+                &mut report.gcj_to_bd,
+                gcj_to_bd(lat, lon),
+                eviltransform::gcj_to_bd(lat, lon),
+            );
+            record(
+                &mut report.bd_to_gcj,
+                bd_to_gcj(lat, lon),
+                eviltransform::bd_to_gcj(lat, lon),
+            );
+            lon += 0.5;
+        }
+        lat += 0.5;
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let report = verify_roundtrip(CHINA_BBOX, 20_000);
+        assert!(report.within_bounds(), "{:?}", report);
+        assert_eq!(report, verify_roundtrip(CHINA_BBOX, 20_000));
+    }
+
+    #[test]
+    fn test_max_error() {
+        let mut rng = SplitMix(1);
+        for &area in &Area::ALL {
+            let bbox = area.bbox();
+            for &(from, to) in &[(Gcj02, Wgs84), (Bd09, Gcj02), (Bd09, Wgs84)] {
+                let bound = max_error_meters(from, to, area);
+                for _ in 0..2000 {
+                    let lat = bbox.min_lat + (bbox.max_lat - bbox.min_lat) * rng.next();
+                    let lon = bbox.min_lon + (bbox.max_lon - bbox.min_lon) * rng.next();
+                    let (p, q) = from.convert_to(to, lat, lon);
+                    if !is_in_china(p, q) {
+                        continue;
+                    }
+                    let error = haversine((lat, lon), to.convert_to(from, p, q));
+                    assert!(
+                        error <= bound,
+                        "{:?} at {:?}: {}",
+                        (from, to),
+                        (lat, lon),
+                        error
+                    );
+                }
+            }
+        }
+        assert_eq!(max_error_meters(Wgs84, Bd09, Area::Western), 0.0);
+        assert_eq!(max_error_meters(Gcj02, Gcj02, Area::China), 0.0);
+    }
+
+    #[test]
+    fn test_error_ellipse() {
+        let mut rng = SplitMix(2);
+        for &(from, to) in &[(Gcj02, Wgs84), (Bd09, Gcj02), (Bd09, Wgs84)] {
+            for &area in &Area::ALL[1..] {
+                let bbox = area.bbox();
+                for _ in 0..200 {
+                    let lat = bbox.min_lat + (bbox.max_lat - bbox.min_lat) * rng.next();
+                    let lon = bbox.min_lon + (bbox.max_lon - bbox.min_lon) * rng.next();
+                    let converter = Converter::new(from, to);
+                    let (p, q) = converter.convert(lat, lon);
+                    if !is_in_china(p, q) {
+                        continue;
+                    }
+                    let ellipse = error_ellipse(&converter, lat, lon);
+                    let error = haversine((lat, lon), to.convert_to(from, p, q));
+                    assert!(ellipse.semi_minor <= ellipse.semi_major);
+                    // The tolerance of 1e-7 degrees on both axes, up to 1.5 cm, both added and
+                    // reached by residuals of iterations stopping just below it.
+                    let bound = max_error_meters(from, to, area) + 0.03;
+                    assert!(ellipse.semi_major <= bound, "{:?}", ellipse);
+                    assert!((0.0..180.0).contains(&ellipse.orientation));
+                    // The residual seen from the target, within a few percent.
+                    assert!(
+                        error <= ellipse.semi_major * 1.05,
+                        "{} {:?}",
+                        error,
+                        ellipse
+                    );
+                }
+            }
+        }
+
+        let empty = ErrorEllipse {
+            semi_major: 0.0,
+            semi_minor: 0.0,
+            orientation: 0.0,
+        };
+        assert_eq!(
+            error_ellipse(&Converter::new(Wgs84, Bd09), 39.9, 116.4),
+            empty
+        );
+        assert_eq!(
+            error_ellipse(&Converter::new(Gcj02, Wgs84), 48.8, 2.35),
+            empty
+        );
+        let precise = Converter::new(Gcj02, Wgs84).with_algorithm(crate::Algorithm::Exact);
+        let coarse = Converter::new(Gcj02, Wgs84);
+        assert!(
+            error_ellipse(&precise, 39.9, 116.4).semi_major
+                < error_ellipse(&coarse, 39.9, 116.4).semi_major
+        );
+        assert!(error_ellipse(&coarse, f64::NAN, 116.4).semi_major.is_nan());
+    }
+
+    #[test]
+    fn test_reference() {
+        let report = verify_against_reference();
+        assert!(report.within_tolerance(), "{:?}", report);
+    }
+
+    #[test]
+    fn test_check_references() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let reference = |name: &str, known: (f64, f64), f: &dyn Fn((f64, f64)) -> (f64, f64)| {
+            let input = Wgs84.convert_to(Gcj02, known.0, known.1);
+            Reference {
+                name: name.to_owned(),
+                converted: f(input),
+                known,
+            }
+        };
+        let convert = |p: (f64, f64)| converter.convert(p.0, p.1);
+        let office = (39.9087, 116.3975);
+        let references = [
+            reference("converted", office, &convert),
+            reference("skipped", (31.2304, 121.4737), &|p| p),
+            reference("reversed", office, &|p| Wgs84.convert_to(Gcj02, p.0, p.1)),
+            reference("twice", office, &|p| convert(convert(p))),
+            reference("baidu", office, &|p| Gcj02.convert_to(Bd09, p.0, p.1)),
+            reference("elsewhere", office, &|_| (0.0, 0.0)),
+        ];
+        let report = check_references(&converter, &references, 20.0);
+        let findings: Vec<_> = report.results.iter().map(|r| r.finding).collect();
+        assert_eq!(
+            findings,
+            [
+                Finding::Match,
+                Finding::InSystem(Gcj02),
+                Finding::Reversed,
+                Finding::ConvertedTwice,
+                Finding::InSystem(Bd09),
+                Finding::Unexplained,
+            ]
+        );
+        assert!(report.results[0].displacement < 0.01);
+        assert!(report.results[1].displacement > 300.0);
+        assert!(!report.passed());
+        assert_eq!(report.diagnosis(), Some(Finding::InSystem(Gcj02)));
+
+        let all_skipped: Vec<_> = (0..3)
+            .map(|i| reference("skipped", (30.0 + f64::from(i), 114.0), &|p| p))
+            .chain(std::iter::once(reference("ok", office, &convert)))
+            .collect();
+        let report = check_references(&converter, &all_skipped, 20.0);
+        assert_eq!(report.diagnosis(), Some(Finding::InSystem(Gcj02)));
+        let report = check_references(&converter, &references[..1], 20.0);
+        assert!(report.passed());
+        assert_eq!(report.diagnosis(), None);
+    }
+}
@@ -0,0 +1,159 @@
+//! WebAssembly interface, enabled by the `wasm` feature. See `js/undrift_gps.js` for the
+//! JavaScript API built on it.
+//!
+//! A GeoJSON document is converted in a single call: the page copies the text into memory from
+//! [`undrift_alloc`], and reads back the converted text, or an error message, from
+//! [`undrift_result_ptr`] and [`undrift_result_len`]. Coordinates never cross into JavaScript,
+//! so uploaded files can be converted client-side at the cost of two copies.
+use crate::geojson::{self, GeoJsonOptions};
+use crate::{Converter, GeodeticSystem};
+use std::cell::RefCell;
+use std::convert::TryFrom;
+
+/// Returned on success.
+pub const UNDRIFT_OK: i32 = 0;
+/// Returned for an unknown system name.
+pub const UNDRIFT_INVALID_SYSTEM: i32 = -1;
+/// Returned for text which is not UTF-8.
+pub const UNDRIFT_INVALID_UTF8: i32 = -2;
+/// Returned for invalid GeoJSON.
+pub const UNDRIFT_INVALID_GEOJSON: i32 = -3;
+
+thread_local! {
+    /// Output of the last conversion.
+    static RESULT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Allocates `len` bytes for an input, to be freed with [`undrift_free`].
+#[no_mangle]
+pub extern "C" fn undrift_alloc(len: usize) -> *mut u8 {
+    let mut buffer = vec![0u8; len].into_boxed_slice();
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+/// Frees a buffer returned by [`undrift_alloc`].
+///
+/// # Safety
+///
+/// `ptr` must come from `undrift_alloc(len)` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn undrift_free(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+/// Start of the output of the last conversion, valid until the next one.
+#[no_mangle]
+pub extern "C" fn undrift_result_ptr() -> *const u8 {
+    RESULT.with(|result| result.borrow().as_ptr())
+}
+
+/// Length in bytes of the output of the last conversion.
+#[no_mangle]
+pub extern "C" fn undrift_result_len() -> usize {
+    RESULT.with(|result| result.borrow().len())
+}
+
+/// Converts the GeoJSON document of `input` between the systems named by `from` and `to`, such as
+/// "gcj02" and "wgs84", with the aliases of [`GeodeticSystem`]'s `FromStr`.
+///
+/// `precision` is the number of decimals written, or negative for the shortest exact text. The
+/// converted document, or the error message, is then the output.
+///
+/// # Safety
+///
+/// Each pointer must be valid for its length in bytes.
+#[no_mangle]
+pub unsafe extern "C" fn undrift_convert_geojson(
+    input: *const u8,
+    input_len: usize,
+    from: *const u8,
+    from_len: usize,
+    to: *const u8,
+    to_len: usize,
+    precision: i32,
+) -> i32 {
+    let text = |ptr: *const u8, len: usize| match len {
+        0 => Ok(""),
+        _ => std::str::from_utf8(std::slice::from_raw_parts(ptr, len)),
+    };
+    let (code, output) = match (
+        text(input, input_len),
+        text(from, from_len),
+        text(to, to_len),
+    ) {
+        (Ok(input), Ok(from), Ok(to)) => convert(input, from, to, precision),
+        _ => (UNDRIFT_INVALID_UTF8, "invalid UTF-8".to_owned()),
+    };
+    RESULT.with(|result| *result.borrow_mut() = output.into_bytes());
+    code
+}
+
+fn convert(input: &str, from: &str, to: &str, precision: i32) -> (i32, String) {
+    let systems = (from.parse::<GeodeticSystem>(), to.parse::<GeodeticSystem>());
+    let (from, to) = match systems {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return (UNDRIFT_INVALID_SYSTEM, e.to_string()),
+    };
+    let options = GeoJsonOptions {
+        precision: usize::try_from(precision).ok(),
+    };
+    match geojson::convert(&Converter::new(from, to), input, &options) {
+        Ok(output) => (UNDRIFT_OK, output),
+        Err(e) => (UNDRIFT_INVALID_GEOJSON, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    fn call(input: &[u8], from: &str, to: &str, precision: i32) -> (i32, String) {
+        let buffer = undrift_alloc(input.len());
+        let code = unsafe {
+            std::ptr::copy_nonoverlapping(input.as_ptr(), buffer, input.len());
+            let code = undrift_convert_geojson(
+                buffer,
+                input.len(),
+                from.as_ptr(),
+                from.len(),
+                to.as_ptr(),
+                to.len(),
+                precision,
+            );
+            undrift_free(buffer, input.len());
+            code
+        };
+        let output =
+            unsafe { std::slice::from_raw_parts(undrift_result_ptr(), undrift_result_len()) };
+        (code, String::from_utf8(output.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_convert_geojson() {
+        let input = r#"{"type":"FeatureCollection","features":[{"type":"Feature","properties":{},
+            "geometry":{"type":"Point","coordinates":[116.4,39.9]}}]}"#;
+        let (code, output) = call(input.as_bytes(), "GCJ-02", "wgs84", -1);
+        assert_eq!(code, UNDRIFT_OK);
+        let expected = geojson::convert(
+            &Converter::new(Gcj02, Wgs84),
+            input,
+            &GeoJsonOptions::default(),
+        );
+        assert_eq!(output, expected.unwrap());
+        let (_, rounded) = call(input.as_bytes(), "gcj02", "wgs84", 3);
+        assert!(
+            rounded.contains(r#""coordinates":[116.394,39.899]"#),
+            "{}",
+            rounded
+        );
+
+        let (code, message) = call(input.as_bytes(), "gcj02", "utm", -1);
+        assert_eq!(code, UNDRIFT_INVALID_SYSTEM);
+        assert!(message.contains("utm"), "{}", message);
+        assert_eq!(call(b"{", "gcj02", "wgs84", -1).0, UNDRIFT_INVALID_GEOJSON);
+        assert_eq!(call(b"\xff", "gcj02", "wgs84", -1).0, UNDRIFT_INVALID_UTF8);
+    }
+}
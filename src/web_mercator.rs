@@ -0,0 +1,44 @@
+//! Conversion for the spherical Web Mercator projection (EPSG:3857).
+use std::f64::consts::PI;
+
+/// Radius of the sphere used by the projection, in meters.
+pub const RADIUS: f64 = 6378137.0;
+
+/// Latitude beyond which the projection is clipped, making the world a square.
+pub const MAX_LAT: f64 = 85.051_128_779_806_59;
+
+/// Converts Web Mercator coordinates in meters to latitude and longitude.
+pub fn xy_to_latlon(x: f64, y: f64) -> (f64, f64) {
+    let lon = (x / RADIUS).to_degrees();
+    let lat = (2.0 * (y / RADIUS).exp().atan() - PI / 2.0).to_degrees();
+    (lat, lon)
+}
+
+/// Converts latitude and longitude to Web Mercator coordinates in meters.
+///
+/// Latitudes are clamped to [`MAX_LAT`], as done by web maps.
+pub fn latlon_to_xy(lat: f64, lon: f64) -> (f64, f64) {
+    let lat = lat.clamp(-MAX_LAT, MAX_LAT);
+    let x = RADIUS * lon.to_radians();
+    let y = RADIUS * (PI / 4.0 + lat.to_radians() / 2.0).tan().ln();
+    (x, y)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::eps_assert;
+
+    #[test]
+    fn test_web_mercator() {
+        let (x, y) = latlon_to_xy(39.9042, 116.4074);
+        assert!((x - 12958412.49).abs() < 0.01, "x = {}", x);
+        assert!((y - 4852030.63).abs() < 0.01, "y = {}", y);
+        let (lat, lon) = xy_to_latlon(x, y);
+        eps_assert(lat, 39.9042, "lat");
+        eps_assert(lon, 116.4074, "lon");
+
+        let (_, top) = latlon_to_xy(90.0, 0.0);
+        assert!((top - PI * RADIUS).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,392 @@
+//! A minimal XML tokenizer for the file formats.
+//!
+//! Events carry the byte ranges they were read from, so that converters can rewrite the few
+//! values they change and copy everything else byte for byte. Entities are not decoded, and DTDs
+//! are skipped.
+use crate::format::Precision;
+use crate::validate::Issue;
+use crate::Converter;
+use std::fmt;
+use std::ops::Range;
+
+/// Error returned for malformed XML, with the byte offset where it was detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlError {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid XML at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for XmlError {}
+
+/// Message of the error for non-finite coordinates rejected by the converter.
+const NON_FINITE: &str = "non-finite coordinate";
+
+/// The attributes of a tag, as written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Attributes<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Attributes<'a> {
+    /// Iterates over the names, raw values and value ranges in the document.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str, Range<usize>)> + 'a {
+        let (text, offset) = (self.text, self.offset);
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            let eq = pos + text[pos..].find('=')?;
+            let name = text[pos..eq].trim();
+            let rest = &text[eq + 1..];
+            // Unquoted values are not XML, and end the attributes.
+            let quote = rest
+                .trim_start()
+                .chars()
+                .next()
+                .filter(|c| matches!(c, '"' | '\''))?;
+            let value_start = eq + 1 + (rest.len() - rest.trim_start().len()) + 1;
+            let value_end = value_start + text[value_start..].find(quote)?;
+            pos = value_end + 1;
+            Some((
+                name,
+                &text[value_start..value_end],
+                offset + value_start..offset + value_end,
+            ))
+        })
+    }
+
+    /// Whether every attribute has a name and a quoted value.
+    fn is_valid(&self) -> bool {
+        let mut end = 0;
+        for (name, _, range) in self.iter() {
+            if name.is_empty() {
+                return false;
+            }
+            end = range.end + 1 - self.offset;
+        }
+        self.text[end..].trim().is_empty()
+    }
+
+    /// Raw value of an attribute and its range in the document.
+    pub(crate) fn get(&self, name: &str) -> Option<(&'a str, Range<usize>)> {
+        self.iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, value, range)| (value, range))
+    }
+}
+
+/// A piece of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Event<'a> {
+    Start {
+        name: &'a str,
+        attributes: Attributes<'a>,
+    },
+    /// A self-closing tag, such as `<a/>`.
+    Empty {
+        name: &'a str,
+        attributes: Attributes<'a>,
+    },
+    End {
+        name: &'a str,
+    },
+    Text(&'a str),
+    /// Comments, CDATA sections, processing instructions and declarations.
+    Other,
+}
+
+/// Iterates over the events of a document with their byte ranges, checking that tags balance.
+pub(crate) struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+    open: Vec<&'a str>,
+    failed: bool,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Reader {
+            text,
+            pos: 0,
+            open: Vec::new(),
+            failed: false,
+        }
+    }
+
+    fn error(&mut self, offset: usize, message: &'static str) -> Option<<Self as Iterator>::Item> {
+        self.failed = true;
+        Some(Err(XmlError { offset, message }))
+    }
+
+    /// Position just after `pattern`, searched from `from`.
+    fn find_end(&self, from: usize, pattern: &str) -> Option<usize> {
+        self.text[from..]
+            .find(pattern)
+            .map(|i| from + i + pattern.len())
+    }
+
+    /// End of a tag starting at `start`, skipping `>` inside quoted values.
+    fn tag_end(&self, start: usize) -> Option<usize> {
+        let mut quote = None;
+        for (i, c) in self.text[start..].char_indices() {
+            match (c, quote) {
+                ('"' | '\'', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                ('>', None) => return Some(start + i + 1),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// End of a declaration such as `<!DOCTYPE ...>`, which may contain a bracketed subset.
+    fn declaration_end(&self, start: usize) -> Option<usize> {
+        let mut depth = 0;
+        for (i, c) in self.text[start..].char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                '>' if depth == 0 => return Some(start + i + 1),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+fn is_name_end(c: char) -> bool {
+    c.is_whitespace() || c == '/' || c == '>'
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<(Event<'a>, Range<usize>), XmlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let start = self.pos;
+        let rest = &self.text[start..];
+        if rest.is_empty() {
+            if !self.open.is_empty() {
+                return self.error(start, "unclosed element");
+            }
+            return None;
+        }
+
+        if !rest.starts_with('<') {
+            let end = rest.find('<').map_or(self.text.len(), |i| start + i);
+            self.pos = end;
+            return Some(Ok((Event::Text(&self.text[start..end]), start..end)));
+        }
+
+        let (end, event) = if rest.starts_with("<!--") {
+            (self.find_end(start + 4, "-->"), Some(Event::Other))
+        } else if rest.starts_with("<![CDATA[") {
+            (self.find_end(start + 9, "]]>"), Some(Event::Other))
+        } else if rest.starts_with("<?") {
+            (self.find_end(start + 2, "?>"), Some(Event::Other))
+        } else if rest.starts_with("<!") {
+            (self.declaration_end(start), Some(Event::Other))
+        } else {
+            (self.tag_end(start), None)
+        };
+        let end = match end {
+            Some(end) => end,
+            None => return self.error(start, "unterminated markup"),
+        };
+        self.pos = end;
+        if let Some(event) = event {
+            return Some(Ok((event, start..end)));
+        }
+
+        let tag = &self.text[start + 1..end - 1];
+        let event = if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim_end();
+            if self.open.pop() != Some(name) {
+                return self.error(start, "mismatched end tag");
+            }
+            Event::End { name }
+        } else {
+            let (tag, empty) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let name_len = tag.find(is_name_end).unwrap_or(tag.len());
+            let name = &tag[..name_len];
+            if name.is_empty() {
+                return self.error(start, "missing tag name");
+            }
+            let attributes = Attributes {
+                text: &tag[name_len..],
+                offset: start + 1 + name_len,
+            };
+            if !attributes.is_valid() {
+                return self.error(start, "invalid attribute");
+            }
+            if empty {
+                Event::Empty { name, attributes }
+            } else {
+                self.open.push(name);
+                Event::Start { name, attributes }
+            }
+        };
+        Some(Ok((event, start..end)))
+    }
+}
+
+/// Name of an element without its namespace prefix.
+pub(crate) fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Range of the text without its surrounding whitespace, given the range of the text.
+pub(crate) fn trimmed(text: &str, range: Range<usize>) -> Range<usize> {
+    let start = range.start + (text.len() - text.trim_start().len());
+    let end = range.end - (text.len() - text.trim_end().len());
+    start..end.max(start)
+}
+
+/// Maps the coordinates held in attributes with `f`, which receives the local name of the element
+/// and the latitude and longitude, returning the edits to apply. `pairs` gives the latitude and
+/// longitude attribute names for each element of interest; pairs missing from an element are
+/// skipped, and so are those for which `f` returns `None`. Mapped coordinates are written with
+/// `precision`.
+pub(crate) fn map_attribute_pairs<P, F>(
+    input: &str,
+    pairs: P,
+    precision: Precision,
+    mut f: F,
+) -> Result<Vec<(Range<usize>, String)>, XmlError>
+where
+    P: Fn(&str) -> &'static [(&'static str, &'static str)],
+    F: FnMut(&str, f64, f64) -> Result<Option<(f64, f64)>, Issue>,
+{
+    let mut edits = Vec::new();
+    for event in Reader::new(input) {
+        let (event, range) = event?;
+        let (name, attributes) = match event {
+            Event::Start { name, attributes } | Event::Empty { name, attributes } => {
+                (local_name(name), attributes)
+            }
+            _ => continue,
+        };
+        for &(lat_name, lon_name) in pairs(name) {
+            let (lat, lon) = match (attributes.get(lat_name), attributes.get(lon_name)) {
+                (Some(lat), Some(lon)) => (lat, lon),
+                _ => continue,
+            };
+            let invalid = |message| XmlError {
+                offset: range.start,
+                message,
+            };
+            let mapped = f(
+                name,
+                lat.0
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("invalid coordinate"))?,
+                lon.0
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("invalid coordinate"))?,
+            )
+            .map_err(|_| invalid(NON_FINITE))?;
+            if let Some((x, y)) = mapped {
+                edits.push((lat.1, precision.format(x)));
+                edits.push((lon.1, precision.format(y)));
+            }
+        }
+    }
+    Ok(edits)
+}
+
+/// Pushes the edits converting a coordinate read from a document, written with the precision of
+/// `converter` unless its non-finite policy keeps it as written.
+pub(crate) fn convert_pair(
+    converter: &Converter,
+    lat: &(f64, Range<usize>),
+    lon: &(f64, Range<usize>),
+    edits: &mut Vec<(Range<usize>, String)>,
+) -> Result<(), XmlError> {
+    let converted = converter
+        .convert_written(lat.0, lon.0)
+        .map_err(|_| XmlError {
+            offset: lat.1.start.min(lon.1.start),
+            message: NON_FINITE,
+        })?;
+    if let Some((x, y)) = converted {
+        edits.push((lat.1.clone(), converter.precision().format(x)));
+        edits.push((lon.1.clone(), converter.precision().format(y)));
+    }
+    Ok(())
+}
+
+/// Replaces ranges of a document, which must not overlap.
+pub(crate) fn apply_edits(text: &str, mut edits: Vec<(Range<usize>, String)>) -> String {
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (range, replacement) in edits {
+        out.push_str(&text[copied..range.start]);
+        out.push_str(&replacement);
+        copied = range.end;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reader() {
+        let text = r#"<?xml version="1.0"?><!-- c --><a x="1" y='a>b'><b:c/>t&amp;</a>"#;
+        let events: Vec<_> = Reader::new(text).map(|e| e.unwrap()).collect();
+        assert_eq!(events[0].0, Event::Other);
+        assert_eq!(events[1].0, Event::Other);
+        match &events[2].0 {
+            Event::Start { name, attributes } => {
+                assert_eq!(*name, "a");
+                let (value, range) = attributes.get("y").unwrap();
+                assert_eq!(value, "a>b");
+                assert_eq!(&text[range], "a>b");
+                assert_eq!(attributes.get("x").map(|a| a.0), Some("1"));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        match &events[3].0 {
+            Event::Empty { name, .. } => assert_eq!(local_name(name), "c"),
+            e => panic!("unexpected {:?}", e),
+        }
+        assert_eq!(events[4].0, Event::Text("t&amp;"));
+        assert_eq!(events[5].0, Event::End { name: "a" });
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text| Reader::new(text).find_map(Result::err).unwrap().message;
+        assert_eq!(error("<a></b>"), "mismatched end tag");
+        assert_eq!(error("<a>"), "unclosed element");
+        assert_eq!(error("<a"), "unterminated markup");
+        assert_eq!(error("<a x=>"), "invalid attribute");
+        assert_eq!(error(r#"<a x="1" y=2/>"#), "invalid attribute");
+        assert_eq!(error(r#"<a ="1">"#), "invalid attribute");
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let text = "<a> 1 </a><b>2</b>";
+        let edits = vec![
+            (13..14, "y".to_owned()),
+            (trimmed(" 1 ", 3..6), "x".to_owned()),
+        ];
+        assert_eq!(apply_edits(text, edits), "<a> x </a><b>y</b>");
+    }
+}
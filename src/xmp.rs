@@ -0,0 +1,321 @@
+//! XMP metadata, in sidecar files or embedded in images.
+//!
+//! The `exif:GPSLatitude` and `exif:GPSLongitude` properties are rewritten, as attributes of
+//! `rdf:Description` or as elements. Their values are XMP coordinates such as `39,54.3722N` or
+//! `116,23,26.66E`; converted values keep the layout and decimals of the original, so that the
+//! length of an embedded packet rarely changes. The image data itself is never read, which suits
+//! RAW workflows keeping their edits and metadata in sidecars.
+use crate::xml::{apply_edits, local_name, trimmed, Event, Reader, XmlError};
+use crate::Converter;
+use std::ops::Range;
+
+/// Layout of an XMP coordinate: whether it has seconds, and the decimals of its last part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    seconds: bool,
+    decimals: usize,
+}
+
+/// Parses an XMP coordinate such as `39,54.3722N` into signed degrees.
+fn parse_coordinate(s: &str) -> Option<(f64, Layout)> {
+    let s = s.trim();
+    let hemisphere = s.chars().next_back()?;
+    let sign = match hemisphere.to_ascii_uppercase() {
+        'N' | 'E' => 1.0,
+        'S' | 'W' => -1.0,
+        _ => return None,
+    };
+    let parts: Vec<&str> = s[..s.len() - 1].split(',').map(str::trim).collect();
+    let number = |part: &str| -> Option<f64> {
+        match part.parse::<f64>() {
+            Ok(x) if x.is_finite() && x >= 0.0 => Some(x),
+            _ => None,
+        }
+    };
+    let degrees = match parts.as_slice() {
+        [d, m] => number(d)? + number(m)? / 60.0,
+        [d, m, s] => number(d)? + number(m)? / 60.0 + number(s)? / 3600.0,
+        _ => return None,
+    };
+    let last = parts[parts.len() - 1];
+    let layout = Layout {
+        seconds: parts.len() == 3,
+        decimals: last.find('.').map_or(0, |dot| last.len() - dot - 1),
+    };
+    Some((sign * degrees, layout))
+}
+
+/// Formats signed degrees as an XMP coordinate with the given layout and hemisphere letters.
+fn format_coordinate(value: f64, layout: Layout, hemispheres: [char; 2]) -> String {
+    let hemisphere = hemispheres[(value < 0.0) as usize];
+    let value = value.abs();
+    let scale = 10f64.powi(layout.decimals as i32);
+    // Rounded in units of the last digit, so that carries reach the degrees.
+    let unit = if layout.seconds { 3600.0 } else { 60.0 };
+    let total = (value * unit * scale).round() / scale;
+    let degrees = (total / unit).floor();
+    let rest = total - degrees * unit;
+    let precision = layout.decimals;
+    if layout.seconds {
+        let minutes = (rest / 60.0).floor();
+        let seconds = rest - minutes * 60.0;
+        format!(
+            "{},{},{:.*}{}",
+            degrees, minutes, precision, seconds, hemisphere
+        )
+    } else {
+        format!("{},{:.*}{}", degrees, precision, rest, hemisphere)
+    }
+}
+
+/// A coordinate property of the document, with the range of its value.
+struct Property {
+    value: f64,
+    layout: Layout,
+    range: Range<usize>,
+}
+
+/// Finds the latitude and longitude properties, paired in order.
+fn find(input: &str) -> Result<Vec<(Property, Property)>, XmlError> {
+    let mut lats = Vec::new();
+    let mut lons = Vec::new();
+    let mut element: Option<(bool, usize)> = None;
+    let mut push = |is_lat: bool, value: &str, range: Range<usize>, offset| {
+        let (value, layout) = parse_coordinate(value).ok_or(XmlError {
+            offset,
+            message: "invalid coordinate",
+        })?;
+        let property = Property {
+            value,
+            layout,
+            range,
+        };
+        if is_lat {
+            lats.push(property);
+        } else {
+            lons.push(property);
+        }
+        Ok(())
+    };
+    let kind = |name: &str| match local_name(name) {
+        "GPSLatitude" => Some(true),
+        "GPSLongitude" => Some(false),
+        _ => None,
+    };
+    for event in Reader::new(input) {
+        let (event, range) = event?;
+        match event {
+            Event::Start { name, attributes } | Event::Empty { name, attributes } => {
+                for (name, value, value_range) in attributes.iter() {
+                    if let Some(is_lat) = kind(name) {
+                        push(is_lat, value, value_range, range.start)?;
+                    }
+                }
+                if let Event::Start { .. } = event {
+                    element = kind(name).map(|is_lat| (is_lat, range.start));
+                }
+            }
+            Event::Text(text) => {
+                if let Some((is_lat, offset)) = element.take() {
+                    push(is_lat, text, trimmed(text, range), offset)?;
+                }
+            }
+            _ => element = None,
+        }
+    }
+    if lats.len() != lons.len() {
+        return Err(XmlError {
+            offset: 0,
+            message: "missing coordinate",
+        });
+    }
+    Ok(lats.into_iter().zip(lons).collect())
+}
+
+/// Reads the GPS positions of an XMP document, in order.
+pub fn positions(input: &str) -> Result<Vec<(f64, f64)>, XmlError> {
+    Ok(find(input)?
+        .into_iter()
+        .map(|(lat, lon)| (lat.value, lon.value))
+        .collect())
+}
+
+/// Converts the GPS positions of an XMP document, such as a sidecar file.
+pub fn convert(converter: &Converter, input: &str) -> Result<String, XmlError> {
+    let mut edits = Vec::new();
+    for (lat, lon) in find(input)? {
+        let converted = converter
+            .convert_written(lat.value, lon.value)
+            .map_err(|_| XmlError {
+                offset: lat.range.start.min(lon.range.start),
+                message: "non-finite coordinate",
+            })?;
+        if let Some((x, y)) = converted {
+            edits.push((lat.range, format_coordinate(x, lat.layout, ['N', 'S'])));
+            edits.push((lon.range, format_coordinate(y, lon.layout, ['E', 'W'])));
+        }
+    }
+    Ok(apply_edits(input, edits))
+}
+
+/// Converts the XMP packets embedded in a file, such as a JPEG or a DNG, in place, returning the
+/// number of packets.
+///
+/// Packets are found by their `<?xpacket` markers. A packet growing or shrinking takes or gives
+/// back the whitespace padding before its end marker, so that the file keeps its layout; the
+/// error offsets are in `data`.
+pub fn convert_embedded(converter: &Converter, data: &mut [u8]) -> Result<usize, XmlError> {
+    const BEGIN: &[u8] = b"<?xpacket begin";
+    const END: &[u8] = b"<?xpacket end";
+    let search = |data: &[u8], from: usize, pattern: &[u8]| {
+        data[from..]
+            .windows(pattern.len())
+            .position(|w| w == pattern)
+            .map(|i| from + i)
+    };
+    let mut count = 0;
+    let mut pos = 0;
+    while let Some(start) = search(data, pos, BEGIN) {
+        let at = |offset: usize| {
+            move |e: XmlError| XmlError {
+                offset: offset + e.offset,
+                ..e
+            }
+        };
+        let end = search(data, start, END).ok_or(XmlError {
+            offset: start,
+            message: "unterminated XMP packet",
+        })?;
+        let text = std::str::from_utf8(&data[start..end]).map_err(|e| XmlError {
+            offset: start + e.valid_up_to(),
+            message: "invalid UTF-8",
+        })?;
+        // The padding is not part of the document, which ends at the end marker.
+        let document = text.trim_end();
+        let padding = text.len() - document.len();
+        let converted = convert(converter, document).map_err(at(start))?;
+        if converted.len() > text.len() {
+            return Err(XmlError {
+                offset: start,
+                message: "XMP packet without room to grow",
+            });
+        }
+        // Whitespace after the document is kept as far as it fits, spaces fill the rest.
+        let keep = padding.min(text.len() - converted.len());
+        let mut packet = converted.into_bytes();
+        packet.resize(text.len() - keep, b' ');
+        packet.extend_from_slice(&text.as_bytes()[text.len() - keep..]);
+        data[start..end].copy_from_slice(&packet);
+        count += 1;
+        pos = end + END.len();
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    const SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:exif="http://ns.adobe.com/exif/1.0/"
+    exif:GPSLatitude="39,54.3722N" exif:GPSLongitude="116,23,26.66E" exif:GPSAltitude="500/10"/>
+  <rdf:Description rdf:about="">
+   <exif:GPSLatitude> 31,13.9800N </exif:GPSLatitude>
+   <exif:GPSLongitude>121,28.4220E</exif:GPSLongitude>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn test_coordinates() {
+        let (value, layout) = parse_coordinate("39,54.3722N").unwrap();
+        assert!((value - (39.0 + 54.3722 / 60.0)).abs() < 1e-12);
+        assert_eq!(
+            layout,
+            Layout {
+                seconds: false,
+                decimals: 4
+            }
+        );
+        assert_eq!(format_coordinate(value, layout, ['N', 'S']), "39,54.3722N");
+        let (value, layout) = parse_coordinate("33,51,54.0S").unwrap();
+        assert_eq!(format_coordinate(value, layout, ['N', 'S']), "33,51,54.0S");
+        assert_eq!(
+            format_coordinate(39.99999999, layout, ['N', 'S']),
+            "40,0,0.0N"
+        );
+        assert_eq!(parse_coordinate("39.5"), None);
+        assert_eq!(parse_coordinate("39,-5N"), None);
+    }
+
+    #[test]
+    fn test_convert() {
+        let positions = positions(SIDECAR).unwrap();
+        assert_eq!(positions.len(), 2);
+        assert!((positions[1].1 - (121.0 + 28.422 / 60.0)).abs() < 1e-12);
+
+        let converter = Converter::new(Gcj02, Wgs84);
+        let output = convert(&converter, SIDECAR).unwrap();
+        for (&(lat, lon), converted) in positions.iter().zip(super::positions(&output).unwrap()) {
+            let expected = converter.convert(lat, lon);
+            // Rounded to the precision of the original.
+            assert!((converted.0 - expected.0).abs() < 1e-6, "{:?}", converted);
+            assert!((converted.1 - expected.1).abs() < 1e-6, "{:?}", converted);
+        }
+        assert!(output.contains(r#"exif:GPSAltitude="500/10"/>"#));
+        assert_eq!(output.lines().count(), SIDECAR.lines().count());
+    }
+
+    #[test]
+    fn test_embedded() {
+        let packet = format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>{}\n{}\n<?xpacket end=\"w\"?>",
+            SIDECAR,
+            " ".repeat(20)
+        );
+        let mut data = b"\xff\xd8\xff\xe1JPEG header ".to_vec();
+        data.extend_from_slice(packet.as_bytes());
+        data.extend_from_slice(b"\xff\xd9");
+        let original = data.clone();
+
+        let converter = Converter::new(Gcj02, Wgs84);
+        assert_eq!(convert_embedded(&converter, &mut data), Ok(1));
+        assert_eq!(data.len(), original.len());
+        assert!(data.starts_with(b"\xff\xd8\xff\xe1JPEG header "));
+        assert!(data.ends_with(b"\n<?xpacket end=\"w\"?>\xff\xd9"));
+        let text = String::from_utf8_lossy(&data[16..data.len() - 2]).into_owned();
+        assert_eq!(
+            positions(&text).unwrap(),
+            positions(&convert(&converter, &packet).unwrap()).unwrap()
+        );
+
+        let mut unchanged = b"no metadata".to_vec();
+        assert_eq!(convert_embedded(&converter, &mut unchanged), Ok(0));
+        let mut truncated = b"<?xpacket begin=\"\"?><x:xmpmeta/>".to_vec();
+        assert_eq!(
+            convert_embedded(&converter, &mut truncated)
+                .unwrap_err()
+                .message,
+            "unterminated XMP packet"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            positions(r#"<rdf:Description exif:GPSLatitude="39,54.3722N"/>"#),
+            Err(XmlError {
+                offset: 0,
+                message: "missing coordinate"
+            })
+        );
+        assert_eq!(
+            positions(r#"<a exif:GPSLatitude="x" exif:GPSLongitude="116,23E"/>"#)
+                .unwrap_err()
+                .message,
+            "invalid coordinate"
+        );
+    }
+}
@@ -0,0 +1,1586 @@
+//! Streaming access to ZIP archives, such as KMZ files and Google Takeout exports.
+//!
+//! [`ZipReader`] reads the entries one after the other by following their local headers, so an
+//! archive can be converted straight from a pipe, holding only one entry in memory at a time, and
+//! none of those it copies unchanged. [`ZipWriter`] writes the entries as they come, followed by
+//! the central directory. Stored and deflated entries can be read, including ZIP64 ones;
+//! encrypted entries cannot. Entries decompressing past a limit are rejected as decompression
+//! bombs. Written entries are deflated with a simple compressor and must each stay below 4 GiB, as
+//! must the archive.
+//!
+//! [`check_archive`] goes through an archive as [`convert_archive`] does without writing anything,
+//! reporting what each entry is read as, its points and whether its conversion would fail, so that
+//! a job can be checked before it replaces the files it converts. [`convert_archive_resumable`]
+//! records the entries written in a manifest, from which an interrupted conversion carries on.
+use crate::audit::{AuditReport, Auditor};
+use crate::Converter;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, Mutex};
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    crc32_continue(0, data)
+}
+
+/// The CRC-32 of every byte, with the reflected polynomial of ZIP, gzip and PNG.
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            bit += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+/// Continues the CRC-32 `crc` of the data before with `data`.
+pub(crate) fn crc32_continue(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &b| {
+        CRC_TABLE[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Bit 3 of the flags: the sizes and CRC follow the data.
+const FLAG_DESCRIPTOR: u16 = 1 << 3;
+/// Bit 11 of the flags: the name is UTF-8.
+const FLAG_UTF8: u16 = 1 << 11;
+
+/// Largest distance of a deflate match.
+const WINDOW: usize = 32768;
+
+/// Largest size an entry is decompressed to by default, 256 MiB, past which it is taken for a
+/// decompression bomb.
+pub const MAX_INFLATED: u64 = 256 << 20;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// A canonical Huffman code, as the number of codes of each length and the symbols in code
+/// order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn fixed() -> (Self, Self) {
+        let mut lengths = [0u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+    }
+}
+
+/// Reads bits, least significant first, without consuming bytes past the last one used.
+struct BitReader<'a, R> {
+    reader: &'a mut R,
+    buffer: u32,
+    bits: u32,
+}
+
+impl<'a, R: BufRead> BitReader<'a, R> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let byte = match self.reader.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+        self.reader.consume(1);
+        Ok(byte)
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.bits < n {
+            self.buffer |= u32::from(self.byte()?) << self.bits;
+            self.bits += 8;
+        }
+        let value = self.buffer & ((1u64 << n) - 1) as u32;
+        self.buffer >>= n;
+        self.bits -= n;
+        Ok(value)
+    }
+
+    fn decode(&mut self, code: &Huffman) -> io::Result<u16> {
+        let (mut value, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            value |= self.bits(1)? as i32;
+            let count = i32::from(code.counts[len]);
+            if value - count < first {
+                return Ok(code.symbols[(index + value - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            value <<= 1;
+        }
+        Err(invalid("invalid deflate code"))
+    }
+}
+
+/// The output of [`inflate_to`], keeping the last [`WINDOW`] bytes for matches to copy.
+struct Output<'a> {
+    window: Vec<u8>,
+    sink: &'a mut dyn Write,
+    /// Bytes that can still be written to the sink.
+    remaining: u64,
+}
+
+impl Output<'_> {
+    fn push(&mut self, byte: u8) -> io::Result<()> {
+        if self.window.len() >= 2 * WINDOW {
+            self.flush(WINDOW)?;
+        }
+        self.window.push(byte);
+        Ok(())
+    }
+
+    /// Writes all but the last `keep` bytes to the sink.
+    fn flush(&mut self, keep: usize) -> io::Result<()> {
+        let n = self.window.len().saturating_sub(keep);
+        self.remaining = self
+            .remaining
+            .checked_sub(n as u64)
+            .ok_or_else(|| invalid("inflated data too large"))?;
+        self.sink.write_all(&self.window[..n])?;
+        self.window.drain(..n);
+        Ok(())
+    }
+}
+
+/// Decompresses a raw deflate stream of at most `limit` bytes.
+pub(crate) fn inflate<R: BufRead>(reader: &mut R, limit: u64) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    inflate_to(reader, &mut out, limit)?;
+    Ok(out)
+}
+
+/// Decompresses a raw deflate stream into `sink`, with a fixed amount of memory. Fails with
+/// [`io::ErrorKind::InvalidData`] past `limit` bytes.
+fn inflate_to<R: BufRead>(reader: &mut R, sink: &mut dyn Write, limit: u64) -> io::Result<()> {
+    let mut input = BitReader {
+        reader,
+        buffer: 0,
+        bits: 0,
+    };
+    let mut out = Output {
+        window: Vec::with_capacity(2 * WINDOW),
+        sink,
+        remaining: limit,
+    };
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.buffer = 0;
+                input.bits = 0;
+                let len = input.bits(16)?;
+                if input.bits(16)? != !len & 0xffff {
+                    return Err(invalid("invalid stored block"));
+                }
+                for _ in 0..len {
+                    out.push(input.byte()?)?;
+                }
+            }
+            1 => {
+                let (literals, distances) = Huffman::fixed();
+                inflate_block(&mut input, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut input)?;
+                inflate_block(&mut input, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(invalid("invalid deflate block")),
+        }
+        if last {
+            return out.flush(0);
+        }
+    }
+}
+
+fn dynamic_codes<R: BufRead>(input: &mut BitReader<R>) -> io::Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let literals = input.bits(5)? as usize + 257;
+    let distances = input.bits(5)? as usize + 1;
+    let code_lengths = input.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &i in &ORDER[..code_lengths] {
+        lengths[i] = input.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (value, repeat) = match input.decode(&code)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&previous) => (previous, 3 + input.bits(2)?),
+                None => return Err(invalid("invalid deflate lengths")),
+            },
+            17 => (0, 3 + input.bits(3)?),
+            _ => (0, 11 + input.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() != literals + distances {
+        return Err(invalid("invalid deflate lengths"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+fn inflate_block<R: BufRead>(
+    input: &mut BitReader<R>,
+    out: &mut Output,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> io::Result<()> {
+    loop {
+        let symbol = input.decode(literals)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8)?,
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                if i >= 29 {
+                    return Err(invalid("invalid deflate length"));
+                }
+                let len =
+                    LENGTH_BASE[i] as usize + input.bits(u32::from(LENGTH_EXTRA[i]))? as usize;
+                let d = input.decode(distances)? as usize;
+                if d >= 30 {
+                    return Err(invalid("invalid deflate distance"));
+                }
+                let distance =
+                    DISTANCE_BASE[d] as usize + input.bits(u32::from(DISTANCE_EXTRA[d]))? as usize;
+                if distance > out.window.len() {
+                    return Err(invalid("invalid deflate distance"));
+                }
+                for _ in 0..len {
+                    out.push(out.window[out.window.len() - distance])?;
+                }
+            }
+        }
+    }
+}
+
+/// Writes bits, least significant first.
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, n: u32) {
+        self.buffer |= value << self.bits;
+        self.bits += n;
+        while self.bits >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, most significant bit first.
+    fn code(&mut self, code: u32, len: u32) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.bits(reversed, len);
+    }
+
+    fn literal(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+}
+
+/// Compresses with greedy LZ77 matching and the fixed Huffman codes of deflate.
+pub(crate) fn deflate(data: &[u8]) -> Vec<u8> {
+    const HASH_SIZE: usize = 1 << 15;
+    const MAX_CHAIN: usize = 64;
+    let hash = |i: usize| {
+        let v = u32::from(data[i]) << 16 | u32::from(data[i + 1]) << 8 | u32::from(data[i + 2]);
+        (v.wrapping_mul(0x9e37_79b1) >> 17) as usize
+    };
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    // The chains of the positions in the window, by position modulo its size: a position is only
+    // overwritten once it is out of reach.
+    let mut previous = vec![usize::MAX; WINDOW];
+    let insert = |i: usize, head: &mut Vec<usize>, previous: &mut Vec<usize>| {
+        if i + 3 <= data.len() {
+            let h = hash(i);
+            previous[i % WINDOW] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut out = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        buffer: 0,
+        bits: 0,
+    };
+    // A single final block with the fixed codes.
+    out.bits(0b011, 3);
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_distance) = (0, 0);
+        if i + 3 <= data.len() {
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let max = (data.len() - i).min(258);
+                let len = (0..max)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_distance = i - candidate;
+                    if len == max {
+                        break;
+                    }
+                }
+                candidate = previous[candidate % WINDOW];
+                chain += 1;
+            }
+        }
+        if best_len >= 3 {
+            let l = LENGTH_BASE
+                .iter()
+                .rposition(|&b| b as usize <= best_len)
+                .unwrap();
+            out.literal(257 + l as u32);
+            out.bits(
+                (best_len - LENGTH_BASE[l] as usize) as u32,
+                u32::from(LENGTH_EXTRA[l]),
+            );
+            let d = DISTANCE_BASE
+                .iter()
+                .rposition(|&b| b as usize <= best_distance)
+                .unwrap();
+            out.code(d as u32, 5);
+            out.bits(
+                (best_distance - DISTANCE_BASE[d] as usize) as u32,
+                u32::from(DISTANCE_EXTRA[d]),
+            );
+            for k in i..i + best_len {
+                insert(k, &mut head, &mut previous);
+            }
+            i += best_len;
+        } else {
+            out.literal(u32::from(data[i]));
+            insert(i, &mut head, &mut previous);
+            i += 1;
+        }
+    }
+    out.literal(256);
+    out.bits(0, 7);
+    out.out
+}
+
+/// An entry of an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The name, with the bytes that are not UTF-8 read as Latin-1 if the archive does not mark it
+    /// as UTF-8: older tools write names in CP437 or in the code page of the system, such as GBK.
+    pub name: String,
+    /// The name as stored in the archive.
+    pub raw_name: Vec<u8>,
+    /// Whether the archive marks the name as UTF-8.
+    pub utf8: bool,
+    pub data: Vec<u8>,
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from(read_u32(bytes, at)) | u64::from(read_u32(bytes, at + 4)) << 32
+}
+
+/// The local header of an entry.
+struct Header {
+    name: Vec<u8>,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+    zip64: bool,
+}
+
+impl Header {
+    fn name(&self) -> String {
+        String::from_utf8(self.name.clone())
+            .unwrap_or_else(|e| e.into_bytes().iter().map(|&b| b as char).collect())
+    }
+
+    /// Reads the compressed data of the entry from `source`, writing the decompressed data to
+    /// `sink`.
+    fn data<S: BufRead>(&self, source: &mut S, sink: &mut dyn Write, limit: u64) -> io::Result<()> {
+        let too_large = || invalid("ZIP entry too large");
+        match self.method {
+            STORED => {
+                if self.compressed > limit {
+                    return Err(too_large());
+                }
+                let copied = io::copy(&mut source.take(self.compressed), sink)?;
+                if copied < self.compressed {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            _ if self.flags & FLAG_DESCRIPTOR != 0 => inflate_to(source, sink, limit)?,
+            _ => {
+                let mut compressed = source.take(self.compressed);
+                inflate_to(&mut compressed, sink, limit)?;
+                // Skip any padding the compressor left after the final block.
+                io::copy(&mut compressed, &mut io::sink())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A writer computing the CRC-32 and size of what goes through.
+struct Checksum<W> {
+    inner: W,
+    crc: u32,
+    size: u64,
+}
+
+impl<W: Write> Write for Checksum<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc = crc32_continue(self.crc, &buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader also writing the bytes consumed from it to `copy`.
+struct Tee<'a, R> {
+    reader: &'a mut R,
+    copy: &'a mut dyn Write,
+    pending: Vec<u8>,
+    copied: u64,
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead> Tee<'_, R> {
+    fn write_pending(&mut self) {
+        if self.error.is_none() {
+            self.error = self.copy.write_all(&self.pending).err();
+        }
+        self.copied += self.pending.len() as u64;
+        self.pending.clear();
+    }
+
+    /// Writes what is left, returning the number of bytes copied.
+    fn finish(mut self) -> io::Result<u64> {
+        self.write_pending();
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.copied),
+        }
+    }
+}
+
+impl<R: BufRead> Read for Tee<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Tee<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, n: usize) {
+        if let Ok(buffer) = self.reader.fill_buf() {
+            self.pending.extend_from_slice(&buffer[..n]);
+        }
+        self.reader.consume(n);
+        if self.pending.len() >= WINDOW {
+            self.write_pending();
+        }
+    }
+}
+
+/// Reads the entries of an archive in order.
+pub struct ZipReader<R> {
+    reader: R,
+    done: bool,
+    max_size: u64,
+}
+
+impl<R: BufRead> ZipReader<R> {
+    pub fn new(reader: R) -> Self {
+        ZipReader {
+            reader,
+            done: false,
+            max_size: MAX_INFLATED,
+        }
+    }
+
+    /// Sets the largest size entries are read to, [`MAX_INFLATED`] by default. Larger entries
+    /// fail with [`io::ErrorKind::InvalidData`].
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Reads the next entry, or `None` after the last one. Directories are returned as empty
+    /// entries with names ending in `/`.
+    pub fn next_entry(&mut self) -> io::Result<Option<Entry>> {
+        let header = match self.header()? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let mut data = Checksum {
+            inner: Vec::new(),
+            crc: 0,
+            size: 0,
+        };
+        header.data(&mut self.reader, &mut data, self.max_size)?;
+        self.check(&header, data.crc)?;
+        Ok(Some(Entry {
+            name: header.name(),
+            utf8: header.flags & FLAG_UTF8 != 0,
+            raw_name: header.name,
+            data: data.inner,
+        }))
+    }
+
+    /// Reads the local header of the next entry, up to its data.
+    fn header(&mut self) -> io::Result<Option<Header>> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut signature = [0; 4];
+        self.reader.read_exact(&mut signature)?;
+        match u32::from_le_bytes(signature) {
+            LOCAL_HEADER => {}
+            CENTRAL_HEADER | END_OF_DIRECTORY => {
+                self.done = true;
+                return Ok(None);
+            }
+            _ => return Err(invalid("invalid ZIP header")),
+        }
+
+        let mut header = [0; 26];
+        self.reader.read_exact(&mut header)?;
+        let flags = read_u16(&header, 2);
+        let method = read_u16(&header, 4);
+        let crc = read_u32(&header, 10);
+        let mut compressed = u64::from(read_u32(&header, 14));
+        let mut size = u64::from(read_u32(&header, 18));
+        let mut name = vec![0; read_u16(&header, 22) as usize];
+        let mut extra = vec![0; read_u16(&header, 24) as usize];
+        self.reader.read_exact(&mut name)?;
+        self.reader.read_exact(&mut extra)?;
+        if flags & 1 != 0 {
+            return Err(invalid("encrypted ZIP entries are not supported"));
+        }
+        match method {
+            STORED if flags & FLAG_DESCRIPTOR != 0 => {
+                return Err(invalid("stored ZIP entry without sizes"))
+            }
+            STORED | DEFLATED => {}
+            _ => return Err(invalid("unsupported ZIP compression method")),
+        }
+
+        // The ZIP64 extra field holds the sizes that do not fit in the header.
+        let mut zip64 = false;
+        let mut at = 0;
+        while at + 4 <= extra.len() {
+            let (id, len) = (read_u16(&extra, at), read_u16(&extra, at + 2) as usize);
+            let field = &extra[at + 4..(at + 4 + len).min(extra.len())];
+            if id == 1 {
+                zip64 = true;
+                let mut next = 0;
+                for value in [&mut size, &mut compressed] {
+                    if *value == u64::from(u32::MAX) && next + 8 <= field.len() {
+                        *value = read_u64(field, next);
+                        next += 8;
+                    }
+                }
+            }
+            at += 4 + len;
+        }
+        Ok(Some(Header {
+            name,
+            flags,
+            method,
+            crc,
+            compressed,
+            size,
+            zip64,
+        }))
+    }
+
+    /// Reads the data of an entry without keeping it, checking its CRC.
+    fn read_through(&mut self, header: &Header) -> io::Result<()> {
+        let mut data = Checksum {
+            inner: io::sink(),
+            crc: 0,
+            size: 0,
+        };
+        header.data(&mut self.reader, &mut data, u64::MAX)?;
+        self.check(header, data.crc)
+    }
+
+    /// Reads any data descriptor after the data of an entry, and checks the CRC of the data.
+    fn check(&mut self, header: &Header, crc: u32) -> io::Result<()> {
+        let mut expected = header.crc;
+        if header.flags & FLAG_DESCRIPTOR != 0 {
+            let mut descriptor = [0; 24];
+            let len = if header.zip64 { 20 } else { 12 };
+            self.reader.read_exact(&mut descriptor[..4])?;
+            let start = if read_u32(&descriptor, 0) == DATA_DESCRIPTOR {
+                self.reader.read_exact(&mut descriptor[4..4 + len])?;
+                4
+            } else {
+                self.reader.read_exact(&mut descriptor[4..len])?;
+                0
+            };
+            expected = read_u32(&descriptor, start);
+        }
+        if crc != expected {
+            return Err(invalid("ZIP entry checksum mismatch"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for ZipReader<R> {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.next_entry().transpose();
+        if let Some(Err(_)) = entry {
+            self.done = true;
+        }
+        entry
+    }
+}
+
+/// Writes an archive entry by entry.
+pub struct ZipWriter<W> {
+    writer: W,
+    offset: u64,
+    central: Vec<u8>,
+    entries: u16,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(writer: W) -> Self {
+        ZipWriter {
+            writer,
+            offset: 0,
+            central: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    /// Appends an entry, deflated unless that would make it larger.
+    pub fn write_entry(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.write_named_entry(name.as_bytes(), true, data)
+    }
+
+    /// Appends an entry with a name that is not necessarily UTF-8, such as the
+    /// [`Entry::raw_name`] of an entry read from another archive, so that its name is kept.
+    pub fn write_named_entry(&mut self, name: &[u8], utf8: bool, data: &[u8]) -> io::Result<()> {
+        let compressed = deflate(data);
+        let (method, body) = if compressed.len() < data.len() {
+            (DEFLATED, &compressed[..])
+        } else {
+            (STORED, data)
+        };
+        let flags = if utf8 { FLAG_UTF8 } else { 0 };
+        let fields = fields(
+            flags,
+            method,
+            crc32(data),
+            body.len() as u64,
+            data.len() as u64,
+            name,
+        )?;
+        let offset = self.start(&fields, name)?;
+        self.writer.write_all(body)?;
+        self.end(&fields, name, offset, body.len() as u64)
+    }
+
+    /// Copies the next entry of `reader` without decompressing it, checking its CRC on the way.
+    fn copy_entry<R: BufRead>(
+        &mut self,
+        reader: &mut ZipReader<R>,
+        header: &Header,
+    ) -> io::Result<()> {
+        // Without the sizes up front, they follow the data in a descriptor, as in the original.
+        let descriptor = header.flags & FLAG_DESCRIPTOR != 0;
+        let flags = header.flags & (FLAG_UTF8 | FLAG_DESCRIPTOR);
+        let (crc, compressed, size) = if descriptor {
+            (0, 0, 0)
+        } else {
+            (header.crc, header.compressed, header.size)
+        };
+        let local = fields(flags, header.method, crc, compressed, size, &header.name)?;
+        let offset = self.start(&local, &header.name)?;
+
+        let mut data = Checksum {
+            inner: io::sink(),
+            crc: 0,
+            size: 0,
+        };
+        let mut tee = Tee {
+            reader: &mut reader.reader,
+            copy: &mut self.writer,
+            pending: Vec::new(),
+            copied: 0,
+            error: None,
+        };
+        header.data(&mut tee, &mut data, u64::MAX)?;
+        let compressed = tee.finish()?;
+        reader.check(header, data.crc)?;
+
+        let fields = fields(
+            flags,
+            header.method,
+            data.crc,
+            compressed,
+            data.size,
+            &header.name,
+        )?;
+        let mut len = compressed;
+        if descriptor {
+            self.writer.write_all(&DATA_DESCRIPTOR.to_le_bytes())?;
+            self.writer.write_all(&fields[10..22])?;
+            len += 16;
+        }
+        self.end(&fields, &header.name, offset, len)
+    }
+
+    /// Writes a local header, returning its offset.
+    fn start(&mut self, fields: &[u8], name: &[u8]) -> io::Result<u32> {
+        let offset = u32::try_from(self.offset).map_err(|_| invalid("ZIP archive too large"))?;
+        self.entries = self
+            .entries
+            .checked_add(1)
+            .ok_or_else(|| invalid("ZIP archive too large"))?;
+        self.writer.write_all(&LOCAL_HEADER.to_le_bytes())?;
+        self.writer.write_all(fields)?;
+        self.writer.write_all(name)?;
+        Ok(offset)
+    }
+
+    /// Adds the entry to the central directory, after `len` bytes of data.
+    fn end(&mut self, fields: &[u8], name: &[u8], offset: u32, len: u64) -> io::Result<()> {
+        self.central
+            .extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        self.central.extend_from_slice(&20u16.to_le_bytes());
+        self.central.extend_from_slice(fields);
+        // No comment, disk 0, no attributes.
+        self.central.extend_from_slice(&[0; 10]);
+        self.central.extend_from_slice(&offset.to_le_bytes());
+        self.central.extend_from_slice(name);
+
+        self.offset += 30 + name.len() as u64 + len;
+        Ok(())
+    }
+
+    /// Writes the central directory, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let offset = u32::try_from(self.offset).map_err(|_| invalid("ZIP archive too large"))?;
+        self.writer.write_all(&self.central)?;
+        self.writer.write_all(&END_OF_DIRECTORY.to_le_bytes())?;
+        self.writer.write_all(&[0; 4])?;
+        self.writer.write_all(&self.entries.to_le_bytes())?;
+        self.writer.write_all(&self.entries.to_le_bytes())?;
+        self.writer
+            .write_all(&(self.central.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&offset.to_le_bytes())?;
+        self.writer.write_all(&[0; 2])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// The fields of a header shared by the local and central ones: version, flags, method, time and
+/// date (1980-01-01), CRC, sizes, name length and no extra field.
+fn fields(
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+    name: &[u8],
+) -> io::Result<Vec<u8>> {
+    let too_large = || invalid("ZIP archive too large");
+    let compressed = u32::try_from(compressed).map_err(|_| too_large())?;
+    let size = u32::try_from(size).map_err(|_| too_large())?;
+    let name_len = u16::try_from(name.len()).map_err(|_| invalid("ZIP entry name too long"))?;
+    let mut fields = Vec::with_capacity(26);
+    fields.extend_from_slice(&20u16.to_le_bytes());
+    fields.extend_from_slice(&flags.to_le_bytes());
+    fields.extend_from_slice(&method.to_le_bytes());
+    fields.extend_from_slice(&[0, 0, 0x21, 0]);
+    fields.extend_from_slice(&crc.to_le_bytes());
+    fields.extend_from_slice(&compressed.to_le_bytes());
+    fields.extend_from_slice(&size.to_le_bytes());
+    fields.extend_from_slice(&name_len.to_le_bytes());
+    fields.extend_from_slice(&0u16.to_le_bytes());
+    Ok(fields)
+}
+
+/// Converts an entry by the extension of its name: `.kml`, `.gpx`, `.tcx`, `.osm`, `.gml`,
+/// `.geojson`, `.topojson` and `.fit` files are converted, as well as the `.json` files of a
+/// `Location History` folder of Google Takeout. Others are returned unchanged.
+///
+/// Gzipped files, such as `.geojson.gz`, are converted by the extension before `.gz` and
+/// compressed again.
+pub fn convert_entry(converter: &Converter, name: &str, data: Vec<u8>) -> io::Result<Vec<u8>> {
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) => extension.to_ascii_lowercase(),
+        None => return Ok(data),
+    };
+    let text = |data: Vec<u8>| String::from_utf8(data).map_err(|_| invalid("entry is not UTF-8"));
+    let xml = |result: Result<String, crate::XmlError>| {
+        result
+            .map(String::into_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+    match extension.as_str() {
+        "kml" => xml(crate::kml::convert(converter, &text(data)?)),
+        "gpx" => xml(crate::gpx::convert(converter, &text(data)?)),
+        "tcx" => xml(crate::tcx::convert(converter, &text(data)?)),
+        "osm" => xml(crate::osm::convert(converter, &text(data)?)),
+        "gml" => xml(crate::gml::convert(converter, &text(data)?)),
+        "geojson" => crate::geojson::convert(converter, &text(data)?, &Default::default())
+            .map(String::into_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        "topojson" => crate::topojson::convert(converter, &text(data)?)
+            .map(String::into_bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        "json" if name.contains("Location History") => {
+            crate::takeout::convert(converter, &text(data)?)
+                .map(String::into_bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+        "gz" => {
+            let inner = &name[..name.len() - 3];
+            let decompressed = crate::gzip::decompress(&data)?;
+            let converted = convert_entry(converter, inner, decompressed.clone())?;
+            if converted == decompressed {
+                Ok(data)
+            } else {
+                Ok(crate::gzip::compress(&converted))
+            }
+        }
+        "fit" => {
+            let mut data = data;
+            crate::fit::convert(converter, &mut data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(data)
+        }
+        _ => Ok(data),
+    }
+}
+
+/// The format an entry is converted as by [`convert_entry`], by the extension before any `.gz`.
+fn entry_format(name: &str) -> Option<&'static str> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    let format = match extension.to_ascii_lowercase().as_str() {
+        "kml" => "kml",
+        "gpx" => "gpx",
+        "tcx" => "tcx",
+        "osm" => "osm",
+        "gml" => "gml",
+        "geojson" => "geojson",
+        "topojson" => "topojson",
+        "json" if name.contains("Location History") => "json",
+        "fit" => "fit",
+        "gz" => return entry_format(stem),
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// What [`convert_entry`] would do to an entry, as found by [`check_entry`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryCheck {
+    pub name: String,
+    /// Extension of the format the entry is converted as, such as `"gpx"` for `track.gpx.gz` and
+    /// `"json"` for Location History; `None` for the entries copied unchanged.
+    pub format: Option<&'static str>,
+    /// Summary of the coordinates the converter was given while converting the entry, with its
+    /// region, policies and algorithm. A conversion that fails stops the summary where it stops.
+    pub audit: AuditReport,
+    /// Whether converting the entry changes it.
+    pub changed: bool,
+    /// Why converting the entry fails, if it does.
+    pub error: Option<String>,
+}
+
+/// Converts an entry as [`convert_entry`] does and reports the outcome, discarding the output.
+/// Files outside of an archive are checked the same way by their name.
+pub fn check_entry(converter: &Converter, name: &str, data: &[u8]) -> EntryCheck {
+    let auditor = Arc::new(Mutex::new(Auditor::new()));
+    let observer = Arc::clone(&auditor);
+    let converter =
+        converter
+            .clone()
+            .with_observer(Arc::new(move |converter, (lat, lon), output| {
+                let in_region = converter.in_region(lat, lon);
+                observer
+                    .lock()
+                    .unwrap()
+                    .record((lat, lon), output, in_region);
+            }));
+    let converted = convert_entry(&converter, name, data.to_vec());
+    let audit = auditor.lock().unwrap().report();
+    EntryCheck {
+        name: name.to_owned(),
+        format: entry_format(name),
+        audit,
+        changed: matches!(&converted, Ok(output) if output[..] != data[..]),
+        error: converted.err().map(|e| e.to_string()),
+    }
+}
+
+/// Converts every entry of an archive with [`convert_entry`], returning the number of entries.
+///
+/// The entries that are not converted are copied as they are compressed, without holding them in
+/// memory, and those that are converted must decompress to at most [`MAX_INFLATED`] bytes. Names
+/// are kept byte for byte, along with whether they are marked as UTF-8.
+pub fn convert_archive<R: BufRead, W: Write>(
+    converter: &Converter,
+    reader: R,
+    writer: W,
+) -> io::Result<u64> {
+    let out = ZipWriter::new(writer);
+    convert_entries(converter, ZipReader::new(reader), out, None)
+}
+
+/// Converts an archive as [`convert_archive`] does, recording its progress in a manifest so that
+/// an interrupted conversion can be resumed instead of started over.
+///
+/// Once each entry is written and `writer` flushed, a line is appended to `manifest` with the size
+/// of the output so far and the central directory record of the entry. To resume, pass the
+/// manifest of the interrupted run as `previous` and, as `writer`, its output truncated to
+/// [`resume_offset`] and positioned at its end, and keep appending to the same manifest. The
+/// entries it lists are then read through instead of converted again, and fail with
+/// [`io::ErrorKind::InvalidInput`] if they are not the first ones of the archive. Lines cut short
+/// by a crash are ignored.
+pub fn convert_archive_resumable<R: BufRead, W: Write, M: Write>(
+    converter: &Converter,
+    reader: R,
+    writer: W,
+    previous: &str,
+    mut manifest: M,
+) -> io::Result<u64> {
+    let done = Manifest::parse(previous);
+    let mut reader = ZipReader::new(reader);
+    for name in &done.names {
+        match reader.header()? {
+            Some(header) if header.name == *name => reader.read_through(&header)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "archive does not match the manifest",
+                ))
+            }
+        }
+    }
+    if !previous.is_empty() && !previous.ends_with('\n') {
+        manifest.write_all(b"\n")?;
+    }
+    let out = ZipWriter {
+        writer,
+        offset: done.end,
+        entries: u16::try_from(done.names.len()).map_err(|_| invalid("ZIP archive too large"))?,
+        central: done.central,
+    };
+    convert_entries(converter, reader, out, Some(&mut manifest))
+}
+
+/// Size the output of an interrupted [`convert_archive_resumable`] must be truncated to before
+/// resuming it, by its manifest.
+pub fn resume_offset(manifest: &str) -> u64 {
+    Manifest::parse(manifest).end
+}
+
+/// The entries written by an interrupted conversion, as listed by its manifest.
+struct Manifest {
+    names: Vec<Vec<u8>>,
+    central: Vec<u8>,
+    /// Size of the output after the last entry.
+    end: u64,
+}
+
+impl Manifest {
+    fn parse(text: &str) -> Self {
+        let mut manifest = Manifest {
+            names: Vec::new(),
+            central: Vec::new(),
+            end: 0,
+        };
+        for line in text.lines() {
+            let parsed = line
+                .split_once('\t')
+                .and_then(|(end, record)| Some((end.parse().ok()?, from_hex(record)?)));
+            // A line cut short lacks part of its record, or follows on from no entry.
+            let (end, record) = match parsed {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            if record.len() < 46
+                || read_u32(&record, 0) != CENTRAL_HEADER
+                || record.len() != 46 + read_u16(&record, 28) as usize
+                || u64::from(read_u32(&record, 42)) != manifest.end
+            {
+                continue;
+            }
+            manifest.names.push(record[46..].to_vec());
+            manifest.central.extend_from_slice(&record);
+            manifest.end = end;
+        }
+        manifest
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    let digit = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match *pair {
+            [high, low] => Some(digit(high)? << 4 | digit(low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Converts the remaining entries of `reader` into `out`, recording each one in `manifest`.
+fn convert_entries<R: BufRead, W: Write>(
+    converter: &Converter,
+    mut reader: ZipReader<R>,
+    mut out: ZipWriter<W>,
+    mut manifest: Option<&mut dyn Write>,
+) -> io::Result<u64> {
+    let mut count = u64::from(out.entries);
+    while let Some(header) = reader.header()? {
+        let name = header.name();
+        let central = out.central.len();
+        if entry_format(&name).is_none() {
+            out.copy_entry(&mut reader, &header)?;
+        } else {
+            let mut data = Checksum {
+                inner: Vec::new(),
+                crc: 0,
+                size: 0,
+            };
+            header.data(&mut reader.reader, &mut data, reader.max_size)?;
+            reader.check(&header, data.crc)?;
+            let data = convert_entry(converter, &name, data.inner)?;
+            out.write_named_entry(&header.name, header.flags & FLAG_UTF8 != 0, &data)?;
+        }
+        if let Some(manifest) = manifest.as_mut() {
+            out.writer.flush()?;
+            writeln!(
+                manifest,
+                "{}\t{}",
+                out.offset,
+                to_hex(&out.central[central..])
+            )?;
+            manifest.flush()?;
+        }
+        count += 1;
+    }
+    out.finish()?;
+    Ok(count)
+}
+
+/// Checks every entry of an archive with [`check_entry`], writing nothing. Only errors reading the
+/// archive itself are returned; those of the entries are in their checks.
+pub fn check_archive<R: BufRead>(converter: &Converter, reader: R) -> io::Result<Vec<EntryCheck>> {
+    let mut reader = ZipReader::new(reader);
+    let mut checks = Vec::new();
+    while let Some(header) = reader.header()? {
+        let name = header.name();
+        let check = if entry_format(&name).is_none() {
+            // The entries copied unchanged are only read through.
+            reader.read_through(&header)?;
+            EntryCheck {
+                name,
+                format: None,
+                audit: Auditor::new().report(),
+                changed: false,
+                error: None,
+            }
+        } else {
+            let mut data = Checksum {
+                inner: Vec::new(),
+                crc: 0,
+                size: 0,
+            };
+            header.data(&mut reader.reader, &mut data, reader.max_size)?;
+            reader.check(&header, data.crc)?;
+            check_entry(converter, &name, &data.inner)
+        };
+        checks.push(check);
+    }
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::GeodeticSystem::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32_continue(crc32(b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_deflate() {
+        let text = "<coordinates>116.40,39.90 116.41,39.91</coordinates>\n".repeat(100);
+        for data in [&b""[..], b"a", b"abcabcabcabc", text.as_bytes()] {
+            let compressed = deflate(data);
+            assert_eq!(inflate(&mut &compressed[..], MAX_INFLATED).unwrap(), data);
+        }
+        assert!(deflate(text.as_bytes()).len() < text.len() / 10);
+
+        // Longer than the window, with matches reaching back to its end.
+        let long: Vec<u8> = (0..200_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 28) as u8 + b'a')
+            .collect();
+        let long = [&long[..], &long[..WINDOW], &long[..]].concat();
+        assert_eq!(
+            inflate(&mut &deflate(&long)[..], MAX_INFLATED).unwrap(),
+            long
+        );
+        let zeros = deflate(&[0; 100_000]);
+        assert_eq!(inflate(&mut &zeros[..], 100_000).unwrap().len(), 100_000);
+        let error = inflate(&mut &zeros[..], 99_999).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // A stored block, then a dynamic block as written by zlib.
+        let mut stream = vec![0x00, 0x02, 0x00, 0xfd, 0xff, b'h', b'i'];
+        assert_eq!(
+            inflate(&mut &stream[..], MAX_INFLATED).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        stream.extend_from_slice(&[
+            0x55, 0xce, 0xb9, 0x0d, 0xc0, 0x20, 0x10, 0x04, 0xc0, 0x86, 0x2c, 0xcc, 0xbd, 0x80,
+            0x84, 0xdc, 0x0c, 0xfd, 0xf7, 0x60, 0xed, 0x39, 0x59, 0xa7, 0x13, 0xcd, 0x3e, 0x8f,
+            0x48, 0xb6, 0x7e, 0xd9, 0x6a, 0x7d, 0xdf, 0xe7, 0xd9, 0x1f, 0x0c, 0x80, 0x18, 0x89,
+            0x38, 0x48, 0x93, 0x48, 0x05, 0x64, 0x8b, 0x69, 0x82, 0x42, 0x89, 0x2c, 0x40, 0x19,
+            0x44, 0xae, 0xa0, 0x31, 0x99, 0x16, 0x68, 0x09, 0x51, 0x24, 0xc8, 0x49, 0xd2, 0xaa,
+            0x35, 0x38, 0x5a, 0x75, 0xfb, 0xdd, 0x2b, 0xef, 0x95, 0x7f, 0x01,
+        ]);
+        let mut expected = "hi".to_owned();
+        for i in 0..12 {
+            expected += &format!("<c>116.{},39.{}</c>", i * 7 % 100, i * 13 % 100);
+        }
+        assert_eq!(
+            inflate(&mut &stream[..], MAX_INFLATED).unwrap(),
+            expected.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_archive() {
+        let kml = "<kml><Placemark><Point><coordinates>116.4074,39.9042</coordinates></Point></Placemark></kml>";
+        let mut archive = ZipWriter::new(Vec::new());
+        archive.write_entry("doc.kml", kml.as_bytes()).unwrap();
+        archive
+            .write_entry("files/icon.png", &[0x89, b'P', b'N', b'G'])
+            .unwrap();
+        let archive = archive.finish().unwrap();
+
+        let entries: Vec<_> = ZipReader::new(&archive[..]).map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].data, kml.as_bytes());
+        assert_eq!(entries[1].name, "files/icon.png");
+
+        let converter = Converter::new(Gcj02, Wgs84);
+        let mut output = Vec::new();
+        assert_eq!(
+            convert_archive(&converter, &archive[..], &mut output).unwrap(),
+            2
+        );
+        let entries: Vec<_> = ZipReader::new(&output[..]).map(|e| e.unwrap()).collect();
+        let kml = String::from_utf8(entries[0].data.clone()).unwrap();
+        assert_eq!(
+            crate::kml::positions(&kml).unwrap(),
+            [converter.convert(39.9042, 116.4074)]
+        );
+        assert_eq!(entries[1].data, [0x89, b'P', b'N', b'G']);
+
+        let mut corrupt = archive.clone();
+        corrupt[14] ^= 1;
+        assert!(ZipReader::new(&corrupt[..]).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_max_size() {
+        let mut archive = ZipWriter::new(Vec::new());
+        archive.write_entry("zeros.bin", &[0; 100_000]).unwrap();
+        let archive = archive.finish().unwrap();
+        let mut reader = ZipReader::new(&archive[..]).with_max_size(99_999);
+        assert_eq!(reader.max_size(), 99_999);
+        let error = reader.next_entry().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let entry = ZipReader::new(&archive[..]).next_entry().unwrap().unwrap();
+        assert_eq!(entry.data.len(), 100_000);
+    }
+
+    #[test]
+    fn test_copy() {
+        // A GBK name without the UTF-8 flag, as written by the archivers of Chinese systems.
+        let gbk = b"\xb9\xec\xbc\xa3.txt";
+        let kml = "<kml><Point><coordinates>116.4074,39.9042</coordinates></Point></kml>";
+        let photo: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let mut archive = ZipWriter::new(Vec::new());
+        archive.write_named_entry(gbk, false, b"notes").unwrap();
+        archive.write_entry("photo.jpg", &photo).unwrap();
+        archive.write_entry("doc.kml", kml.as_bytes()).unwrap();
+        let archive = archive.finish().unwrap();
+
+        let converter = Converter::new(Gcj02, Wgs84);
+        let mut output = Vec::new();
+        convert_archive(&converter, &archive[..], &mut output).unwrap();
+        let entries: Vec<_> = ZipReader::new(&output[..]).map(|e| e.unwrap()).collect();
+        assert_eq!(
+            (&entries[0].raw_name[..], entries[0].utf8),
+            (&gbk[..], false)
+        );
+        assert_eq!(entries[0].name, "¹ì¼£.txt");
+        assert_eq!(entries[1].data, photo);
+        assert!(entries[2].utf8);
+        // The entries copied are written as they were read.
+        let kml_at = |archive: &[u8]| {
+            let at = archive.windows(7).position(|w| w == b"doc.kml").unwrap();
+            at - 30
+        };
+        assert_eq!(output[..kml_at(&output)], archive[..kml_at(&archive)]);
+
+        // Unless corrupt.
+        let mut corrupt = archive.clone();
+        let last = kml_at(&archive) - 1;
+        corrupt[last] ^= 1;
+        let result = convert_archive(&converter, &corrupt[..], &mut Vec::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_resume() {
+        /// Fails once `limit` bytes are written, as a conversion killed midway.
+        struct Cut<'a> {
+            out: &'a mut Vec<u8>,
+            limit: usize,
+        }
+
+        impl Write for Cut<'_> {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = buf.len().min(self.limit - self.out.len());
+                if n == 0 {
+                    return Err(io::ErrorKind::BrokenPipe.into());
+                }
+                self.out.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let kml = "<kml><Point><coordinates>116.4074,39.9042</coordinates></Point></kml>";
+        let mut state = 1u32;
+        let photo: Vec<u8> = (0..10_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let mut archive = ZipWriter::new(Vec::new());
+        archive.write_entry("a.kml", kml.as_bytes()).unwrap();
+        archive.write_entry("photo.jpg", &photo).unwrap();
+        archive.write_entry("b.kml", kml.as_bytes()).unwrap();
+        let archive = archive.finish().unwrap();
+        let converter = Converter::new(Gcj02, Wgs84);
+        let mut expected = Vec::new();
+        convert_archive(&converter, &archive[..], &mut expected).unwrap();
+
+        let mut output = Vec::new();
+        let mut manifest = Vec::new();
+        let cut = Cut {
+            out: &mut output,
+            limit: 5000,
+        };
+        assert!(
+            convert_archive_resumable(&converter, &archive[..], cut, "", &mut manifest).is_err()
+        );
+        // Only the first entry was done, and the manifest is cut within the second line.
+        manifest.extend_from_slice(b"5100\t504b0102");
+        let previous = String::from_utf8(manifest.clone()).unwrap();
+        assert_eq!(previous.lines().count(), 2);
+        let offset = resume_offset(&previous);
+        assert!(offset > 0 && offset < 5000);
+
+        output.truncate(offset as usize);
+        let count = convert_archive_resumable(
+            &converter,
+            &archive[..],
+            &mut output,
+            &previous,
+            &mut manifest,
+        );
+        assert_eq!(count.unwrap(), 3);
+        assert_eq!(output, expected);
+        let manifest = String::from_utf8(manifest).unwrap();
+        assert_eq!(Manifest::parse(&manifest).names.len(), 3);
+        // Up to the central directory.
+        let central = read_u32(&expected, expected.len() - 6);
+        assert_eq!(resume_offset(&manifest), u64::from(central));
+
+        // Another archive does not resume from it.
+        let mut other = ZipWriter::new(Vec::new());
+        other.write_entry("c.kml", kml.as_bytes()).unwrap();
+        let other = other.finish().unwrap();
+        let result =
+            convert_archive_resumable(&converter, &other[..], Vec::new(), &previous, io::sink());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_check() {
+        let kml =
+            "<kml><Point><coordinates>116.4074,39.9042 116.41,39.91</coordinates></Point></kml>";
+        let paris = r#"<gpx><wpt lat="48.8566" lon="2.3522"/></gpx>"#;
+        let invalid = r#"<gpx><wpt lat="NaN" lon="116.4"/><wpt lat="39.9" lon="116.4"/></gpx>"#;
+        let mut archive = ZipWriter::new(Vec::new());
+        archive.write_entry("doc.kml", kml.as_bytes()).unwrap();
+        archive.write_entry("paris.GPX", paris.as_bytes()).unwrap();
+        archive
+            .write_entry("invalid.gpx", invalid.as_bytes())
+            .unwrap();
+        archive.write_entry("track.kml.gz", b"plain").unwrap();
+        archive.write_entry("icon.png", b"\x89PNG").unwrap();
+        let archive = archive.finish().unwrap();
+
+        let converter = Converter::new(Gcj02, Wgs84);
+        let checks = check_archive(&converter, &archive[..]).unwrap();
+        let formats: Vec<_> = checks.iter().map(|check| check.format).collect();
+        assert_eq!(
+            formats,
+            [Some("kml"), Some("gpx"), Some("gpx"), Some("kml"), None]
+        );
+        let changed: Vec<_> = checks.iter().map(|check| check.changed).collect();
+        assert_eq!(changed, [true, false, false, false, false]);
+
+        let audit = &checks[0].audit;
+        assert_eq!((audit.count, audit.inside_china), (2, 2));
+        assert_eq!(
+            audit.bounds,
+            Some(crate::BoundingBox::new(39.9042, 116.4074, 39.91, 116.41))
+        );
+        assert!(audit.drift.unwrap().min > 100.0);
+        assert_eq!(checks[1].audit.outside_china, 1);
+        assert_eq!(checks[2].audit.nan_inputs, 1);
+        assert_eq!(
+            checks[2].error.as_deref(),
+            Some("invalid XML at byte 5: non-finite coordinate")
+        );
+        assert!(checks[3].error.is_some() && checks[3].audit.count == 0);
+        assert_eq!(
+            checks.iter().filter(|check| check.error.is_some()).count(),
+            2
+        );
+
+        // As the policy allows, the invalid entry would be converted.
+        let skip = converter.with_non_finite(crate::NonFinitePolicy::Skip);
+        let check = check_entry(&skip, "invalid.gpx", invalid.as_bytes());
+        assert_eq!((check.audit.count, check.audit.nan_inputs), (2, 1));
+        assert_eq!((check.changed, check.error), (true, None));
+
+        // So do the region and algorithm.
+        let nowhere = Converter::new(Gcj02, Wgs84).with_region(|_, _| false);
+        let check = check_entry(&nowhere, "doc.kml", kml.as_bytes());
+        assert_eq!((check.changed, check.audit.outside_china), (false, 2));
+        assert_eq!(check.audit.drift.unwrap().max, 0.0);
+        let point = "<kml><Point><coordinates>116.4074,39.9042</coordinates></Point></kml>";
+        let gzipped = crate::gzip::compress(point.as_bytes());
+        let fast = Converter::new(Gcj02, Wgs84).with_algorithm(crate::Algorithm::FastApprox);
+        let check = check_entry(&fast, "doc.kml.gz", &gzipped);
+        let drift = crate::haversine((39.9042, 116.4074), fast.convert(39.9042, 116.4074));
+        assert!(check.changed);
+        assert_eq!(check.audit.drift.unwrap().max, drift);
+    }
+
+    #[test]
+    fn test_takeout_entry() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let records = r#"{"locations":[{"latitudeE7":399042000,"longitudeE7":1164074000}]}"#;
+        let name = "Takeout/Location History/Records.json";
+        let output = convert_entry(&converter, name, records.as_bytes().to_vec()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            crate::takeout::convert(&converter, records).unwrap()
+        );
+        assert_ne!(output, records);
+        // Other JSON files are not touched.
+        let output = convert_entry(&converter, "Takeout/Maps/Records.json", records.into());
+        assert_eq!(output.unwrap(), records.as_bytes());
+    }
+
+    #[test]
+    fn test_gzip_entry() {
+        let converter = Converter::new(Gcj02, Wgs84);
+        let geojson = r#"{"type":"Point","coordinates":[116.4074,39.9042]}"#;
+        let compressed = crate::gzip::compress(geojson.as_bytes());
+        let output = convert_entry(&converter, "track.GeoJSON.gz", compressed).unwrap();
+        let output = String::from_utf8(crate::gzip::decompress(&output).unwrap()).unwrap();
+        assert_eq!(
+            crate::geojson::positions(&output).unwrap(),
+            [converter.convert(39.9042, 116.4074)]
+        );
+
+        let notes = crate::gzip::compress(b"notes");
+        assert_eq!(
+            convert_entry(&converter, "notes.txt.gz", notes.clone()).unwrap(),
+            notes
+        );
+        assert!(convert_entry(&converter, "track.kml.gz", b"plain".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_data_descriptor() {
+        // A deflated entry with its CRC in a trailing descriptor, as written by streaming tools.
+        let data = b"hello hello hello";
+        let compressed = deflate(data);
+        let mut archive = LOCAL_HEADER.to_le_bytes().to_vec();
+        archive.extend_from_slice(&20u16.to_le_bytes());
+        archive.extend_from_slice(&FLAG_DESCRIPTOR.to_le_bytes());
+        archive.extend_from_slice(&DEFLATED.to_le_bytes());
+        archive.extend_from_slice(&[0; 16]);
+        archive.extend_from_slice(&5u16.to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive.extend_from_slice(b"a.txt");
+        archive.extend_from_slice(&compressed);
+        archive.extend_from_slice(&DATA_DESCRIPTOR.to_le_bytes());
+        archive.extend_from_slice(&crc32(data).to_le_bytes());
+        archive.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+
+        let mut reader = ZipReader::new(&archive[..]);
+        assert_eq!(reader.next_entry().unwrap().unwrap().data, data);
+        assert!(reader.next_entry().unwrap().is_none());
+
+        // Copied with a descriptor too, whose sizes are in the central directory.
+        let converter = Converter::new(Gcj02, Wgs84);
+        let mut output = Vec::new();
+        convert_archive(&converter, &archive[..], &mut output).unwrap();
+        let descriptor = output
+            .windows(4)
+            .position(|w| w == DATA_DESCRIPTOR.to_le_bytes());
+        assert_eq!(descriptor, Some(30 + 5 + compressed.len()));
+        let central = descriptor.unwrap() + 16;
+        assert_eq!(read_u32(&output, central), CENTRAL_HEADER);
+        assert_eq!(read_u32(&output, central + 16), crc32(data));
+        let mut reader = ZipReader::new(&output[..]);
+        assert_eq!(reader.next_entry().unwrap().unwrap().data, data);
+        assert!(reader.next_entry().unwrap().is_none());
+    }
+}